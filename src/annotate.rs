@@ -0,0 +1,309 @@
+// annotating games: every position is searched once, then each move gets the evaluation after
+// it, a NAG when it gave away winning chances, the engine's better move as a variation, and
+// each player an accuracy and average centipawn loss in the tags
+use crate::cli::{exit_on_error, flag_value, search_limits, usage};
+use crate::moves::Move;
+use crate::pgn::{PgnGame, PgnMove};
+use crate::piece::Color;
+use crate::search::{mate_in, Search, SearchLimits, MATE_SCORE};
+use crate::state::State;
+use crate::uci::ENGINE_NAME;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::time::Duration;
+
+// scores are capped here for the centipawn loss, so one missed mate doesn't swamp a game
+const CP_CAP: i32 = 1000;
+// winning chances lost, in percent, for an inaccuracy, a mistake and a blunder
+const INACCURACY: f64 = 10.0;
+const MISTAKE: f64 = 20.0;
+const BLUNDER: f64 = 30.0;
+const NAG_MISTAKE: u8 = 2;
+const NAG_BLUNDER: u8 = 4;
+const NAG_DUBIOUS: u8 = 6;
+// the better move and the engine's line after it
+const VARIATION_PLIES: usize = 6;
+
+// one player's play over a game
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub moves: u32,
+    // the average of the move accuracies, each from 0 to 100
+    pub accuracy: f64,
+    pub acpl: f64,
+    pub inaccuracies: u32,
+    pub mistakes: u32,
+    pub blunders: u32,
+}
+
+// a searched position, the score for the side to move
+struct Evaluation {
+    score: i32,
+    pv: Vec<Move>,
+    // no legal moves, the score is exact
+    finished: bool,
+}
+
+// the chance to win, from 0 to 100, of the side with a score in centipawns; the scale is the
+// one lichess fitted to its games
+pub fn win_chance(score: i32) -> f64 {
+    50.0 + 50.0 * (2.0 / (1.0 + (-0.00368208 * score as f64).exp()) - 1.0)
+}
+
+// how good a move was, from 0 to 100, for the winning chances it lost
+pub fn move_accuracy(loss: f64) -> f64 {
+    (103.1668 * (-0.04354 * loss).exp() - 3.1669).clamp(0.0, 100.0)
+}
+
+// the NAG for a move that lost `loss` winning chances, if it deserves one
+pub fn classify(loss: f64) -> Option<u8> {
+    if loss >= BLUNDER {
+        Some(NAG_BLUNDER)
+    } else if loss >= MISTAKE {
+        Some(NAG_MISTAKE)
+    } else if loss >= INACCURACY {
+        Some(NAG_DUBIOUS)
+    } else {
+        None
+    }
+}
+
+// a score for the side to move as a [%eval] value from white's point of view: pawns, or #n
+// for a mate in n moves
+fn eval_comment(score: i32, side: Color) -> String {
+    let sign = if side == Color::White { 1 } else { -1 };
+    match mate_in(score) {
+        Some(moves) => format!("[%eval #{}]", sign * moves),
+        None => format!("[%eval {:.2}]", (sign * score) as f64 / 100.0),
+    }
+}
+
+// the comment without an earlier [%eval], so annotating twice doesn't pile them up
+fn strip_eval(comment: &str) -> String {
+    let Some(start) = comment.find("[%eval") else {
+        return comment.trim().to_string();
+    };
+    let end = comment[start..]
+        .find(']')
+        .map_or(comment.len(), |end| start + end + 1);
+    format!("{} {}", &comment[..start], &comment[end..])
+        .trim()
+        .to_string()
+}
+
+fn evaluate(search: &mut Search, state: &mut State) -> Evaluation {
+    if state.legal_moves().is_empty() {
+        let score = if state.in_check() { -MATE_SCORE } else { 0 };
+        return Evaluation {
+            score,
+            pv: Vec::new(),
+            finished: true,
+        };
+    }
+    let result = search.run(state);
+    Evaluation {
+        score: result.score,
+        pv: result.pv,
+        finished: false,
+    }
+}
+
+// the annotated game and the summaries of white and black; `progress` is called with the number
+// of positions searched so far and the total
+pub fn annotate(
+    game: &PgnGame,
+    limits: SearchLimits,
+    mut progress: impl FnMut(usize, usize),
+) -> (PgnGame, [Summary; 2]) {
+    let mut search = Search::new(limits);
+    let mut state = game.start.clone();
+    let mut evaluations = vec![evaluate(&mut search, &mut state)];
+    for (index, pgn_move) in game.moves.iter().enumerate() {
+        state.make_move(pgn_move.mv);
+        evaluations.push(evaluate(&mut search, &mut state));
+        progress(index + 1, game.moves.len());
+    }
+
+    let mut annotated = game.clone();
+    let mut summaries = [Summary::default(); 2];
+    let mut state = game.start.clone();
+    for (index, pgn_move) in annotated.moves.iter_mut().enumerate() {
+        let side = state.side_to_move();
+        let (before, after) = (&evaluations[index], &evaluations[index + 1]);
+        // the search may see more after the move than before it, the best move loses nothing
+        let best = before.score;
+        let played = if before.pv.first() == Some(&pgn_move.mv) {
+            best
+        } else {
+            (-after.score).min(best)
+        };
+        let loss = win_chance(best) - win_chance(played);
+        let cp_loss = best.clamp(-CP_CAP, CP_CAP) - played.clamp(-CP_CAP, CP_CAP);
+
+        let summary = &mut summaries[side.index()];
+        summary.moves += 1;
+        summary.accuracy += move_accuracy(loss);
+        summary.acpl += cp_loss as f64;
+        let nag = classify(loss).filter(|_| played < best);
+        match nag {
+            Some(NAG_BLUNDER) => summary.blunders += 1,
+            Some(NAG_MISTAKE) => summary.mistakes += 1,
+            Some(_) => summary.inaccuracies += 1,
+            None => {}
+        }
+
+        // the glyph replaces any earlier judgement of the move, other NAGs stay
+        if let Some(nag) = nag {
+            pgn_move.nags.retain(|&old| !(1..=6).contains(&old));
+            pgn_move.nags.insert(0, nag);
+            let line: Vec<PgnMove> = before
+                .pv
+                .iter()
+                .take(VARIATION_PLIES)
+                .map(|&mv| PgnMove::new(mv))
+                .collect();
+            let known = pgn_move
+                .variations
+                .iter()
+                .any(|variation| variation.iter().map(|m| m.mv).eq(line.iter().map(|m| m.mv)));
+            if !line.is_empty() && !known {
+                pgn_move.variations.insert(0, line);
+            }
+        }
+        let old = pgn_move.comment.as_deref().map(strip_eval);
+        let eval = (!after.finished).then(|| eval_comment(after.score, !side));
+        pgn_move.comment = match (eval, old) {
+            (Some(eval), Some(old)) if !old.is_empty() => Some(format!("{} {}", eval, old)),
+            (Some(eval), _) => Some(eval),
+            (None, old) => old.filter(|old| !old.is_empty()),
+        };
+        state.make_move(pgn_move.mv);
+    }
+
+    for summary in &mut summaries {
+        if summary.moves > 0 {
+            summary.accuracy /= summary.moves as f64;
+            summary.acpl /= summary.moves as f64;
+        }
+    }
+    annotated.set_tag("Annotator", ENGINE_NAME);
+    for (color, summary) in ["White", "Black"].iter().zip(&summaries) {
+        if summary.moves > 0 {
+            annotated.set_tag(
+                &format!("{}Accuracy", color),
+                &format!("{:.1}", summary.accuracy),
+            );
+            annotated.set_tag(&format!("{}ACPL", color), &format!("{:.0}", summary.acpl));
+        }
+    }
+    (annotated, summaries)
+}
+
+// annotate <games.pgn> [--output file] [--depth d] [--nodes n] [--movetime ms]
+// every position gets half a second unless a limit is given; without --output the annotated
+// games go to stdout, the progress always goes to stderr
+pub fn command(args: &[String]) {
+    let Some(path) = args.first() else {
+        usage("annotate <games.pgn> [--output file] [--depth d] [--nodes n] [--movetime ms]");
+    };
+    let limits = search_limits(args, Duration::from_millis(500));
+
+    let text = exit_on_error(fs::read_to_string(path).map_err(Into::into));
+    let games = exit_on_error(PgnGame::parse_all(&text));
+    let mut out: Box<dyn Write> = match flag_value(args, "--output") {
+        Some(path) => Box::new(BufWriter::new(exit_on_error(
+            File::create(path).map_err(Into::into),
+        ))),
+        None => Box::new(io::stdout().lock()),
+    };
+
+    for (index, game) in games.iter().enumerate() {
+        let (annotated, summaries) = annotate(game, limits, |done, total| {
+            eprint!(
+                "\rgame {}/{}: move {}/{}",
+                index + 1,
+                games.len(),
+                done,
+                total
+            );
+        });
+        eprintln!();
+        exit_on_error(writeln!(out, "{}", annotated).map_err(Into::into));
+        for (color, summary) in ["White", "Black"].iter().zip(&summaries) {
+            eprintln!(
+                "  {} {}: accuracy {:.1}%, acpl {:.0}, {} inaccuracies, {} mistakes, {} blunders",
+                color,
+                game.tag(color).unwrap_or("?"),
+                summary.accuracy,
+                summary.acpl,
+                summary.inaccuracies,
+                summary.mistakes,
+                summary.blunders
+            );
+        }
+    }
+    exit_on_error(out.flush().map_err(Into::into));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scales() {
+        assert!((win_chance(0) - 50.0).abs() < 1e-9);
+        assert!((win_chance(300) + win_chance(-300) - 100.0).abs() < 1e-9);
+        assert!(win_chance(MATE_SCORE) > 99.9);
+        assert!(move_accuracy(0.0) > 99.9);
+        assert!(move_accuracy(10.0) < move_accuracy(5.0));
+        assert_eq!(move_accuracy(100.0), 0.0);
+        assert_eq!(classify(5.0), None);
+        assert_eq!(classify(12.0), Some(NAG_DUBIOUS));
+        assert_eq!(classify(25.0), Some(NAG_MISTAKE));
+        assert_eq!(classify(60.0), Some(NAG_BLUNDER));
+
+        assert_eq!(eval_comment(34, Color::White), "[%eval 0.34]");
+        assert_eq!(eval_comment(34, Color::Black), "[%eval -0.34]");
+        assert_eq!(eval_comment(MATE_SCORE - 3, Color::Black), "[%eval #-2]");
+        assert_eq!(strip_eval("[%eval 0.21] a fine move"), "a fine move");
+        assert_eq!(strip_eval("the [%eval #3]"), "the");
+    }
+
+    #[test]
+    fn annotates_blunders() {
+        let game: PgnGame = "1. e4 e5 2. Qh5 Nc6 3. Bc4 {threatening mate} Nf6 4. Qxf7# 1-0"
+            .parse()
+            .unwrap();
+        let limits = SearchLimits {
+            depth: Some(3),
+            ..SearchLimits::default()
+        };
+        let mut calls = 0;
+        let (annotated, [white, black]) = annotate(&game, limits, |_, _| calls += 1);
+        assert_eq!(calls, 7);
+
+        let blunder = &annotated.moves[5];
+        assert_eq!(blunder.nags, [NAG_BLUNDER]);
+        assert_eq!(blunder.comment.as_deref(), Some("[%eval #1]"));
+        assert!(!blunder.variations[0].is_empty());
+        assert_eq!(
+            annotated.moves[4]
+                .comment
+                .as_deref()
+                .map(|c| c.ends_with("] threatening mate")),
+            Some(true)
+        );
+        // nothing to evaluate after the mate
+        assert_eq!(annotated.moves[6].comment, None);
+
+        assert_eq!((white.moves, black.moves), (4, 3));
+        assert_eq!(black.blunders, 1);
+        assert!(white.accuracy > black.accuracy);
+        assert!(black.acpl > white.acpl);
+        assert_eq!(annotated.tag("Annotator"), Some(ENGINE_NAME));
+        assert!(annotated.tag("BlackAccuracy").is_some());
+        let text = annotated.to_string();
+        assert!(text.contains("Nf6?? {[%eval #1]}"));
+        assert!(text.contains("(3... "));
+    }
+}
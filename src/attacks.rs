@@ -0,0 +1,168 @@
+use crate::bitboard::*;
+use crate::bitboard_moves::*;
+use crate::geometry::between;
+use crate::piece::*;
+use crate::square::*;
+use crate::state::State;
+
+impl State {
+    fn sliders(&self, color: Color) -> (BitBoard, BitBoard) {
+        let board = &self.board;
+        let queens = board.by_piece(Piece::new(color, PieceType::Queen));
+        let rooks = board.by_piece(Piece::new(color, PieceType::Rook)) | queens;
+        let bishops = board.by_piece(Piece::new(color, PieceType::Bishop)) | queens;
+        (rooks, bishops)
+    }
+
+    // pieces of both colors attacking the square, with sliders seeing through
+    // anything not in `occupancy` (SEE removes pieces as they capture)
+    pub fn attackers_to(&self, square: Square, occupancy: BitBoard) -> BitBoard {
+        let board = &self.board;
+        let queens = board.by_piece_type(PieceType::Queen);
+        let rooks = board.by_piece_type(PieceType::Rook) | queens;
+        let bishops = board.by_piece_type(PieceType::Bishop) | queens;
+
+        (pawn_attacks(Color::Black, square)
+            & board.by_piece(Piece::new(Color::White, PieceType::Pawn)))
+            | (pawn_attacks(Color::White, square)
+                & board.by_piece(Piece::new(Color::Black, PieceType::Pawn)))
+            | (knight_moves(square) & board.by_piece_type(PieceType::Knight))
+            | (king_moves(square) & board.by_piece_type(PieceType::King))
+            | (rook_moves(occupancy, square) & rooks)
+            | (bishop_moves(occupancy, square) & bishops)
+    }
+
+    pub fn is_attacked(&self, square: Square, by: Color) -> bool {
+        self.attackers_to(square, self.board.occupied()) & self.board.by_color(by) != EMPTY
+    }
+
+    // pieces giving check to the side to move
+    pub fn checkers(&self) -> BitBoard {
+        let us = self.side_to_move();
+        match self.board.king_square(us) {
+            Some(king) => self.attackers_to(king, self.board.occupied()) & self.board.by_color(!us),
+            None => EMPTY,
+        }
+    }
+
+    // (pinned pieces of `color`, enemy sliders pinning them)
+    fn pins(&self, color: Color) -> (BitBoard, BitBoard) {
+        let king = match self.board.king_square(color) {
+            Some(king) => king,
+            None => return (EMPTY, EMPTY),
+        };
+        let occupied = self.board.occupied();
+        let (rooks, bishops) = self.sliders(!color);
+        let snipers = (rook_moves(EMPTY, king) & rooks) | (bishop_moves(EMPTY, king) & bishops);
+
+        let mut pinned = EMPTY;
+        let mut pinners = EMPTY;
+        for sniper in snipers {
+            let blockers = between(king, sniper) & occupied;
+            if blockers.count() == 1 && blockers & self.board.by_color(color) != EMPTY {
+                pinned |= blockers;
+                pinners |= BitBoard::from_square(sniper);
+            }
+        }
+        (pinned, pinners)
+    }
+
+    // pieces of `color` that can't leave the line between their king and an enemy slider
+    pub fn pinned(&self, color: Color) -> BitBoard {
+        self.pins(color).0
+    }
+
+    // enemy sliders pinning a piece of `color` to its king
+    pub fn pinners(&self, color: Color) -> BitBoard {
+        self.pins(color).1
+    }
+
+    // sliders of `by` that would attack the square if the first piece in their way moved
+    pub fn xray_attackers(&self, square: Square, by: Color) -> BitBoard {
+        let occupied = self.board.occupied();
+        let (rooks, bishops) = self.sliders(by);
+
+        let rook_attacks = rook_moves(occupied, square);
+        let bishop_attacks = bishop_moves(occupied, square);
+        let rook_xrays = rook_moves(occupied ^ (rook_attacks & occupied), square) - rook_attacks;
+        let bishop_xrays =
+            bishop_moves(occupied ^ (bishop_attacks & occupied), square) - bishop_attacks;
+        (rook_xrays & rooks) | (bishop_xrays & bishops)
+    }
+
+    // how many pieces of `color` attack each square
+    pub fn attack_map(&self, color: Color) -> [u8; SQUARE_COUNT] {
+        let board = &self.board;
+        let occupied = board.occupied();
+        let mut map = [0; SQUARE_COUNT];
+
+        for from in board.by_color(color) {
+            let attacks = match board.piece(from).unwrap().piece_type() {
+                PieceType::Pawn => pawn_attacks(color, from),
+                PieceType::Knight => knight_moves(from),
+                PieceType::Bishop => bishop_moves(occupied, from),
+                PieceType::Rook => rook_moves(occupied, from),
+                PieceType::Queen => queen_moves(occupied, from),
+                PieceType::King => king_moves(from),
+            };
+            for square in attacks {
+                map[square.index()] += 1;
+            }
+        }
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bitboard(list: &[Square]) -> BitBoard {
+        list.iter().copied().collect()
+    }
+
+    #[test]
+    fn attackers_and_checkers() {
+        let state = State::from_fen("4k3/8/8/1b6/8/2N5/3P4/r3K2R w - - 0 1").unwrap();
+        let occupied = state.board.occupied();
+        assert_eq!(
+            state.attackers_to(Square::E4, occupied),
+            bitboard(&[Square::C3])
+        );
+        assert_eq!(
+            state.attackers_to(Square::E3, occupied),
+            bitboard(&[Square::D2])
+        );
+        assert_eq!(
+            state.attackers_to(Square::E2, occupied),
+            bitboard(&[Square::E1, Square::C3, Square::B5])
+        );
+        assert_eq!(state.checkers(), bitboard(&[Square::A1]));
+        assert!(state.is_attacked(Square::D1, Color::Black));
+        assert!(state.is_attacked(Square::F1, Color::Black));
+        assert!(!state.is_attacked(Square::G1, Color::Black));
+        assert!(state.is_attacked(Square::F1, Color::White));
+    }
+
+    #[test]
+    fn pins_and_xrays() {
+        let state = State::from_fen("4k3/8/8/b7/8/8/3P4/4K2R w - - 0 1").unwrap();
+        assert_eq!(state.pinned(Color::White), bitboard(&[Square::D2]));
+        assert_eq!(state.pinners(Color::White), bitboard(&[Square::A5]));
+        assert_eq!(state.pinned(Color::Black), EMPTY);
+        assert_eq!(
+            state.xray_attackers(Square::E1, Color::Black),
+            bitboard(&[Square::A5])
+        );
+        assert_eq!(state.xray_attackers(Square::H8, Color::White), EMPTY);
+    }
+
+    #[test]
+    fn attack_map_counts() {
+        let map = State::default().attack_map(Color::White);
+        assert_eq!(map[Square::F3.index()], 3);
+        assert_eq!(map[Square::E2.index()], 4);
+        assert_eq!(map[Square::E4.index()], 0);
+        assert_eq!(map.iter().map(|&n| n as u32).sum::<u32>(), 38);
+    }
+}
@@ -0,0 +1,219 @@
+// KPK win/draw bitbase, generated on first use by classifying every position until nothing
+// changes. Positions are stored with white as the side with the pawn and the pawn on files
+// a-d, probe flips and mirrors everything else into that form.
+use crate::bitboard_moves::{king_moves, pawn_attacks};
+use crate::geometry::distance;
+use crate::piece::Color;
+use crate::square::Square;
+use std::sync::OnceLock;
+
+// side to move, two kings and a pawn on 24 squares
+const SIZE: usize = 2 * 24 * 64 * 64;
+
+const INVALID: u8 = 0;
+const UNKNOWN: u8 = 1;
+const DRAW: u8 = 2;
+const WIN: u8 = 4;
+
+// white king, black king, side to move, pawn file, then the pawn rank from the 7th down
+fn encode(side: Color, white_king: usize, black_king: usize, pawn: usize) -> usize {
+    white_king | black_king << 6 | side.index() << 12 | (pawn % 8) << 13 | (6 - pawn / 8) << 15
+}
+
+fn decode(index: usize) -> (Color, Square, Square, Square) {
+    let square = |index: usize| Square::from_index(index).unwrap();
+    let side = if index >> 12 & 1 == 0 {
+        Color::White
+    } else {
+        Color::Black
+    };
+    let pawn = (6 - (index >> 15)) * 8 + (index >> 13 & 3);
+    (
+        side,
+        square(index & 63),
+        square(index >> 6 & 63),
+        square(pawn),
+    )
+}
+
+// what's known without looking at the moves
+fn initial(index: usize) -> u8 {
+    let (side, white_king, black_king, pawn) = decode(index);
+    let attacks = pawn_attacks(Color::White, pawn);
+    let promotion = Square::from_index(pawn.index() + 8).unwrap();
+
+    if distance(white_king, black_king) <= 1
+        || white_king == pawn
+        || black_king == pawn
+        || (side == Color::White && attacks.contains(black_king))
+    {
+        INVALID
+    } else if side == Color::White
+        && pawn.index() / 8 == 6
+        && white_king != promotion
+        && (distance(black_king, promotion) > 1 || distance(white_king, promotion) == 1)
+    {
+        // promotes without the queen being taken
+        WIN
+    } else if side == Color::Black
+        && ((king_moves(black_king) & !(king_moves(white_king) | attacks)).is_empty()
+            || (king_moves(black_king) & !king_moves(white_king)).contains(pawn))
+    {
+        // stalemate, or the pawn falls
+        DRAW
+    } else {
+        UNKNOWN
+    }
+}
+
+// a position is good for the side to move once one move reaches a good position for it,
+// and bad once every move reaches a bad one
+fn classify(results: &[u8], index: usize) -> u8 {
+    let (side, white_king, black_king, pawn) = decode(index);
+    let (good, bad) = match side {
+        Color::White => (WIN, DRAW),
+        Color::Black => (DRAW, WIN),
+    };
+
+    // illegal moves lead to INVALID positions, which add nothing
+    let mut reached = INVALID;
+    match side {
+        Color::White => {
+            for square in king_moves(white_king) {
+                reached |= results[encode(
+                    Color::Black,
+                    square.index(),
+                    black_king.index(),
+                    pawn.index(),
+                )];
+            }
+            let (wk, bk, pawn) = (white_king.index(), black_king.index(), pawn.index());
+            if pawn / 8 < 6 {
+                reached |= results[encode(Color::Black, wk, bk, pawn + 8)];
+                if pawn / 8 == 1 && pawn + 8 != wk && pawn + 8 != bk {
+                    reached |= results[encode(Color::Black, wk, bk, pawn + 16)];
+                }
+            }
+        }
+        Color::Black => {
+            for square in king_moves(black_king) {
+                reached |= results[encode(
+                    Color::White,
+                    white_king.index(),
+                    square.index(),
+                    pawn.index(),
+                )];
+            }
+        }
+    }
+
+    if reached & good != 0 {
+        good
+    } else if reached & UNKNOWN != 0 {
+        UNKNOWN
+    } else {
+        bad
+    }
+}
+
+fn bitbase() -> &'static [u64] {
+    static BITBASE: OnceLock<Vec<u64>> = OnceLock::new();
+    BITBASE.get_or_init(|| {
+        let mut results: Vec<u8> = (0..SIZE).map(initial).collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for index in 0..SIZE {
+                if results[index] == UNKNOWN {
+                    results[index] = classify(&results, index);
+                    changed |= results[index] != UNKNOWN;
+                }
+            }
+        }
+
+        let mut bits = vec![0; SIZE / 64];
+        for (index, &result) in results.iter().enumerate() {
+            if result == WIN {
+                bits[index / 64] |= 1 << (index % 64);
+            }
+        }
+        bits
+    })
+}
+
+// whether the side with the pawn wins, assuming a legal position
+pub fn probe(
+    strong: Color,
+    strong_king: Square,
+    pawn: Square,
+    weak_king: Square,
+    side_to_move: Color,
+) -> bool {
+    let flip = if strong == Color::White { 0 } else { 56 };
+    let mirror = if (pawn.index() ^ flip) % 8 >= 4 { 7 } else { 0 };
+    let normalize = |square: Square| square.index() ^ flip ^ mirror;
+    let side = if side_to_move == strong {
+        Color::White
+    } else {
+        Color::Black
+    };
+
+    let index = encode(
+        side,
+        normalize(strong_king),
+        normalize(weak_king),
+        normalize(pawn),
+    );
+    bitbase()[index / 64] & 1 << (index % 64) != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::State;
+
+    fn wins(fen: &str) -> bool {
+        let state = State::from_fen(fen).unwrap();
+        let board = &state.board;
+        let pawns = board.by_piece_type(crate::piece::PieceType::Pawn);
+        let pawn = pawns.to_square().unwrap();
+        let strong = board.piece(pawn).unwrap().color();
+        probe(
+            strong,
+            board.king_square(strong).unwrap(),
+            pawn,
+            board.king_square(!strong).unwrap(),
+            state.side_to_move(),
+        )
+    }
+
+    #[test]
+    fn known_positions() {
+        for (mirrored, flipped) in [
+            // whoever has to move gives up the opposition
+            ("8/4k3/8/4K3/4P3/8/8/8 b", "8/8/8/4p3/4k3/8/4K3/8 w"),
+            // a king on the sixth rank wins either way
+            ("4k3/8/4K3/4P3/8/8/8/8 w", "8/8/8/8/4p3/4k3/8/4K3 b"),
+            ("4k3/8/4K3/4P3/8/8/8/8 b", "8/8/8/8/4p3/4k3/8/4K3 w"),
+            // the pawn outruns the king
+            ("8/8/8/8/8/k7/6P1/K7 w", "k7/6p1/K7/8/8/8/8/8 b"),
+        ] {
+            assert!(wins(&format!("{} - - 0 1", mirrored)), "{}", mirrored);
+            assert!(wins(&format!("{} - - 0 1", flipped)), "{}", flipped);
+        }
+
+        for fen in [
+            "8/4k3/8/4K3/4P3/8/8/8 w",
+            "8/8/8/4p3/4k3/8/4K3/8 b",
+            // a rook pawn with the defending king in front
+            "k7/8/8/8/P7/8/8/K7 w",
+            "7k/8/8/8/7P/8/8/7K w",
+            // stalemate
+            "4k3/4P3/4K3/8/8/8/8/8 b",
+            // the pawn is lost
+            "8/8/8/8/8/5k2/4P3/K7 b",
+        ] {
+            assert!(!wins(&format!("{} - - 0 1", fen)), "{}", fen);
+        }
+    }
+}
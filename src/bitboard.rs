@@ -1,24 +1,32 @@
 use crate::direction::Direction;
+use crate::piece::Color;
 use crate::square::Square;
 use crate::{file::File, rank::Rank};
 use std::{fmt, ops::*};
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct BitBoard(pub u64);
 
 pub const EMPTY: BitBoard = BitBoard(0);
 pub const UNIVERSE: BitBoard = BitBoard(!0);
+pub const LIGHT_SQUARES: BitBoard = BitBoard(0x55AA55AA55AA55AA);
+pub const DARK_SQUARES: BitBoard = BitBoard(!0x55AA55AA55AA55AA);
 
 impl BitBoard {
-    pub fn new(b: u64) -> Self {
+    pub const fn new(b: u64) -> Self {
         BitBoard(b)
     }
 
-    pub fn from_square(sq: Square) -> Self {
+    pub const fn from_square(sq: Square) -> Self {
         BitBoard(1u64.wrapping_shl(sq.index() as u32))
     }
 
-    pub fn from_file(file: File) -> Self {
+    pub const fn from_file(file: File) -> Self {
         match file {
             File::A => BitBoard(0x0101010101010101),
             File::B => BitBoard(0x0202020202020202),
@@ -31,7 +39,7 @@ impl BitBoard {
         }
     }
 
-    pub fn from_rank(rank: Rank) -> Self {
+    pub const fn from_rank(rank: Rank) -> Self {
         match rank {
             Rank::Rank1 => BitBoard(0x00000000000000FF),
             Rank::Rank2 => BitBoard(0x000000000000FF00),
@@ -44,67 +52,227 @@ impl BitBoard {
         }
     }
 
-    pub fn to_square(self) -> Option<Square> {
+    pub const fn to_square(self) -> Option<Square> {
         Square::from_index(self.0.trailing_zeros() as usize)
     }
 
-    pub fn swap(self) -> BitBoard {
-        BitBoard(self.0.swap_bytes())
+    pub const fn count(self) -> u32 {
+        self.0.count_ones()
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn more_than_one(self) -> bool {
+        self.0 & self.0.wrapping_sub(1) != 0
+    }
+
+    pub const fn contains(self, sq: Square) -> bool {
+        self.0 & BitBoard::from_square(sq).0 != 0
+    }
+
+    pub const fn lsb(self) -> Option<Square> {
+        self.to_square()
     }
 
-    pub fn up(self) -> BitBoard {
+    pub const fn msb(self) -> Option<Square> {
+        if self.0 == 0 {
+            None
+        } else {
+            Square::from_index(63 - self.0.leading_zeros() as usize)
+        }
+    }
+
+    pub fn pop_lsb(&mut self) -> Option<Square> {
+        let sq = self.lsb();
+        self.0 &= self.0.wrapping_sub(1);
+        sq
+    }
+
+    pub const fn up(self) -> BitBoard {
         BitBoard(self.0.wrapping_shl(8))
     }
 
-    pub fn up_n(self, n: u32) -> BitBoard {
+    pub const fn up_n(self, n: u32) -> BitBoard {
         BitBoard(self.0.wrapping_shl(n << 3))
     }
 
-    pub fn down(self) -> BitBoard {
+    pub const fn down(self) -> BitBoard {
         BitBoard(self.0.wrapping_shr(8))
     }
 
-    pub fn down_n(self, n: u32) -> BitBoard {
+    pub const fn down_n(self, n: u32) -> BitBoard {
         BitBoard(self.0.wrapping_shr(n << 3))
     }
 
-    pub fn left(self) -> BitBoard {
+    pub const fn left(self) -> BitBoard {
         BitBoard(self.0.wrapping_shr(1) & 0x7F7F7F7F7F7F7F7Fu64)
     }
 
-    pub fn left_n(self, n: u32) -> BitBoard {
-        (0..n).fold(self, |bb, _| bb.left())
+    pub const fn left_n(self, n: u32) -> BitBoard {
+        let mut bb = self;
+        let mut i = 0;
+        while i < n {
+            bb = bb.left();
+            i += 1;
+        }
+        bb
     }
 
-    pub fn right(self) -> BitBoard {
+    pub const fn right(self) -> BitBoard {
         BitBoard(self.0.wrapping_shl(1) & 0xFEFEFEFEFEFEFEFEu64)
     }
 
-    pub fn right_n(self, n: u32) -> BitBoard {
-        (0..n).fold(self, |bb, _| bb.right())
+    pub const fn right_n(self, n: u32) -> BitBoard {
+        let mut bb = self;
+        let mut i = 0;
+        while i < n {
+            bb = bb.right();
+            i += 1;
+        }
+        bb
+    }
+
+    pub const fn up_left(self) -> BitBoard {
+        BitBoard(self.0.wrapping_shl(7) & 0x7F7F7F7F7F7F7F7Fu64)
+    }
+
+    pub const fn up_right(self) -> BitBoard {
+        BitBoard(self.0.wrapping_shl(9) & 0xFEFEFEFEFEFEFEFEu64)
     }
 
-    pub fn shift(self, direction: Direction) -> BitBoard {
+    pub const fn down_left(self) -> BitBoard {
+        BitBoard(self.0.wrapping_shr(9) & 0x7F7F7F7F7F7F7F7Fu64)
+    }
+
+    pub const fn down_right(self) -> BitBoard {
+        BitBoard(self.0.wrapping_shr(7) & 0xFEFEFEFEFEFEFEFEu64)
+    }
+
+    pub const fn shift(self, direction: Direction) -> BitBoard {
         match direction {
             Direction::Up => self.up(),
             Direction::Down => self.down(),
             Direction::Left => self.left(),
             Direction::Right => self.right(),
+            Direction::UpLeft => self.up_left(),
+            Direction::DownRight => self.down_right(),
+            Direction::UpRight => self.up_right(),
+            Direction::DownLeft => self.down_left(),
         }
     }
+
+    // every square on or above a set square, on the same file
+    pub const fn north_fill(self) -> BitBoard {
+        let mut b = self.0;
+        b |= b << 8;
+        b |= b << 16;
+        b |= b << 32;
+        BitBoard(b)
+    }
+
+    pub const fn south_fill(self) -> BitBoard {
+        let mut b = self.0;
+        b |= b >> 8;
+        b |= b >> 16;
+        b |= b >> 32;
+        BitBoard(b)
+    }
+
+    // whole files that contain a set square
+    pub const fn file_fill(self) -> BitBoard {
+        BitBoard(self.north_fill().0 | self.south_fill().0)
+    }
+
+    // squares in front of the set squares from color's point of view, not including them
+    pub const fn front_span(self, color: Color) -> BitBoard {
+        match color {
+            Color::White => self.up().north_fill(),
+            Color::Black => self.down().south_fill(),
+        }
+    }
+
+    pub const fn rear_span(self, color: Color) -> BitBoard {
+        match color {
+            Color::White => self.down().south_fill(),
+            Color::Black => self.up().north_fill(),
+        }
+    }
+
+    // rank 1 <-> rank 8
+    pub const fn flip_vertical(self) -> BitBoard {
+        BitBoard(self.0.swap_bytes())
+    }
+
+    // file a <-> file h
+    pub const fn mirror_horizontal(self) -> BitBoard {
+        const K1: u64 = 0x5555555555555555;
+        const K2: u64 = 0x3333333333333333;
+        const K4: u64 = 0x0F0F0F0F0F0F0F0F;
+        let mut b = self.0;
+        b = ((b >> 1) & K1) | ((b & K1) << 1);
+        b = ((b >> 2) & K2) | ((b & K2) << 2);
+        b = ((b >> 4) & K4) | ((b & K4) << 4);
+        BitBoard(b)
+    }
+
+    // mirrored along a1-h8, so a2 <-> b1
+    pub const fn flip_diagonal(self) -> BitBoard {
+        const K1: u64 = 0x5500550055005500;
+        const K2: u64 = 0x3333000033330000;
+        const K4: u64 = 0x0F0F0F0F00000000;
+        let mut b = self.0;
+        let mut t = K4 & (b ^ (b << 28));
+        b ^= t ^ (t >> 28);
+        t = K2 & (b ^ (b << 14));
+        b ^= t ^ (t >> 14);
+        t = K1 & (b ^ (b << 7));
+        b ^= t ^ (t >> 7);
+        BitBoard(b)
+    }
+
+    // mirrored along a8-h1, so a1 <-> h8
+    pub const fn flip_anti_diagonal(self) -> BitBoard {
+        const K1: u64 = 0xAA00AA00AA00AA00;
+        const K2: u64 = 0xCCCC0000CCCC0000;
+        const K4: u64 = 0xF0F0F0F00F0F0F0F;
+        let mut b = self.0;
+        let mut t = b ^ (b << 36);
+        b ^= K4 & (t ^ (b >> 36));
+        t = K2 & (b ^ (b << 18));
+        b ^= t ^ (t >> 18);
+        t = K1 & (b ^ (b << 9));
+        b ^= t ^ (t >> 9);
+        BitBoard(b)
+    }
+
+    // as seen from white's side, so a1 ends up on a8
+    pub const fn rotate_clockwise(self) -> BitBoard {
+        self.flip_diagonal().flip_vertical()
+    }
+
+    pub const fn rotate_anticlockwise(self) -> BitBoard {
+        self.flip_vertical().flip_diagonal()
+    }
+
+    pub const fn rotate_180(self) -> BitBoard {
+        BitBoard(self.0.reverse_bits())
+    }
 }
 
 impl Iterator for BitBoard {
     type Item = Square;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.0 == 0 {
-            None
-        } else {
-            let sq = self.to_square();
-            self.0 &= self.0 - 1;
-            sq
-        }
+        self.pop_lsb()
+    }
+}
+
+impl FromIterator<Square> for BitBoard {
+    fn from_iter<I: IntoIterator<Item = Square>>(iter: I) -> Self {
+        iter.into_iter()
+            .fold(EMPTY, |bb, sq| bb | BitBoard::from_square(sq))
     }
 }
 
@@ -119,7 +287,7 @@ impl fmt::Display for BitBoard {
                 s.push_str(". ");
             }
             if i % 8 == 7 {
-                s.push_str("\n");
+                s.push('\n');
             }
         }
         write!(f, "{}", s)
@@ -175,3 +343,92 @@ impl BitAndAssign for BitBoard {
         self.0 &= rhs.0;
     }
 }
+
+impl Sub for BitBoard {
+    type Output = BitBoard;
+
+    // squares in self but not in rhs
+    fn sub(self, rhs: BitBoard) -> Self::Output {
+        BitBoard(self.0 & !rhs.0)
+    }
+}
+
+impl SubAssign for BitBoard {
+    fn sub_assign(&mut self, rhs: BitBoard) {
+        self.0 &= !rhs.0;
+    }
+}
+
+impl Shl<u32> for BitBoard {
+    type Output = BitBoard;
+
+    fn shl(self, rhs: u32) -> Self::Output {
+        BitBoard(self.0.wrapping_shl(rhs))
+    }
+}
+
+impl Shr<u32> for BitBoard {
+    type Output = BitBoard;
+
+    fn shr(self, rhs: u32) -> Self::Output {
+        BitBoard(self.0.wrapping_shr(rhs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::squares;
+
+    // each transform against where it should send every single square
+    #[test]
+    fn flips_and_rotations() {
+        for sq in squares() {
+            let (rank, file) = (sq.rank().index(), sq.file().index());
+            let at = |rank: usize, file: usize| {
+                BitBoard::from_square(Square::from_index(rank * 8 + file).unwrap())
+            };
+            let bb = BitBoard::from_square(sq);
+            assert_eq!(bb.flip_vertical(), at(7 - rank, file));
+            assert_eq!(bb.mirror_horizontal(), at(rank, 7 - file));
+            assert_eq!(bb.flip_diagonal(), at(file, rank));
+            assert_eq!(bb.flip_anti_diagonal(), at(7 - file, 7 - rank));
+            assert_eq!(bb.rotate_clockwise(), at(7 - file, rank));
+            assert_eq!(bb.rotate_anticlockwise(), at(file, 7 - rank));
+            assert_eq!(bb.rotate_180(), at(7 - rank, 7 - file));
+        }
+    }
+
+    #[test]
+    fn spans_and_counting() {
+        let bb: BitBoard = [Square::C3, Square::F6].into_iter().collect();
+        assert_eq!(bb.count(), 2);
+        assert!(bb.more_than_one());
+        assert!(!BitBoard::from_square(Square::C3).more_than_one());
+        assert_eq!(bb.lsb(), Some(Square::C3));
+        assert_eq!(bb.msb(), Some(Square::F6));
+        assert_eq!(EMPTY.msb(), None);
+        assert_eq!(
+            bb.file_fill(),
+            BitBoard::from_file(File::C) | BitBoard::from_file(File::F)
+        );
+        assert_eq!(
+            BitBoard::from_square(Square::E4).front_span(Color::White),
+            [Square::E5, Square::E6, Square::E7, Square::E8]
+                .into_iter()
+                .collect()
+        );
+        assert_eq!(
+            BitBoard::from_square(Square::E4).rear_span(Color::Black),
+            BitBoard::from_square(Square::E4).front_span(Color::White)
+        );
+        assert_eq!(
+            bb - BitBoard::from_square(Square::C3),
+            BitBoard::from_square(Square::F6)
+        );
+        assert_eq!(
+            BitBoard::from_square(Square::A1) << 9,
+            BitBoard::from_square(Square::B2)
+        );
+    }
+}
@@ -4,17 +4,130 @@ use crate::file::*;
 use crate::piece::*;
 use crate::rank::*;
 use crate::square::*;
+use std::sync::OnceLock;
 
+// fixed-shift magics (shift = 64 - mask bits) so both backends need the same table size
+#[rustfmt::skip]
+const ROOK_MAGICS: [u64; 64] = [
+    0x0980008011400020, 0x8340004410002000, 0x0880200090008268, 0x0080080080100004,
+    0x8100110004020800, 0x0300010004000822, 0x08801A0029000080, 0x8100050001204882,
+    0x0844800081400320, 0x0804402010004000, 0x0108802003100480, 0x0004808008001000,
+    0x0003001801001014, 0x0002000200041008, 0x0004008108042210, 0x0105000100009042,
+    0x0400808000400021, 0xC100404010002000, 0x0060008010002088, 0x0400808008001000,
+    0x4440808008000400, 0x1002008004000280, 0x40024400300D1248, 0x0010020000408104,
+    0x0101008200204200, 0x8020002040005000, 0x4100100080802000, 0x4008006A80100280,
+    0x1020080080040080, 0x0004010040020040, 0x0018A12400080290, 0x6140004200008104,
+    0x4000400020800090, 0x2020002080804000, 0x0000408202002010, 0x0080100501000820,
+    0x0000800400800800, 0x000A200408014010, 0x0100800200800100, 0xA00800570200008C,
+    0x008000406000C010, 0x1040100028002000, 0x0048200100110040, 0x0068490210030020,
+    0x1009080005010010, 0x2142000804010100, 0x1001080110840002, 0x1801004400820001,
+    0x010440208D020200, 0x0000400020008080, 0x0200200080100280, 0x0000100020090100,
+    0x0204008008020480, 0x8104010040020040, 0x78000201B0080400, 0x0040800051002880,
+    0x0050108001002041, 0x208A801100614003, 0x0006002042089082, 0x0011090004201001,
+    0x1002001004200802, 0x0005000208040001, 0x0002002701AC0822, 0x000010250184004A,
+];
+
+#[rustfmt::skip]
+const BISHOP_MAGICS: [u64; 64] = [
+    0xC0A0012206040EA0, 0x8010228200420001, 0x0110008220400400, 0x02445C0080106000,
+    0x0044042004008100, 0x0880900420408C05, 0x0201080110080002, 0x0000108094202000,
+    0x0000042002040108, 0x0000623024110042, 0x0086100094811002, 0x0000044502002080,
+    0x0100460211400040, 0x0008109004200004, 0x0202320084844000, 0x8040042421041009,
+    0x201010C05102008C, 0x1020888208024080, 0x0108000C80290200, 0x8048000420425203,
+    0x0005000090402000, 0x2080400201104100, 0x8820420111101000, 0x4AC0302208821802,
+    0x000440001002A840, 0x2002200010041080, 0x1012080201004400, 0x8440040002410120,
+    0x1090820084010400, 0x2084852012021000, 0x12040062C1011003, 0x02008205E1090080,
+    0x088C102808042080, 0x0802102200904280, 0x8020209002080020, 0x2200080800060A00,
+    0x20C0004010010100, 0x0802004100821003, 0x0008024400008080, 0x0000840102008090,
+    0x0030A40420244007, 0x0A19084210011282, 0x0004082090019806, 0x6108004208020080,
+    0x0081200410110100, 0x1040810701010208, 0x0282047832012080, 0x0010020099000020,
+    0x000E010422400840, 0x10204208B0089090, 0x081004440C048000, 0x88C0180084040001,
+    0x3100020803040080, 0x890070A041210C00, 0x0020200101010A09, 0x0004100240410400,
+    0x0006004402080200, 0x0801062484042000, 0x00010002D7441004, 0x0810080000208800,
+    0x0000020808030411, 0x1450001020014440, 0x004060081081A288, 0x0044011404108A00,
+];
+
+// knight, king and pawn attacks don't depend on occupancy, so they're computed at compile time
+const fn leaper_table(steps: &[(i8, i8)]) -> [BitBoard; SQUARE_COUNT] {
+    let mut table = [EMPTY; SQUARE_COUNT];
+    let mut square = 0;
+    while square < SQUARE_COUNT {
+        let (rank, file) = ((square / 8) as i8, (square % 8) as i8);
+        let mut bits = 0;
+        let mut i = 0;
+        while i < steps.len() {
+            let (r, f) = (rank + steps[i].0, file + steps[i].1);
+            if r >= 0 && r < 8 && f >= 0 && f < 8 {
+                bits |= 1 << (r * 8 + f);
+            }
+            i += 1;
+        }
+        table[square] = BitBoard(bits);
+        square += 1;
+    }
+    table
+}
+
+#[rustfmt::skip]
+const KNIGHT_MOVES: [BitBoard; SQUARE_COUNT] = leaper_table(&[
+    (1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2),
+]);
+
+#[rustfmt::skip]
+const KING_MOVES: [BitBoard; SQUARE_COUNT] = leaper_table(&[
+    (1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1),
+]);
+
+const PAWN_ATTACKS: [[BitBoard; SQUARE_COUNT]; COLOR_COUNT] = [
+    leaper_table(&[(1, -1), (1, 1)]),
+    leaper_table(&[(-1, -1), (-1, 1)]),
+];
+
+// built on first use and shared by every thread, so nothing has to pass a table around
+static SLIDER_TABLE: OnceLock<SliderTable> = OnceLock::new();
+
+// how slider attacks are looked up: pext where BMI2 is available and fast,
+// magic multiplication everywhere else (or when the `magic` feature asks for it)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SliderBackend {
+    Pext,
+    Magic,
+}
+
+impl SliderBackend {
+    pub fn detect() -> SliderBackend {
+        if !cfg!(feature = "magic") && SliderBackend::Pext.is_supported() {
+            SliderBackend::Pext
+        } else {
+            SliderBackend::Magic
+        }
+    }
+
+    pub fn is_supported(self) -> bool {
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            SliderBackend::Pext => is_x86_feature_detected!("bmi2"),
+            #[cfg(not(target_arch = "x86_64"))]
+            SliderBackend::Pext => false,
+            SliderBackend::Magic => true,
+        }
+    }
+}
+
+// where one square's attacks start in the table, and how its occupancy is turned into an index
 #[derive(Default, Copy, Clone)]
-pub struct PextBitBoard {
+pub struct SliderEntry {
     mask: BitBoard,
+    magic: u64,
+    shift: u32,
     offset: usize,
 }
 
-pub struct PextTable {
-    table: [BitBoard; 107648],
-    rooks: [PextBitBoard; 64],
-    bishops: [PextBitBoard; 64],
+pub struct SliderTable {
+    backend: SliderBackend,
+    table: Vec<BitBoard>,
+    rooks: [SliderEntry; 64],
+    bishops: [SliderEntry; 64],
 }
 
 impl BitBoard {
@@ -54,26 +167,31 @@ impl BitBoard {
     }
 }
 
-impl PextBitBoard {
+impl SliderEntry {
     pub fn new<F: Fn(BitBoard, Square) -> BitBoard>(
         table: &mut [BitBoard],
         index: &mut usize,
         square: Square,
         gen: F,
-    ) -> PextBitBoard {
+        magic: u64,
+        backend: SliderBackend,
+    ) -> SliderEntry {
         let h = (BitBoard::from_file(File::A) | BitBoard::from_file(File::H))
             & !BitBoard::from_file(square.file());
         let v = (BitBoard::from_rank(Rank::Rank1) | BitBoard::from_rank(Rank::Rank8))
             & !BitBoard::from_rank(square.rank());
 
         let mut bb = EMPTY;
-        let result = PextBitBoard {
-            mask: gen(EMPTY, square) & !(h | v),
+        let mask = gen(EMPTY, square) & !(h | v);
+        let result = SliderEntry {
+            mask,
+            magic,
+            shift: 64 - mask.count(),
             offset: *index,
         };
 
         loop {
-            table[result.index(bb)] = gen(bb, square);
+            table[result.index(bb, backend)] = gen(bb, square);
             bb = BitBoard(bb.0.wrapping_sub(result.mask.0)) & result.mask;
             *index += 1;
             if bb == EMPTY {
@@ -84,77 +202,225 @@ impl PextBitBoard {
         result
     }
 
-    pub fn index(&self, bb: BitBoard) -> usize {
-        (unsafe { std::arch::x86_64::_pext_u64(bb.0, self.mask.0) }) as usize + self.offset
+    pub fn index(&self, bb: BitBoard, backend: SliderBackend) -> usize {
+        let index = match backend {
+            // only reachable through a table whose backend passed is_supported
+            #[cfg(target_arch = "x86_64")]
+            SliderBackend::Pext => unsafe { pext(bb.0, self.mask.0) },
+            #[cfg(not(target_arch = "x86_64"))]
+            SliderBackend::Pext => unreachable!(),
+            SliderBackend::Magic => (bb.0 & self.mask.0).wrapping_mul(self.magic) >> self.shift,
+        };
+        index as usize + self.offset
     }
 }
 
-pub fn ray_attacks(bb: BitBoard, square: Square, direction: &[Direction]) -> BitBoard {
-    let mut result = BitBoard::from_square(square);
-    for _ in 0..8 {
-        let mut tmp = result;
-        for dir in direction {
-            tmp = tmp.shift(*dir);
-        }
-        result |= tmp & !bb;
+// callers have to check for BMI2 first
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "bmi2")]
+unsafe fn pext(bits: u64, mask: u64) -> u64 {
+    std::arch::x86_64::_pext_u64(bits, mask)
+}
+
+// the first blocker in each direction is included so the result can be used for captures
+pub fn ray_attacks(bb: BitBoard, square: Square, direction: Direction) -> BitBoard {
+    let mut result = EMPTY;
+    let mut ray = BitBoard::from_square(square);
+    for _ in 0..7 {
+        ray = ray.shift(direction);
+        result |= ray;
+        ray &= !bb;
     }
-    result & !BitBoard::from_square(square)
+    result
 }
 
 pub fn rook_attacks(bb: BitBoard, square: Square) -> BitBoard {
     let mut result = EMPTY;
-    result |= ray_attacks(bb, square, &[Direction::Up]);
-    result |= ray_attacks(bb, square, &[Direction::Down]);
-    result |= ray_attacks(bb, square, &[Direction::Left]);
-    result |= ray_attacks(bb, square, &[Direction::Right]);
+    result |= ray_attacks(bb, square, Direction::Up);
+    result |= ray_attacks(bb, square, Direction::Down);
+    result |= ray_attacks(bb, square, Direction::Left);
+    result |= ray_attacks(bb, square, Direction::Right);
     result
 }
 
 pub fn bishop_attacks(bb: BitBoard, square: Square) -> BitBoard {
     let mut result = EMPTY;
-    result |= ray_attacks(bb, square, &[Direction::Up, Direction::Left]);
-    result |= ray_attacks(bb, square, &[Direction::Down, Direction::Left]);
-    result |= ray_attacks(bb, square, &[Direction::Up, Direction::Right]);
-    result |= ray_attacks(bb, square, &[Direction::Down, Direction::Right]);
+    result |= ray_attacks(bb, square, Direction::UpLeft);
+    result |= ray_attacks(bb, square, Direction::DownLeft);
+    result |= ray_attacks(bb, square, Direction::UpRight);
+    result |= ray_attacks(bb, square, Direction::DownRight);
     result
 }
 
-impl PextTable {
-    pub fn new() -> PextTable {
+impl Default for SliderTable {
+    fn default() -> SliderTable {
+        SliderTable::new()
+    }
+}
+
+impl SliderTable {
+    pub fn new() -> SliderTable {
+        SliderTable::with_backend(SliderBackend::detect())
+    }
+
+    pub fn with_backend(backend: SliderBackend) -> SliderTable {
+        assert!(
+            backend.is_supported(),
+            "{:?} is not supported on this CPU",
+            backend
+        );
+
         let mut index = 0;
-        let mut table = PextTable {
-            table: [EMPTY; 107648],
-            rooks: [PextBitBoard::default(); 64],
-            bishops: [PextBitBoard::default(); 64],
+        let mut table = SliderTable {
+            backend,
+            table: vec![EMPTY; 107648],
+            rooks: [SliderEntry::default(); 64],
+            bishops: [SliderEntry::default(); 64],
         };
 
         for i in 0..64 {
-            table.rooks[i] = PextBitBoard::new(
+            table.rooks[i] = SliderEntry::new(
                 &mut table.table,
                 &mut index,
                 Square::from_index(i).unwrap(),
                 rook_attacks,
+                ROOK_MAGICS[i],
+                backend,
             );
-            table.bishops[i] = PextBitBoard::new(
+            table.bishops[i] = SliderEntry::new(
                 &mut table.table,
                 &mut index,
                 Square::from_index(i).unwrap(),
                 bishop_attacks,
+                BISHOP_MAGICS[i],
+                backend,
             );
         }
 
         table
     }
 
+    pub fn backend(&self) -> SliderBackend {
+        self.backend
+    }
+
     pub fn rook_moves(&self, bb: BitBoard, sq: Square) -> BitBoard {
-        self.table[self.rooks[sq.index()].index(bb)]
+        self.table[self.rooks[sq.index()].index(bb, self.backend)]
     }
 
     pub fn bishop_moves(&self, bb: BitBoard, sq: Square) -> BitBoard {
-        self.table[self.bishops[sq.index()].index(bb)]
+        self.table[self.bishops[sq.index()].index(bb, self.backend)]
     }
 
     pub fn queen_moves(&self, bb: BitBoard, sq: Square) -> BitBoard {
         self.rook_moves(bb, sq) | self.bishop_moves(bb, sq)
     }
 }
+
+pub fn slider_table() -> &'static SliderTable {
+    SLIDER_TABLE.get_or_init(SliderTable::new)
+}
+
+pub fn rook_moves(occupancy: BitBoard, square: Square) -> BitBoard {
+    slider_table().rook_moves(occupancy, square)
+}
+
+pub fn bishop_moves(occupancy: BitBoard, square: Square) -> BitBoard {
+    slider_table().bishop_moves(occupancy, square)
+}
+
+pub fn queen_moves(occupancy: BitBoard, square: Square) -> BitBoard {
+    slider_table().queen_moves(occupancy, square)
+}
+
+pub fn knight_moves(square: Square) -> BitBoard {
+    KNIGHT_MOVES[square.index()]
+}
+
+pub fn king_moves(square: Square) -> BitBoard {
+    KING_MOVES[square.index()]
+}
+
+// squares a pawn of this color on `square` attacks
+pub fn pawn_attacks(color: Color, square: Square) -> BitBoard {
+    PAWN_ATTACKS[color.index()][square.index()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every square with every subset of its relevant occupancy
+    fn assert_matches_rays(table: &SliderTable) {
+        for square in (0..64).map(|i| Square::from_index(i).unwrap()) {
+            for (entries, gen, lookup) in [
+                (
+                    &table.rooks,
+                    rook_attacks as fn(BitBoard, Square) -> BitBoard,
+                    SliderTable::rook_moves as fn(&SliderTable, BitBoard, Square) -> BitBoard,
+                ),
+                (&table.bishops, bishop_attacks, SliderTable::bishop_moves),
+            ] {
+                let mask = entries[square.index()].mask;
+                let mut bb = EMPTY;
+                loop {
+                    assert_eq!(
+                        lookup(table, bb, square),
+                        gen(bb, square),
+                        "{} {:?}",
+                        square,
+                        bb
+                    );
+                    bb = BitBoard(bb.0.wrapping_sub(mask.0)) & mask;
+                    if bb == EMPTY {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn magic_backend_matches_rays() {
+        assert_matches_rays(&SliderTable::with_backend(SliderBackend::Magic));
+    }
+
+    #[test]
+    fn pext_backend_matches_rays() {
+        if SliderBackend::Pext.is_supported() {
+            assert_matches_rays(&SliderTable::with_backend(SliderBackend::Pext));
+        }
+    }
+
+    #[test]
+    fn leaper_tables_match_shifts() {
+        for square in (0..64).map(|i| Square::from_index(i).unwrap()) {
+            let bb = BitBoard::from_square(square);
+            assert_eq!(knight_moves(square), bb.knight_moves(), "{}", square);
+            assert_eq!(king_moves(square), bb.king_moves(), "{}", square);
+            for color in [Color::White, Color::Black] {
+                assert_eq!(pawn_attacks(color, square), bb.pawn_attacks(color));
+            }
+        }
+    }
+
+    // bits outside the mask must not change the result either
+    #[test]
+    fn backends_agree_on_full_boards() {
+        if !SliderBackend::Pext.is_supported() {
+            return;
+        }
+        let pext = SliderTable::with_backend(SliderBackend::Pext);
+        let magic = SliderTable::with_backend(SliderBackend::Magic);
+        let mut occupancy = 0x9E3779B97F4A7C15u64;
+        for _ in 0..1000 {
+            occupancy ^= occupancy << 13;
+            occupancy ^= occupancy >> 7;
+            occupancy ^= occupancy << 17;
+            for square in (0..64).map(|i| Square::from_index(i).unwrap()) {
+                let bb = BitBoard(occupancy);
+                assert_eq!(pext.queen_moves(bb, square), magic.queen_moves(bb, square));
+            }
+        }
+    }
+}
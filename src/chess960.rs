@@ -0,0 +1,140 @@
+use crate::errors::ChessError;
+use crate::file::{File, FILE_COUNT};
+use crate::piece::*;
+use crate::rank::Rank;
+use crate::square::Square;
+use crate::state::{Castling, CastlingSide, State};
+
+pub const CHESS960_POSITION_COUNT: u16 = 960;
+
+// the 10 ways to put two knights on the 5 squares left after bishops and queen
+const KNIGHT_PLACEMENTS: [(usize, usize); 10] = [
+    (0, 1),
+    (0, 2),
+    (0, 3),
+    (0, 4),
+    (1, 2),
+    (1, 3),
+    (1, 4),
+    (2, 3),
+    (2, 4),
+    (3, 4),
+];
+
+fn place(back_rank: &mut [Option<PieceType>; FILE_COUNT], nth_empty: usize, piece: PieceType) {
+    let file = (0..FILE_COUNT)
+        .filter(|&file| back_rank[file].is_none())
+        .nth(nth_empty)
+        .unwrap();
+    back_rank[file] = Some(piece);
+}
+
+impl State {
+    // start position from its Scharnagl index, 518 is the regular starting position
+    pub fn chess960(index: u16) -> Result<State, ChessError> {
+        if index >= CHESS960_POSITION_COUNT {
+            return Err(ChessError::InvalidChess960Index(index));
+        }
+
+        let mut back_rank = [None; FILE_COUNT];
+        let mut n = index as usize;
+        back_rank[(n % 4) * 2 + 1] = Some(PieceType::Bishop);
+        n /= 4;
+        back_rank[(n % 4) * 2] = Some(PieceType::Bishop);
+        n /= 4;
+        place(&mut back_rank, n % 6, PieceType::Queen);
+        n /= 6;
+
+        // place the second knight first so the first one's index isn't shifted
+        let (first, second) = KNIGHT_PLACEMENTS[n];
+        place(&mut back_rank, second, PieceType::Knight);
+        place(&mut back_rank, first, PieceType::Knight);
+
+        // the king always ends up between the rooks
+        place(&mut back_rank, 0, PieceType::Rook);
+        place(&mut back_rank, 0, PieceType::King);
+        place(&mut back_rank, 0, PieceType::Rook);
+
+        let mut state = State::new();
+        let mut castling = Castling::none();
+        let mut side = CastlingSide::Queen;
+        for (index, piece_type) in back_rank.iter().enumerate() {
+            let piece_type = piece_type.unwrap();
+            let file = File::from_index(index).unwrap();
+            for (color, pawn_rank) in [(Color::White, Rank::Rank2), (Color::Black, Rank::Rank7)] {
+                let rank = Rank::back_rank(color);
+                state
+                    .board
+                    .set_piece(Square::new(rank, file), Some(Piece::new(color, piece_type)));
+                state.board.set_piece(
+                    Square::new(pawn_rank, file),
+                    Some(Piece::new(color, PieceType::Pawn)),
+                );
+                if piece_type == PieceType::Rook {
+                    castling.set_rook_file(color, side, Some(file));
+                }
+            }
+            if piece_type == PieceType::Rook {
+                side = CastlingSide::King;
+            }
+        }
+        state.castling = castling;
+
+        Ok(state)
+    }
+    // castling rights only chess960 has: a king off the e-file or a rook that isn't in its corner
+    pub fn is_chess960(&self) -> bool {
+        [Color::White, Color::Black].into_iter().any(|color| {
+            let king = self.board.king_square(color).map(|square| square.file());
+            [CastlingSide::King, CastlingSide::Queen]
+                .into_iter()
+                .any(|side| {
+                    let corner = match side {
+                        CastlingSide::King => File::H,
+                        CastlingSide::Queen => File::A,
+                    };
+                    self.castling
+                        .rook_file(color, side)
+                        .is_some_and(|file| file != corner || king != Some(File::E))
+                })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn start_positions() {
+        let standard = State::chess960(518).unwrap();
+        assert_eq!(standard.to_fen(), State::default().to_fen());
+        assert!(!standard.is_chess960());
+
+        let first = State::chess960(0).unwrap();
+        assert_eq!(
+            first.to_fen(),
+            "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w KQkq - 0 1"
+        );
+        assert_eq!(
+            first.to_shredder_fen(),
+            "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w HFhf - 0 1"
+        );
+        assert!(first.is_chess960());
+        assert_eq!(
+            State::chess960(959).unwrap().to_fen(),
+            "rkrnnqbb/pppppppp/8/8/8/8/PPPPPPPP/RKRNNQBB w KQkq - 0 1"
+        );
+        assert!(State::chess960(CHESS960_POSITION_COUNT).is_err());
+    }
+
+    #[test]
+    fn every_position_is_distinct() {
+        let mut fens: Vec<String> = (0..CHESS960_POSITION_COUNT)
+            .map(|index| State::chess960(index).unwrap().to_fen())
+            .collect();
+        fens.sort();
+        fens.dedup();
+        assert_eq!(fens.len(), CHESS960_POSITION_COUNT as usize);
+    }
+}
@@ -0,0 +1,117 @@
+// argument helpers shared by the subcommands; each subcommand's `command` lives next to the
+// module it drives and exits the process on bad input
+use crate::errors::ChessError;
+use crate::search::SearchLimits;
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub fn exit_on_error<T>(result: Result<T, ChessError>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    })
+}
+
+pub fn usage(text: &str) -> ! {
+    eprintln!("usage: {}", text);
+    process::exit(1);
+}
+
+// the argument after `flag`, None when the flag isn't given
+pub fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    let index = args.iter().position(|arg| arg == flag)?;
+    match args.get(index + 1) {
+        Some(value) => Some(value),
+        None => {
+            eprintln!("{} expects a value", flag);
+            process::exit(1);
+        }
+    }
+}
+
+pub fn parse_value<T: FromStr>(name: &str, value: &str) -> T {
+    value.parse().unwrap_or_else(|_| {
+        eprintln!("invalid value {} for {}", value, name);
+        process::exit(1);
+    })
+}
+
+pub fn parse_flag<T: FromStr>(args: &[String], flag: &str) -> Option<T> {
+    flag_value(args, flag).map(|value| parse_value(flag, value))
+}
+
+pub fn has_flag(args: &[String], flag: &str) -> bool {
+    args.iter().any(|arg| arg == flag)
+}
+
+// the arguments after every occurrence of `flag`, each up to the next flag
+pub fn flag_groups<'a>(args: &'a [String], flag: &str) -> Vec<&'a [String]> {
+    args.iter()
+        .enumerate()
+        .filter(|(_, arg)| *arg == flag)
+        .map(|(index, _)| {
+            let rest = &args[index + 1..];
+            let end = rest.iter().position(|arg| arg.starts_with("--"));
+            &rest[..end.unwrap_or(rest.len())]
+        })
+        .collect()
+}
+
+// the value of a key=value argument in a flag group
+pub fn setting<T: FromStr>(tokens: &[String], key: &str) -> Option<T> {
+    let value = tokens
+        .iter()
+        .find_map(|token| token.strip_prefix(key)?.strip_prefix('='))?;
+    Some(parse_value(key, value))
+}
+
+// --depth, --nodes and --movetime, searching for `movetime` when none of them is given
+pub fn search_limits(args: &[String], movetime: Duration) -> SearchLimits {
+    let mut limits = SearchLimits {
+        depth: parse_flag(args, "--depth"),
+        nodes: parse_flag(args, "--nodes"),
+        movetime: parse_flag(args, "--movetime").map(Duration::from_millis),
+    };
+    if limits.depth.is_none() && limits.nodes.is_none() && limits.movetime.is_none() {
+        limits.movetime = Some(movetime);
+    }
+    limits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn flags() {
+        let args = args("games.pgn --depth 12 --checks-only --output out.pgn");
+        assert_eq!(flag_value(&args, "--output"), Some("out.pgn"));
+        assert_eq!(flag_value(&args, "--nodes"), None);
+        assert_eq!(parse_flag::<u32>(&args, "--depth"), Some(12));
+        assert!(has_flag(&args, "--checks-only"));
+
+        let limits = search_limits(&args, Duration::from_secs(1));
+        assert_eq!(limits.depth, Some(12));
+        assert_eq!(limits.movetime, None);
+        let limits = search_limits(&args[..1], Duration::from_secs(1));
+        assert_eq!(limits.movetime, Some(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn groups() {
+        let args = args("--engine cmd=self name=a --tc 10+0.1 --engine cmd=self --sprt elo1=5");
+        let engines = flag_groups(&args, "--engine");
+        assert_eq!(engines.len(), 2);
+        assert_eq!(engines[0], ["cmd=self", "name=a"]);
+        assert_eq!(engines[1], ["cmd=self"]);
+
+        let sprt = flag_groups(&args, "--sprt")[0];
+        assert_eq!(setting::<f64>(sprt, "elo1"), Some(5.0));
+        assert_eq!(setting::<f64>(sprt, "elo0"), None);
+    }
+}
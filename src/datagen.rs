@@ -0,0 +1,528 @@
+// self-play games for training data: each game starts from a few random plies (after a book
+// position, or a random chess960 one, if asked for), every position is searched to a fixed node count, and the
+// quiet ones are written out with their score and the game's result once it's decided.
+//
+// the text format is one position per line, `<fen> | <score> | <result>`, with the score in
+// centipawns and the result as 1.0, 0.5 or 0.0, both from white's point of view. The binary
+// format is the packed one from packed.rs, whose score is from the side to move's.
+use crate::chess960::CHESS960_POSITION_COUNT;
+use crate::cli::{exit_on_error, flag_value, has_flag, parse_flag, usage};
+use crate::errors::ChessError;
+use crate::game::{Game, GameResult};
+use crate::moves::{Move, MoveKind};
+use crate::packed::{PackedEntry, PackedWriter};
+use crate::piece::Color;
+use crate::search::{is_mate_score, Search, SearchLimits, MAX_DEPTH, TB_WIN_SCORE};
+use crate::state::State;
+use crate::syzygy;
+use crate::testsuite;
+use crate::zobrist::splitmix64;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Instant;
+
+// openings further off balance than this are thrown away
+const MAX_OPENING_SCORE: i32 = 400;
+const OPENING_ATTEMPTS: usize = 16;
+
+pub struct Settings {
+    pub games: usize,
+    pub threads: usize,
+    pub nodes: u64,
+    pub random_plies: usize,
+    // start positions the random plies are played from, the standard one when empty
+    pub book: Vec<State>,
+    // random chess960 start positions instead of the standard one, when there's no book
+    pub chess960: bool,
+    pub seed: u64,
+    // a side is adjudicated the winner once every search for this many plies gives it at
+    // least win_score
+    pub win_score: i32,
+    pub win_plies: usize,
+    // and a draw once the score stays within draw_score for draw_plies, from draw_after on
+    pub draw_score: i32,
+    pub draw_plies: usize,
+    pub draw_after: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            games: 100,
+            threads: 1,
+            nodes: 5000,
+            random_plies: 8,
+            book: Vec::new(),
+            chess960: false,
+            seed: 1,
+            win_score: 2000,
+            win_plies: 6,
+            draw_score: 10,
+            draw_plies: 12,
+            draw_after: 80,
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Stats {
+    pub games: usize,
+    pub positions: usize,
+    pub white_wins: usize,
+    pub draws: usize,
+    pub black_wins: usize,
+}
+
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        let (state, output) = splitmix64(self.0);
+        self.0 = state;
+        (output % n as u64) as usize
+    }
+}
+
+fn search(state: &State, nodes: u64) -> (Option<Move>, i32) {
+    let limits = SearchLimits {
+        nodes: Some(nodes),
+        ..SearchLimits::default()
+    };
+    let result = Search::new(limits).run(&mut state.clone());
+    (result.best_move, result.score)
+}
+
+fn is_noisy(state: &State, mv: Move) -> bool {
+    match mv.kind {
+        MoveKind::EnPassant | MoveKind::Promotion(_) => true,
+        MoveKind::Castling => false,
+        _ => state.board.piece(mv.to).is_some(),
+    }
+}
+
+// positions whose score says little about the position itself: checks, a capture coming,
+// or a mate or tablebase result
+fn is_quiet(state: &State, best_move: Move, score: i32) -> bool {
+    !state.in_check()
+        && !is_noisy(state, best_move)
+        && !is_mate_score(score)
+        && score.abs() < TB_WIN_SCORE - MAX_DEPTH as i32 * 2
+}
+
+fn opening(settings: &Settings, rng: &mut Rng) -> Game {
+    let mut game = Game::default();
+    for _ in 0..OPENING_ATTEMPTS {
+        game = match settings.book.len() {
+            0 if settings.chess960 => {
+                let index = rng.below(CHESS960_POSITION_COUNT as usize) as u16;
+                Game::new(State::chess960(index).unwrap())
+            }
+            0 => Game::default(),
+            n => Game::new(settings.book[rng.below(n)].clone()),
+        };
+        for _ in 0..settings.random_plies {
+            let moves = game.legal_moves();
+            if moves.is_empty() {
+                break;
+            }
+            game.make_move(moves[rng.below(moves.len())]);
+        }
+        if game.outcome().is_some() {
+            continue;
+        }
+        let (_, score) = search(game.state(), settings.nodes);
+        if score.abs() <= MAX_OPENING_SCORE {
+            break;
+        }
+    }
+    game
+}
+
+// the result according to the tablebases, when they have the position
+fn tablebase_result(state: &State) -> Option<GameResult> {
+    let tablebases = syzygy::tablebases()?;
+    if state.board.occupied().count() as usize > tablebases.max_pieces()
+        || !state.castling.is_empty()
+    {
+        return None;
+    }
+    let draw = if syzygy::rule50() { 1 } else { 0 };
+    Some(match tablebases.probe_wdl(state)?.value() {
+        value if value > draw => GameResult::win_for(state.side_to_move()),
+        value if value < -draw => GameResult::win_for(!state.side_to_move()),
+        _ => GameResult::Draw,
+    })
+}
+
+// plays game number `index` and returns its quiet positions with the result filled in
+pub fn play_game(settings: &Settings, index: usize) -> (Vec<PackedEntry>, GameResult) {
+    let mut rng = Rng(settings.seed ^ splitmix64(index as u64).1);
+    let mut game = opening(settings, &mut rng);
+    let mut entries = Vec::new();
+    let (mut winning, mut drawn) = (0i32, 0);
+
+    let result = loop {
+        if let Some(outcome) = game.outcome() {
+            break outcome.result;
+        }
+        if let Some(result) = tablebase_result(game.state()) {
+            break result;
+        }
+
+        let state = game.state();
+        let (best_move, score) = search(state, settings.nodes);
+        let best_move = match best_move {
+            Some(mv) => mv,
+            None => break GameResult::Draw,
+        };
+        if is_quiet(state, best_move, score) {
+            entries.push(PackedEntry {
+                state: state.clone(),
+                score: score as i16,
+                result: None,
+            });
+        }
+
+        let white_score = match state.side_to_move() {
+            Color::White => score,
+            Color::Black => -score,
+        };
+        // counts plies in a row the score stays decisive for the same side
+        winning = match white_score {
+            s if s >= settings.win_score => winning.max(0) + 1,
+            s if s <= -settings.win_score => winning.min(0) - 1,
+            _ => 0,
+        };
+        if winning.unsigned_abs() as usize >= settings.win_plies {
+            break if winning > 0 {
+                GameResult::WhiteWins
+            } else {
+                GameResult::BlackWins
+            };
+        }
+        drawn = if game.moves().len() >= settings.draw_after
+            && white_score.abs() <= settings.draw_score
+        {
+            drawn + 1
+        } else {
+            0
+        };
+        if drawn >= settings.draw_plies {
+            break GameResult::Draw;
+        }
+
+        game.make_move(best_move);
+    };
+
+    for entry in &mut entries {
+        entry.result = Some(result);
+    }
+    (entries, result)
+}
+
+// plays the games on `settings.threads` threads, handing each finished one to `on_game`;
+// stops early when that fails
+pub fn generate<F>(settings: &Settings, mut on_game: F) -> Result<Stats, ChessError>
+where
+    F: FnMut(&[PackedEntry], GameResult) -> Result<(), ChessError>,
+{
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+    let mut stats = Stats::default();
+
+    thread::scope(|scope| {
+        for _ in 0..settings.threads.max(1) {
+            let sender = sender.clone();
+            let (next, stop) = (&next, &stop);
+            scope.spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                if index >= settings.games || stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                if sender.send(play_game(settings, index)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        for (entries, result) in receiver {
+            if let Err(e) = on_game(&entries, result) {
+                stop.store(true, Ordering::Relaxed);
+                return Err(e);
+            }
+            stats.games += 1;
+            stats.positions += entries.len();
+            match result {
+                GameResult::WhiteWins => stats.white_wins += 1,
+                GameResult::Draw => stats.draws += 1,
+                GameResult::BlackWins => stats.black_wins += 1,
+            }
+        }
+        Ok(stats)
+    })
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Binary,
+}
+
+impl FromStr for Format {
+    type Err = ChessError;
+
+    fn from_str(s: &str) -> Result<Format, ChessError> {
+        match s {
+            "text" => Ok(Format::Text),
+            "binary" => Ok(Format::Binary),
+            _ => Err(ChessError::ParseError(s.to_string(), "data format")),
+        }
+    }
+}
+
+pub fn text_line(entry: &PackedEntry) -> String {
+    let score = match entry.state.side_to_move() {
+        Color::White => entry.score,
+        Color::Black => -entry.score,
+    };
+    let result = match entry.result {
+        Some(GameResult::WhiteWins) => "1.0",
+        Some(GameResult::BlackWins) => "0.0",
+        _ => "0.5",
+    };
+    format!("{} | {} | {}", entry.state.to_fen(), score, result)
+}
+
+pub enum DataWriter<W: Write> {
+    Text(W),
+    Binary(PackedWriter<W>),
+}
+
+impl<W: Write> DataWriter<W> {
+    pub fn new(inner: W, format: Format) -> DataWriter<W> {
+        match format {
+            Format::Text => DataWriter::Text(inner),
+            Format::Binary => DataWriter::Binary(PackedWriter::new(inner)),
+        }
+    }
+
+    pub fn write(&mut self, entry: &PackedEntry) -> Result<(), ChessError> {
+        match self {
+            DataWriter::Text(inner) => writeln!(inner, "{}", text_line(entry))?,
+            DataWriter::Binary(writer) => writer.write(entry)?,
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), ChessError> {
+        match self {
+            DataWriter::Text(inner) => inner.flush()?,
+            DataWriter::Binary(writer) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+// datagen <output> [--games n] [--threads n] [--nodes n] [--random-plies n] [--book file.epd]
+//   [--chess960] [--format text|binary] [--seed n]
+// the format defaults to text for .txt files and binary for everything else
+pub fn command(args: &[String]) {
+    let Some(output) = args.first().map(Path::new) else {
+        usage(
+            "datagen <output> [--games n] [--threads n] [--nodes n] [--random-plies n] \
+             [--book file.epd] [--chess960] [--format text|binary] [--seed n]",
+        );
+    };
+
+    let book: Vec<State> = match flag_value(args, "--book") {
+        Some(path) => exit_on_error(testsuite::load_suite(path))
+            .into_iter()
+            .map(|epd| epd.state)
+            .collect(),
+        None => Vec::new(),
+    };
+    let defaults = Settings::default();
+    let settings = Settings {
+        games: parse_flag(args, "--games").unwrap_or(defaults.games),
+        threads: parse_flag(args, "--threads").unwrap_or(defaults.threads),
+        nodes: parse_flag(args, "--nodes").unwrap_or(defaults.nodes),
+        // a book already gives variety, so it's played as is unless asked otherwise
+        random_plies: parse_flag(args, "--random-plies").unwrap_or(if book.is_empty() {
+            defaults.random_plies
+        } else {
+            0
+        }),
+        chess960: has_flag(args, "--chess960"),
+        seed: parse_flag(args, "--seed").unwrap_or(defaults.seed),
+        book,
+        ..defaults
+    };
+    let format = match flag_value(args, "--format") {
+        Some(format) => exit_on_error(format.parse()),
+        None if output
+            .extension()
+            .is_some_and(|extension| extension == "txt") =>
+        {
+            Format::Text
+        }
+        None => Format::Binary,
+    };
+
+    let file = exit_on_error(File::create(output).map_err(Into::into));
+    let mut writer = DataWriter::new(BufWriter::new(file), format);
+    let start = Instant::now();
+    let report_every = (settings.games / 20).max(1);
+    let mut played = 0;
+    let stats = exit_on_error(generate(&settings, |entries, _| {
+        for entry in entries {
+            writer.write(entry)?;
+        }
+        played += 1;
+        if played % report_every == 0 {
+            println!(
+                "{}/{} games, {:.1}s",
+                played,
+                settings.games,
+                start.elapsed().as_secs_f64()
+            );
+        }
+        Ok(())
+    }));
+    exit_on_error(writer.flush());
+
+    println!(
+        "{} games (+{} ={} -{}), {} positions written to {} in {:.1}s",
+        stats.games,
+        stats.white_wins,
+        stats.draws,
+        stats.black_wins,
+        stats.positions,
+        output.display(),
+        start.elapsed().as_secs_f64()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packed::PackedReader;
+
+    fn settings() -> Settings {
+        Settings {
+            games: 3,
+            threads: 2,
+            nodes: 300,
+            draw_after: 40,
+            draw_plies: 4,
+            draw_score: 50,
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn quiet_positions() {
+        let state = State::from_fen("rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2")
+            .unwrap();
+        let capture = state.parse_uci_move("e4d5", false).unwrap();
+        let push = state.parse_uci_move("e4e5", false).unwrap();
+        assert!(!is_quiet(&state, capture, 0));
+        assert!(is_quiet(&state, push, 0));
+        assert!(!is_quiet(&state, push, TB_WIN_SCORE - 3));
+        assert!(!is_quiet(&state, push, -29990));
+
+        let check = State::from_fen("4k3/8/8/8/8/8/4R3/4K3 b - - 0 1").unwrap();
+        let evade = check.parse_uci_move("e8d7", false).unwrap();
+        assert!(!is_quiet(&check, evade, 0));
+    }
+
+    #[test]
+    fn games_are_reproducible() {
+        let settings = settings();
+        let (entries, result) = play_game(&settings, 1);
+        assert!(!entries.is_empty());
+        for entry in &entries {
+            assert_eq!(entry.result, Some(result));
+            assert!(!entry.state.in_check());
+        }
+
+        let (again, _) = play_game(&settings, 1);
+        let fens = |entries: &[PackedEntry]| -> Vec<String> {
+            entries.iter().map(|entry| entry.state.to_fen()).collect()
+        };
+        assert_eq!(fens(&entries), fens(&again));
+        assert_ne!(fens(&entries), fens(&play_game(&settings, 2).0));
+    }
+
+    #[test]
+    fn chess960_openings() {
+        let settings = Settings {
+            chess960: true,
+            random_plies: 0,
+            ..settings()
+        };
+        let index = Rng(7).below(CHESS960_POSITION_COUNT as usize) as u16;
+        let game = opening(&settings, &mut Rng(7));
+        assert_eq!(
+            game.state().to_fen(),
+            State::chess960(index).unwrap().to_fen()
+        );
+    }
+
+    #[test]
+    fn writes_every_game() {
+        let settings = settings();
+        let mut writer = DataWriter::new(Vec::new(), Format::Binary);
+        let mut lines = Vec::new();
+        let stats = generate(&settings, |entries, _| {
+            for entry in entries {
+                writer.write(entry)?;
+                lines.push(text_line(entry));
+            }
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(stats.games, 3);
+        assert_eq!(stats.white_wins + stats.draws + stats.black_wins, 3);
+        assert_eq!(stats.positions, lines.len());
+
+        let bytes = match writer {
+            DataWriter::Binary(writer) => writer.into_inner(),
+            DataWriter::Text(_) => unreachable!(),
+        };
+        let read: Vec<PackedEntry> = PackedReader::new(&bytes[..])
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read.len(), lines.len());
+        for (entry, line) in read.iter().zip(&lines) {
+            assert_eq!(&text_line(entry), line);
+        }
+
+        let failed = generate(&settings, |_, _| {
+            Err(ChessError::InvalidPackedPosition("full".to_string()))
+        });
+        assert!(failed.is_err());
+    }
+
+    #[test]
+    fn text_lines() {
+        let state = State::from_fen("4k3/8/8/8/8/8/4P3/4K3 b - - 0 1").unwrap();
+        let entry = PackedEntry {
+            state,
+            score: -120,
+            result: Some(GameResult::WhiteWins),
+        };
+        assert_eq!(
+            text_line(&entry),
+            "4k3/8/8/8/8/8/4P3/4K3 b - - 0 1 | 120 | 1.0"
+        );
+        assert_eq!("text".parse::<Format>().unwrap(), Format::Text);
+        assert!("csv".parse::<Format>().is_err());
+    }
+}
@@ -1,22 +1,59 @@
+use crate::utils::impl_index;
+use std::mem::transmute;
 use std::ops::Not;
 
+// opposite directions are paired up so that flipping the lowest bit of the index reverses one
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
 pub enum Direction {
     Up,
     Down,
     Left,
     Right,
+    UpLeft,
+    DownRight,
+    UpRight,
+    DownLeft,
+}
+
+pub const DIRECTION_COUNT: usize = 8;
+
+impl_index! { Direction(DIRECTION_COUNT) }
+
+pub const DIRECTIONS: [Direction; DIRECTION_COUNT] = [
+    Direction::Up,
+    Direction::Down,
+    Direction::Left,
+    Direction::Right,
+    Direction::UpLeft,
+    Direction::DownRight,
+    Direction::UpRight,
+    Direction::DownLeft,
+];
+
+impl Direction {
+    // (ranks, files) moved by one step
+    pub const fn delta(self) -> (i8, i8) {
+        match self {
+            Direction::Up => (1, 0),
+            Direction::Down => (-1, 0),
+            Direction::Left => (0, -1),
+            Direction::Right => (0, 1),
+            Direction::UpLeft => (1, -1),
+            Direction::DownRight => (-1, 1),
+            Direction::UpRight => (1, 1),
+            Direction::DownLeft => (-1, -1),
+        }
+    }
+
+    pub fn is_diagonal(self) -> bool {
+        self.index() >= 4
+    }
 }
 
 impl Not for Direction {
     type Output = Direction;
 
     fn not(self) -> Self::Output {
-        match self {
-            Direction::Up => Direction::Down,
-            Direction::Down => Direction::Up,
-            Direction::Left => Direction::Right,
-            Direction::Right => Direction::Left,
-        }
+        DIRECTIONS[self.index() ^ 1]
     }
 }
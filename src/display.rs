@@ -0,0 +1,191 @@
+use crate::bitboard::*;
+use crate::file::File;
+use crate::piece::*;
+use crate::rank::Rank;
+use crate::square::Square;
+use crate::state::{Board, State};
+use std::fmt;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BoardStyle {
+    Ascii,
+    Unicode,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct RenderOptions {
+    pub style: BoardStyle,
+    // the side shown at the bottom
+    pub orientation: Color,
+    pub coordinates: bool,
+    // squares drawn in brackets, e.g. everything a piece attacks
+    pub highlight: BitBoard,
+}
+
+impl Default for RenderOptions {
+    fn default() -> RenderOptions {
+        RenderOptions {
+            style: BoardStyle::Ascii,
+            orientation: Color::White,
+            coordinates: true,
+            highlight: EMPTY,
+        }
+    }
+}
+
+impl RenderOptions {
+    // `{:#}` switches Display to figurines
+    fn from_formatter(f: &fmt::Formatter) -> RenderOptions {
+        RenderOptions {
+            style: if f.alternate() {
+                BoardStyle::Unicode
+            } else {
+                BoardStyle::Ascii
+            },
+            ..RenderOptions::default()
+        }
+    }
+}
+
+impl Board {
+    pub fn render(&self, options: &RenderOptions) -> String {
+        let (top, bottom, side, empty) = match options.style {
+            BoardStyle::Ascii => (("+", "-", "+"), ("+", "-", "+"), '|', '.'),
+            BoardStyle::Unicode => (("┌", "─", "┐"), ("└", "─", "┘"), '│', '·'),
+        };
+        let margin = if options.coordinates { "  " } else { "" };
+        let border = |(left, line, right): (&str, &str, &str)| {
+            format!("{}{}{}{}\n", margin, left, line.repeat(8 * 3), right)
+        };
+
+        let mut ranks: Vec<usize> = (0..8).rev().collect();
+        let mut files: Vec<usize> = (0..8).collect();
+        if options.orientation == Color::Black {
+            ranks.reverse();
+            files.reverse();
+        }
+
+        let mut s = border(top);
+        for &rank in &ranks {
+            let rank = Rank::from_index(rank).unwrap();
+            if options.coordinates {
+                s.push(rank.to_char());
+                s.push(' ');
+            }
+            s.push(side);
+            for &file in &files {
+                let square = Square::new(rank, File::from_index(file).unwrap());
+                let c = match (self.piece(square), options.style) {
+                    (Some(piece), BoardStyle::Ascii) => piece.as_str().chars().next().unwrap(),
+                    (Some(piece), BoardStyle::Unicode) => piece.to_unicode(),
+                    (None, _) => empty,
+                };
+                if options.highlight.contains(square) {
+                    s.push('[');
+                    s.push(c);
+                    s.push(']');
+                } else {
+                    s.push(' ');
+                    s.push(c);
+                    s.push(' ');
+                }
+            }
+            s.push(side);
+            s.push('\n');
+        }
+        s.push_str(&border(bottom));
+
+        if options.coordinates {
+            let labels: String = files
+                .iter()
+                .map(|&file| format!(" {} ", File::from_index(file).unwrap().to_char()))
+                .collect();
+            s.push_str(format!("{} {}", margin, labels).trim_end());
+            s.push('\n');
+        }
+        s
+    }
+}
+
+impl State {
+    // the board plus what FEN says about the rest of the position
+    pub fn render(&self, options: &RenderOptions) -> String {
+        let fen = self.to_fen();
+        let fields: Vec<&str> = fen.split(' ').collect();
+        let side = match self.side_to_move() {
+            Color::White => "white",
+            Color::Black => "black",
+        };
+
+        let mut s = self.board.render(options);
+        s.push_str(&format!("\nFEN: {}\n", fen));
+        s.push_str(&format!(
+            "Side to move: {}, castling: {}, en passant: {}\n",
+            side, fields[2], fields[3]
+        ));
+        s.push_str(&format!(
+            "Halfmove clock: {}, fullmove number: {}\n",
+            self.halfmove_clock(),
+            self.ply() / 2 + 1
+        ));
+        s
+    }
+}
+
+impl fmt::Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.render(&RenderOptions::from_formatter(f)))
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.render(&RenderOptions::from_formatter(f)))
+    }
+}
+
+impl fmt::Debug for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.render(&RenderOptions::default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn start_position() {
+        let expected = "  +------------------------+
+8 | r  n  b  q  k  b  n  r |
+7 | p  p  p  p  p  p  p  p |
+6 | .  .  .  .  .  .  .  . |
+5 | .  .  .  .  .  .  .  . |
+4 | .  .  .  .  .  .  .  . |
+3 | .  .  .  .  .  .  .  . |
+2 | P  P  P  P  P  P  P  P |
+1 | R  N  B  Q  K  B  N  R |
+  +------------------------+
+    a  b  c  d  e  f  g  h
+";
+        assert_eq!(State::default().board.to_string(), expected);
+    }
+
+    #[test]
+    fn black_at_bottom_with_highlight() {
+        let state = State::from_fen("7k/8/8/8/8/8/8/K7 b - - 3 40").unwrap();
+        let options = RenderOptions {
+            style: BoardStyle::Unicode,
+            orientation: Color::Black,
+            coordinates: true,
+            highlight: BitBoard::from_square(Square::A1) | BitBoard::from_square(Square::B1),
+        };
+        let rendered = state.render(&options);
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[1], "1 │ ·  ·  ·  ·  ·  · [·][♔]│");
+        assert_eq!(lines[8], "8 │ ♚  ·  ·  ·  ·  ·  ·  · │");
+        assert_eq!(lines[10], "    h  g  f  e  d  c  b  a");
+        assert!(rendered.contains("Side to move: black, castling: -, en passant: -"));
+        assert!(rendered.contains("Halfmove clock: 3, fullmove number: 40"));
+    }
+}
@@ -0,0 +1,463 @@
+// endings the general evaluation gets wrong: specialised scores for some material, and
+// scale factors pulling drawish endings towards zero, both looked up by a key of the
+// piece counts
+use crate::bitbase;
+use crate::bitboard::*;
+use crate::eval::PIECE_VALUES;
+use crate::file::File;
+use crate::geometry::{distance, manhattan_distance};
+use crate::piece::*;
+use crate::rank::Rank;
+use crate::square::Square;
+use crate::state::{Board, State};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+// more than any material advantage, less than tablebase wins and mates
+pub const KNOWN_WIN: i32 = 10000;
+pub const SCALE_NORMAL: i32 = 64;
+const SCALE_DRAW: i32 = 0;
+
+const PAWN_VALUE: i32 = PIECE_VALUES[0];
+const BISHOP_VALUE: i32 = PIECE_VALUES[2];
+const ROOK_VALUE: i32 = PIECE_VALUES[3];
+const QUEEN_VALUE: i32 = PIECE_VALUES[4];
+
+#[rustfmt::skip]
+const PUSH_TO_EDGES: [i32; 64] = [
+    100, 90, 80, 70, 70, 80, 90,100,
+     90, 70, 60, 50, 50, 60, 70, 90,
+     80, 60, 40, 30, 30, 40, 60, 80,
+     70, 50, 30, 20, 20, 30, 50, 70,
+     70, 50, 30, 20, 20, 30, 50, 70,
+     80, 60, 40, 30, 30, 40, 60, 80,
+     90, 70, 60, 50, 50, 60, 70, 90,
+    100, 90, 80, 70, 70, 80, 90,100,
+];
+
+// by king distance
+const PUSH_CLOSE: [i32; 8] = [0, 0, 100, 80, 60, 40, 20, 10];
+const PUSH_AWAY: [i32; 8] = [0, 5, 20, 40, 60, 80, 90, 100];
+
+// four bits of count per piece, in Piece::index() order
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct MaterialKey(u64);
+
+impl MaterialKey {
+    pub fn from_board(board: &Board) -> MaterialKey {
+        let mut key = 0;
+        for (piece_type, &pieces) in board.piece_bb.iter().enumerate() {
+            for (color, &colored) in board.color_bb.iter().enumerate() {
+                let count = (pieces & colored).count().min(15) as u64;
+                key |= count << (4 * (color + piece_type * 2));
+            }
+        }
+        MaterialKey(key)
+    }
+
+    // "KRKP", the strong side's pieces first, each side starting with its king
+    fn from_code(code: &str, strong: Color) -> MaterialKey {
+        let weak = code[1..].find('K').unwrap() + 1;
+        let mut key = 0;
+        for (i, c) in code.chars().enumerate() {
+            let color = if i < weak { strong } else { !strong };
+            let piece: Piece = c.to_string().parse().unwrap();
+            key += 1 << (4 * Piece::new(color, piece.piece_type()).index());
+        }
+        MaterialKey(key)
+    }
+
+    pub fn count(self, piece: Piece) -> u32 {
+        (self.0 >> (4 * piece.index()) & 15) as u32
+    }
+}
+
+// scores from the strong side's point of view
+type Evaluator = fn(&State, Color) -> i32;
+// None when the position isn't the drawish kind the scaler is about
+type Scaler = fn(&State, Color) -> Option<i32>;
+
+struct Endgames {
+    evaluators: HashMap<MaterialKey, (Evaluator, Color)>,
+    scalers: HashMap<MaterialKey, (Scaler, Color)>,
+}
+
+fn endgames() -> &'static Endgames {
+    static ENDGAMES: OnceLock<Endgames> = OnceLock::new();
+    ENDGAMES.get_or_init(|| {
+        let mut endgames = Endgames {
+            evaluators: HashMap::new(),
+            scalers: HashMap::new(),
+        };
+        for strong in [Color::White, Color::Black] {
+            for (code, evaluator) in [
+                ("KPK", kpk as Evaluator),
+                ("KBNK", kbnk),
+                ("KRKP", krkp),
+                ("KQKP", kqkp),
+                ("KRKB", krkb),
+                ("KRKN", krkn),
+            ] {
+                let key = MaterialKey::from_code(code, strong);
+                endgames.evaluators.insert(key, (evaluator, strong));
+            }
+            let key = MaterialKey::from_code("KNNK", strong);
+            endgames.scalers.insert(key, (knnk as Scaler, strong));
+        }
+        endgames
+    })
+}
+
+fn kings(board: &Board, strong: Color) -> (Square, Square) {
+    (
+        board.king_square(strong).unwrap(),
+        board.king_square(!strong).unwrap(),
+    )
+}
+
+fn piece_square(board: &Board, color: Color, piece_type: PieceType) -> Square {
+    board
+        .by_piece(Piece::new(color, piece_type))
+        .to_square()
+        .unwrap()
+}
+
+// the square as seen with the strong side playing up the board
+fn relative(square: Square, strong: Color) -> Square {
+    match strong {
+        Color::White => square,
+        Color::Black => Square::from_index(square.index() ^ 56).unwrap(),
+    }
+}
+
+fn non_pawn_material(board: &Board, color: Color) -> i32 {
+    [
+        PieceType::Knight,
+        PieceType::Bishop,
+        PieceType::Rook,
+        PieceType::Queen,
+    ]
+    .iter()
+    .map(|&piece_type| {
+        board.by_piece(Piece::new(color, piece_type)).count() as i32
+            * PIECE_VALUES[piece_type.index()]
+    })
+    .sum()
+}
+
+// a lone king against enough material to mate, two knights aren't
+fn is_kxk(board: &Board, strong: Color) -> bool {
+    let knights_and_king =
+        board.by_piece_type(PieceType::Knight) | board.by_piece_type(PieceType::King);
+    board.by_color(!strong).count() == 1
+        && non_pawn_material(board, strong) >= ROOK_VALUE
+        && !(board.by_color(strong) & !knights_and_king).is_empty()
+}
+
+// drives the lone king to the edge and brings the other one closer
+fn kxk(state: &State, strong: Color) -> i32 {
+    if state.side_to_move() != strong && !state.in_check() && state.legal_moves().is_empty() {
+        return 0;
+    }
+
+    let board = &state.board;
+    let (strong_king, weak_king) = kings(board, strong);
+    let pieces = |piece_type| board.by_piece(Piece::new(strong, piece_type));
+    let mut score = non_pawn_material(board, strong)
+        + pieces(PieceType::Pawn).count() as i32 * PAWN_VALUE
+        + PUSH_TO_EDGES[weak_king.index()]
+        + PUSH_CLOSE[distance(strong_king, weak_king) as usize];
+
+    let bishops = pieces(PieceType::Bishop);
+    if !(pieces(PieceType::Queen) | pieces(PieceType::Rook)).is_empty()
+        || (!bishops.is_empty() && !pieces(PieceType::Knight).is_empty())
+        || (!(bishops & LIGHT_SQUARES).is_empty() && !(bishops & DARK_SQUARES).is_empty())
+    {
+        score += KNOWN_WIN;
+    }
+    score
+}
+
+// exact, from the bitbase
+fn kpk(state: &State, strong: Color) -> i32 {
+    let board = &state.board;
+    let (strong_king, weak_king) = kings(board, strong);
+    let pawn = piece_square(board, strong, PieceType::Pawn);
+    if !bitbase::probe(strong, strong_king, pawn, weak_king, state.side_to_move()) {
+        return 0;
+    }
+    KNOWN_WIN + PAWN_VALUE + (relative(pawn, strong).index() / 8) as i32
+}
+
+// mate can only be forced in a corner the bishop covers
+fn kbnk(state: &State, strong: Color) -> i32 {
+    if state.side_to_move() != strong && !state.in_check() && state.legal_moves().is_empty() {
+        return 0;
+    }
+
+    let board = &state.board;
+    let (strong_king, weak_king) = kings(board, strong);
+    let bishop = piece_square(board, strong, PieceType::Bishop);
+    let corners = if LIGHT_SQUARES.contains(bishop) {
+        [Square::A8, Square::H1]
+    } else {
+        [Square::A1, Square::H8]
+    };
+    let corner = corners
+        .iter()
+        .map(|&corner| manhattan_distance(weak_king, corner))
+        .min()
+        .unwrap() as i32;
+    KNOWN_WIN + PUSH_CLOSE[distance(strong_king, weak_king) as usize] + 40 * (14 - corner)
+}
+
+// usually a win, unless the pawn is far advanced with its king next to it and the
+// attacking king is out of play
+fn krkp(state: &State, strong: Color) -> i32 {
+    let board = &state.board;
+    let (strong_king, weak_king) = kings(board, strong);
+    let strong_king = relative(strong_king, strong);
+    let weak_king = relative(weak_king, strong);
+    let rook = relative(piece_square(board, strong, PieceType::Rook), strong);
+    let pawn = relative(piece_square(board, !strong, PieceType::Pawn), strong);
+    let queening = Square::from_index(pawn.index() % 8).unwrap();
+    let next = Square::from_index(pawn.index() - 8).unwrap();
+    let weak_to_move = (state.side_to_move() != strong) as u8;
+
+    if strong_king.file() == pawn.file() && strong_king.index() < pawn.index() {
+        // the king is in front of the pawn
+        ROOK_VALUE - distance(strong_king, pawn) as i32
+    } else if distance(weak_king, pawn) >= 3 + weak_to_move && distance(weak_king, rook) >= 3 {
+        // the pawn is left alone
+        ROOK_VALUE - distance(strong_king, pawn) as i32
+    } else if weak_king.rank().index() <= 2
+        && distance(weak_king, pawn) == 1
+        && strong_king.rank().index() >= 3
+        && distance(strong_king, pawn) > 3 - weak_to_move
+    {
+        80 - 8 * distance(strong_king, pawn) as i32
+    } else {
+        200 - 8
+            * (distance(strong_king, next) as i32
+                - distance(weak_king, next) as i32
+                - distance(pawn, queening) as i32)
+    }
+}
+
+// a win, except for a bishop or rook pawn on the seventh with its king next to it
+fn kqkp(state: &State, strong: Color) -> i32 {
+    let board = &state.board;
+    let (strong_king, weak_king) = kings(board, strong);
+    let pawn = relative(piece_square(board, !strong, PieceType::Pawn), strong);
+    let mut score = PUSH_CLOSE[distance(strong_king, weak_king) as usize];
+    let drawing_file = [File::A, File::C, File::F, File::H].contains(&pawn.file());
+    if pawn.rank() != Rank::Rank2
+        || distance(relative(weak_king, strong), pawn) != 1
+        || !drawing_file
+    {
+        score += QUEEN_VALUE - PAWN_VALUE;
+    }
+    score
+}
+
+// drawish, a bit better with the defending king on the edge
+fn krkb(state: &State, strong: Color) -> i32 {
+    let (_, weak_king) = kings(&state.board, strong);
+    PUSH_TO_EDGES[weak_king.index()]
+}
+
+// drawish, a bit better with the knight cut off from its king
+fn krkn(state: &State, strong: Color) -> i32 {
+    let board = &state.board;
+    let (_, weak_king) = kings(board, strong);
+    let knight = piece_square(board, !strong, PieceType::Knight);
+    PUSH_TO_EDGES[weak_king.index()] + PUSH_AWAY[distance(weak_king, knight) as usize]
+}
+
+fn knnk(_: &State, _: Color) -> Option<i32> {
+    Some(SCALE_DRAW)
+}
+
+// pawns only on a rook file with the defending king in the corner, where a bishop of the
+// wrong color can't drive it out
+fn wrong_rook_pawn(state: &State, strong: Color) -> Option<i32> {
+    let board = &state.board;
+    let pawns = board.by_piece(Piece::new(strong, PieceType::Pawn));
+    let bishops = board.by_piece(Piece::new(strong, PieceType::Bishop));
+    let others = board.by_color(strong) & !pawns & !board.by_piece_type(PieceType::King);
+    if pawns.is_empty() || others != bishops || board.by_color(!strong).count() != 1 {
+        return None;
+    }
+
+    let file = [File::A, File::H]
+        .into_iter()
+        .find(|&file| (pawns & !BitBoard::from_file(file)).is_empty())?;
+    let rank = match strong {
+        Color::White => Rank::Rank8,
+        Color::Black => Rank::Rank1,
+    };
+    let queening = Square::new(rank, file);
+    let color = if LIGHT_SQUARES.contains(queening) {
+        LIGHT_SQUARES
+    } else {
+        DARK_SQUARES
+    };
+    let weak_king = board.king_square(!strong).unwrap();
+    ((bishops & color).is_empty() && distance(weak_king, queening) <= 1).then_some(SCALE_DRAW)
+}
+
+fn passed_pawns(board: &Board, color: Color) -> u32 {
+    let enemy_pawns = board.by_piece(Piece::new(!color, PieceType::Pawn));
+    board
+        .by_piece(Piece::new(color, PieceType::Pawn))
+        .filter(|&pawn| {
+            let pawn = BitBoard::from_square(pawn);
+            ((pawn | pawn.left() | pawn.right()).front_span(color) & enemy_pawns).is_empty()
+        })
+        .count() as u32
+}
+
+// the defender blockades on the squares the attacking bishop can't reach
+fn opposite_bishops(state: &State, strong: Color) -> Option<i32> {
+    let board = &state.board;
+    let white = board.by_piece(Piece::WhiteBishop);
+    let black = board.by_piece(Piece::BlackBishop);
+    let light = |bishops: BitBoard| !(bishops & LIGHT_SQUARES).is_empty();
+    if white.count() != 1 || black.count() != 1 || light(white) == light(black) {
+        return None;
+    }
+
+    let bishops_only = non_pawn_material(board, Color::White) == BISHOP_VALUE
+        && non_pawn_material(board, Color::Black) == BISHOP_VALUE;
+    let scale = if bishops_only {
+        18 + 4 * passed_pawns(board, strong) as i32
+    } else {
+        22 + 3 * board.by_color(strong).count() as i32
+    };
+    Some(scale.min(SCALE_NORMAL))
+}
+
+// without pawns, a minor piece more isn't enough
+fn minor_piece_up(state: &State, strong: Color) -> Option<i32> {
+    let board = &state.board;
+    if !board
+        .by_piece(Piece::new(strong, PieceType::Pawn))
+        .is_empty()
+    {
+        return None;
+    }
+    let ours = non_pawn_material(board, strong);
+    let theirs = non_pawn_material(board, !strong);
+    if ours - theirs > BISHOP_VALUE {
+        None
+    } else if ours < ROOK_VALUE {
+        Some(SCALE_DRAW)
+    } else if theirs <= BISHOP_VALUE {
+        Some(4)
+    } else {
+        Some(14)
+    }
+}
+
+// a specialised score from the side to move's point of view, if there is one
+pub fn evaluate(state: &State, key: MaterialKey) -> Option<i32> {
+    let (score, strong) = match endgames().evaluators.get(&key) {
+        Some(&(evaluator, strong)) => (evaluator(state, strong), strong),
+        None => {
+            let strong = [Color::White, Color::Black]
+                .into_iter()
+                .find(|&color| is_kxk(&state.board, color))?;
+            (kxk(state, strong), strong)
+        }
+    };
+    Some(if state.side_to_move() == strong {
+        score
+    } else {
+        -score
+    })
+}
+
+// how much of its advantage the side that's ahead keeps, out of SCALE_NORMAL
+pub fn scale_factor(state: &State, key: MaterialKey, strong: Color) -> i32 {
+    if let Some(&(scaler, color)) = endgames().scalers.get(&key) {
+        if color == strong {
+            if let Some(scale) = scaler(state, strong) {
+                return scale;
+            }
+        }
+    }
+    [wrong_rook_pawn as Scaler, opposite_bishops, minor_piece_up]
+        .iter()
+        .find_map(|scaler| scaler(state, strong))
+        .unwrap_or(SCALE_NORMAL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval;
+
+    fn state(fen: &str) -> State {
+        State::from_fen(&format!("{} - - 0 1", fen)).unwrap()
+    }
+
+    fn score(fen: &str) -> i32 {
+        eval::evaluate(&state(fen))
+    }
+
+    #[test]
+    fn material_keys() {
+        let board = state("8/8/4k3/8/3p4/8/8/1R2K3 w").board;
+        let key = MaterialKey::from_board(&board);
+        assert_eq!(key, MaterialKey::from_code("KRKP", Color::White));
+        assert_ne!(key, MaterialKey::from_code("KRKP", Color::Black));
+        assert_eq!(key.count(Piece::WhiteRook), 1);
+        assert_eq!(key.count(Piece::BlackPawn), 1);
+        assert_eq!(key.count(Piece::BlackRook), 0);
+    }
+
+    #[test]
+    fn specialised_scores() {
+        // KPK follows the bitbase
+        assert!(score("8/4k3/8/4K3/4P3/8/8/8 b") < -KNOWN_WIN);
+        assert_eq!(score("8/4k3/8/4K3/4P3/8/8/8 w"), 0);
+
+        // the lone king is better off in the centre, and stalemate is a draw
+        assert!(score("8/8/8/3k4/8/8/8/R3K3 w") < score("k7/8/8/8/8/8/8/R3K3 w"));
+        assert!(score("8/8/8/3k4/8/8/8/R3K3 b") > -KNOWN_WIN * 2);
+        assert_eq!(score("k7/2Q5/1K6/8/8/8/8/8 b"), 0);
+        assert!(score("7k/8/8/8/8/8/8/KNN5 w").abs() < KNOWN_WIN);
+
+        // the dark-squared bishop mates in a1 or h8
+        let right = score("7k/8/5K2/8/8/8/8/2B1N3 w");
+        let wrong = score("k7/8/2K5/8/8/8/8/2B1N3 w");
+        assert!(right > wrong && wrong > KNOWN_WIN);
+        assert_eq!(score("k7/3N4/1K6/8/8/8/8/B7 b"), 0);
+
+        // a bishop pawn on the seventh with its king next to it holds the queen off
+        assert!(score("8/8/8/8/8/1K6/2pk4/Q7 w") < QUEEN_VALUE - PAWN_VALUE);
+        assert!(score("8/8/8/8/8/1K6/3pk3/Q7 w") > QUEEN_VALUE - PAWN_VALUE);
+
+        // the rook wins a pawn the king doesn't protect
+        assert!(score("8/8/8/8/8/3k4/7p/R3K3 b") < -ROOK_VALUE / 2);
+        assert!(score("8/8/8/8/8/8/2kp4/R5K1 w") < ROOK_VALUE / 2);
+    }
+
+    #[test]
+    fn scale_factors() {
+        assert_eq!(score("8/8/3k4/8/8/8/8/2KNN3 w"), 0);
+
+        // the a8 corner is light, so a dark-squared bishop can't help the pawn through
+        assert_eq!(score("k7/8/8/8/8/8/P7/K1B5 w"), 0);
+        assert!(score("k7/8/8/8/8/8/P7/KB6 w") > BISHOP_VALUE);
+        assert!(score("8/8/8/8/8/8/7p/5b1K b") > BISHOP_VALUE);
+        assert_eq!(score("8/8/8/8/8/8/7p/4b2K b"), 0);
+
+        let same_colors = score("4k3/8/4b3/8/1P6/P7/2B5/4K3 w");
+        let opposite_colors = score("4k3/8/3b4/8/1P6/P7/2B5/4K3 w");
+        assert!(opposite_colors > 0 && opposite_colors < same_colors / 2);
+
+        assert_eq!(score("4k3/8/8/8/8/8/8/3BK3 w"), 0);
+        assert!(score("4k3/8/8/8/8/8/8/3RKb2 w") < ROOK_VALUE / 4);
+    }
+}
@@ -0,0 +1,347 @@
+use crate::errors::ChessError;
+use crate::moves::Move;
+use crate::state::State;
+use std::fmt;
+use std::str::FromStr;
+
+// opcodes whose operand is free text and always written quoted
+const STRING_OPCODES: [&str; 11] = [
+    "id", "c0", "c1", "c2", "c3", "c4", "c5", "c6", "c7", "c8", "c9",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Operation {
+    pub opcode: String,
+    pub operands: Vec<String>,
+}
+
+// EPD is the first four FEN fields followed by `opcode operand...;` operations
+#[derive(Clone)]
+pub struct Epd {
+    pub state: State,
+    pub operations: Vec<Operation>,
+}
+
+fn parse_operations(s: &str) -> Result<Vec<Operation>, ChessError> {
+    let mut operations = Vec::new();
+    let mut tokens: Vec<String> = Vec::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => {
+                if tokens.is_empty() {
+                    return Err(ChessError::InvalidEPD("Empty operation".into()));
+                }
+                operations.push(Operation {
+                    opcode: tokens.remove(0),
+                    operands: std::mem::take(&mut tokens),
+                });
+            }
+            '"' => {
+                let mut token = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => token.push(c),
+                        None => return Err(ChessError::InvalidEPD("Unterminated string".into())),
+                    }
+                }
+                tokens.push(token);
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut token = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == ';' || c == '"' {
+                        break;
+                    }
+                    token.push(c);
+                    chars.next();
+                }
+                tokens.push(token);
+            }
+        }
+    }
+
+    if !tokens.is_empty() {
+        return Err(ChessError::InvalidEPD(format!(
+            "Missing ';' after '{}'",
+            tokens.join(" ")
+        )));
+    }
+    Ok(operations)
+}
+
+impl Epd {
+    pub fn new(state: State) -> Epd {
+        Epd {
+            state,
+            operations: Vec::new(),
+        }
+    }
+
+    pub fn operation(&self, opcode: &str) -> Option<&Operation> {
+        self.operations.iter().find(|op| op.opcode == opcode)
+    }
+
+    pub fn operands(&self, opcode: &str) -> &[String] {
+        self.operation(opcode).map_or(&[], |op| &op.operands)
+    }
+
+    fn first_operand(&self, opcode: &str) -> Option<&str> {
+        self.operands(opcode).first().map(String::as_str)
+    }
+
+    // replaces an existing operation with the same opcode, keeping its position
+    pub fn set_operation(&mut self, opcode: &str, operands: Vec<String>) {
+        match self.operations.iter_mut().find(|op| op.opcode == opcode) {
+            Some(op) => op.operands = operands,
+            None => self.operations.push(Operation {
+                opcode: opcode.to_string(),
+                operands,
+            }),
+        }
+    }
+
+    pub fn remove_operation(&mut self, opcode: &str) {
+        self.operations.retain(|op| op.opcode != opcode);
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        self.first_operand("id")
+    }
+
+    // c0 to c9
+    pub fn comment(&self, n: usize) -> Option<&str> {
+        self.first_operand(&format!("c{}", n))
+    }
+
+    // analysis count depth
+    pub fn acd(&self) -> Option<u32> {
+        self.first_operand("acd").and_then(|s| s.parse().ok())
+    }
+
+    // centipawn evaluation from the side to move's point of view
+    pub fn ce(&self) -> Option<i32> {
+        self.first_operand("ce").and_then(|s| s.parse().ok())
+    }
+
+    fn parse_moves(&self, opcode: &str) -> Result<Vec<Move>, ChessError> {
+        self.operands(opcode)
+            .iter()
+            .map(|san| self.state.parse_san(san))
+            .collect()
+    }
+
+    pub fn best_moves(&self) -> Result<Vec<Move>, ChessError> {
+        self.parse_moves("bm")
+    }
+
+    pub fn avoid_moves(&self) -> Result<Vec<Move>, ChessError> {
+        self.parse_moves("am")
+    }
+
+    // unlike bm and am the pv moves are played one after another
+    pub fn pv(&self) -> Result<Vec<Move>, ChessError> {
+        let mut state = self.state.clone();
+        let mut moves = Vec::new();
+        for san in self.operands("pv") {
+            let mv = state.parse_san(san)?;
+            state.make_move(mv);
+            moves.push(mv);
+        }
+        Ok(moves)
+    }
+
+    pub fn set_pv(&mut self, pv: &[Move]) {
+        let mut state = self.state.clone();
+        let mut operands = Vec::new();
+        for &mv in pv {
+            operands.push(state.to_san(mv));
+            state.make_move(mv);
+        }
+        self.set_operation("pv", operands);
+    }
+
+    // STS-style scoring: c0 "Qd2=10, Qe1=5, Nd3=3"
+    // returns None when c0 is missing or is an ordinary comment
+    pub fn move_points(&self) -> Option<Vec<(Move, u32)>> {
+        let c0 = self.comment(0)?;
+        c0.split(',')
+            .map(|entry| {
+                let (san, points) = entry.trim().split_once('=')?;
+                let mv = self.state.parse_san(san.trim()).ok()?;
+                Some((mv, points.trim().parse().ok()?))
+            })
+            .collect()
+    }
+}
+
+impl FromStr for Epd {
+    type Err = ChessError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let mut rest = s;
+        let mut fields = Vec::new();
+        for _ in 0..4 {
+            rest = rest.trim_start();
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            if end == 0 {
+                return Err(ChessError::InvalidEPD("Missing position fields".into()));
+            }
+            fields.push(&rest[..end]);
+            rest = &rest[end..];
+        }
+
+        let operations = parse_operations(rest)?;
+        let mut epd = Epd {
+            state: State::from_fen(&format!("{} 0 1", fields.join(" ")))?,
+            operations,
+        };
+
+        // the move counters live in opcodes instead of the position fields
+        if let Some(hmvc) = epd.first_operand("hmvc") {
+            let hmvc = hmvc
+                .parse()
+                .map_err(|_| ChessError::InvalidEPD("Invalid hmvc".into()))?;
+            epd.state.set_halfmove_clock(hmvc);
+        }
+        if let Some(fmvn) = epd.first_operand("fmvn") {
+            let fmvn: u16 = fmvn
+                .parse()
+                .map_err(|_| ChessError::InvalidEPD("Invalid fmvn".into()))?;
+            let ply = (fmvn.max(1) - 1) * 2 + epd.state.side_to_move().index() as u16;
+            epd.state.set_ply(ply);
+        }
+
+        Ok(epd)
+    }
+}
+
+impl fmt::Display for Epd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let fen = self.state.to_fen();
+        let fields: Vec<&str> = fen.split(' ').take(4).collect();
+        write!(f, "{}", fields.join(" "))?;
+
+        for op in &self.operations {
+            write!(f, " {}", op.opcode)?;
+            let quote = STRING_OPCODES.contains(&op.opcode.as_str());
+            for operand in &op.operands {
+                if quote || operand.contains(char::is_whitespace) || operand.contains(';') {
+                    write!(f, " \"{}\"", operand)?;
+                } else {
+                    write!(f, " {}", operand)?;
+                }
+            }
+            write!(f, ";")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WAC1: &str =
+        "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id \"WAC.001\";";
+
+    #[test]
+    fn round_trip() {
+        let epd: Epd = WAC1.parse().unwrap();
+        assert_eq!(epd.id(), Some("WAC.001"));
+        let best = epd.best_moves().unwrap();
+        assert_eq!(best.len(), 1);
+        assert_eq!(best[0].to_uci(false), "g3g6");
+        assert!(epd.avoid_moves().unwrap().is_empty());
+        assert_eq!(epd.to_string(), WAC1);
+
+        for line in [
+            "r1b2rk1/1p1nbppp/pq1p4/3B4/P2NP3/2N1p3/1PP3PP/R2Q1R1K w - - am Nf5 Qh5; bm Rxf7 Qg4; c0 \"two moves\"; acd 12; ce -35;",
+            "8/8/4k3/8/8/8/4P3/4K3 w - - hmvc 12; fmvn 40; pv e4 Kd6 Kd2;",
+            "4k3/8/8/8/8/8/8/4K2R w K - c9 \"a; b\";",
+        ] {
+            let epd: Epd = line.parse().unwrap();
+            let again: Epd = epd.to_string().parse().unwrap();
+            assert_eq!(again.state.to_fen(), epd.state.to_fen());
+            assert_eq!(again.operations, epd.operations);
+        }
+    }
+
+    #[test]
+    fn operations() {
+        let epd: Epd = "r1b2rk1/1p1nbppp/pq1p4/3B4/P2NP3/2N1p3/1PP3PP/R2Q1R1K w - - am Nf5 Qh5; bm Rxf7 Qg4; acd 12; ce -35;"
+            .parse()
+            .unwrap();
+        assert_eq!(epd.avoid_moves().unwrap().len(), 2);
+        assert_eq!(epd.best_moves().unwrap().len(), 2);
+        assert_eq!(epd.acd(), Some(12));
+        assert_eq!(epd.ce(), Some(-35));
+        assert_eq!(epd.comment(0), None);
+
+        // the counters come from hmvc and fmvn, pv is a line
+        let epd: Epd = "8/8/4k3/8/8/8/4P3/4K3 w - - hmvc 12; fmvn 40; pv e4 Kd6 Kd2;"
+            .parse()
+            .unwrap();
+        assert_eq!(epd.state.to_fen(), "8/8/4k3/8/8/8/4P3/4K3 w - - 12 40");
+        let pv: Vec<String> = epd
+            .pv()
+            .unwrap()
+            .iter()
+            .map(|mv| mv.to_uci(false))
+            .collect();
+        assert_eq!(pv, ["e2e4", "e6d6", "e1d2"]);
+
+        let mut epd = Epd::new(State::default());
+        let e4 = epd.state.parse_uci_move("e2e4", false).unwrap();
+        let e5 = State::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1")
+            .unwrap()
+            .parse_uci_move("e7e5", false)
+            .unwrap();
+        epd.set_operation("id", vec!["start".to_string()]);
+        epd.set_pv(&[e4, e5]);
+        epd.set_operation("id", vec!["open game".to_string()]);
+        assert_eq!(
+            epd.to_string(),
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - id \"open game\"; pv e4 e5;"
+        );
+        epd.remove_operation("pv");
+        assert_eq!(epd.operations.len(), 1);
+    }
+
+    #[test]
+    fn sts_points() {
+        let epd: Epd = "1kr5/3n4/q3p2p/p2n2p1/PppB1P2/5BP1/1P2Q2P/3R2K1 w - - bm f5; id \"STS(v1.0) Undermine.001\"; c0 \"f5=10, Be5+=2, Bf2=3, Bg4=2\";"
+            .parse()
+            .unwrap();
+        let points: Vec<(String, u32)> = epd
+            .move_points()
+            .unwrap()
+            .into_iter()
+            .map(|(mv, points)| (epd.state.to_san(mv), points))
+            .collect();
+        assert_eq!(points[0], ("f5".to_string(), 10));
+        assert_eq!(points.len(), 4);
+
+        let plain: Epd = "4k3/8/8/8/8/8/8/4K3 w - - c0 \"just a comment\";"
+            .parse()
+            .unwrap();
+        assert_eq!(plain.move_points(), None);
+    }
+
+    #[test]
+    fn invalid() {
+        for line in [
+            "",
+            "4k3/8/8/8/8/8/8/4K3 w -",
+            "4k3/8/8/8/8/8/8/4K3 w - - bm",
+            "4k3/8/8/8/8/8/8/4K3 w - - id \"open;",
+            "4k3/8/8/8/8/8/8/4K3 w - - hmvc x;",
+        ] {
+            assert!(line.parse::<Epd>().is_err(), "{}", line);
+        }
+    }
+}
@@ -6,4 +6,26 @@ pub enum ChessError {
     ParseError(String, &'static str),
     #[error("Invalid FEN: {0}")]
     InvalidFEN(String),
+    #[error("Invalid EPD: {0}")]
+    InvalidEPD(String),
+    #[error("Invalid PGN: {0}")]
+    InvalidPGN(String),
+    #[error("Invalid problem: {0}")]
+    InvalidProblem(String),
+    #[error("Illegal move: {0}")]
+    IllegalMove(String),
+    #[error("Invalid chess960 position index: {0}")]
+    InvalidChess960Index(u16),
+    #[error("Invalid packed position: {0}")]
+    InvalidPackedPosition(String),
+    #[error("Invalid tablebase: {0}")]
+    InvalidTablebase(String),
+    #[error("Invalid network: {0}")]
+    InvalidNetwork(String),
+    #[error("Engine timed out waiting for {0}")]
+    EngineTimeout(String),
+    #[error("Engine exited: {0}")]
+    EngineCrashed(String),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
 }
@@ -0,0 +1,177 @@
+use crate::endgame::{self, MaterialKey, SCALE_NORMAL};
+use crate::nnue::{Accumulator, Network};
+use crate::piece::*;
+use crate::state::State;
+use std::sync::Arc;
+
+pub const PIECE_VALUES: [i32; PIECE_TYPE_COUNT] = [100, 320, 330, 500, 900, 0];
+
+// piece-square tables from white's point of view, a1 first
+#[rustfmt::skip]
+const PAWN_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+     5, 10, 10,-20,-20, 10, 10,  5,
+     5, -5,-10,  0,  0,-10, -5,  5,
+     0,  0,  0, 20, 20,  0,  0,  0,
+     5,  5, 10, 25, 25, 10,  5,  5,
+    10, 10, 20, 30, 30, 20, 10, 10,
+    50, 50, 50, 50, 50, 50, 50, 50,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+
+#[rustfmt::skip]
+const KNIGHT_TABLE: [i32; 64] = [
+    -50,-40,-30,-30,-30,-30,-40,-50,
+    -40,-20,  0,  5,  5,  0,-20,-40,
+    -30,  5, 10, 15, 15, 10,  5,-30,
+    -30,  0, 15, 20, 20, 15,  0,-30,
+    -30,  5, 15, 20, 20, 15,  5,-30,
+    -30,  0, 10, 15, 15, 10,  0,-30,
+    -40,-20,  0,  0,  0,  0,-20,-40,
+    -50,-40,-30,-30,-30,-30,-40,-50,
+];
+
+#[rustfmt::skip]
+const BISHOP_TABLE: [i32; 64] = [
+    -20,-10,-10,-10,-10,-10,-10,-20,
+    -10,  5,  0,  0,  0,  0,  5,-10,
+    -10, 10, 10, 10, 10, 10, 10,-10,
+    -10,  0, 10, 10, 10, 10,  0,-10,
+    -10,  5,  5, 10, 10,  5,  5,-10,
+    -10,  0,  5, 10, 10,  5,  0,-10,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -20,-10,-10,-10,-10,-10,-10,-20,
+];
+
+#[rustfmt::skip]
+const ROOK_TABLE: [i32; 64] = [
+     0,  0,  0,  5,  5,  0,  0,  0,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+     5, 10, 10, 10, 10, 10, 10,  5,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+
+#[rustfmt::skip]
+const QUEEN_TABLE: [i32; 64] = [
+    -20,-10,-10, -5, -5,-10,-10,-20,
+    -10,  0,  5,  0,  0,  0,  0,-10,
+    -10,  5,  5,  5,  5,  5,  0,-10,
+      0,  0,  5,  5,  5,  5,  0, -5,
+     -5,  0,  5,  5,  5,  5,  0, -5,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -20,-10,-10, -5, -5,-10,-10,-20,
+];
+
+#[rustfmt::skip]
+const KING_MIDDLEGAME_TABLE: [i32; 64] = [
+     20, 30, 10,  0,  0, 10, 30, 20,
+     20, 20,  0,  0,  0,  0, 20, 20,
+    -10,-20,-20,-20,-20,-20,-20,-10,
+    -20,-30,-30,-40,-40,-30,-30,-20,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+];
+
+#[rustfmt::skip]
+const KING_ENDGAME_TABLE: [i32; 64] = [
+    -50,-30,-30,-30,-30,-30,-30,-50,
+    -30,-30,  0,  0,  0,  0,-30,-30,
+    -30,-10, 20, 30, 30, 20,-10,-30,
+    -30,-10, 30, 40, 40, 30,-10,-30,
+    -30,-10, 30, 40, 40, 30,-10,-30,
+    -30,-10, 20, 30, 30, 20,-10,-30,
+    -30,-20,-10,  0,  0,-10,-20,-30,
+    -50,-40,-30,-20,-20,-30,-40,-50,
+];
+
+const PHASE_WEIGHTS: [i32; PIECE_TYPE_COUNT] = [0, 1, 1, 2, 4, 0];
+const MAX_PHASE: i32 = 24;
+
+fn square_value(piece: Piece, index: usize, phase: i32) -> i32 {
+    // black reads the tables upside down
+    let index = match piece.color() {
+        Color::White => index,
+        Color::Black => index ^ 56,
+    };
+    match piece.piece_type() {
+        PieceType::Pawn => PAWN_TABLE[index],
+        PieceType::Knight => KNIGHT_TABLE[index],
+        PieceType::Bishop => BISHOP_TABLE[index],
+        PieceType::Rook => ROOK_TABLE[index],
+        PieceType::Queen => QUEEN_TABLE[index],
+        PieceType::King => {
+            (KING_MIDDLEGAME_TABLE[index] * phase + KING_ENDGAME_TABLE[index] * (MAX_PHASE - phase))
+                / MAX_PHASE
+        }
+    }
+}
+
+// how the search scores positions
+pub trait Evaluator: Send + Sync {
+    // a network the search should keep accumulators for while it makes moves
+    fn network(&self) -> Option<Arc<Network>> {
+        None
+    }
+    // centipawns from the side to move's point of view
+    fn evaluate(&self, state: &State) -> i32;
+    // the same, with the search's up to date accumulator for the position
+    fn evaluate_incremental(&self, state: &State, _accumulator: &Accumulator) -> i32 {
+        self.evaluate(state)
+    }
+}
+
+// the piece-square tables below
+pub struct Classical;
+
+impl Evaluator for Classical {
+    fn evaluate(&self, state: &State) -> i32 {
+        evaluate(state)
+    }
+}
+
+// centipawns from the side to move's point of view
+pub fn evaluate(state: &State) -> i32 {
+    let key = MaterialKey::from_board(&state.board);
+    if let Some(score) = endgame::evaluate(state, key) {
+        return score;
+    }
+
+    let phase = state
+        .board
+        .pieces()
+        .filter_map(|(_, piece)| piece)
+        .map(|piece| PHASE_WEIGHTS[piece.piece_type().index()])
+        .sum::<i32>()
+        .min(MAX_PHASE);
+
+    let mut score = 0;
+    for (square, piece) in state.board.pieces() {
+        if let Some(piece) = piece {
+            let value = PIECE_VALUES[piece.piece_type().index()]
+                + square_value(piece, square.index(), phase);
+            match piece.color() {
+                Color::White => score += value,
+                Color::Black => score -= value,
+            }
+        }
+    }
+
+    let ahead = if score > 0 {
+        Color::White
+    } else {
+        Color::Black
+    };
+    let score = score * endgame::scale_factor(state, key, ahead) / SCALE_NORMAL;
+
+    match state.side_to_move() {
+        Color::White => score,
+        Color::Black => -score,
+    }
+}
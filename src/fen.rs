@@ -1,12 +1,13 @@
 use std::str::FromStr;
 
 use crate::{
+    bitboard::BitBoard,
     errors::ChessError,
-    file::File,
+    file::{File, FILE_COUNT},
     piece::*, // too lazy to import individually
     rank::Rank,
     square::Square,
-    state::{Castling, State},
+    state::{Castling, CastlingSide, Ply, State},
 };
 
 impl State {
@@ -24,7 +25,10 @@ impl State {
         // '/' = new rank
         // [1-8] = empty squares
 
-        let (pieces, metadata) = fen.split_at(fen.find(' ').unwrap()); // TODO remove unwrap
+        let mut fields = fen.split(' ').filter(|s| !s.is_empty());
+        let pieces = fields
+            .next()
+            .ok_or(ChessError::InvalidFEN("Missing piece placement".into()))?;
 
         let mut state = State::new();
         let ranks: Vec<&str> = pieces.split('/').collect();
@@ -32,82 +36,169 @@ impl State {
             return Err(ChessError::InvalidFEN("Invalid number of ranks".into()));
         }
 
+        // FEN starts at the 8th rank
         for (rank_index, rank_data) in ranks.iter().enumerate() {
-            let rank = Rank::from_index(rank_index).expect("Invalid rank index");
-            let mut file = File::A;
+            let rank = Rank::from_index(7 - rank_index).expect("Invalid rank index");
+            let mut file_index = 0;
             for piece in rank_data.chars() {
-                if piece.is_digit(10) {
-                    file = file
-                        .right_n(piece.to_digit(10).unwrap() as usize - 1)
-                        .ok_or(ChessError::InvalidFEN("Invalid file index".into()))?;
+                if let Some(empty) = piece.to_digit(10) {
+                    file_index += empty as usize;
                 } else {
                     let piece = Piece::from_str(&piece.to_string())?;
-                    let square = Square::new(rank, file);
-                    state.board.set_piece(square, Some(piece));
-                    if file != File::H {
-                        file = file
-                            .right()
-                            .ok_or(ChessError::InvalidFEN("Invalid file index".into()))?;
-                    }
+                    let file = File::from_index(file_index)
+                        .ok_or(ChessError::InvalidFEN("Invalid file index".into()))?;
+                    state.board.set_piece(Square::new(rank, file), Some(piece));
+                    file_index += 1;
                 }
             }
+            if file_index != FILE_COUNT {
+                return Err(ChessError::InvalidFEN("Invalid file index".into()));
+            }
         }
 
-        let mut metadata_iter = metadata.split(' ').filter(|s| !s.is_empty());
-        let _turn = metadata_iter // TODO: what to do with this?
+        let turn = fields
             .next()
             .ok_or(ChessError::InvalidFEN("Missing turn".into()))?
             .parse::<Color>()?;
-        let castling = metadata_iter
-            .next()
-            .ok_or(ChessError::InvalidFEN("Missing castling".into()))?
-            .parse::<Castling>()?;
-        let en_passant = metadata_iter
+        let castling = state.parse_castling(
+            fields
+                .next()
+                .ok_or(ChessError::InvalidFEN("Missing castling".into()))?,
+        )?;
+        let en_passant = fields
             .next()
             .map(|s| State::map_empty_fen(s).map(|s| s.parse::<Square>()))
-            .ok_or(ChessError::InvalidFEN("Missing/invalid en passant".into()))?;
-        let half_move_clock = metadata_iter
+            .ok_or(ChessError::InvalidFEN("Missing/invalid en passant".into()))?
+            .transpose()?;
+        let half_move_clock = fields
             .next()
             .ok_or(ChessError::InvalidFEN("Missing half move clock".into()))?
-            .parse::<u16>()
+            .parse::<Ply>()
             .map_err(|_| ChessError::InvalidFEN("Invalid half move clock".into()))?;
-        let full_move_number = metadata_iter
+        let full_move_number = fields
             .next()
             .ok_or(ChessError::InvalidFEN("Missing full move number".into()))?
-            .parse::<u16>()
+            .parse::<Ply>()
             .map_err(|_| ChessError::InvalidFEN("Invalid full move number".into()))?;
 
-        // state.turn = turn;
         state.castling = castling;
-        state.en_passant = en_passant.map(|s| s.unwrap());
+        state.en_passant = en_passant;
         state.halfmove_clock = half_move_clock;
-        state.ply = full_move_number;
+        state.ply = (full_move_number.max(1) - 1) * 2 + turn.index() as Ply;
 
         Ok(state)
     }
 
-    fn rank_fen(&self, rank: usize) -> String {
-        if rank > 7 {
-            panic!("Invalid rank");
+    // the rook furthest from the king on the given side, which is what K/Q mean in X-FEN
+    fn outermost_rook(&self, color: Color, side: CastlingSide) -> Option<File> {
+        let back_rank = Rank::back_rank(color);
+        let king = self.board.king_square(color)?;
+        if king.rank() != back_rank {
+            return None;
+        }
+
+        let mut rooks = (self.board.by_piece(Piece::new(color, PieceType::Rook))
+            & BitBoard::from_rank(back_rank))
+        .map(|square| square.file());
+        match side {
+            CastlingSide::King => rooks
+                .filter(|file| file.index() > king.file().index())
+                .last(),
+            CastlingSide::Queen => rooks.find(|file| file.index() < king.file().index()),
+        }
+    }
+
+    // accepts classic (KQkq), Shredder-FEN (HAha) and X-FEN (mixed) castling fields
+    fn parse_castling(&self, s: &str) -> Result<Castling, ChessError> {
+        let mut castling = Castling::none();
+
+        if s == "-" {
+            return Ok(castling);
+        }
+
+        let invalid = || ChessError::ParseError(s.to_string(), "Castling");
+        for c in s.chars() {
+            let color = if c.is_ascii_uppercase() {
+                Color::White
+            } else {
+                Color::Black
+            };
+            let back_rank = Rank::back_rank(color);
+            let king = self
+                .board
+                .king_square(color)
+                .filter(|square| square.rank() == back_rank)
+                .ok_or_else(invalid)?;
+
+            let (side, file) = match c.to_ascii_lowercase() {
+                'k' => (
+                    CastlingSide::King,
+                    self.outermost_rook(color, CastlingSide::King),
+                ),
+                'q' => (
+                    CastlingSide::Queen,
+                    self.outermost_rook(color, CastlingSide::Queen),
+                ),
+                c => {
+                    let file = File::from_char(c).ok_or_else(invalid)?;
+                    let side = if file.index() > king.file().index() {
+                        CastlingSide::King
+                    } else {
+                        CastlingSide::Queen
+                    };
+                    let rook = self.board.piece(Square::new(back_rank, file));
+                    if rook != Some(Piece::new(color, PieceType::Rook)) {
+                        return Err(invalid());
+                    }
+                    (side, Some(file))
+                }
+            };
+
+            castling.set_rook_file(color, side, Some(file.ok_or_else(invalid)?));
+        }
+
+        Ok(castling)
+    }
+
+    fn castling_fen(&self, shredder: bool) -> String {
+        let mut fen = String::new();
+        for color in [Color::White, Color::Black] {
+            for side in [CastlingSide::King, CastlingSide::Queen] {
+                if let Some(file) = self.castling.rook_file(color, side) {
+                    let c = if shredder || self.outermost_rook(color, side) != Some(file) {
+                        file.to_char()
+                    } else {
+                        match side {
+                            CastlingSide::King => 'k',
+                            CastlingSide::Queen => 'q',
+                        }
+                    };
+                    fen.push(match color {
+                        Color::White => c.to_ascii_uppercase(),
+                        Color::Black => c,
+                    });
+                }
+            }
+        }
+        if fen.is_empty() {
+            fen.push('-');
         }
-        // i'm gonna make ranges work with files and ranks and squares
-        // so you can do stuff like: for rank in Rank::Rank1..Rank::Rank8
-        // how would that work with Board?
-        // i think it's better to loop over the "indices" (meaning our enums) cause enumerate doesn't give you our enums
+        fen
+    }
 
+    fn rank_fen(&self, rank: Rank) -> String {
         let mut fen = String::new();
         let mut empty_count: u8 = 0;
-        for (i, piece) in self.board.board.iter().rev().enumerate() {
-            if i == rank {
-                if piece.is_some() {
-                    if empty_count > 0 {
-                        fen.push_str(&empty_count.to_string());
-                        empty_count = 0;
-                    }
-                    fen.push_str(&piece.unwrap().as_str());
-                } else {
-                    empty_count += 1;
+        for file in 0..FILE_COUNT {
+            let square = Square::new(rank, File::from_index(file).unwrap());
+            if let Some(piece) = self.board.piece(square) {
+                if empty_count > 0 {
+                    fen.push_str(&empty_count.to_string());
+                    empty_count = 0;
                 }
+                fen.push_str(piece.as_str());
+            } else {
+                empty_count += 1;
             }
         }
         if empty_count > 0 {
@@ -116,10 +207,10 @@ impl State {
         fen
     }
 
-    pub fn to_fen(&self) -> String {
+    fn fen(&self, shredder: bool) -> String {
         let mut fen = String::new();
         for rank in 0..8 {
-            fen.push_str(&self.rank_fen(7 - rank));
+            fen.push_str(&self.rank_fen(Rank::from_index(7 - rank).unwrap()));
             if rank < 7 {
                 fen.push('/');
             }
@@ -131,25 +222,7 @@ impl State {
             'b'
         });
         fen.push(' ');
-        if self.castling.white_king {
-            fen.push('K');
-        }
-        if self.castling.white_queen {
-            fen.push('Q');
-        }
-        if self.castling.black_king {
-            fen.push('k');
-        }
-        if self.castling.black_queen {
-            fen.push('q');
-        }
-        if !self.castling.white_king
-            && !self.castling.white_queen
-            && !self.castling.black_king
-            && !self.castling.black_queen
-        {
-            fen.push('-');
-        }
+        fen.push_str(&self.castling_fen(shredder));
         fen.push(' ');
         if let Some(square) = self.en_passant {
             fen.push_str(&square.to_string());
@@ -159,7 +232,89 @@ impl State {
         fen.push(' ');
         fen.push_str(&self.halfmove_clock.to_string());
         fen.push(' ');
-        fen.push_str(&self.ply.to_string());
+        fen.push_str(&(self.ply / 2 + 1).to_string());
         fen
     }
+
+    // X-FEN: identical to regular FEN unless an inner rook has castling rights
+    pub fn to_fen(&self) -> String {
+        self.fen(false)
+    }
+
+    // Shredder-FEN: castling rights are always written as rook files (HAha)
+    pub fn to_shredder_fen(&self) -> String {
+        self.fen(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b Kq - 3 12",
+            "8/8/4k3/8/8/8/8/4K3 b - - 30 90",
+        ] {
+            assert_eq!(State::from_fen(fen).unwrap().to_fen(), fen);
+        }
+
+        let state = State::default();
+        assert_eq!(
+            state.board.piece(Square::E1),
+            Some(Piece::new(Color::White, PieceType::King))
+        );
+        assert_eq!(
+            state.board.piece(Square::D8),
+            Some(Piece::new(Color::Black, PieceType::Queen))
+        );
+        let black = State::from_fen("8/8/4k3/8/8/8/8/4K3 b - - 0 40").unwrap();
+        assert_eq!(black.side_to_move(), Color::Black);
+        assert_eq!(black.ply(), 79);
+    }
+
+    #[test]
+    fn castling_fields() {
+        // X-FEN only names the rook when it isn't the outermost one
+        let xfen = "rn2k1r1/ppp1pp1p/3p2p1/5bn1/P7/2N2B2/1PPPPP2/2BNK1RR w Gkq - 4 11";
+        let state = State::from_fen(xfen).unwrap();
+        assert_eq!(state.castling.white_king, Some(File::G));
+        assert_eq!(state.castling.white_queen, None);
+        assert_eq!(state.castling.black_king, Some(File::G));
+        assert_eq!(state.castling.black_queen, Some(File::A));
+        assert_eq!(state.to_fen(), xfen);
+        let shredder = state.to_shredder_fen();
+        assert_eq!(
+            shredder,
+            "rn2k1r1/ppp1pp1p/3p2p1/5bn1/P7/2N2B2/1PPPPP2/2BNK1RR w Gga - 4 11"
+        );
+        assert_eq!(State::from_fen(&shredder).unwrap().castling, state.castling);
+
+        let classic = State::default();
+        let shredder = classic.to_shredder_fen();
+        assert!(shredder.contains(" w HAha - "));
+        assert_eq!(
+            State::from_fen(&shredder).unwrap().to_fen(),
+            classic.to_fen()
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        for fen in [
+            "",
+            "8/8/8/8/8/8/8 w - - 0 1",
+            "9/8/8/8/8/8/8/8 w - - 0 1",
+            "4k3/8/8/8/8/8/8/4K3 x - - 0 1",
+            "4k3/8/8/8/8/8/8/4K3 w K - 0 1",
+            "4k3/8/8/8/8/8/8/R3K3 w B - 0 1",
+            "4k3/8/8/8/8/8/8/4K3 w - e9 0 1",
+            "4k3/8/8/8/8/8/8/4K3 w - - 0",
+        ] {
+            assert!(State::from_fen(fen).is_err(), "{}", fen);
+        }
+    }
 }
@@ -44,11 +44,11 @@ impl File {
     }
 
     pub fn wrapping_left(self) -> File {
-        unsafe { transmute((self.index().wrapping_sub(1) % FILE_COUNT) as u8) }
+        unsafe { transmute::<u8, File>((self.index().wrapping_sub(1) % FILE_COUNT) as u8) }
     }
 
     pub fn wrapping_right(self) -> File {
-        unsafe { transmute((self.index().wrapping_add(1) % FILE_COUNT) as u8) }
+        unsafe { transmute::<u8, File>((self.index().wrapping_add(1) % FILE_COUNT) as u8) }
     }
 }
 
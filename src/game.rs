@@ -0,0 +1,381 @@
+use crate::bitboard::*;
+use crate::errors::ChessError;
+use crate::moves::{Move, Undo};
+use crate::piece::*;
+use crate::state::State;
+use std::fmt;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+}
+
+impl GameResult {
+    pub fn win_for(color: Color) -> GameResult {
+        match color {
+            Color::White => GameResult::WhiteWins,
+            Color::Black => GameResult::BlackWins,
+        }
+    }
+}
+
+// PGN result tokens
+impl fmt::Display for GameResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GameResult::WhiteWins => write!(f, "1-0"),
+            GameResult::BlackWins => write!(f, "0-1"),
+            GameResult::Draw => write!(f, "1/2-1/2"),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Termination {
+    Checkmate,
+    Stalemate,
+    InsufficientMaterial,
+    // automatic draws
+    FivefoldRepetition,
+    SeventyFiveMoveRule,
+    // draws a player may claim
+    ThreefoldRepetition,
+    FiftyMoveRule,
+}
+
+impl fmt::Display for Termination {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Termination::Checkmate => "checkmate",
+            Termination::Stalemate => "stalemate",
+            Termination::InsufficientMaterial => "insufficient material",
+            Termination::FivefoldRepetition => "fivefold repetition",
+            Termination::SeventyFiveMoveRule => "seventy-five move rule",
+            Termination::ThreefoldRepetition => "threefold repetition",
+            Termination::FiftyMoveRule => "fifty move rule",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub result: GameResult,
+    pub termination: Termination,
+}
+
+// a State plus everything needed for the rules that depend on history
+#[derive(Clone)]
+pub struct Game {
+    start: State,
+    state: State,
+    moves: Vec<Move>,
+    undos: Vec<Undo>,
+    // hashes[i] is the position after i moves, so hashes[0] is the start position
+    hashes: Vec<u64>,
+}
+
+impl Default for Game {
+    fn default() -> Game {
+        Game::new(State::default())
+    }
+}
+
+impl Game {
+    pub fn new(start: State) -> Game {
+        Game {
+            hashes: vec![start.hash()],
+            state: start.clone(),
+            start,
+            moves: Vec::new(),
+            undos: Vec::new(),
+        }
+    }
+
+    pub fn from_fen(fen: &str) -> Result<Game, ChessError> {
+        Ok(Game::new(State::from_fen(fen)?))
+    }
+
+    pub fn start(&self) -> &State {
+        &self.start
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn moves(&self) -> &[Move] {
+        &self.moves
+    }
+
+    pub fn hashes(&self) -> &[u64] {
+        &self.hashes
+    }
+
+    pub fn legal_moves(&self) -> Vec<Move> {
+        self.state.legal_moves()
+    }
+
+    // the move isn't checked, use play for untrusted input
+    pub fn make_move(&mut self, mv: Move) {
+        self.undos.push(self.state.make_move(mv));
+        self.moves.push(mv);
+        self.hashes.push(self.state.hash());
+    }
+
+    pub fn play(&mut self, mv: Move) -> Result<(), ChessError> {
+        if !self.legal_moves().contains(&mv) {
+            return Err(ChessError::IllegalMove(mv.to_string()));
+        }
+        self.make_move(mv);
+        Ok(())
+    }
+
+    pub fn play_san(&mut self, san: &str) -> Result<Move, ChessError> {
+        let mv = self.state.parse_san(san)?;
+        self.make_move(mv);
+        Ok(mv)
+    }
+
+    pub fn play_uci(&mut self, uci: &str, chess960: bool) -> Result<Move, ChessError> {
+        let mv = self.state.parse_uci_move(uci, chess960)?;
+        self.play(mv)?;
+        Ok(mv)
+    }
+
+    pub fn undo(&mut self) -> Option<Move> {
+        let mv = self.moves.pop()?;
+        self.state.unmake_move(mv, self.undos.pop().unwrap());
+        self.hashes.pop();
+        Some(mv)
+    }
+
+    // how often the current position has occurred, including now; only positions since
+    // the last capture or pawn move can repeat, and only with the same side to move
+    pub fn repetition_count(&self) -> usize {
+        let current = *self.hashes.last().unwrap();
+        let window = (self.state.halfmove_clock() as usize).min(self.hashes.len() - 1);
+        self.hashes[self.hashes.len() - 1 - window..]
+            .iter()
+            .rev()
+            .step_by(2)
+            .filter(|&&hash| hash == current)
+            .count()
+    }
+
+    pub fn is_threefold_repetition(&self) -> bool {
+        self.repetition_count() >= 3
+    }
+
+    pub fn is_fivefold_repetition(&self) -> bool {
+        self.repetition_count() >= 5
+    }
+
+    pub fn is_fifty_moves(&self) -> bool {
+        self.state.halfmove_clock() >= 100
+    }
+
+    pub fn is_seventy_five_moves(&self) -> bool {
+        self.state.halfmove_clock() >= 150
+    }
+
+    pub fn is_checkmate(&self) -> bool {
+        self.state.in_check() && self.legal_moves().is_empty()
+    }
+
+    pub fn is_stalemate(&self) -> bool {
+        !self.state.in_check() && self.legal_moves().is_empty()
+    }
+
+    // neither side can ever mate: bare kings, a single minor piece,
+    // or only bishops that all stand on the same color of square
+    pub fn is_insufficient_material(&self) -> bool {
+        let board = &self.state.board;
+        let heavy = board.by_piece_type(PieceType::Pawn)
+            | board.by_piece_type(PieceType::Rook)
+            | board.by_piece_type(PieceType::Queen);
+        if heavy != EMPTY {
+            return false;
+        }
+
+        let knights = board.by_piece_type(PieceType::Knight);
+        let bishops = board.by_piece_type(PieceType::Bishop);
+        let minors = (knights | bishops).count();
+        minors <= 1
+            || (knights == EMPTY
+                && (bishops & LIGHT_SQUARES == EMPTY || bishops & DARK_SQUARES == EMPTY))
+    }
+
+    // checkmate wins over the move-count rules when both happen on the same move
+    pub fn outcome(&self) -> Option<Outcome> {
+        let draw = |termination| {
+            Some(Outcome {
+                result: GameResult::Draw,
+                termination,
+            })
+        };
+
+        if self.legal_moves().is_empty() {
+            return if self.state.in_check() {
+                Some(Outcome {
+                    result: GameResult::win_for(!self.state.side_to_move()),
+                    termination: Termination::Checkmate,
+                })
+            } else {
+                draw(Termination::Stalemate)
+            };
+        }
+
+        if self.is_insufficient_material() {
+            draw(Termination::InsufficientMaterial)
+        } else if self.is_fivefold_repetition() {
+            draw(Termination::FivefoldRepetition)
+        } else if self.is_seventy_five_moves() {
+            draw(Termination::SeventyFiveMoveRule)
+        } else if self.is_threefold_repetition() {
+            draw(Termination::ThreefoldRepetition)
+        } else if self.is_fifty_moves() {
+            draw(Termination::FiftyMoveRule)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(game: &mut Game, moves: &str) {
+        for san in moves.split_whitespace() {
+            game.play_san(san).unwrap();
+        }
+    }
+
+    fn draw(termination: Termination) -> Option<Outcome> {
+        Some(Outcome {
+            result: GameResult::Draw,
+            termination,
+        })
+    }
+
+    #[test]
+    fn repetitions() {
+        let mut game = Game::default();
+        play(&mut game, "Nf3 Nf6 Ng1 Ng8");
+        assert_eq!(game.repetition_count(), 2);
+        assert_eq!(game.outcome(), None);
+        play(&mut game, "Nf3 Nf6 Ng1 Ng8");
+        assert!(game.is_threefold_repetition());
+        assert_eq!(game.outcome(), draw(Termination::ThreefoldRepetition));
+        play(&mut game, "Nf3 Nf6 Ng1 Ng8 Nf3 Nf6 Ng1 Ng8");
+        assert!(game.is_fivefold_repetition());
+        assert_eq!(game.outcome(), draw(Termination::FivefoldRepetition));
+        assert_eq!(game.hashes().len(), 17);
+
+        // the same pieces with different castling rights are a different position
+        let mut game = Game::from_fen("r3k3/8/8/8/8/8/8/4K2R w Kq - 0 1").unwrap();
+        play(&mut game, "Rh2 Ra7 Rh1 Ra8");
+        assert_eq!(game.repetition_count(), 1);
+        play(&mut game, "Rh2 Ra7 Rh1 Ra8");
+        assert_eq!(game.repetition_count(), 2);
+    }
+
+    #[test]
+    fn insufficient_material() {
+        for (fen, insufficient) in [
+            ("4k3/8/8/8/8/8/8/4K3 w - - 0 1", true),
+            ("4k3/8/8/8/8/8/8/1N2K3 w - - 0 1", true),
+            ("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1", true),
+            // bishops on the same colour, on one side or both
+            ("4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1", true),
+            ("4k3/8/8/8/8/8/8/2B1KB2 w - - 0 1", false),
+            ("4k3/8/8/8/8/8/3B4/2B1K3 w - - 0 1", true),
+            ("2b1k3/8/8/8/8/8/8/2B1K3 w - - 0 1", false),
+            ("4k3/8/8/8/8/8/8/1NN1K3 w - - 0 1", false),
+            ("4kn2/8/8/8/8/8/8/2B1K3 w - - 0 1", false),
+            ("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1", false),
+            ("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", false),
+        ] {
+            let game = Game::from_fen(fen).unwrap();
+            assert_eq!(game.is_insufficient_material(), insufficient, "{}", fen);
+        }
+        let bare = Game::from_fen("4k3/8/8/8/8/8/8/1N2K3 w - - 0 1").unwrap();
+        assert_eq!(bare.outcome(), draw(Termination::InsufficientMaterial));
+    }
+
+    #[test]
+    fn move_rules() {
+        let mut game = Game::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 99 80").unwrap();
+        assert!(!game.is_fifty_moves());
+        play(&mut game, "Ra2");
+        assert!(game.is_fifty_moves());
+        assert_eq!(game.outcome(), draw(Termination::FiftyMoveRule));
+        // a pawn move or capture resets the count
+        let mut game = Game::from_fen("4k3/8/8/8/8/8/4P3/R3K3 w - - 99 80").unwrap();
+        play(&mut game, "e4");
+        assert_eq!(game.outcome(), None);
+
+        let mut game = Game::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 149 100").unwrap();
+        play(&mut game, "Ra2");
+        assert!(game.is_seventy_five_moves());
+        assert_eq!(game.outcome(), draw(Termination::SeventyFiveMoveRule));
+
+        // mate on the 75th move still counts
+        let mut game = Game::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 149 100").unwrap();
+        play(&mut game, "Ra8#");
+        assert!(game.is_checkmate());
+        assert_eq!(
+            game.outcome().map(|outcome| outcome.termination),
+            Some(Termination::Checkmate)
+        );
+    }
+
+    #[test]
+    fn mate_and_stalemate() {
+        let mut game = Game::default();
+        play(&mut game, "f3 e5 g4 Qh4#");
+        assert!(game.is_checkmate());
+        assert_eq!(
+            game.outcome(),
+            Some(Outcome {
+                result: GameResult::BlackWins,
+                termination: Termination::Checkmate,
+            })
+        );
+
+        let game = Game::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        assert!(game.is_stalemate());
+        assert!(!game.is_checkmate());
+        assert_eq!(game.outcome(), draw(Termination::Stalemate));
+    }
+
+    #[test]
+    fn undo() {
+        let mut game = Game::default();
+        assert_eq!(game.undo(), None);
+        let e4 = game.play_uci("e2e4", false).unwrap();
+        play(&mut game, "e5 Nf3");
+        assert!(game.play_uci("e1e3", false).is_err());
+        assert_eq!(game.moves().len(), 3);
+
+        let after_e4 = {
+            let mut state = State::default();
+            state.make_move(e4);
+            state
+        };
+
+        let nf3 = game.moves()[2];
+        assert_eq!(game.undo(), Some(nf3));
+        assert!(game.undo().is_some());
+        assert_eq!(game.moves(), [e4]);
+        assert_eq!(game.hashes().len(), 2);
+        assert_eq!(game.state().to_fen(), after_e4.to_fen());
+        assert_eq!(game.state().hash(), after_e4.hash());
+        assert_eq!(game.undo(), Some(e4));
+        assert_eq!(game.state().to_fen(), game.start().to_fen());
+        assert_eq!(game.hashes(), [game.start().hash()]);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::bitboard::*;
+use crate::direction::*;
+use crate::square::*;
+
+// one step from `square` in `direction`, None when it leaves the board
+const fn step(square: usize, direction: usize) -> Option<usize> {
+    let (dr, df) = DIRECTIONS[direction].delta();
+    let rank = (square / 8) as i8 + dr;
+    let file = (square % 8) as i8 + df;
+    if rank >= 0 && rank < 8 && file >= 0 && file < 8 {
+        Some((rank * 8 + file) as usize)
+    } else {
+        None
+    }
+}
+
+// every square from `square` to the edge of the board, excluding `square` itself
+const fn ray_table() -> [[BitBoard; SQUARE_COUNT]; DIRECTION_COUNT] {
+    let mut table = [[EMPTY; SQUARE_COUNT]; DIRECTION_COUNT];
+    let mut direction = 0;
+    while direction < DIRECTION_COUNT {
+        let mut square = 0;
+        while square < SQUARE_COUNT {
+            let mut bits = 0;
+            let mut current = square;
+            while let Some(next) = step(current, direction) {
+                bits |= 1 << next;
+                current = next;
+            }
+            table[direction][square] = BitBoard(bits);
+            square += 1;
+        }
+        direction += 1;
+    }
+    table
+}
+
+// walks from a to every square it's aligned with, so pairs that aren't aligned stay empty
+const fn pair_table(line: bool) -> [[BitBoard; SQUARE_COUNT]; SQUARE_COUNT] {
+    let mut table = [[EMPTY; SQUARE_COUNT]; SQUARE_COUNT];
+    let mut a = 0;
+    while a < SQUARE_COUNT {
+        let mut direction = 0;
+        while direction < DIRECTION_COUNT {
+            let full = RAYS[direction][a].0 | RAYS[direction ^ 1][a].0 | 1 << a;
+            let mut between = 0;
+            let mut current = a;
+            while let Some(b) = step(current, direction) {
+                table[a][b] = BitBoard(if line { full } else { between });
+                between |= 1 << b;
+                current = b;
+            }
+            direction += 1;
+        }
+        a += 1;
+    }
+    table
+}
+
+const fn distance_table(manhattan: bool) -> [[u8; SQUARE_COUNT]; SQUARE_COUNT] {
+    let mut table = [[0; SQUARE_COUNT]; SQUARE_COUNT];
+    let mut a = 0;
+    while a < SQUARE_COUNT {
+        let mut b = 0;
+        while b < SQUARE_COUNT {
+            let ranks = (a / 8).abs_diff(b / 8) as u8;
+            let files = (a % 8).abs_diff(b % 8) as u8;
+            table[a][b] = if manhattan {
+                ranks + files
+            } else if ranks > files {
+                ranks
+            } else {
+                files
+            };
+            b += 1;
+        }
+        a += 1;
+    }
+    table
+}
+
+static RAYS: [[BitBoard; SQUARE_COUNT]; DIRECTION_COUNT] = ray_table();
+static BETWEEN: [[BitBoard; SQUARE_COUNT]; SQUARE_COUNT] = pair_table(false);
+static LINE: [[BitBoard; SQUARE_COUNT]; SQUARE_COUNT] = pair_table(true);
+static DISTANCE: [[u8; SQUARE_COUNT]; SQUARE_COUNT] = distance_table(false);
+static MANHATTAN_DISTANCE: [[u8; SQUARE_COUNT]; SQUARE_COUNT] = distance_table(true);
+
+pub fn ray(square: Square, direction: Direction) -> BitBoard {
+    RAYS[direction.index()][square.index()]
+}
+
+// squares strictly between a and b, empty unless they share a rank, file or diagonal
+pub fn between(a: Square, b: Square) -> BitBoard {
+    BETWEEN[a.index()][b.index()]
+}
+
+// the whole rank, file or diagonal through a and b, empty unless they're aligned
+pub fn line(a: Square, b: Square) -> BitBoard {
+    LINE[a.index()][b.index()]
+}
+
+pub fn aligned(a: Square, b: Square, c: Square) -> bool {
+    line(a, b) & BitBoard::from_square(c) != EMPTY
+}
+
+// the a1-h8 direction diagonal through the square
+pub fn diagonal(square: Square) -> BitBoard {
+    ray(square, Direction::UpRight)
+        | ray(square, Direction::DownLeft)
+        | BitBoard::from_square(square)
+}
+
+// the a8-h1 direction diagonal through the square
+pub fn anti_diagonal(square: Square) -> BitBoard {
+    ray(square, Direction::UpLeft)
+        | ray(square, Direction::DownRight)
+        | BitBoard::from_square(square)
+}
+
+// king moves needed to get from a to b
+pub fn distance(a: Square, b: Square) -> u8 {
+    DISTANCE[a.index()][b.index()]
+}
+
+pub fn manhattan_distance(a: Square, b: Square) -> u8 {
+    MANHATTAN_DISTANCE[a.index()][b.index()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::Color;
+
+    #[test]
+    fn between_and_line() {
+        assert_eq!(
+            between(Square::A1, Square::D4),
+            BitBoard::from_square(Square::B2) | BitBoard::from_square(Square::C3)
+        );
+        assert_eq!(between(Square::E1, Square::E2), EMPTY);
+        assert_eq!(between(Square::A1, Square::B3), EMPTY);
+        assert_eq!(line(Square::A1, Square::B3), EMPTY);
+        assert_eq!(line(Square::C3, Square::E5), diagonal(Square::H8));
+        assert_eq!(line(Square::H1, Square::H5).count(), 8);
+        assert!(aligned(Square::B7, Square::G2, Square::D5));
+        assert!(!aligned(Square::B7, Square::G2, Square::D4));
+    }
+
+    #[test]
+    fn lines_are_symmetric() {
+        for a in (0..64).map(|i| Square::from_index(i).unwrap()) {
+            for b in (0..64).map(|i| Square::from_index(i).unwrap()) {
+                assert_eq!(between(a, b), between(b, a));
+                assert_eq!(line(a, b), line(b, a));
+                assert_eq!(distance(a, b), distance(b, a));
+            }
+        }
+    }
+
+    #[test]
+    fn distances_and_colors() {
+        assert_eq!(distance(Square::A1, Square::H8), 7);
+        assert_eq!(distance(Square::B1, Square::C3), 2);
+        assert_eq!(manhattan_distance(Square::A1, Square::H8), 14);
+        assert_eq!(anti_diagonal(Square::A8), BitBoard(0x0102040810204080));
+        assert_eq!(Square::A1.color(), Color::Black);
+        assert_eq!(Square::H1.color(), Color::White);
+        assert_eq!(Square::D1.color(), Color::White);
+    }
+}
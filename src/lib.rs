@@ -0,0 +1,45 @@
+// everything but the command line front-end, which lives in main.rs
+pub mod annotate;
+pub mod attacks;
+pub mod bitbase;
+pub mod bitboard;
+pub mod bitboard_moves;
+pub mod chess960;
+pub mod cli;
+pub mod datagen;
+pub mod direction;
+pub mod display;
+pub mod endgame;
+pub mod epd;
+pub mod errors;
+pub mod eval;
+pub mod fen;
+pub mod file;
+pub mod game;
+pub mod geometry;
+pub mod mate;
+pub mod movegen;
+pub mod moves;
+pub mod nnue;
+pub mod packed;
+pub mod pgn;
+pub mod piece;
+pub mod problems;
+pub mod rank;
+pub mod san;
+pub mod search;
+#[cfg(feature = "serde")]
+pub mod serialization;
+pub mod sprt;
+pub mod square;
+pub mod state;
+pub mod svg;
+pub mod syzygy;
+pub mod tbgen;
+pub mod testsuite;
+pub mod tournament;
+pub mod uci;
+pub mod uci_client;
+pub mod utils;
+pub mod xboard;
+pub mod zobrist;
@@ -1,30 +1,44 @@
-mod bitboard;
-mod bitboard_moves;
-mod direction;
-mod errors;
-mod fen;
-mod file;
-mod piece;
-mod rank;
-mod square;
-mod state;
-mod utils;
+use chesstionable::{
+    annotate, datagen, mate, problems, svg, tbgen, testsuite, tournament, uci, xboard,
+};
+use std::env;
+use std::io::{self, BufRead};
+use std::iter;
+use std::process;
 
-use bitboard::*;
-use bitboard_moves::*;
-use square::*;
-use state::State;
-
-// this is turning into enterprise code very quickly :kekwait:
+// talks UCI unless the GUI's first command is xboard
+fn engine() {
+    let mut lines = io::stdin().lock().lines().map_while(Result::ok);
+    let Some(first) = lines.by_ref().find(|line| !line.trim().is_empty()) else {
+        return;
+    };
+    let xboard = first.trim() == "xboard";
+    let lines = iter::once(first).chain(lines);
+    if xboard {
+        xboard::run(lines);
+    } else {
+        uci::run(lines);
+    }
+}
 
 fn main() {
-    // let fen = "8/5k2/3p4/1p1Pp2p/pP2Pp1P/P4P1K/8/8 b - - 99 50";
-    // let state = State::from_fen(fen).unwrap();
-    let state = State::default();
-    println!("{:?}", state);
-    println!("{:?}", state.to_fen()); // TODO: this is rotated 90 degrees lmao
-
-    let pext_table = PextTable::new();
-    let bb = pext_table.queen_moves(EMPTY, Square::F5);
-    println!("{}", bb);
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("testsuite") => testsuite::command(&args[1..]),
+        Some("tbgen") => tbgen::command(&args[1..]),
+        Some("datagen") => datagen::command(&args[1..]),
+        Some("match") => tournament::command(&args[1..]),
+        Some("mate") => mate::command(&args[1..]),
+        Some("problem") => problems::command(&args[1..]),
+        Some("annotate") => annotate::command(&args[1..]),
+        Some("svg") => svg::command(&args[1..]),
+        Some(command) => {
+            eprintln!(
+                "unknown command {}, expected testsuite, tbgen, datagen, match, mate, problem, annotate, svg or nothing for UCI",
+                command
+            );
+            process::exit(1);
+        }
+        None => engine(),
+    }
 }
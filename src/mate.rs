@@ -0,0 +1,656 @@
+// a solver for "mate in N": proves that the side to move, the attacker, mates within N moves
+// against every defence, finding all the first moves that do, or shows there is no such mate.
+// The default is a depth-first search on the yes/no question with a table of proven and
+// disproven positions; proof-number search is the alternative for deep, narrow problems.
+use crate::cli::{exit_on_error, flag_value, has_flag, parse_flag, parse_value, usage};
+use crate::errors::ChessError;
+use crate::moves::{Move, MoveKind};
+use crate::state::State;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// proof and disproof numbers of a settled node
+const INFINITE: u32 = u32::MAX;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Method {
+    AlphaBeta,
+    ProofNumber,
+}
+
+impl FromStr for Method {
+    type Err = ChessError;
+
+    fn from_str(s: &str) -> Result<Method, ChessError> {
+        match s {
+            "alphabeta" => Ok(Method::AlphaBeta),
+            "pns" => Ok(Method::ProofNumber),
+            _ => Err(ChessError::ParseError(s.to_string(), "mate search method")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MateSettings {
+    // the attacker's moves, counting the mating one
+    pub moves: u32,
+    // only checking moves for the attacker: much faster, but blind to quiet moves
+    pub checks_only: bool,
+    pub method: Method,
+    pub max_nodes: Option<u64>,
+    pub movetime: Option<Duration>,
+}
+
+impl Default for MateSettings {
+    fn default() -> MateSettings {
+        MateSettings {
+            moves: 2,
+            checks_only: false,
+            method: Method::AlphaBeta,
+            max_nodes: None,
+            movetime: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MateTree {
+    pub mv: Move,
+    // every defence with the attacker's continuation, empty when `mv` mates
+    pub defences: Vec<(Move, MateTree)>,
+}
+
+impl MateTree {
+    // the attacker's moves to mate against the most stubborn defence
+    pub fn moves(&self) -> u32 {
+        1 + self
+            .defences
+            .iter()
+            .map(|(_, tree)| tree.moves())
+            .max()
+            .unwrap_or(0)
+    }
+
+    // the move, the most stubborn defence, the answer to it and so on
+    pub fn main_line(&self) -> Vec<Move> {
+        let mut line = vec![self.mv];
+        let stubborn = self.defences.iter().max_by_key(|(_, tree)| tree.moves());
+        if let Some((defence, tree)) = stubborn {
+            line.push(*defence);
+            line.extend(tree.main_line());
+        }
+        line
+    }
+
+    // the solution in the usual problem layout, the key marked with "!" and every
+    // defence on a line of its own, indented under the move it answers
+    pub fn format(&self, state: &State) -> String {
+        let mut text = String::new();
+        self.write(state, 0, true, &mut text);
+        text
+    }
+
+    fn write(&self, state: &State, indent: usize, key: bool, text: &mut String) {
+        let mut state = state.clone();
+        let number = state.ply() / 2 + 1;
+        let _ = write!(text, "{:indent$}{}. {}", "", number, state.to_san(self.mv));
+        if key {
+            text.push('!');
+        }
+        text.push('\n');
+        state.make_move(self.mv);
+        for (defence, tree) in &self.defences {
+            let _ = writeln!(
+                text,
+                "{:indent$}  {}... {}",
+                "",
+                number,
+                state.to_san(*defence)
+            );
+            let undo = state.make_move(*defence);
+            tree.write(&state, indent + 4, false, text);
+            state.unmake_move(*defence, undo);
+        }
+    }
+}
+
+pub struct MateResult {
+    // every first move that mates in time, quickest first
+    pub solutions: Vec<MateTree>,
+    pub nodes: u64,
+    // false when the node limit, the movetime or a stop cut the search short, so whatever
+    // wasn't found isn't disproven
+    pub complete: bool,
+}
+
+// a node of the proof-number search; attacker nodes need one child proven, defender nodes
+// all of them
+struct PnNode {
+    mv: Option<Move>,
+    parent: usize,
+    children: Vec<usize>,
+    attacker: bool,
+    // the attacker's moves left, counting the one that led to a defender node
+    moves: u32,
+    proof: u32,
+    disproof: u32,
+}
+
+pub struct MateSolver {
+    settings: MateSettings,
+    nodes: u64,
+    start: Instant,
+    aborted: bool,
+    stop: Arc<AtomicBool>,
+    // for attacker positions: the fewest moves known to mate, and the most known not to
+    table: HashMap<u64, (u32, u32)>,
+}
+
+impl MateSolver {
+    pub fn new(settings: MateSettings) -> MateSolver {
+        MateSolver {
+            settings,
+            nodes: 0,
+            start: Instant::now(),
+            aborted: false,
+            stop: Arc::new(AtomicBool::new(false)),
+            table: HashMap::new(),
+        }
+    }
+
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    // to share a stop flag with a regular search
+    pub fn set_stop_flag(&mut self, stop: Arc<AtomicBool>) {
+        self.stop = stop;
+    }
+
+    // the limits count from here, for callers asking their own questions with mates()
+    pub fn restart(&mut self) {
+        self.nodes = 0;
+        self.start = Instant::now();
+        self.aborted = false;
+    }
+
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+
+    // set once the node limit, the movetime or a stop cut the search short
+    pub fn aborted(&self) -> bool {
+        self.aborted
+    }
+
+    // whether the side to move mates within `moves`; false from then on once aborted
+    pub fn mates(&mut self, state: &mut State, moves: u32) -> bool {
+        self.attack(state, moves)
+    }
+
+    pub fn solve(&mut self, state: &State) -> MateResult {
+        self.restart();
+        let moves = self.settings.moves;
+        let mut state = state.clone();
+        let mut solutions = Vec::new();
+        for mv in self.attacker_moves(&mut state, moves) {
+            let undo = state.make_move(mv);
+            let defences = match self.settings.method {
+                Method::AlphaBeta => {
+                    if self.defend(&mut state, moves) {
+                        self.defence_tree(&mut state, moves)
+                    } else {
+                        None
+                    }
+                }
+                Method::ProofNumber => self.prove(&state, moves),
+            };
+            state.unmake_move(mv, undo);
+            if let Some(defences) = defences {
+                solutions.push(MateTree { mv, defences });
+            }
+            if self.aborted {
+                break;
+            }
+        }
+        solutions.sort_by_key(MateTree::moves);
+        MateResult {
+            solutions,
+            nodes: self.nodes,
+            complete: !self.aborted,
+        }
+    }
+
+    // counts a node, true once the search has to give up
+    fn tick(&mut self) -> bool {
+        self.nodes += 1;
+        let out_of_time = || {
+            self.stop.load(Ordering::Relaxed)
+                || (self.settings.movetime).is_some_and(|time| self.start.elapsed() >= time)
+        };
+        if self.settings.max_nodes.is_some_and(|max| self.nodes > max)
+            || (self.nodes.is_multiple_of(1024) && out_of_time())
+        {
+            self.aborted = true;
+        }
+        self.aborted
+    }
+
+    // the attacker's candidates, checks first and then captures; only checks can mate
+    // with the last move
+    fn attacker_moves(&self, state: &mut State, moves: u32) -> Vec<Move> {
+        let checks_only = self.settings.checks_only || moves == 1;
+        let mut candidates: Vec<(u8, Move)> = Vec::new();
+        for mv in state.legal_moves() {
+            let capture = mv.kind == MoveKind::EnPassant || state.board.piece(mv.to).is_some();
+            let undo = state.make_move(mv);
+            let check = state.in_check();
+            state.unmake_move(mv, undo);
+            if check || !checks_only {
+                candidates.push((if check { 0 } else { 1 + !capture as u8 }, mv));
+            }
+        }
+        candidates.sort_by_key(|&(order, _)| order);
+        candidates.into_iter().map(|(_, mv)| mv).collect()
+    }
+
+    // whether the attacker, to move, mates within `moves`
+    fn attack(&mut self, state: &mut State, moves: u32) -> bool {
+        if moves == 0 || self.tick() {
+            return false;
+        }
+        let hash = state.hash();
+        if let Some(&(proven, disproven)) = self.table.get(&hash) {
+            if proven <= moves {
+                return true;
+            } else if disproven >= moves {
+                return false;
+            }
+        }
+
+        let mut mates = false;
+        for mv in self.attacker_moves(state, moves) {
+            let undo = state.make_move(mv);
+            mates = self.defend(state, moves);
+            state.unmake_move(mv, undo);
+            if mates || self.aborted {
+                break;
+            }
+        }
+        if !self.aborted {
+            let entry = self.table.entry(hash).or_insert((INFINITE, 0));
+            if mates {
+                entry.0 = entry.0.min(moves);
+            } else {
+                entry.1 = entry.1.max(moves);
+            }
+        }
+        mates
+    }
+
+    // whether every defence loses, after an attacker's move that used up one of `moves`
+    fn defend(&mut self, state: &mut State, moves: u32) -> bool {
+        if self.tick() {
+            return false;
+        }
+        let defences = state.legal_moves();
+        if defences.is_empty() {
+            return state.in_check();
+        } else if moves == 1 {
+            return false;
+        }
+        for defence in defences {
+            let undo = state.make_move(defence);
+            let mated = self.attack(state, moves - 1);
+            state.unmake_move(defence, undo);
+            if !mated {
+                return false;
+            }
+        }
+        !self.aborted
+    }
+
+    // the quickest answer to every defence, once the attacker's last move is proven; None
+    // when the search gives up before every defence has one, rather than a partial tree
+    fn defence_tree(&mut self, state: &mut State, moves: u32) -> Option<Vec<(Move, MateTree)>> {
+        let mut defences = Vec::new();
+        for defence in state.legal_moves() {
+            let undo = state.make_move(defence);
+            let answer = (1..moves).find_map(|left| self.continuation(state, left));
+            state.unmake_move(defence, undo);
+            defences.push((defence, answer?));
+        }
+        Some(defences)
+    }
+
+    fn continuation(&mut self, state: &mut State, moves: u32) -> Option<MateTree> {
+        if !self.attack(state, moves) {
+            return None;
+        }
+        for mv in self.attacker_moves(state, moves) {
+            let undo = state.make_move(mv);
+            let tree = if self.defend(state, moves) {
+                self.defence_tree(state, moves)
+                    .map(|defences| MateTree { mv, defences })
+            } else {
+                None
+            };
+            state.unmake_move(mv, undo);
+            if tree.is_some() || self.aborted {
+                return tree;
+            }
+        }
+        None
+    }
+
+    // proof-number search from a defender to move, after an attacker's move that used up
+    // one of `moves`; the proven tree of defences, or None when disproven or given up
+    fn prove(&mut self, state: &State, moves: u32) -> Option<Vec<(Move, MateTree)>> {
+        let mut root = PnNode {
+            mv: None,
+            parent: 0,
+            children: Vec::new(),
+            attacker: false,
+            moves,
+            proof: 1,
+            disproof: 1,
+        };
+        (root.proof, root.disproof) = self.evaluate(&mut state.clone(), false, moves);
+        let mut tree = vec![root];
+
+        while tree[0].proof != 0 && tree[0].disproof != 0 {
+            if self.tick() {
+                return None;
+            }
+            // the most proving node: the easiest child to prove at attacker nodes, the
+            // easiest to disprove at defender ones
+            let mut position = state.clone();
+            let mut index = 0;
+            while !tree[index].children.is_empty() {
+                let children = &tree[index].children;
+                index = if tree[index].attacker {
+                    *children
+                        .iter()
+                        .min_by_key(|&&child| tree[child].proof)
+                        .unwrap()
+                } else {
+                    *children
+                        .iter()
+                        .min_by_key(|&&child| tree[child].disproof)
+                        .unwrap()
+                };
+                position.make_move(tree[index].mv.unwrap());
+            }
+            self.expand(&mut tree, index, &mut position);
+
+            loop {
+                let (proof, disproof) = numbers(&tree, index);
+                tree[index].proof = proof;
+                tree[index].disproof = disproof;
+                if index == 0 {
+                    break;
+                }
+                index = tree[index].parent;
+            }
+        }
+        (tree[0].proof == 0).then(|| proven_defences(&tree, 0))
+    }
+
+    fn expand(&mut self, tree: &mut Vec<PnNode>, index: usize, state: &mut State) {
+        let (attacker, moves) = (tree[index].attacker, tree[index].moves);
+        let children = if attacker {
+            self.attacker_moves(state, moves)
+        } else {
+            state.legal_moves()
+        };
+        for mv in children {
+            let undo = state.make_move(mv);
+            let child_moves = if attacker { moves } else { moves - 1 };
+            let (proof, disproof) = self.evaluate(state, !attacker, child_moves);
+            state.unmake_move(mv, undo);
+            tree.push(PnNode {
+                mv: Some(mv),
+                parent: index,
+                children: Vec::new(),
+                attacker: !attacker,
+                moves: child_moves,
+                proof,
+                disproof,
+            });
+            let child = tree.len() - 1;
+            tree[index].children.push(child);
+        }
+    }
+
+    // initial numbers for a new node, exact when it's already decided; unsettled ones
+    // start out as hard as the moves they have
+    fn evaluate(&mut self, state: &mut State, attacker: bool, moves: u32) -> (u32, u32) {
+        self.nodes += 1;
+        if attacker {
+            let candidates = self.attacker_moves(state, moves).len() as u32;
+            if moves == 0 || candidates == 0 {
+                return (INFINITE, 0);
+            }
+            return (1, candidates);
+        }
+        let defences = state.legal_moves().len() as u32;
+        match (defences, state.in_check()) {
+            (0, true) => (0, INFINITE),
+            (0, false) => (INFINITE, 0),
+            _ if moves <= 1 => (INFINITE, 0),
+            _ => (defences, 1),
+        }
+    }
+}
+
+// the numbers of an expanded node from its children
+fn numbers(tree: &[PnNode], index: usize) -> (u32, u32) {
+    let node = &tree[index];
+    if node.children.is_empty() {
+        return (node.proof, node.disproof);
+    }
+    let proofs = node.children.iter().map(|&child| tree[child].proof);
+    let disproofs = node.children.iter().map(|&child| tree[child].disproof);
+    if node.attacker {
+        let disproof = disproofs.fold(0, u32::saturating_add);
+        (proofs.min().unwrap(), disproof)
+    } else {
+        let proof = proofs.fold(0, u32::saturating_add);
+        (proof, disproofs.min().unwrap())
+    }
+}
+
+fn proven_defences(tree: &[PnNode], index: usize) -> Vec<(Move, MateTree)> {
+    let mut defences = Vec::new();
+    for &defence in &tree[index].children {
+        let answer = tree[defence]
+            .children
+            .iter()
+            .copied()
+            .find(|&answer| tree[answer].proof == 0)
+            .unwrap();
+        let tree_of_answer = MateTree {
+            mv: tree[answer].mv.unwrap(),
+            defences: proven_defences(tree, answer),
+        };
+        defences.push((tree[defence].mv.unwrap(), tree_of_answer));
+    }
+    defences
+}
+
+// mate <fen> <moves> [--checks-only] [--method alphabeta|pns] [--nodes n] [--movetime ms]
+pub fn command(args: &[String]) {
+    let [fen, moves, ..] = args else {
+        usage(
+            "mate <fen> <moves> [--checks-only] [--method alphabeta|pns] [--nodes n] \
+             [--movetime ms]",
+        );
+    };
+    let state = exit_on_error(State::from_fen(fen));
+    let settings = MateSettings {
+        moves: parse_value("moves", moves),
+        checks_only: has_flag(args, "--checks-only"),
+        method: match flag_value(args, "--method") {
+            Some(method) => exit_on_error(method.parse()),
+            None => Method::AlphaBeta,
+        },
+        max_nodes: parse_flag(args, "--nodes"),
+        movetime: parse_flag(args, "--movetime").map(Duration::from_millis),
+    };
+
+    let start = Instant::now();
+    let result = MateSolver::new(settings.clone()).solve(&state);
+    for tree in &result.solutions {
+        print!("{}", tree.format(&state));
+    }
+    let verdict = match (result.solutions.len(), result.complete) {
+        (0, true) => format!("no mate in {}", settings.moves),
+        (0, false) => format!("no mate in {} found within the limits", settings.moves),
+        (1, _) => format!("mate in {}", result.solutions[0].moves()),
+        (keys, _) => format!("mate in {}, {} keys", result.solutions[0].moves(), keys),
+    };
+    println!(
+        "{}, {} nodes in {:.2}s",
+        verdict,
+        result.nodes,
+        start.elapsed().as_secs_f64()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solve(fen: &str, moves: u32, checks_only: bool, method: Method) -> MateResult {
+        let state = State::from_fen(fen).unwrap();
+        let mut solver = MateSolver::new(MateSettings {
+            moves,
+            checks_only,
+            method,
+            ..MateSettings::default()
+        });
+        solver.solve(&state)
+    }
+
+    fn keys(fen: &str, result: &MateResult) -> Vec<String> {
+        let state = State::from_fen(fen).unwrap();
+        result
+            .solutions
+            .iter()
+            .map(|tree| state.to_san(tree.mv))
+            .collect()
+    }
+
+    // Qd8+ Bxd8 Re8#
+    const SACRIFICE: &str = "r1b2k1r/ppp1bppp/8/1B1Q4/5q2/2P5/PPP2PPP/R3R1K1 w - - 1 1";
+
+    #[test]
+    fn proves_mates() {
+        for method in [Method::AlphaBeta, Method::ProofNumber] {
+            for checks_only in [false, true] {
+                let result = solve(SACRIFICE, 2, checks_only, method);
+                assert!(result.complete);
+                assert_eq!(keys(SACRIFICE, &result), ["Qd8+"]);
+                let tree = &result.solutions[0];
+                assert_eq!(tree.moves(), 2);
+                assert_eq!(tree.defences.len(), 1);
+                assert_eq!(tree.main_line().len(), 3);
+            }
+            // there's no mate in one, and the table doesn't mix up lengths
+            let result = solve(SACRIFICE, 1, false, method);
+            assert!(result.complete && result.solutions.is_empty());
+        }
+    }
+
+    #[test]
+    fn finds_every_solution() {
+        // both rooks mate on the back rank, and the king can't
+        let fen = "6k1/5ppp/8/8/8/8/5PPP/R3R1K1 w - - 0 1";
+        let result = solve(fen, 1, false, Method::AlphaBeta);
+        assert_eq!(keys(fen, &result), ["Ra8#", "Re8#"]);
+        let pns = solve(fen, 1, false, Method::ProofNumber);
+        assert_eq!(keys(fen, &pns), ["Ra8#", "Re8#"]);
+        // a longer limit keeps the quick mates and adds whatever else mates in two
+        let longer = solve(fen, 2, false, Method::AlphaBeta);
+        assert_eq!(keys(fen, &longer)[..2], ["Ra8#", "Re8#"]);
+        assert!(longer.solutions[2..].iter().all(|tree| tree.moves() == 2));
+    }
+
+    #[test]
+    fn quiet_keys_and_limits() {
+        // Morphy's problem: 1. Ra6! bxa6 2. b7#, a quiet key that checks alone misses
+        let fen = "kbK5/pp6/1P6/8/8/8/8/R7 w - - 0 1";
+        let full = solve(fen, 2, false, Method::AlphaBeta);
+        assert!(full.complete);
+        assert_eq!(keys(fen, &full), ["Ra6"]);
+        let pns = solve(fen, 2, false, Method::ProofNumber);
+        assert_eq!(keys(fen, &pns), ["Ra6"]);
+        let checks = solve(fen, 2, true, Method::AlphaBeta);
+        assert!(checks.complete && checks.solutions.is_empty());
+
+        let state = State::from_fen(SACRIFICE).unwrap();
+        let mut solver = MateSolver::new(MateSettings {
+            moves: 3,
+            max_nodes: Some(10),
+            ..MateSettings::default()
+        });
+        assert!(!solver.solve(&state).complete);
+        let mut solver = MateSolver::new(MateSettings {
+            moves: 5,
+            movetime: Some(Duration::ZERO),
+            ..MateSettings::default()
+        });
+        let result = solver.solve(&state);
+        assert!(!result.complete && result.nodes < 2048);
+    }
+
+    // every legal defence has an answer, all the way down
+    fn answers_every_defence(state: &State, tree: &MateTree) -> bool {
+        let mut state = state.clone();
+        state.make_move(tree.mv);
+        let mut defences: Vec<Move> = tree.defences.iter().map(|&(defence, _)| defence).collect();
+        let mut legal = state.legal_moves();
+        defences.sort_by_key(|mv| mv.to_uci(false));
+        legal.sort_by_key(|mv| mv.to_uci(false));
+        defences == legal
+            && tree.defences.iter().all(|(defence, answer)| {
+                let mut state = state.clone();
+                state.make_move(*defence);
+                answers_every_defence(&state, answer)
+            })
+    }
+
+    #[test]
+    fn limits_never_leave_partial_trees() {
+        // two defences to Ra6, only one of them found before the budget runs out for some
+        // of these limits
+        let fen = "kbK5/pp6/1P6/8/8/8/8/R7 w - - 0 1";
+        let state = State::from_fen(fen).unwrap();
+        let nodes = solve(fen, 2, false, Method::AlphaBeta).nodes;
+        for max_nodes in 1..=nodes {
+            let mut solver = MateSolver::new(MateSettings {
+                moves: 2,
+                max_nodes: Some(max_nodes),
+                ..MateSettings::default()
+            });
+            let result = solver.solve(&state);
+            for tree in &result.solutions {
+                assert!(answers_every_defence(&state, tree), "{} nodes", max_nodes);
+            }
+        }
+    }
+
+    #[test]
+    fn formats_solutions() {
+        let result = solve(SACRIFICE, 2, false, Method::AlphaBeta);
+        let state = State::from_fen(SACRIFICE).unwrap();
+        assert_eq!(
+            result.solutions[0].format(&state),
+            "1. Qd8+!\n  1... Bxd8\n    2. Re8#\n"
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use crate::bitboard::*;
+use crate::bitboard_moves::*;
+use crate::moves::Move;
+use crate::piece::*;
+use crate::rank::Rank;
+use crate::square::Square;
+use crate::state::{CastlingSide, State};
+
+const PROMOTIONS: [PieceType; 4] = [
+    PieceType::Queen,
+    PieceType::Rook,
+    PieceType::Bishop,
+    PieceType::Knight,
+];
+
+fn push_pawn_move(from: Square, to: Square, moves: &mut Vec<Move>) {
+    if to.rank() == Rank::Rank1 || to.rank() == Rank::Rank8 {
+        for piece_type in PROMOTIONS {
+            moves.push(Move::promotion(from, to, piece_type));
+        }
+    } else {
+        moves.push(Move::new(from, to));
+    }
+}
+
+impl State {
+    fn square_attacked(&self, square: Square, by: Color, occupancy: BitBoard) -> bool {
+        self.attackers_to(square, occupancy) & self.board.by_color(by) != EMPTY
+    }
+
+    // inclusive range of squares on one rank
+    fn rank_span(a: Square, b: Square) -> BitBoard {
+        let (low, high) = if a.index() < b.index() {
+            (a.index(), b.index())
+        } else {
+            (b.index(), a.index())
+        };
+        BitBoard((u64::MAX >> (63 - high)) & (u64::MAX << low))
+    }
+
+    // handles chess960 too: the king and rook may start anywhere on the back rank,
+    // but always end on the same squares as in regular chess
+    pub fn castling_moves(&self, moves: &mut Vec<Move>) {
+        let us = self.side_to_move();
+        let king = match self.board.king_square(us) {
+            Some(king) => king,
+            None => return,
+        };
+
+        for side in [CastlingSide::King, CastlingSide::Queen] {
+            let rook = match self.castling.rook_file(us, side) {
+                Some(file) => Square::new(king.rank(), file),
+                None => continue,
+            };
+            let king_to = Square::new(king.rank(), side.king_file());
+            let rook_to = Square::new(king.rank(), side.rook_file());
+
+            // everything both pieces travel over has to be empty, apart from the two pieces themselves
+            let movers = BitBoard::from_square(king) | BitBoard::from_square(rook);
+            let occupancy = self.board.occupied() ^ movers;
+            let path = State::rank_span(king, king_to) | State::rank_span(rook, rook_to);
+            if path & occupancy != EMPTY {
+                continue;
+            }
+
+            // the king can't start in, pass through or land on an attacked square
+            if State::rank_span(king, king_to)
+                .any(|square| self.square_attacked(square, !us, occupancy))
+            {
+                continue;
+            }
+
+            moves.push(Move::castling(king, rook));
+        }
+    }
+
+    pub fn king_attacked(&self, color: Color) -> bool {
+        match self.board.king_square(color) {
+            Some(king) => self.is_attacked(king, !color),
+            None => false,
+        }
+    }
+
+    pub fn in_check(&self) -> bool {
+        self.king_attacked(self.side_to_move())
+    }
+
+    fn generate(&self, moves: &mut Vec<Move>, captures_only: bool) {
+        let us = self.side_to_move();
+        let board = &self.board;
+        let occupied = board.occupied();
+        let them = board.by_color(!us);
+        let targets = if captures_only {
+            them
+        } else {
+            !board.by_color(us)
+        };
+
+        let start_rank = match us {
+            Color::White => Rank::Rank2,
+            Color::Black => Rank::Rank7,
+        };
+        let last_rank = match us {
+            Color::White => BitBoard::from_rank(Rank::Rank8),
+            Color::Black => BitBoard::from_rank(Rank::Rank1),
+        };
+        for from in board.by_piece(Piece::new(us, PieceType::Pawn)) {
+            let bb = BitBoard::from_square(from);
+            let mut pushes = bb.pawn_moves(us) & !occupied;
+            if from.rank() == start_rank {
+                pushes |= pushes.pawn_moves(us) & !occupied;
+            }
+            if captures_only {
+                // promotions change the material balance too
+                pushes &= last_rank;
+            }
+            for to in pushes | (pawn_attacks(us, from) & them) {
+                push_pawn_move(from, to, moves);
+            }
+            if let Some(ep) = self.en_passant {
+                if pawn_attacks(us, from) & BitBoard::from_square(ep) != EMPTY {
+                    moves.push(Move::en_passant(from, ep));
+                }
+            }
+        }
+
+        for from in board.by_piece(Piece::new(us, PieceType::Knight)) {
+            for to in knight_moves(from) & targets {
+                moves.push(Move::new(from, to));
+            }
+        }
+        for from in board.by_piece(Piece::new(us, PieceType::Bishop)) {
+            for to in bishop_moves(occupied, from) & targets {
+                moves.push(Move::new(from, to));
+            }
+        }
+        for from in board.by_piece(Piece::new(us, PieceType::Rook)) {
+            for to in rook_moves(occupied, from) & targets {
+                moves.push(Move::new(from, to));
+            }
+        }
+        for from in board.by_piece(Piece::new(us, PieceType::Queen)) {
+            for to in queen_moves(occupied, from) & targets {
+                moves.push(Move::new(from, to));
+            }
+        }
+        for from in board.by_piece(Piece::new(us, PieceType::King)) {
+            for to in king_moves(from) & targets {
+                moves.push(Move::new(from, to));
+            }
+        }
+
+        if !captures_only {
+            self.castling_moves(moves);
+        }
+    }
+
+    // may leave the king in check, make the move and call king_attacked to filter
+    pub fn pseudo_legal_moves(&self, moves: &mut Vec<Move>) {
+        self.generate(moves, false);
+    }
+
+    // captures, en passant and promotions, for quiescence search
+    pub fn pseudo_legal_captures(&self, moves: &mut Vec<Move>) {
+        self.generate(moves, true);
+    }
+
+    pub fn is_legal(&self, mv: Move) -> bool {
+        let mut state = self.clone();
+        let us = state.side_to_move();
+        state.make_move(mv);
+        !state.king_attacked(us)
+    }
+
+    pub fn legal_moves(&self) -> Vec<Move> {
+        let mut moves = Vec::new();
+        self.pseudo_legal_moves(&mut moves);
+        moves.retain(|&mv| self.is_legal(mv));
+        moves
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn perft(state: &mut State, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let mut nodes = 0;
+        for mv in state.legal_moves() {
+            let undo = state.make_move(mv);
+            nodes += perft(state, depth - 1);
+            state.unmake_move(mv, undo);
+        }
+        nodes
+    }
+
+    fn check_perft(positions: &[(&str, &[u64])]) {
+        for (fen, counts) in positions {
+            let mut state = State::from_fen(fen).unwrap();
+            for (depth, &count) in counts.iter().enumerate() {
+                assert_eq!(perft(&mut state, depth as u32 + 1), count, "{}", fen);
+            }
+            assert_eq!(state.to_fen(), State::from_fen(fen).unwrap().to_fen());
+        }
+    }
+
+    // the usual positions from the chess programming wiki
+    #[test]
+    fn perft_positions() {
+        check_perft(&[
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                &[20, 400, 8902, 197281],
+            ),
+            (
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                &[48, 2039, 97862],
+            ),
+            (
+                "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+                &[14, 191, 2812, 43238, 674624],
+            ),
+            (
+                "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+                &[6, 264, 9467, 422333],
+            ),
+            (
+                "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+                &[44, 1486, 62379],
+            ),
+            (
+                "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+                &[46, 2079, 89890],
+            ),
+        ]);
+    }
+
+    #[test]
+    fn perft_chess960() {
+        check_perft(&[
+            (
+                "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+                &[21, 528, 12189, 326672],
+            ),
+            (
+                "2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9",
+                &[21, 807, 18002],
+            ),
+            (
+                "b1q1rrkb/pppppppp/3nn3/8/P7/1PPP4/4PPPP/BQNNRKRB w GE - 1 9",
+                &[20, 479, 10471],
+            ),
+        ]);
+    }
+
+    fn castling(fen: &str) -> Vec<String> {
+        let state = State::from_fen(fen).unwrap();
+        let mut moves = Vec::new();
+        state.castling_moves(&mut moves);
+        moves.iter().map(|mv| mv.to_uci(true)).collect()
+    }
+
+    #[test]
+    fn castling_moves() {
+        assert_eq!(
+            castling("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1"),
+            ["e1h1", "e1a1"]
+        );
+        // no castling through an attacked square or out of check
+        assert_eq!(castling("r3k2r/8/8/8/8/8/5r2/R3K2R w KQkq - 0 1"), ["e1a1"]);
+        assert!(castling("r3k2r/8/8/8/4r3/8/8/R3K2R w KQkq - 0 1").is_empty());
+        // the b-file may be attacked when castling queen side
+        assert_eq!(castling("r3k2r/8/8/8/8/8/1r6/R3K2R w Qkq - 0 1"), ["e1a1"]);
+
+        // chess960: the king on b1 castles queen side by moving one square
+        assert_eq!(
+            castling("4k3/8/8/8/8/8/8/RK4R1 w GA - 0 1"),
+            ["b1g1", "b1a1"]
+        );
+        // the king stays on g1 and only the rook moves
+        assert_eq!(
+            castling("4k3/8/8/8/8/8/8/1R4KR w HB - 0 1"),
+            ["g1h1", "g1b1"]
+        );
+        // a piece on the rook's destination blocks even though the king doesn't pass it
+        assert!(castling("4k3/8/8/8/8/8/8/1RN3KR w B - 0 1").is_empty());
+        // the rook between king and destination is no obstacle to itself
+        assert_eq!(castling("4k3/8/8/8/8/8/8/2RK4 w C - 0 1"), ["d1c1"]);
+
+        let state = State::from_fen("4k3/8/8/8/8/8/8/1R4KR w HB - 0 1").unwrap();
+        let mut castled = state.clone();
+        castled.make_move(state.parse_uci_move("g1b1", true).unwrap());
+        assert_eq!(castled.to_fen(), "4k3/8/8/8/8/8/8/2KR3R b - - 1 1");
+    }
+}
@@ -0,0 +1,270 @@
+use crate::direction::Direction;
+use crate::errors::ChessError;
+use crate::piece::*;
+use crate::rank::Rank;
+use crate::square::Square;
+use crate::state::{Castling, CastlingSide, Ply, State};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MoveKind {
+    Normal,
+    Promotion(PieceType),
+    EnPassant,
+    Castling,
+}
+
+// castling is stored as king-takes-rook so chess960 and regular chess share one encoding
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Move {
+    pub from: Square,
+    pub to: Square,
+    pub kind: MoveKind,
+}
+
+impl Move {
+    pub fn new(from: Square, to: Square) -> Move {
+        Move {
+            from,
+            to,
+            kind: MoveKind::Normal,
+        }
+    }
+
+    pub fn promotion(from: Square, to: Square, piece_type: PieceType) -> Move {
+        Move {
+            from,
+            to,
+            kind: MoveKind::Promotion(piece_type),
+        }
+    }
+
+    pub fn en_passant(from: Square, to: Square) -> Move {
+        Move {
+            from,
+            to,
+            kind: MoveKind::EnPassant,
+        }
+    }
+
+    pub fn castling(king: Square, rook: Square) -> Move {
+        Move {
+            from: king,
+            to: rook,
+            kind: MoveKind::Castling,
+        }
+    }
+
+    pub fn is_castling(self) -> bool {
+        self.kind == MoveKind::Castling
+    }
+
+    pub fn castling_side(self) -> Option<CastlingSide> {
+        if !self.is_castling() {
+            None
+        } else if self.to.file().index() > self.from.file().index() {
+            Some(CastlingSide::King)
+        } else {
+            Some(CastlingSide::Queen)
+        }
+    }
+
+    // where the king actually lands, which differs from `to` when castling
+    pub fn king_destination(self) -> Square {
+        match self.castling_side() {
+            Some(side) => Square::new(self.from.rank(), side.king_file()),
+            None => self.to,
+        }
+    }
+
+    // UCI_Chess960 sends castling as king-takes-rook (e1h1), otherwise the king's two-square move (e1g1)
+    pub fn to_uci(self, chess960: bool) -> String {
+        let to = if chess960 {
+            self.to
+        } else {
+            self.king_destination()
+        };
+        let mut uci = format!("{}{}", self.from, to);
+        if let MoveKind::Promotion(piece_type) = self.kind {
+            uci.push_str(Piece::new(Color::Black, piece_type).as_str());
+        }
+        uci
+    }
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_uci(false))
+    }
+}
+
+impl State {
+    // the move kind can't be told from the text alone, so this needs the position
+    pub fn parse_uci_move(&self, s: &str, chess960: bool) -> Result<Move, ChessError> {
+        let invalid = || ChessError::ParseError(s.to_string(), "Move");
+        if !s.is_ascii() || s.len() < 4 || s.len() > 5 {
+            return Err(invalid());
+        }
+
+        let from = s[0..2].parse::<Square>()?;
+        let to = s[2..4].parse::<Square>()?;
+        let piece = self.board.piece(from).ok_or_else(invalid)?;
+
+        if s.len() == 5 {
+            let piece_type = Piece::from_str(&s[4..5])?.piece_type();
+            return Ok(Move::promotion(from, to, piece_type));
+        }
+
+        match piece.piece_type() {
+            PieceType::King => {
+                let own_rook = Some(Piece::new(piece.color(), PieceType::Rook));
+                if self.board.piece(to) == own_rook {
+                    return Ok(Move::castling(from, to));
+                }
+
+                let distance = to.file().index() as isize - from.file().index() as isize;
+                if !chess960 && from.rank() == to.rank() && distance.abs() == 2 {
+                    let side = if distance > 0 {
+                        CastlingSide::King
+                    } else {
+                        CastlingSide::Queen
+                    };
+                    let file = self
+                        .castling
+                        .rook_file(piece.color(), side)
+                        .ok_or_else(invalid)?;
+                    return Ok(Move::castling(from, Square::new(from.rank(), file)));
+                }
+
+                Ok(Move::new(from, to))
+            }
+            PieceType::Pawn if Some(to) == self.en_passant => Ok(Move::en_passant(from, to)),
+            _ => Ok(Move::new(from, to)),
+        }
+    }
+}
+
+// everything make_move can't recompute when taking a move back
+#[derive(Debug, Copy, Clone)]
+pub struct Undo {
+    pub captured: Option<Piece>,
+    pub castling: Castling,
+    pub en_passant: Option<Square>,
+    pub halfmove_clock: Ply,
+}
+
+impl State {
+    // doesn't check legality, the move is expected to come from the move generator
+    pub fn make_move(&mut self, mv: Move) -> Undo {
+        let us = self.side_to_move();
+        let piece = self.board.piece(mv.from).expect("No piece to move");
+        let undo = Undo {
+            captured: match mv.kind {
+                MoveKind::Castling => None,
+                MoveKind::EnPassant => Some(Piece::new(!us, PieceType::Pawn)),
+                _ => self.board.piece(mv.to),
+            },
+            castling: self.castling,
+            en_passant: self.en_passant,
+            halfmove_clock: self.halfmove_clock,
+        };
+
+        self.en_passant = None;
+        match mv.kind {
+            MoveKind::Normal => {
+                self.board.set_piece(mv.from, None);
+                self.board.set_piece(mv.to, Some(piece));
+                if piece.piece_type() == PieceType::Pawn
+                    && mv.from.rank().index().abs_diff(mv.to.rank().index()) == 2
+                {
+                    self.en_passant = mv.from.relative(Direction::Up, us);
+                }
+            }
+            MoveKind::Promotion(piece_type) => {
+                self.board.set_piece(mv.from, None);
+                self.board
+                    .set_piece(mv.to, Some(Piece::new(us, piece_type)));
+            }
+            MoveKind::EnPassant => {
+                self.board.set_piece(mv.from, None);
+                self.board
+                    .set_piece(Square::new(mv.from.rank(), mv.to.file()), None);
+                self.board.set_piece(mv.to, Some(piece));
+            }
+            MoveKind::Castling => {
+                let side = mv.castling_side().unwrap();
+                let rook = self.board.piece(mv.to);
+                self.board.set_piece(mv.from, None);
+                self.board.set_piece(mv.to, None);
+                self.board
+                    .set_piece(Square::new(mv.from.rank(), side.king_file()), Some(piece));
+                self.board
+                    .set_piece(Square::new(mv.from.rank(), side.rook_file()), rook);
+            }
+        }
+
+        if piece.piece_type() == PieceType::Pawn || undo.captured.is_some() {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+
+        // moving the king loses both rights, moving or capturing a rook loses that side
+        if piece.piece_type() == PieceType::King {
+            self.castling.clear(us);
+        }
+        for color in [Color::White, Color::Black] {
+            for side in [CastlingSide::King, CastlingSide::Queen] {
+                if let Some(file) = self.castling.rook_file(color, side) {
+                    let rook = Square::new(Rank::back_rank(color), file);
+                    if rook == mv.from || rook == mv.to {
+                        self.castling.set_rook_file(color, side, None);
+                    }
+                }
+            }
+        }
+
+        self.ply += 1;
+        undo
+    }
+
+    pub fn unmake_move(&mut self, mv: Move, undo: Undo) {
+        self.ply -= 1;
+        self.castling = undo.castling;
+        self.en_passant = undo.en_passant;
+        self.halfmove_clock = undo.halfmove_clock;
+
+        let us = self.side_to_move();
+        match mv.kind {
+            MoveKind::Normal => {
+                let piece = self.board.piece(mv.to);
+                self.board.set_piece(mv.to, undo.captured);
+                self.board.set_piece(mv.from, piece);
+            }
+            MoveKind::Promotion(_) => {
+                self.board.set_piece(mv.to, undo.captured);
+                self.board
+                    .set_piece(mv.from, Some(Piece::new(us, PieceType::Pawn)));
+            }
+            MoveKind::EnPassant => {
+                let piece = self.board.piece(mv.to);
+                self.board.set_piece(mv.to, None);
+                self.board
+                    .set_piece(Square::new(mv.from.rank(), mv.to.file()), undo.captured);
+                self.board.set_piece(mv.from, piece);
+            }
+            MoveKind::Castling => {
+                let side = mv.castling_side().unwrap();
+                let king_to = Square::new(mv.from.rank(), side.king_file());
+                let rook_to = Square::new(mv.from.rank(), side.rook_file());
+                let king = self.board.piece(king_to);
+                let rook = self.board.piece(rook_to);
+                self.board.set_piece(king_to, None);
+                self.board.set_piece(rook_to, None);
+                self.board.set_piece(mv.from, king);
+                self.board.set_piece(mv.to, rook);
+            }
+        }
+    }
+}
@@ -0,0 +1,612 @@
+// a quantized 768->N->1 network with one accumulator per perspective. The search keeps a
+// stack of accumulators, one per ply, and updates each from its parent with only the
+// squares a move changed instead of refreshing it from the whole board.
+//
+// file layout, all integers little endian:
+//   0..4   magic "CNUE"
+//   4      format version
+//   5..7   hidden layer size as a u16, a multiple of 16
+//   ..     feature weights as i16, 768 rows of hidden size
+//   ..     feature biases as i16, hidden size of them
+//   ..     output weights as i16, the side to move's half first
+//   ..     output bias as an i32
+//
+// features are (relative color, piece type, square) from each side's point of view, black
+// seeing the board flipped. Weights are scaled by QA in the first layer and by QB in the
+// second, activations are clipped to 0..=QA.
+use crate::endgame::{self, MaterialKey};
+use crate::errors::ChessError;
+use crate::eval::Evaluator;
+use crate::moves::{Move, MoveKind, Undo};
+use crate::piece::*;
+use crate::search::{MAX_DEPTH, TB_WIN_SCORE};
+use crate::square::Square;
+use crate::state::{Board, State};
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+const MAGIC: [u8; 4] = *b"CNUE";
+const VERSION: u8 = 1;
+pub const FEATURES: usize = 768;
+const MAX_HIDDEN: usize = 1024;
+
+const QA: i32 = 255;
+const QB: i32 = 64;
+// centipawns per unit of network output
+const SCALE: i32 = 400;
+// the network can't claim a mate or a tablebase win, whatever its weights
+const MAX_EVAL: i32 = TB_WIN_SCORE - MAX_DEPTH as i32 - 1;
+
+fn feature(perspective: Color, piece: Piece, square: Square) -> usize {
+    let (color, square) = match perspective {
+        Color::White => (piece.color(), square.index()),
+        Color::Black => (!piece.color(), square.index() ^ 56),
+    };
+    (color.index() * PIECE_TYPE_COUNT + piece.piece_type().index()) * 64 + square
+}
+
+// how the accumulators are updated and the output computed: AVX2 where the CPU has it,
+// plain loops everywhere else
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SimdBackend {
+    Avx2,
+    Scalar,
+}
+
+impl SimdBackend {
+    pub fn detect() -> SimdBackend {
+        if SimdBackend::Avx2.is_supported() {
+            SimdBackend::Avx2
+        } else {
+            SimdBackend::Scalar
+        }
+    }
+
+    pub fn is_supported(self) -> bool {
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            SimdBackend::Avx2 => is_x86_feature_detected!("avx2"),
+            #[cfg(not(target_arch = "x86_64"))]
+            SimdBackend::Avx2 => false,
+            SimdBackend::Scalar => true,
+        }
+    }
+
+    fn add(self, values: &mut [i16], weights: &[i16]) {
+        match self {
+            // only chosen when is_supported, lengths are multiples of 16
+            #[cfg(target_arch = "x86_64")]
+            SimdBackend::Avx2 => unsafe { avx2::add(values, weights) },
+            #[cfg(not(target_arch = "x86_64"))]
+            SimdBackend::Avx2 => unreachable!(),
+            SimdBackend::Scalar => {
+                for (value, &weight) in values.iter_mut().zip(weights) {
+                    *value = value.wrapping_add(weight);
+                }
+            }
+        }
+    }
+
+    fn sub(self, values: &mut [i16], weights: &[i16]) {
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            SimdBackend::Avx2 => unsafe { avx2::sub(values, weights) },
+            #[cfg(not(target_arch = "x86_64"))]
+            SimdBackend::Avx2 => unreachable!(),
+            SimdBackend::Scalar => {
+                for (value, &weight) in values.iter_mut().zip(weights) {
+                    *value = value.wrapping_sub(weight);
+                }
+            }
+        }
+    }
+
+    // the clipped activations times the weights, which can pass i32::MAX on wide layers
+    fn dot(self, values: &[i16], weights: &[i16]) -> i64 {
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            SimdBackend::Avx2 => unsafe { avx2::dot(values, weights) },
+            #[cfg(not(target_arch = "x86_64"))]
+            SimdBackend::Avx2 => unreachable!(),
+            SimdBackend::Scalar => values
+                .iter()
+                .zip(weights)
+                .map(|(&value, &weight)| ((value as i32).clamp(0, QA) * weight as i32) as i64)
+                .sum(),
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use super::QA;
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn add(values: &mut [i16], weights: &[i16]) {
+        for (values, weights) in values.chunks_exact_mut(16).zip(weights.chunks_exact(16)) {
+            let v = _mm256_loadu_si256(values.as_ptr() as *const __m256i);
+            let w = _mm256_loadu_si256(weights.as_ptr() as *const __m256i);
+            _mm256_storeu_si256(values.as_mut_ptr() as *mut __m256i, _mm256_add_epi16(v, w));
+        }
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn sub(values: &mut [i16], weights: &[i16]) {
+        for (values, weights) in values.chunks_exact_mut(16).zip(weights.chunks_exact(16)) {
+            let v = _mm256_loadu_si256(values.as_ptr() as *const __m256i);
+            let w = _mm256_loadu_si256(weights.as_ptr() as *const __m256i);
+            _mm256_storeu_si256(values.as_mut_ptr() as *mut __m256i, _mm256_sub_epi16(v, w));
+        }
+    }
+
+    #[target_feature(enable = "avx2")]
+    // each i32 lane sums at most MAX_HIDDEN / 16 pairs, which fits; the lanes together don't
+    pub(super) unsafe fn dot(values: &[i16], weights: &[i16]) -> i64 {
+        let zero = _mm256_setzero_si256();
+        let max = _mm256_set1_epi16(QA as i16);
+        let mut sum = zero;
+        for (values, weights) in values.chunks_exact(16).zip(weights.chunks_exact(16)) {
+            let v = _mm256_loadu_si256(values.as_ptr() as *const __m256i);
+            let w = _mm256_loadu_si256(weights.as_ptr() as *const __m256i);
+            let clipped = _mm256_min_epi16(_mm256_max_epi16(v, zero), max);
+            sum = _mm256_add_epi32(sum, _mm256_madd_epi16(clipped, w));
+        }
+        let mut lanes = [0i32; 8];
+        _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, sum);
+        lanes.iter().map(|&lane| lane as i64).sum()
+    }
+}
+
+pub struct Network {
+    hidden: usize,
+    feature_weights: Vec<i16>,
+    feature_biases: Vec<i16>,
+    output_weights: Vec<i16>,
+    output_bias: i32,
+    backend: SimdBackend,
+}
+
+impl Network {
+    pub fn hidden(&self) -> usize {
+        self.hidden
+    }
+
+    pub fn backend(&self) -> SimdBackend {
+        self.backend
+    }
+
+    // the plain loops give the same results, only slower
+    pub fn set_backend(&mut self, backend: SimdBackend) {
+        if backend.is_supported() {
+            self.backend = backend;
+        }
+    }
+
+    fn weights(&self, feature: usize) -> &[i16] {
+        &self.feature_weights[feature * self.hidden..(feature + 1) * self.hidden]
+    }
+
+    // centipawns from the side to move's point of view
+    fn output(&self, us: &[i16], them: &[i16]) -> i32 {
+        let (our_weights, their_weights) = self.output_weights.split_at(self.hidden);
+        let sum = self.backend.dot(us, our_weights)
+            + self.backend.dot(them, their_weights)
+            + self.output_bias as i64;
+        (sum * SCALE as i64 / (QA * QB) as i64).clamp(-MAX_EVAL as i64, MAX_EVAL as i64) as i32
+    }
+
+    pub fn open(path: &Path) -> Result<Network, ChessError> {
+        Network::read(BufReader::new(File::open(path)?))
+    }
+
+    pub fn read<R: Read>(mut reader: R) -> Result<Network, ChessError> {
+        let invalid = |reason: String| ChessError::InvalidNetwork(reason);
+        let mut header = [0; 7];
+        reader.read_exact(&mut header)?;
+        if header[..4] != MAGIC || header[4] != VERSION {
+            return Err(invalid("not a network file".to_string()));
+        }
+        let hidden = u16::from_le_bytes([header[5], header[6]]) as usize;
+        if hidden == 0 || !hidden.is_multiple_of(16) || hidden > MAX_HIDDEN {
+            return Err(invalid(format!("hidden layer size {}", hidden)));
+        }
+
+        let mut read_i16s = |count: usize| -> Result<Vec<i16>, ChessError> {
+            let mut bytes = vec![0; count * 2];
+            reader.read_exact(&mut bytes)?;
+            Ok(bytes
+                .chunks_exact(2)
+                .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]]))
+                .collect())
+        };
+        let feature_weights = read_i16s(FEATURES * hidden)?;
+        let feature_biases = read_i16s(hidden)?;
+        let output_weights = read_i16s(2 * hidden)?;
+        let mut bias = [0; 4];
+        reader.read_exact(&mut bias)?;
+        if reader.read(&mut [0])? != 0 {
+            return Err(invalid("trailing data".to_string()));
+        }
+
+        Ok(Network {
+            hidden,
+            feature_weights,
+            feature_biases,
+            output_weights,
+            output_bias: i32::from_le_bytes(bias),
+            backend: SimdBackend::detect(),
+        })
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<(), ChessError> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        writer.write_all(&(self.hidden as u16).to_le_bytes())?;
+        for values in [
+            &self.feature_weights,
+            &self.feature_biases,
+            &self.output_weights,
+        ] {
+            for value in values.iter() {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+        writer.write_all(&self.output_bias.to_le_bytes())?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+// the hidden layer before activation, one per perspective
+#[derive(Clone)]
+pub struct Accumulator {
+    network: Arc<Network>,
+    values: [Vec<i16>; 2],
+}
+
+impl Accumulator {
+    pub fn new(network: Arc<Network>, board: &Board) -> Accumulator {
+        let mut accumulator = Accumulator {
+            values: [
+                network.feature_biases.clone(),
+                network.feature_biases.clone(),
+            ],
+            network,
+        };
+        for (square, piece) in board.pieces() {
+            if let Some(piece) = piece {
+                accumulator.add(piece, square);
+            }
+        }
+        accumulator
+    }
+
+    pub fn add(&mut self, piece: Piece, square: Square) {
+        for perspective in [Color::White, Color::Black] {
+            let weights = self.network.weights(feature(perspective, piece, square));
+            (self.network.backend).add(&mut self.values[perspective.index()], weights);
+        }
+    }
+
+    pub fn remove(&mut self, piece: Piece, square: Square) {
+        for perspective in [Color::White, Color::Black] {
+            let weights = self.network.weights(feature(perspective, piece, square));
+            (self.network.backend).sub(&mut self.values[perspective.index()], weights);
+        }
+    }
+
+    pub fn evaluate(&self, side_to_move: Color) -> i32 {
+        let us = &self.values[side_to_move.index()];
+        let them = &self.values[(!side_to_move).index()];
+        self.network.output(us, them)
+    }
+
+    fn copy_from(&mut self, other: &Accumulator) {
+        for (values, other) in self.values.iter_mut().zip(&other.values) {
+            values.copy_from_slice(other);
+        }
+    }
+}
+
+// the squares a move can change: both ends, the pawn taken en passant and where the king
+// and rook land when castling; None fills the rest
+fn changed_squares(mv: Move) -> [Option<Square>; 4] {
+    match mv.kind {
+        MoveKind::EnPassant => [
+            Some(mv.from),
+            Some(mv.to),
+            Some(Square::new(mv.from.rank(), mv.to.file())),
+            None,
+        ],
+        MoveKind::Castling => {
+            let side = mv.castling_side().unwrap();
+            let king_to = Square::new(mv.from.rank(), side.king_file());
+            let rook_to = Square::new(mv.from.rank(), side.rook_file());
+            // in chess960 the king or rook may end where one of them started
+            let mut squares = [Some(mv.from), Some(mv.to), None, None];
+            for (slot, square) in squares[2..].iter_mut().zip([king_to, rook_to]) {
+                if square != mv.from && square != mv.to {
+                    *slot = Some(square);
+                }
+            }
+            squares
+        }
+        _ => [Some(mv.from), Some(mv.to), None, None],
+    }
+}
+
+// one accumulator per ply of a search, the last one belongs to the current position. The
+// buffers are kept when popped so deeper plies don't allocate again.
+pub struct AccumulatorStack {
+    accumulators: Vec<Accumulator>,
+    len: usize,
+}
+
+impl AccumulatorStack {
+    pub fn new(network: Arc<Network>, board: &Board) -> AccumulatorStack {
+        AccumulatorStack {
+            accumulators: vec![Accumulator::new(network, board)],
+            len: 1,
+        }
+    }
+
+    pub fn current(&self) -> &Accumulator {
+        &self.accumulators[self.len - 1]
+    }
+
+    // makes the move and pushes its position's accumulator
+    pub fn make_move(&mut self, state: &mut State, mv: Move) -> Undo {
+        let squares = changed_squares(mv);
+        let before = squares.map(|square| square.map(|square| state.board.piece(square)));
+        let undo = state.make_move(mv);
+
+        if self.len == self.accumulators.len() {
+            self.accumulators
+                .push(self.accumulators[self.len - 1].clone());
+        } else {
+            let (parents, children) = self.accumulators.split_at_mut(self.len);
+            children[0].copy_from(&parents[self.len - 1]);
+        }
+        let accumulator = &mut self.accumulators[self.len];
+        self.len += 1;
+        for (square, before) in squares.into_iter().zip(before) {
+            let (Some(square), Some(before)) = (square, before) else {
+                continue;
+            };
+            let after = state.board.piece(square);
+            if before != after {
+                if let Some(piece) = before {
+                    accumulator.remove(piece, square);
+                }
+                if let Some(piece) = after {
+                    accumulator.add(piece, square);
+                }
+            }
+        }
+        undo
+    }
+
+    pub fn unmake_move(&mut self, state: &mut State, mv: Move, undo: Undo) {
+        state.unmake_move(mv, undo);
+        self.len -= 1;
+    }
+}
+
+// the network as an Evaluator; a search evaluates with its own accumulators
+pub struct Nnue {
+    network: Arc<Network>,
+}
+
+impl Nnue {
+    pub fn new(network: Arc<Network>) -> Nnue {
+        Nnue { network }
+    }
+}
+
+impl Evaluator for Nnue {
+    fn network(&self) -> Option<Arc<Network>> {
+        Some(self.network.clone())
+    }
+
+    fn evaluate(&self, state: &State) -> i32 {
+        let accumulator = Accumulator::new(self.network.clone(), &state.board);
+        self.evaluate_incremental(state, &accumulator)
+    }
+
+    // the specialised endgames still know better
+    fn evaluate_incremental(&self, state: &State, accumulator: &Accumulator) -> i32 {
+        let key = MaterialKey::from_board(&state.board);
+        if let Some(score) = endgame::evaluate(state, key) {
+            return score;
+        }
+        accumulator.evaluate(state.side_to_move())
+    }
+}
+
+// the network behind the EvalFile and Use NNUE UCI options, picked up by every new Search
+static NETWORK: RwLock<Option<Arc<Network>>> = RwLock::new(None);
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// an empty path unloads the network; returns its hidden layer size
+pub fn set_eval_file(path: &str) -> Result<usize, ChessError> {
+    let network = if path.is_empty() || path == "<empty>" {
+        None
+    } else {
+        Some(Arc::new(Network::open(Path::new(path))?))
+    };
+    let hidden = network.as_ref().map_or(0, |network| network.hidden());
+    *NETWORK.write().unwrap() = network;
+    Ok(hidden)
+}
+
+pub fn network() -> Option<Arc<Network>> {
+    NETWORK.read().unwrap().clone()
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+// the network when it's turned on and loaded, the handcrafted evaluation otherwise
+pub fn evaluator() -> Arc<dyn Evaluator> {
+    match network() {
+        Some(network) if enabled() => Arc::new(Nnue::new(network)),
+        _ => Arc::new(crate::eval::Classical),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::{is_mate_score, Search, SearchLimits};
+
+    // small made-up weights, enough to tell features apart
+    fn network(hidden: usize, backend: SimdBackend) -> Network {
+        let mut seed = 0x2545f4914f6cdd1du64;
+        let mut random = |range: i16| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % (2 * range as u64 + 1)) as i16 - range
+        };
+        Network {
+            hidden,
+            feature_weights: (0..FEATURES * hidden).map(|_| random(40)).collect(),
+            feature_biases: (0..hidden).map(|_| random(100)).collect(),
+            output_weights: (0..2 * hidden).map(|_| random(60)).collect(),
+            output_bias: 1000,
+            backend,
+        }
+    }
+
+    #[test]
+    fn incremental_updates() {
+        let network = Arc::new(network(32, SimdBackend::detect()));
+        // castling, en passant, a promotion with capture and plain captures, then chess960
+        // castling where the king stays put or lands on the rook's square
+        for fen in [
+            "r3k2r/pPpp1ppp/8/3Pp3/8/5n2/P1PP1PPP/R3K2R w KQkq e6 0 1",
+            "1r4kr/8/8/8/8/8/8/1R4KR w BHbh - 0 1",
+        ] {
+            let mut state = State::from_fen(fen).unwrap();
+            let mut stack = AccumulatorStack::new(network.clone(), &state.board);
+            let start = stack.current().values.clone();
+
+            // two plies deep, so popped buffers get reused
+            for mv in state.legal_moves() {
+                let undo = stack.make_move(&mut state, mv);
+                let fresh = Accumulator::new(network.clone(), &state.board);
+                assert!(stack.current().values == fresh.values, "{:?}", mv);
+                for reply in state.legal_moves() {
+                    let undo = stack.make_move(&mut state, reply);
+                    let fresh = Accumulator::new(network.clone(), &state.board);
+                    assert!(
+                        stack.current().values == fresh.values,
+                        "{:?} {:?}",
+                        mv,
+                        reply
+                    );
+                    stack.unmake_move(&mut state, reply, undo);
+                }
+                stack.unmake_move(&mut state, mv, undo);
+                assert!(stack.current().values == start, "{:?}", mv);
+            }
+        }
+    }
+
+    #[test]
+    fn output_stays_below_mate_scores() {
+        let state = State::default();
+        for bias in [1_000_000_000, -1_000_000_000] {
+            let mut network = network(16, SimdBackend::detect());
+            network.output_bias = bias;
+            let score = Nnue::new(Arc::new(network)).evaluate(&state);
+            assert_eq!(score, MAX_EVAL * bias.signum());
+            assert!(!is_mate_score(score));
+        }
+    }
+
+    #[test]
+    fn backends_agree() {
+        let scalar = Arc::new(network(64, SimdBackend::Scalar));
+        let mut detected = network(64, SimdBackend::Scalar);
+        detected.set_backend(SimdBackend::detect());
+        let detected = Arc::new(detected);
+
+        let mut state = State::default();
+        for mv in ["e2e4", "c7c5", "g1f3", "d7d6", "d2d4", "c5d4", "f3d4"] {
+            let mv = state.parse_uci_move(mv, false).unwrap();
+            state.make_move(mv);
+            let a = Accumulator::new(scalar.clone(), &state.board);
+            let b = Accumulator::new(detected.clone(), &state.board);
+            assert!(a.values == b.values);
+            for side in [Color::White, Color::Black] {
+                assert_eq!(a.evaluate(side), b.evaluate(side));
+            }
+        }
+
+        // saturated activations on the widest layer sum past i32::MAX
+        let values = vec![QA as i16; MAX_HIDDEN];
+        let weights = vec![i16::MAX; MAX_HIDDEN];
+        let expected = MAX_HIDDEN as i64 * QA as i64 * i16::MAX as i64;
+        assert!(expected > i32::MAX as i64);
+        assert_eq!(SimdBackend::Scalar.dot(&values, &weights), expected);
+        assert_eq!(SimdBackend::detect().dot(&values, &weights), expected);
+    }
+
+    #[test]
+    fn perspectives_are_symmetric() {
+        let nnue = Nnue::new(Arc::new(network(16, SimdBackend::detect())));
+        let state =
+            State::from_fen("r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4")
+                .unwrap();
+        let flipped =
+            State::from_fen("rnbqk2r/pppp1ppp/5n2/2b1p3/4P3/2N2N2/PPPP1PPP/R1BQKB1R b KQkq - 4 4")
+                .unwrap();
+        assert_eq!(nnue.evaluate(&state), nnue.evaluate(&flipped));
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let network = network(16, SimdBackend::Scalar);
+        let mut bytes = Vec::new();
+        network.write(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 7 + 2 * (FEATURES * 16 + 16 + 32) + 4);
+
+        let read = Network::read(&bytes[..]).unwrap();
+        assert_eq!(read.hidden(), 16);
+        assert!(read.feature_weights == network.feature_weights);
+        assert!(read.output_weights == network.output_weights);
+        assert_eq!(read.output_bias, network.output_bias);
+
+        assert!(Network::read(&bytes[..bytes.len() - 1]).is_err());
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert!(Network::read(&longer[..]).is_err());
+        bytes[5] = 15;
+        assert!(Network::read(&bytes[..]).is_err());
+    }
+
+    #[test]
+    fn searches_with_the_network() {
+        let mut state = State::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        let mut search = Search::new(SearchLimits {
+            depth: Some(3),
+            ..SearchLimits::default()
+        });
+        search.set_evaluator(Arc::new(Nnue::new(Arc::new(network(
+            16,
+            SimdBackend::detect(),
+        )))));
+        let result = search.run(&mut state);
+        assert_eq!(result.best_move.unwrap().to_uci(false), "a1a8");
+    }
+}
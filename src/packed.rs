@@ -0,0 +1,299 @@
+use crate::bitboard::*;
+use crate::errors::ChessError;
+use crate::game::GameResult;
+use crate::piece::*;
+use crate::rank::Rank;
+use crate::square::*;
+use crate::state::{CastlingSide, State};
+use std::io::{self, Read, Write};
+
+// layout, all integers little endian:
+//   0..8   occupancy
+//   8..24  a nibble per occupied square in square order, low nibble first:
+//          Piece::index(), or CASTLING_ROOK + color for a rook that can still castle
+//   24     side to move in the top bit, en passant square (or NO_EN_PASSANT) below it
+//   25     halfmove clock, saturating
+//   26..28 fullmove number
+//   28..30 score in centipawns from the side to move's point of view
+//   30     game result, see encode_result
+//   31     reserved, always zero
+pub const PACKED_SIZE: usize = 32;
+
+const MAX_PIECES: usize = 32;
+const CASTLING_ROOK: u8 = PIECE_COUNT as u8;
+const NO_EN_PASSANT: u8 = SQUARE_COUNT as u8;
+const NO_RESULT: u8 = 3;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PackedPosition(pub [u8; PACKED_SIZE]);
+
+// a position as stored in a training file
+#[derive(Clone)]
+pub struct PackedEntry {
+    pub state: State,
+    pub score: i16,
+    pub result: Option<GameResult>,
+}
+
+fn encode_result(result: Option<GameResult>) -> u8 {
+    match result {
+        Some(GameResult::BlackWins) => 0,
+        Some(GameResult::Draw) => 1,
+        Some(GameResult::WhiteWins) => 2,
+        None => NO_RESULT,
+    }
+}
+
+impl PackedPosition {
+    pub fn score(&self) -> i16 {
+        i16::from_le_bytes([self.0[28], self.0[29]])
+    }
+
+    pub fn set_score(&mut self, score: i16) {
+        self.0[28..30].copy_from_slice(&score.to_le_bytes());
+    }
+
+    pub fn result(&self) -> Option<GameResult> {
+        match self.0[30] {
+            0 => Some(GameResult::BlackWins),
+            1 => Some(GameResult::Draw),
+            2 => Some(GameResult::WhiteWins),
+            _ => None,
+        }
+    }
+
+    pub fn set_result(&mut self, result: Option<GameResult>) {
+        self.0[30] = encode_result(result);
+    }
+}
+
+impl State {
+    // fails only when there are more than 32 pieces, which no legal position has
+    pub fn to_packed(&self) -> Result<PackedPosition, ChessError> {
+        let occupied = self.board.occupied();
+        if occupied.count() as usize > MAX_PIECES {
+            return Err(ChessError::InvalidPackedPosition(format!(
+                "{} pieces don't fit",
+                occupied.count()
+            )));
+        }
+
+        let mut bytes = [0; PACKED_SIZE];
+        bytes[0..8].copy_from_slice(&occupied.0.to_le_bytes());
+        for (i, square) in occupied.enumerate() {
+            let piece = self.board.piece(square).unwrap();
+            let castling_rook = piece.piece_type() == PieceType::Rook
+                && [CastlingSide::King, CastlingSide::Queen]
+                    .iter()
+                    .any(|&side| {
+                        self.castling.rook_file(piece.color(), side) == Some(square.file())
+                            && square.rank() == Rank::back_rank(piece.color())
+                    });
+            let code = if castling_rook {
+                CASTLING_ROOK + piece.color().index() as u8
+            } else {
+                piece.index() as u8
+            };
+            bytes[8 + i / 2] |= code << (4 * (i % 2));
+        }
+
+        let stm = (self.side_to_move().index() as u8) << 7;
+        bytes[24] = stm | self.en_passant.map_or(NO_EN_PASSANT, |ep| ep.index() as u8);
+        bytes[25] = self.halfmove_clock.min(u8::MAX as u16) as u8;
+        bytes[26..28].copy_from_slice(&(self.ply / 2 + 1).to_le_bytes());
+        bytes[30] = NO_RESULT;
+        Ok(PackedPosition(bytes))
+    }
+
+    pub fn from_packed(packed: &PackedPosition) -> Result<State, ChessError> {
+        let invalid = |reason: &str| ChessError::InvalidPackedPosition(reason.to_string());
+        let bytes = &packed.0;
+        let occupied = BitBoard(u64::from_le_bytes(bytes[0..8].try_into().unwrap()));
+        if occupied.count() as usize > MAX_PIECES {
+            return Err(invalid("more than 32 pieces"));
+        }
+
+        let mut state = State::new();
+        let mut castling_rooks = Vec::new();
+        for (i, square) in occupied.enumerate() {
+            let code = (bytes[8 + i / 2] >> (4 * (i % 2))) & 0xF;
+            let piece = if code >= CASTLING_ROOK {
+                let color = Color::from_index((code - CASTLING_ROOK) as usize)
+                    .ok_or_else(|| invalid("unknown piece code"))?;
+                castling_rooks.push((color, square));
+                Piece::new(color, PieceType::Rook)
+            } else {
+                Piece::from_index(code as usize).unwrap()
+            };
+            state.board.set_piece(square, Some(piece));
+        }
+
+        // which side a rook castles to depends on where its king is, for chess960
+        for (color, rook) in castling_rooks {
+            let king = state
+                .board
+                .king_square(color)
+                .filter(|king| king.rank() == Rank::back_rank(color) && rook.rank() == king.rank())
+                .ok_or_else(|| invalid("castling rook without a king on its rank"))?;
+            let side = if rook.file().index() > king.file().index() {
+                CastlingSide::King
+            } else {
+                CastlingSide::Queen
+            };
+            state.castling.set_rook_file(color, side, Some(rook.file()));
+        }
+
+        let side_to_move = (bytes[24] >> 7) as u16;
+        state.en_passant = match bytes[24] & 0x7F {
+            NO_EN_PASSANT => None,
+            index => Some(
+                Square::from_index(index as usize)
+                    .ok_or_else(|| invalid("bad en passant square"))?,
+            ),
+        };
+        state.halfmove_clock = bytes[25] as u16;
+        let fullmove = u16::from_le_bytes([bytes[26], bytes[27]]).max(1);
+        state.ply = (fullmove - 1) * 2 + side_to_move;
+        Ok(state)
+    }
+}
+
+pub struct PackedWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> PackedWriter<W> {
+    pub fn new(inner: W) -> PackedWriter<W> {
+        PackedWriter { inner }
+    }
+
+    pub fn write(&mut self, entry: &PackedEntry) -> Result<(), ChessError> {
+        let mut packed = entry.state.to_packed()?;
+        packed.set_score(entry.score);
+        packed.set_result(entry.result);
+        self.inner.write_all(&packed.0)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), ChessError> {
+        self.inner.flush()?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+// iterates over the entries of a file; a truncated final record is an error
+pub struct PackedReader<R: Read> {
+    inner: R,
+}
+
+impl<R: Read> PackedReader<R> {
+    pub fn new(inner: R) -> PackedReader<R> {
+        PackedReader { inner }
+    }
+
+    fn read_entry(&mut self) -> Result<Option<PackedEntry>, ChessError> {
+        let mut bytes = [0; PACKED_SIZE];
+        let mut filled = 0;
+        while filled < PACKED_SIZE {
+            match self.inner.read(&mut bytes[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => {
+                    return Err(ChessError::InvalidPackedPosition(format!(
+                        "truncated record of {} bytes",
+                        filled
+                    )))
+                }
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let packed = PackedPosition(bytes);
+        Ok(Some(PackedEntry {
+            state: State::from_packed(&packed)?,
+            score: packed.score(),
+            result: packed.result(),
+        }))
+    }
+}
+
+impl<R: Read> Iterator for PackedReader<R> {
+    type Item = Result<PackedEntry, ChessError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_entry().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FENS: [&str; 5] = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b Kq - 3 12",
+        "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+        // chess960 with inner rooks on both sides
+        "1r2k1r1/8/8/8/8/8/8/1R2K1R1 w GBgb - 0 1",
+        "8/8/4k3/8/8/8/8/4K3 b - - 300 900",
+    ];
+
+    #[test]
+    fn round_trip() {
+        for fen in FENS {
+            let state = State::from_fen(fen).unwrap();
+            let restored = State::from_packed(&state.to_packed().unwrap()).unwrap();
+            // the halfmove clock saturates at 255
+            let expected = fen.replace(" 300 ", " 255 ");
+            assert_eq!(
+                restored.to_shredder_fen(),
+                State::from_fen(&expected).unwrap().to_shredder_fen()
+            );
+        }
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let entries: Vec<PackedEntry> = FENS
+            .iter()
+            .zip([
+                Some(GameResult::WhiteWins),
+                None,
+                Some(GameResult::Draw),
+                Some(GameResult::BlackWins),
+                None,
+            ])
+            .enumerate()
+            .map(|(i, (fen, result))| PackedEntry {
+                state: State::from_fen(fen).unwrap(),
+                score: i as i16 * 100 - 250,
+                result,
+            })
+            .collect();
+
+        let mut writer = PackedWriter::new(Vec::new());
+        for entry in &entries {
+            writer.write(entry).unwrap();
+        }
+        let bytes = writer.into_inner();
+        assert_eq!(bytes.len(), entries.len() * PACKED_SIZE);
+
+        let read: Vec<PackedEntry> = PackedReader::new(&bytes[..]).map(Result::unwrap).collect();
+        assert_eq!(read.len(), entries.len());
+        for (a, b) in entries.iter().zip(&read) {
+            assert_eq!(a.state.hash(), b.state.hash());
+            assert_eq!(a.score, b.score);
+            assert_eq!(a.result, b.result);
+        }
+
+        let mut truncated = PackedReader::new(&bytes[..PACKED_SIZE + 5]);
+        assert!(truncated.next().unwrap().is_ok());
+        assert!(truncated.next().unwrap().is_err());
+        assert!(truncated.next().is_none());
+    }
+}
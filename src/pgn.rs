@@ -0,0 +1,522 @@
+// reading and writing PGN games: tags, moves and, for each move, its comment, NAGs and the
+// variations that could have been played instead
+use crate::errors::ChessError;
+use crate::game::GameResult;
+use crate::moves::Move;
+use crate::piece::Color;
+use crate::state::State;
+use std::fmt;
+use std::str::FromStr;
+
+const STANDARD_START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+const SEVEN_TAGS: [&str; 7] = ["Event", "Site", "Date", "Round", "White", "Black", "Result"];
+const LINE_WIDTH: usize = 80;
+// the move suffixes for NAGs 1 to 6
+const GLYPHS: [&str; 6] = ["!", "?", "!!", "??", "!?", "?!"];
+
+#[derive(Debug, Clone)]
+pub struct PgnMove {
+    pub mv: Move,
+    pub comment: Option<String>,
+    // numeric annotation glyphs, written as move suffixes where there is one
+    pub nags: Vec<u8>,
+    // alternatives to this move, from the position before it
+    pub variations: Vec<Vec<PgnMove>>,
+}
+
+impl PgnMove {
+    pub fn new(mv: Move) -> PgnMove {
+        PgnMove {
+            mv,
+            comment: None,
+            nags: Vec::new(),
+            variations: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub start: State,
+    pub moves: Vec<PgnMove>,
+    // None for an unfinished game, written as *
+    pub result: Option<GameResult>,
+}
+
+impl PgnGame {
+    // the seven tag roster filled with "?", plus SetUp and FEN for other start positions
+    pub fn new(start: State) -> PgnGame {
+        let mut game = PgnGame {
+            tags: SEVEN_TAGS
+                .iter()
+                .map(|&name| (name.to_string(), "?".to_string()))
+                .collect(),
+            start,
+            moves: Vec::new(),
+            result: None,
+        };
+        game.set_tag("Result", "*");
+        let fen = game.start.to_fen();
+        if fen != STANDARD_START {
+            game.set_tag("SetUp", "1");
+            game.set_tag("FEN", &fen);
+        }
+        game
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some((_, old)) => *old = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn push(&mut self, mv: Move, comment: Option<String>) {
+        self.moves.push(PgnMove {
+            comment,
+            ..PgnMove::new(mv)
+        });
+    }
+
+    pub fn set_result(&mut self, result: Option<GameResult>) {
+        self.result = result;
+        let token = result_token(result);
+        self.set_tag("Result", &token);
+    }
+
+    // the position after the main line
+    pub fn end_state(&self) -> State {
+        let mut state = self.start.clone();
+        for pgn_move in &self.moves {
+            state.make_move(pgn_move.mv);
+        }
+        state
+    }
+
+    // every game in `text`, in order
+    pub fn parse_all(text: &str) -> Result<Vec<PgnGame>, ChessError> {
+        let mut games = Vec::new();
+        let mut parser = Parser::new(tokenize(text)?);
+        while let Some(game) = parser.game()? {
+            games.push(game);
+        }
+        Ok(games)
+    }
+}
+
+fn result_token(result: Option<GameResult>) -> String {
+    result.map_or("*".to_string(), |result| result.to_string())
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl fmt::Display for PgnGame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, value) in &self.tags {
+            writeln!(f, "[{} \"{}\"]", name, escape(value))?;
+        }
+        writeln!(f)?;
+
+        let mut words = Vec::new();
+        write_moves(&mut words, &self.start, &self.moves);
+        words.push(result_token(self.result));
+
+        let mut line = String::new();
+        for word in words {
+            if !line.is_empty() && line.len() + 1 + word.len() > LINE_WIDTH {
+                writeln!(f, "{}", line)?;
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line += &word;
+        }
+        writeln!(f, "{}", line)
+    }
+}
+
+// the words for a line of moves; black's moves get their number at the start of the line and
+// after a comment or variation
+fn write_moves(words: &mut Vec<String>, start: &State, moves: &[PgnMove]) {
+    let mut state = start.clone();
+    let mut resume = true;
+    for pgn_move in moves {
+        let number = state.ply() / 2 + 1;
+        match state.side_to_move() {
+            Color::White => words.push(format!("{}.", number)),
+            Color::Black if resume => words.push(format!("{}...", number)),
+            Color::Black => {}
+        }
+        // the first NAG as a suffix when it has one, the rest as $n
+        let mut san = state.to_san(pgn_move.mv);
+        let mut nags = pgn_move.nags.iter();
+        let glyph = pgn_move
+            .nags
+            .first()
+            .and_then(|&nag| GLYPHS.get(nag.wrapping_sub(1) as usize));
+        if let Some(glyph) = glyph {
+            san.push_str(glyph);
+            nags.next();
+        }
+        words.push(san);
+        words.extend(nags.map(|nag| format!("${}", nag)));
+        resume = false;
+        if let Some(comment) = &pgn_move.comment {
+            words.push(format!("{{{}}}", comment.replace('}', ")")));
+            resume = true;
+        }
+        for variation in &pgn_move.variations {
+            let first = words.len();
+            write_moves(words, &state, variation);
+            if words.len() > first {
+                words[first].insert(0, '(');
+                words.last_mut().unwrap().push(')');
+                resume = true;
+            }
+        }
+        state.make_move(pgn_move.mv);
+    }
+}
+
+impl FromStr for PgnGame {
+    type Err = ChessError;
+
+    // the first game in `s`
+    fn from_str(s: &str) -> Result<PgnGame, ChessError> {
+        Parser::new(tokenize(s)?)
+            .game()?
+            .ok_or_else(|| ChessError::InvalidPGN("no game found".to_string()))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Tag(String, String),
+    Comment(String),
+    Nag(u8),
+    Open,
+    Close,
+    Result(Option<GameResult>),
+    San(String),
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, ChessError> {
+    let invalid = |reason: &str| ChessError::InvalidPGN(reason.to_string());
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '[' => {
+                let mut name = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '"' {
+                        break;
+                    }
+                    name.push(c);
+                    chars.next();
+                }
+                while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                    chars.next();
+                }
+                if chars.next() != Some('"') {
+                    return Err(invalid("tag without a quoted value"));
+                }
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => value.extend(chars.next()),
+                        Some('"') => break,
+                        Some(c) => value.push(c),
+                        None => return Err(invalid("unterminated tag")),
+                    }
+                }
+                while chars.next_if(|&c| c != ']').is_some() {}
+                if chars.next() != Some(']') {
+                    return Err(invalid("unterminated tag"));
+                }
+                tokens.push(Token::Tag(name, value));
+            }
+            '{' => {
+                let mut comment = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => comment.push(c),
+                        None => return Err(invalid("unterminated comment")),
+                    }
+                }
+                tokens.push(Token::Comment(comment.trim().to_string()));
+            }
+            // rest of line comments and escaped lines
+            ';' | '%' => while chars.next_if(|&c| c != '\n').is_some() {},
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '$' => {
+                let mut digits = String::new();
+                while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                    digits.push(digit);
+                }
+                let nag = digits.parse().map_err(|_| invalid("bad NAG"))?;
+                tokens.push(Token::Nag(nag));
+            }
+            _ => {
+                let mut word = c.to_string();
+                while let Some(c) =
+                    chars.next_if(|&c| !c.is_whitespace() && !"{}()[];$".contains(c))
+                {
+                    word.push(c);
+                }
+                match word.as_str() {
+                    "1-0" => tokens.push(Token::Result(Some(GameResult::WhiteWins))),
+                    "0-1" => tokens.push(Token::Result(Some(GameResult::BlackWins))),
+                    "1/2-1/2" => tokens.push(Token::Result(Some(GameResult::Draw))),
+                    "*" => tokens.push(Token::Result(None)),
+                    _ => {
+                        // move numbers, possibly glued to the move: "12.", "12...", "12.e4"
+                        let san = word.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+                        if !san.is_empty() {
+                            tokens.push(Token::San(san.to_string()));
+                        }
+                        // and suffixes such as "e4!?" which stand for a NAG
+                        let suffix = &san[san.trim_end_matches(['!', '?']).len()..];
+                        if let Some(index) = GLYPHS.iter().position(|&glyph| glyph == suffix) {
+                            tokens.push(Token::Nag(index as u8 + 1));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: std::vec::IntoIter<Token>,
+    peeked: Option<Token>,
+}
+
+impl Parser {
+    fn new(tokens: Vec<Token>) -> Parser {
+        Parser {
+            tokens: tokens.into_iter(),
+            peeked: None,
+        }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        self.peeked.take().or_else(|| self.tokens.next())
+    }
+
+    fn game(&mut self) -> Result<Option<PgnGame>, ChessError> {
+        let mut tags = Vec::new();
+        while let Some(token) = self.next() {
+            match token {
+                Token::Tag(name, value) => tags.push((name, value)),
+                token => {
+                    self.peeked = Some(token);
+                    break;
+                }
+            }
+        }
+        if tags.is_empty() && self.peeked.is_none() {
+            return Ok(None);
+        }
+
+        let start = match tags.iter().find(|(name, _)| name == "FEN") {
+            Some((_, fen)) => State::from_fen(fen)?,
+            None => State::default(),
+        };
+        let moves = self.line(&start, false)?;
+        let mut game = PgnGame {
+            tags,
+            start,
+            moves,
+            result: None,
+        };
+        // a game may end without a result token, before the next game's tags
+        match self.next() {
+            Some(Token::Result(result)) => game.result = result,
+            token => self.peeked = token,
+        }
+        Ok(Some(game))
+    }
+
+    // the moves of the main line or a variation, each with the comment, NAGs and variations
+    // that follow it; a variation ends at its ), the main line before the result or the
+    // next game's tags
+    fn line(&mut self, start: &State, nested: bool) -> Result<Vec<PgnMove>, ChessError> {
+        let invalid = |reason: &str| ChessError::InvalidPGN(reason.to_string());
+        let mut moves: Vec<PgnMove> = Vec::new();
+        let mut state = start.clone();
+        // where the variations of the last move start
+        let mut before = start.clone();
+        while let Some(token) = self.next() {
+            match token {
+                Token::San(san) => {
+                    let mv = state.parse_san(&san)?;
+                    before = state.clone();
+                    state.make_move(mv);
+                    moves.push(PgnMove::new(mv));
+                }
+                Token::Comment(comment) => {
+                    if let Some(last) = moves.last_mut() {
+                        last.comment = Some(comment);
+                    }
+                }
+                Token::Nag(nag) => {
+                    if let Some(last) = moves.last_mut() {
+                        last.nags.push(nag);
+                    }
+                }
+                Token::Open => {
+                    let variation = self.line(&before, true)?;
+                    moves
+                        .last_mut()
+                        .ok_or_else(|| invalid("variation before the first move"))?
+                        .variations
+                        .push(variation);
+                }
+                Token::Close if nested => return Ok(moves),
+                Token::Close => return Err(invalid("unbalanced )")),
+                token @ (Token::Result(_) | Token::Tag(..)) if !nested => {
+                    self.peeked = Some(token);
+                    return Ok(moves);
+                }
+                Token::Result(_) | Token::Tag(..) => break,
+            }
+        }
+        if nested {
+            return Err(invalid("unterminated variation"));
+        }
+        Ok(moves)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAMES: &str = r#"
+[Event "Casual \"blitz\""]
+[Site "?"]
+[White "Anderssen"]
+[Black "Kieseritzky"]
+[Result "1-0"]
+
+1. e4 e5 2. f4 {the King's Gambit} exf4 $1 (2... d5 3. exd5) 3. Bc4 Qh4+ 4.Kf1 b5
+5. Bxb5 1-0
+
+[Event "Endgame"]
+[SetUp "1"]
+[FEN "8/8/8/8/8/4k3/4p3/4K3 b - - 0 60"]
+
+60... Kd3 ; stalemate next
+*
+"#;
+
+    #[test]
+    fn reads_games() {
+        let games = PgnGame::parse_all(GAMES).unwrap();
+        assert_eq!(games.len(), 2);
+
+        let first = &games[0];
+        assert_eq!(first.tag("Event"), Some("Casual \"blitz\""));
+        assert_eq!(first.tag("Black"), Some("Kieseritzky"));
+        assert_eq!(first.moves.len(), 9);
+        assert_eq!(first.moves[2].comment.as_deref(), Some("the King's Gambit"));
+        assert_eq!(first.moves[3].nags, [1]);
+        let variation = &first.moves[3].variations[0];
+        assert_eq!(variation.len(), 2);
+        assert_eq!(variation[0].mv.to_uci(false), "d7d5");
+        assert_eq!(first.result, Some(GameResult::WhiteWins));
+        assert_eq!(
+            first.end_state().to_fen(),
+            "rnb1kbnr/p1pp1ppp/8/1B6/4Pp1q/8/PPPP2PP/RNBQ1KNR b kq - 0 5"
+        );
+
+        let second = &games[1];
+        assert_eq!(second.moves.len(), 1);
+        assert_eq!(second.result, None);
+        assert_eq!(second.start.side_to_move(), Color::Black);
+
+        assert!(PgnGame::parse_all("1. e4 e5 2. Ke3 *").is_err());
+        assert!(PgnGame::parse_all("1. e4 (1. d4 e5 *").is_err());
+        assert!(PgnGame::parse_all("1. e4 (1. e3) e5 (1... d5 2. Qd5) *").is_err());
+        assert!(PgnGame::parse_all("[Event \"unterminated").is_err());
+        assert!("".parse::<PgnGame>().is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        for game in PgnGame::parse_all(GAMES).unwrap() {
+            let text = game.to_string();
+            let again: PgnGame = text.parse().unwrap();
+            assert_eq!(again.tags, game.tags);
+            assert_eq!(again.to_string(), text);
+        }
+    }
+
+    #[test]
+    fn writes_games() {
+        let mut game = PgnGame::new(State::default());
+        for san in ["e4", "e5", "Nf3"] {
+            let mv = game.end_state().parse_san(san).unwrap();
+            game.push(mv, None);
+        }
+        game.moves[1].comment = Some("+0.20/12 0.5s".to_string());
+        game.set_result(Some(GameResult::Draw));
+        assert_eq!(
+            game.to_string(),
+            "[Event \"?\"]\n[Site \"?\"]\n[Date \"?\"]\n[Round \"?\"]\n[White \"?\"]\n\
+             [Black \"?\"]\n[Result \"1/2-1/2\"]\n\n1. e4 e5 {+0.20/12 0.5s} 2. Nf3 1/2-1/2\n"
+        );
+
+        let start = State::from_fen("4k3/8/8/8/8/8/8/4K2R b K - 3 20").unwrap();
+        let mut game = PgnGame::new(start);
+        let mv = game.end_state().parse_san("Kd7").unwrap();
+        game.push(mv, None);
+        let text = game.to_string();
+        assert!(text.contains("[FEN \"4k3/8/8/8/8/8/8/4K2R b K - 3 20\"]"));
+        assert!(text.ends_with("20... Kd7 *\n"));
+
+        let mut annotated = PgnGame::new(State::default());
+        for san in ["e4", "e5"] {
+            let mv = annotated.end_state().parse_san(san).unwrap();
+            annotated.push(mv, None);
+        }
+        annotated.moves[0].nags = vec![6, 146];
+        let d4 = State::default().parse_san("d4").unwrap();
+        annotated.moves[0].variations.push(vec![PgnMove::new(d4)]);
+        annotated.moves[1].nags = vec![4];
+        let text = annotated.to_string();
+        assert!(text.ends_with("\n1. e4?! $146 (1. d4) 1... e5?? *\n"));
+        let again: PgnGame = text.parse().unwrap();
+        assert_eq!(again.moves[0].nags, [6, 146]);
+        assert_eq!(again.moves[1].nags, [4]);
+        assert_eq!(again.to_string(), text);
+
+        let mut long = PgnGame::new(State::default());
+        for _ in 0..10 {
+            for san in ["Nf3", "Nf6", "Ng1", "Ng8"] {
+                let mv = long.end_state().parse_san(san).unwrap();
+                long.push(mv, None);
+            }
+        }
+        assert!(long
+            .to_string()
+            .lines()
+            .all(|line| line.len() <= LINE_WIDTH));
+    }
+}
@@ -1,8 +1,13 @@
-use std::{mem::transmute, str::FromStr};
+use std::{fmt, mem::transmute, ops::Not, str::FromStr};
 
 use crate::{errors::ChessError, utils::impl_index};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
 pub enum Color {
     White,
     Black,
@@ -20,7 +25,23 @@ impl FromStr for Color {
     }
 }
 
+impl Not for Color {
+    type Output = Color;
+
+    fn not(self) -> Self::Output {
+        match self {
+            Color::White => Color::Black,
+            Color::Black => Color::White,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
 pub enum PieceType {
     Pawn,
     Knight,
@@ -56,15 +77,15 @@ impl_index! { Piece(PIECE_COUNT) }
 
 impl Piece {
     pub fn new(color: Color, piece_type: PieceType) -> Piece {
-        unsafe { transmute((color.index() + piece_type.index() * 2) as u8) }
+        unsafe { transmute::<u8, Piece>((color.index() + piece_type.index() * 2) as u8) }
     }
 
     pub fn piece_type(self) -> PieceType {
-        unsafe { transmute((self.index() / 2) as u8) }
+        unsafe { transmute::<u8, PieceType>((self.index() / 2) as u8) }
     }
 
     pub fn color(self) -> Color {
-        unsafe { transmute((self.index() % 2) as u8) }
+        unsafe { transmute::<u8, Color>((self.index() % 2) as u8) }
     }
 
     pub fn as_str(&self) -> &'static str {
@@ -83,6 +104,23 @@ impl Piece {
             Piece::BlackKing => "k",
         }
     }
+
+    pub fn to_unicode(self) -> char {
+        match self {
+            Piece::WhitePawn => '♙',
+            Piece::BlackPawn => '♟',
+            Piece::WhiteKnight => '♘',
+            Piece::BlackKnight => '♞',
+            Piece::WhiteBishop => '♗',
+            Piece::BlackBishop => '♝',
+            Piece::WhiteRook => '♖',
+            Piece::BlackRook => '♜',
+            Piece::WhiteQueen => '♕',
+            Piece::BlackQueen => '♛',
+            Piece::WhiteKing => '♔',
+            Piece::BlackKing => '♚',
+        }
+    }
 }
 
 impl FromStr for Piece {
@@ -106,3 +144,9 @@ impl FromStr for Piece {
         }
     }
 }
+
+impl fmt::Display for Piece {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
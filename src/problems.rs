@@ -0,0 +1,851 @@
+// chess problems: direct mates, helpmates, selfmates and reflexmates, solved for every solution
+// so that cooks, duals and duplicates show up, and twins derived from the diagram position
+use crate::cli::{exit_on_error, flag_groups, parse_flag, usage};
+use crate::errors::ChessError;
+use crate::mate::{MateSettings, MateSolver};
+use crate::moves::Move;
+use crate::piece::{Color, Piece, PieceType};
+use crate::rank::Rank;
+use crate::square::Square;
+use crate::state::{CastlingSide, State};
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Kind {
+    // the side to move mates against any defence
+    Mate,
+    // both sides cooperate, the side to move starts and gets mated
+    Helpmate,
+    // the side to move forces the other side to mate it, which the other side avoids
+    Selfmate,
+    // a selfmate where the other side has to mate whenever it can
+    Reflexmate,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Stipulation {
+    pub kind: Kind,
+    // moves of the side to move
+    pub moves: u32,
+}
+
+impl FromStr for Stipulation {
+    type Err = ChessError;
+
+    // #2, h#3, s#4 or r#2
+    fn from_str(s: &str) -> Result<Stipulation, ChessError> {
+        let (kind, moves) = match s.split_once('#') {
+            Some(("", moves)) => (Kind::Mate, moves),
+            Some(("h", moves)) => (Kind::Helpmate, moves),
+            Some(("s", moves)) => (Kind::Selfmate, moves),
+            Some(("r", moves)) => (Kind::Reflexmate, moves),
+            _ => return Err(ChessError::ParseError(s.to_string(), "stipulation")),
+        };
+        match moves.parse() {
+            Ok(moves) if moves > 0 => Ok(Stipulation { kind, moves }),
+            _ => Err(ChessError::ParseError(s.to_string(), "stipulation")),
+        }
+    }
+}
+
+impl fmt::Display for Stipulation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let prefix = match self.kind {
+            Kind::Mate => "",
+            Kind::Helpmate => "h",
+            Kind::Selfmate => "s",
+            Kind::Reflexmate => "r",
+        };
+        write!(f, "{}#{}", prefix, self.moves)
+    }
+}
+
+// one change to the diagram for a twin
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Change {
+    Remove(Square),
+    Add(Piece, Square),
+    Move(Square, Square),
+    Exchange(Square, Square),
+    Stipulation(Stipulation),
+}
+
+// the square at the end of a piece and square such as wPb6, Pb6 or just b6
+fn trailing_square(s: &str) -> Option<Square> {
+    s.get(s.len().checked_sub(2)?..)?.parse().ok()
+}
+
+impl FromStr for Change {
+    type Err = ChessError;
+
+    // -b6 (or -wPb6), +bQa1, Kc8-d8, c8<->d8 or a new stipulation such as h#3
+    fn from_str(s: &str) -> Result<Change, ChessError> {
+        let invalid = || ChessError::InvalidProblem(format!("bad twin {}", s));
+        if let Some(removed) = s.strip_prefix('-') {
+            return trailing_square(removed)
+                .map(Change::Remove)
+                .ok_or_else(invalid);
+        }
+        if let Some(added) = s.strip_prefix('+') {
+            let mut chars = added.chars();
+            let (Some(color), Some(letter), 4) = (chars.next(), chars.next(), added.len()) else {
+                return Err(invalid());
+            };
+            let letter = match color {
+                'w' => letter.to_ascii_uppercase(),
+                'b' => letter.to_ascii_lowercase(),
+                _ => return Err(invalid()),
+            };
+            let piece = letter.to_string().parse().map_err(|_| invalid())?;
+            return trailing_square(added)
+                .map(|square| Change::Add(piece, square))
+                .ok_or_else(invalid);
+        }
+        let squares = |from: &str, to: &str| Some((trailing_square(from)?, trailing_square(to)?));
+        if let Some((from, to)) = s.split_once("<->") {
+            let (from, to) = squares(from, to).ok_or_else(invalid)?;
+            return Ok(Change::Exchange(from, to));
+        }
+        if let Some((from, to)) = s.split_once('-') {
+            let (from, to) = squares(from, to).ok_or_else(invalid)?;
+            return Ok(Change::Move(from, to));
+        }
+        s.parse().map(Change::Stipulation).map_err(|_| invalid())
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Remove(square) => write!(f, "-{}", square),
+            Change::Add(piece, square) => {
+                let color = if piece.color() == Color::White {
+                    'w'
+                } else {
+                    'b'
+                };
+                let letter = piece.as_str().to_ascii_uppercase();
+                write!(f, "+{}{}{}", color, letter, square)
+            }
+            Change::Move(from, to) => write!(f, "{}-{}", from, to),
+            Change::Exchange(from, to) => write!(f, "{}<->{}", from, to),
+            Change::Stipulation(stipulation) => write!(f, "{}", stipulation),
+        }
+    }
+}
+
+// a twin of the diagram: the changes are applied to the original position, not to the
+// previous twin
+pub fn twin(
+    state: &State,
+    stipulation: Stipulation,
+    changes: &[Change],
+) -> Result<(State, Stipulation), ChessError> {
+    let mut state = state.clone();
+    let mut stipulation = stipulation;
+    for change in changes {
+        let board = &mut state.board;
+        let missing =
+            |square: Square| ChessError::InvalidProblem(format!("no piece on {}", square));
+        match *change {
+            Change::Remove(square) => {
+                board.piece(square).ok_or_else(|| missing(square))?;
+                board.set_piece(square, None);
+            }
+            Change::Add(piece, square) => {
+                if board.piece(square).is_some() {
+                    return Err(ChessError::InvalidProblem(format!(
+                        "{} is already occupied",
+                        square
+                    )));
+                }
+                board.set_piece(square, Some(piece));
+            }
+            Change::Move(from, to) => {
+                let piece = board.piece(from).ok_or_else(|| missing(from))?;
+                board.set_piece(from, None);
+                board.set_piece(to, Some(piece));
+            }
+            Change::Exchange(from, to) => {
+                let (first, second) = (board.piece(from), board.piece(to));
+                board.set_piece(from, second);
+                board.set_piece(to, first);
+            }
+            Change::Stipulation(new) => stipulation = new,
+        }
+    }
+
+    // castling survives where the king and rook are still at home, en passant never does
+    state.en_passant = None;
+    for color in [Color::White, Color::Black] {
+        let home = Rank::back_rank(color);
+        let king_home = state
+            .board
+            .king_square(color)
+            .is_some_and(|square| square.rank() == home);
+        for side in [CastlingSide::King, CastlingSide::Queen] {
+            let rook = Piece::new(color, PieceType::Rook);
+            let rook_home = state
+                .castling
+                .rook_file(color, side)
+                .is_some_and(|file| state.board.piece(Square::new(home, file)) == Some(rook));
+            if !king_home || !rook_home {
+                state.castling.set_rook_file(color, side, None);
+            }
+        }
+    }
+    validate(&state)?;
+    Ok((state, stipulation))
+}
+
+// a position the solver can work with: one king each, no pawns on the first or last rank and
+// the side that just moved not in check
+pub fn validate(state: &State) -> Result<(), ChessError> {
+    for color in [Color::White, Color::Black] {
+        if state
+            .board
+            .by_piece(Piece::new(color, PieceType::King))
+            .count()
+            != 1
+        {
+            return Err(ChessError::InvalidProblem(format!(
+                "{:?} needs exactly one king",
+                color
+            )));
+        }
+    }
+    let stranded = state.board.pieces().any(|(square, piece)| {
+        piece.is_some_and(|piece| piece.piece_type() == PieceType::Pawn)
+            && matches!(square.rank(), Rank::Rank1 | Rank::Rank8)
+    });
+    if stranded {
+        return Err(ChessError::InvalidProblem(
+            "pawn on the first or last rank".to_string(),
+        ));
+    }
+    if state.king_attacked(!state.side_to_move()) {
+        return Err(ChessError::InvalidProblem(
+            "the side not to move is in check".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// a move of the side fulfilling the stipulation, with the play after it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Play {
+    pub mv: Move,
+    // every defence worth playing, with every answer that fulfils the stipulation soonest, so
+    // more than one answer is a dual; defences without answers end the problem
+    pub defences: Vec<(Move, Vec<Play>)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Solution {
+    // a helpmate line, both sides' moves starting with the side to move
+    Help(Vec<Move>),
+    // the key of the other stipulations and the play after it
+    Forced(Play),
+}
+
+impl Solution {
+    pub fn first_move(&self) -> Move {
+        match self {
+            Solution::Help(line) => line[0],
+            Solution::Forced(play) => play.mv,
+        }
+    }
+
+    // standard problem notation, counting moves from 1: "1.Kg8 Ra8#" for helpmates, the key
+    // marked with "!" and each defence on its own line for the rest
+    pub fn format(&self, state: &State) -> String {
+        let mut text = String::new();
+        match self {
+            Solution::Help(line) => {
+                text.push_str(&notation(state, line));
+                text.push('\n');
+            }
+            Solution::Forced(play) => write_play(play, state, 1, 0, true, &mut text),
+        }
+        text
+    }
+}
+
+// a line of moves from the problem position, "1.Kg8 Ra8#"
+pub fn notation(state: &State, line: &[Move]) -> String {
+    let mut state = state.clone();
+    let mut text = String::new();
+    for (index, &mv) in line.iter().enumerate() {
+        if index % 2 == 0 {
+            let space = if index > 0 { " " } else { "" };
+            let _ = write!(text, "{}{}.", space, index / 2 + 1);
+        } else {
+            text.push(' ');
+        }
+        text.push_str(&state.to_san(mv));
+        state.make_move(mv);
+    }
+    text
+}
+
+fn write_play(
+    play: &Play,
+    state: &State,
+    number: usize,
+    indent: usize,
+    key: bool,
+    text: &mut String,
+) {
+    let mut state = state.clone();
+    let _ = write!(text, "{:indent$}{}.{}", "", number, state.to_san(play.mv));
+    text.push_str(if key { "!\n" } else { "\n" });
+    state.make_move(play.mv);
+    for (defence, answers) in &play.defences {
+        let _ = write!(
+            text,
+            "{:indent$}  {}...{}",
+            "",
+            number,
+            state.to_san(*defence)
+        );
+        let undo = state.make_move(*defence);
+        // answers that end the problem fit on the defence's line, duals separated by "/"
+        if answers.iter().all(|answer| answer.defences.is_empty()) {
+            let sans: Vec<String> = answers
+                .iter()
+                .map(|answer| state.to_san(answer.mv))
+                .collect();
+            if !sans.is_empty() {
+                let _ = write!(text, " {}.{}", number + 1, sans.join("/"));
+            }
+            text.push('\n');
+        } else {
+            text.push('\n');
+            for answer in answers {
+                write_play(answer, &state, number + 1, indent + 4, false, text);
+            }
+        }
+        state.unmake_move(*defence, undo);
+    }
+}
+
+pub struct Report {
+    pub solutions: Vec<Solution>,
+    // pairs of helpmate solutions that are the same moves in a different order
+    pub duplicates: Vec<(usize, usize)>,
+    // the lines, from the key up to a defence, where the defence has more than one answer
+    pub duals: Vec<Vec<Move>>,
+    pub nodes: u64,
+    // false when the limits cut a direct mate short, so solutions may be missing
+    pub complete: bool,
+}
+
+impl Report {
+    // more solutions than the composer intended
+    pub fn cooked(&self, intended: usize) -> bool {
+        self.solutions.len() > intended
+    }
+}
+
+// what the defender can do after a move of the side fulfilling the stipulation
+enum Turn {
+    // the stipulation is fulfilled, by the defender's mating moves in a self- or reflexmate
+    Fulfilled(Vec<Move>),
+    Failed,
+    // the defences that keep the problem going
+    Defences(Vec<Move>),
+}
+
+pub struct Solver {
+    stipulation: Stipulation,
+    nodes: u64,
+    // the outcome of positions by hash and the moves left, for helpmates only the failures
+    table: HashMap<(u64, u32), bool>,
+    // answers whether a direct mate is still there, within its node and time limits
+    mate: MateSolver,
+}
+
+impl Solver {
+    pub fn new(stipulation: Stipulation) -> Solver {
+        Solver {
+            stipulation,
+            nodes: 0,
+            table: HashMap::new(),
+            mate: MateSolver::new(MateSettings::default()),
+        }
+    }
+
+    // for direct mates, which can take a long time to disprove
+    pub fn set_limits(&mut self, max_nodes: Option<u64>, movetime: Option<Duration>) {
+        self.mate = MateSolver::new(MateSettings {
+            max_nodes,
+            movetime,
+            ..MateSettings::default()
+        });
+    }
+
+    pub fn solve(&mut self, state: &State) -> Report {
+        self.nodes = 0;
+        self.mate.restart();
+        let mut state = state.clone();
+        let moves = self.stipulation.moves;
+        let solutions: Vec<Solution> = if self.stipulation.kind == Kind::Helpmate {
+            let mut lines = Vec::new();
+            self.help(&mut state, moves * 2, &mut Vec::new(), &mut lines);
+            lines.into_iter().map(Solution::Help).collect()
+        } else {
+            let mut keys = Vec::new();
+            let keys_left = if self.must_mate(&mut state) {
+                Vec::new()
+            } else {
+                state.legal_moves()
+            };
+            for mv in keys_left {
+                let undo = state.make_move(mv);
+                let solves = self.defend(&mut state, moves);
+                state.unmake_move(mv, undo);
+                let play = solves.then(|| self.play(&mut state, mv, moves));
+                // play cut short by the limits would look complete, so it's left out
+                if self.mate.aborted() {
+                    break;
+                }
+                keys.extend(play.map(Solution::Forced));
+            }
+            keys
+        };
+
+        let mut duplicates = Vec::new();
+        let sorted: Vec<Vec<String>> = solutions
+            .iter()
+            .map(|solution| match solution {
+                Solution::Help(line) => {
+                    let mut moves: Vec<String> = line.iter().map(|mv| mv.to_uci(false)).collect();
+                    moves.sort();
+                    moves
+                }
+                Solution::Forced(_) => Vec::new(),
+            })
+            .collect();
+        for (first, moves) in sorted.iter().enumerate() {
+            for (second, other) in sorted.iter().enumerate().skip(first + 1) {
+                if !moves.is_empty() && moves == other {
+                    duplicates.push((first, second));
+                }
+            }
+        }
+        let mut duals = Vec::new();
+        for solution in &solutions {
+            if let Solution::Forced(play) = solution {
+                find_duals(play, &mut Vec::new(), &mut duals);
+            }
+        }
+        Report {
+            solutions,
+            duplicates,
+            duals,
+            nodes: self.nodes + self.mate.nodes(),
+            complete: !self.mate.aborted(),
+        }
+    }
+
+    // every line of `plies` cooperating moves that ends with the starting side mated
+    fn help(
+        &mut self,
+        state: &mut State,
+        plies: u32,
+        line: &mut Vec<Move>,
+        lines: &mut Vec<Vec<Move>>,
+    ) -> bool {
+        self.nodes += 1;
+        let key = (state.hash(), plies);
+        if self.table.contains_key(&key) {
+            return false;
+        }
+        let mut found = false;
+        for mv in state.legal_moves() {
+            let undo = state.make_move(mv);
+            line.push(mv);
+            if plies > 1 {
+                found |= self.help(state, plies - 1, line, lines);
+            } else if state.in_check() && state.legal_moves().is_empty() {
+                lines.push(line.clone());
+                found = true;
+            }
+            line.pop();
+            state.unmake_move(mv, undo);
+        }
+        if !found {
+            self.table.insert(key, false);
+        }
+        found
+    }
+
+    fn turn(&self, state: &mut State) -> Turn {
+        let defences = state.legal_moves();
+        if self.stipulation.kind == Kind::Mate {
+            return match (defences.is_empty(), state.in_check()) {
+                (true, true) => Turn::Fulfilled(Vec::new()),
+                (true, false) => Turn::Failed,
+                (false, _) => Turn::Defences(defences),
+            };
+        }
+        if defences.is_empty() {
+            return Turn::Failed;
+        }
+        let (mating, others): (Vec<Move>, Vec<Move>) =
+            defences.into_iter().partition(|&mv| mates(state, mv));
+        let reflex = self.stipulation.kind == Kind::Reflexmate && !mating.is_empty();
+        if others.is_empty() || reflex {
+            Turn::Fulfilled(mating)
+        } else {
+            Turn::Defences(others)
+        }
+    }
+
+    // in a reflexmate the attacker has to mate when it can, which fails the stipulation
+    fn must_mate(&self, state: &mut State) -> bool {
+        self.stipulation.kind == Kind::Reflexmate
+            && state.legal_moves().into_iter().any(|mv| mates(state, mv))
+    }
+
+    // whether the side to move fulfils the stipulation within `moves`; direct mates are left
+    // to the mate solver
+    fn attack(&mut self, state: &mut State, moves: u32) -> bool {
+        if self.stipulation.kind == Kind::Mate {
+            return self.mate.mates(state, moves);
+        }
+        if moves == 0 {
+            return false;
+        }
+        self.nodes += 1;
+        let key = (state.hash(), moves);
+        if let Some(&known) = self.table.get(&key) {
+            return known;
+        }
+        if self.must_mate(state) {
+            self.table.insert(key, false);
+            return false;
+        }
+        let mut solves = false;
+        for mv in state.legal_moves() {
+            let undo = state.make_move(mv);
+            solves = self.defend(state, moves);
+            state.unmake_move(mv, undo);
+            if solves {
+                break;
+            }
+        }
+        self.table.insert(key, solves);
+        solves
+    }
+
+    // whether every defence fails, after a move that used up one of `moves`
+    fn defend(&mut self, state: &mut State, moves: u32) -> bool {
+        self.nodes += 1;
+        let defences = match self.turn(state) {
+            Turn::Fulfilled(_) => return true,
+            Turn::Failed => return false,
+            Turn::Defences(_) if moves == 1 => return false,
+            Turn::Defences(defences) => defences,
+        };
+        for defence in defences {
+            let undo = state.make_move(defence);
+            let solves = self.attack(state, moves - 1);
+            state.unmake_move(defence, undo);
+            if !solves {
+                return false;
+            }
+        }
+        true
+    }
+
+    // the play after a move that's known to fulfil the stipulation within `moves`
+    fn play(&mut self, state: &mut State, mv: Move, moves: u32) -> Play {
+        let undo = state.make_move(mv);
+        let defences = match self.turn(state) {
+            Turn::Fulfilled(mating) => mating.into_iter().map(|mv| (mv, Vec::new())).collect(),
+            Turn::Failed => Vec::new(),
+            Turn::Defences(defences) => {
+                let mut play = Vec::new();
+                for defence in defences {
+                    let undo = state.make_move(defence);
+                    play.push((defence, self.answers(state, moves - 1)));
+                    state.unmake_move(defence, undo);
+                }
+                play
+            }
+        };
+        state.unmake_move(mv, undo);
+        Play { mv, defences }
+    }
+
+    // every move fulfilling the stipulation as soon as possible
+    fn answers(&mut self, state: &mut State, moves: u32) -> Vec<Play> {
+        let Some(soonest) = (1..=moves).find(|&left| self.attack(state, left)) else {
+            return Vec::new();
+        };
+        let mut answers = Vec::new();
+        for mv in state.legal_moves() {
+            let undo = state.make_move(mv);
+            let solves = self.defend(state, soonest);
+            state.unmake_move(mv, undo);
+            if solves {
+                answers.push(self.play(state, mv, soonest));
+            }
+        }
+        answers
+    }
+}
+
+fn mates(state: &mut State, mv: Move) -> bool {
+    let undo = state.make_move(mv);
+    let mate = state.in_check() && state.legal_moves().is_empty();
+    state.unmake_move(mv, undo);
+    mate
+}
+
+fn find_duals(play: &Play, line: &mut Vec<Move>, duals: &mut Vec<Vec<Move>>) {
+    line.push(play.mv);
+    for (defence, answers) in &play.defences {
+        line.push(*defence);
+        if answers.len() > 1 {
+            duals.push(line.clone());
+        }
+        for answer in answers {
+            find_duals(answer, line, duals);
+        }
+        line.pop();
+    }
+    line.pop();
+}
+
+// problem <fen> <stipulation> [--twin change...]... [--solutions n] [--nodes n] [--movetime ms]
+// stipulations are #n, h#n, s#n or r#n; each --twin is a part b), c)... of the diagram with
+// changes such as -b6, +wQa1, Kc8-d8, c8<->d8 or a new stipulation. The limits apply to
+// direct mates, per part.
+pub fn command(args: &[String]) {
+    let [fen, stipulation, ..] = args else {
+        usage(
+            "problem <fen> <stipulation> [--twin change...]... [--solutions n] [--nodes n] \
+             [--movetime ms]",
+        );
+    };
+    let max_nodes = parse_flag(args, "--nodes");
+    let movetime = parse_flag(args, "--movetime").map(Duration::from_millis);
+    let state = exit_on_error(State::from_fen(fen));
+    exit_on_error(validate(&state));
+    let stipulation: Stipulation = exit_on_error(stipulation.parse());
+    let intended = parse_flag(args, "--solutions").unwrap_or(1);
+    let mut twins = vec![Vec::new()];
+    for group in flag_groups(args, "--twin") {
+        let changes = group.iter().map(|change| change.parse::<Change>());
+        twins.push(exit_on_error(changes.collect()));
+    }
+    // every twin is checked before solving any of them
+    let parts: Vec<_> = twins
+        .iter()
+        .map(|changes| exit_on_error(twin(&state, stipulation, changes)))
+        .collect();
+
+    for (index, (state, stipulation)) in parts.iter().enumerate() {
+        if parts.len() > 1 {
+            let label = (b'a' + index as u8) as char;
+            let described: Vec<String> = twins[index].iter().map(Change::to_string).collect();
+            match index {
+                0 => println!("{}) diagram {}", label, stipulation),
+                _ => println!("{}) {}", label, described.join(" ")),
+            }
+        }
+
+        let start = Instant::now();
+        let mut solver = Solver::new(*stipulation);
+        solver.set_limits(max_nodes, movetime);
+        let report = solver.solve(state);
+        for solution in &report.solutions {
+            print!("{}", solution.format(state));
+        }
+        if report.cooked(intended) {
+            println!(
+                "cooked: {} solutions, {} intended",
+                report.solutions.len(),
+                intended
+            );
+        }
+        for (first, second) in &report.duplicates {
+            println!(
+                "duplicate: solutions {} and {} are the same moves",
+                first + 1,
+                second + 1
+            );
+        }
+        for line in &report.duals {
+            println!("dual after {}", notation(state, line));
+        }
+        if !report.complete {
+            println!("stopped at the limits, there may be more solutions");
+        }
+        println!(
+            "{} solutions, {} nodes in {:.2}s",
+            report.solutions.len(),
+            report.nodes,
+            start.elapsed().as_secs_f64()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solve(fen: &str, stipulation: &str) -> (State, Report) {
+        let state = State::from_fen(fen).unwrap();
+        let report = Solver::new(stipulation.parse().unwrap()).solve(&state);
+        (state, report)
+    }
+
+    fn formatted(state: &State, report: &Report) -> Vec<String> {
+        report
+            .solutions
+            .iter()
+            .map(|solution| solution.format(state))
+            .collect()
+    }
+
+    #[test]
+    fn parsing() {
+        for text in ["#2", "h#3", "s#1", "r#12"] {
+            assert_eq!(text.parse::<Stipulation>().unwrap().to_string(), text);
+        }
+        assert!("h#0".parse::<Stipulation>().is_err());
+        assert!("x#2".parse::<Stipulation>().is_err());
+        for text in ["-b6", "+wQa1", "+bPc3", "c8-d8", "c8<->d8", "s#2"] {
+            assert_eq!(text.parse::<Change>().unwrap().to_string(), text);
+        }
+        assert_eq!(
+            "-wPb6".parse::<Change>().unwrap(),
+            "-b6".parse::<Change>().unwrap()
+        );
+        assert!("+xQa1".parse::<Change>().is_err());
+        assert!("Kc8-z9".parse::<Change>().is_err());
+    }
+
+    #[test]
+    fn direct_mates() {
+        // Morphy: 1.Ra6! bxa6 2.b7#
+        let (state, report) = solve("kbK5/pp6/1P6/8/8/8/8/R7 w - - 0 1", "#2");
+        assert!(!report.cooked(1));
+        let text = &formatted(&state, &report)[0];
+        assert!(text.starts_with("1.Ra6!\n  1...bxa6 2.b7#\n"));
+        assert!(text.contains("  1...Bc7 2.Rxa7#\n"));
+
+        // the rooks mate in all sorts of ways, and after 1.Kg6 Kg8 either of them does
+        let (state, report) = solve("7k/8/5K2/8/8/8/8/RR6 w - - 0 1", "#2");
+        assert!(report.cooked(1));
+        let keys: Vec<String> = report
+            .solutions
+            .iter()
+            .map(|solution| state.to_san(solution.first_move()))
+            .collect();
+        assert!(keys.contains(&"Kg6".to_string()) && keys.contains(&"Rh1+".to_string()));
+        let duals: Vec<String> = report
+            .duals
+            .iter()
+            .map(|line| notation(&state, line))
+            .collect();
+        assert_eq!(duals, ["1.Kg6 Kg8"]);
+        let text = &formatted(&state, &report)[5];
+        assert_eq!(text, "1.Kg6!\n  1...Kg8 2.Ra8#/Rb8#\n");
+    }
+
+    #[test]
+    fn direct_mates_keep_to_the_limits() {
+        // solving this as #5 takes tens of thousands of nodes, far more than these budgets
+        let state = State::from_fen("kbK5/pp6/1P6/8/8/8/8/R7 w - - 0 1").unwrap();
+        let mut solver = Solver::new("#5".parse().unwrap());
+        solver.set_limits(Some(50), None);
+        let report = solver.solve(&state);
+        assert!(!report.complete);
+        assert!(report.solutions.is_empty());
+
+        let mut solver = Solver::new("#9".parse().unwrap());
+        solver.set_limits(None, Some(Duration::ZERO));
+        let report = solver.solve(&state);
+        assert!(!report.complete && report.nodes < 10_000);
+
+        let (_, report) = solve("kbK5/pp6/1P6/8/8/8/8/R7 w - - 0 1", "#2");
+        assert!(report.complete);
+    }
+
+    #[test]
+    fn helpmates() {
+        let (state, report) = solve("7k/8/6K1/8/8/8/8/R7 b - - 0 1", "h#1");
+        assert_eq!(formatted(&state, &report), ["1.Kg8 Ra8#\n"]);
+
+        let (state, report) = solve("7k/8/6K1/8/8/8/8/R7 b - - 0 1", "h#2");
+        assert!(report.solutions.len() > 1);
+        for solution in &report.solutions {
+            let Solution::Help(line) = solution else {
+                panic!("a helpmate solution should be a line");
+            };
+            let mut end = state.clone();
+            for &mv in line {
+                end.make_move(mv);
+            }
+            assert_eq!(line.len(), 4);
+            assert!(end.in_check() && end.legal_moves().is_empty());
+        }
+
+        // the pawn and king moves of black come in either order
+        let (_, report) = solve("7k/8/6K1/8/p7/8/8/1R6 b - - 0 1", "h#2");
+        assert!(!report.duplicates.is_empty());
+        for &(first, second) in &report.duplicates {
+            assert_ne!(report.solutions[first], report.solutions[second]);
+        }
+    }
+
+    #[test]
+    fn self_and_reflexmates() {
+        // black mates with Rd1 whenever it can; only 1.Ng3 keeps the rank open
+        let fen = "3r3k/6pp/8/8/8/8/PP6/K6N w - - 0 1";
+        let (state, report) = solve(fen, "r#1");
+        assert_eq!(formatted(&state, &report), ["1.Ng3!\n  1...Rd1#\n"]);
+        // in a selfmate black would rather move a pawn
+        let (_, report) = solve(fen, "s#1");
+        assert!(report.solutions.is_empty());
+
+        // white has to mate too when it can: 1.c8=Q? Rf8! 2.Qxf8#
+        let fen = "3r3k/2P4p/8/8/8/8/PP6/K6N w - - 0 1";
+        let (state, report) = solve(fen, "r#2");
+        let keys: Vec<String> = report
+            .solutions
+            .iter()
+            .map(|solution| state.to_san(solution.first_move()))
+            .collect();
+        assert_eq!(keys, ["c8=B", "c8=N", "Ng3"]);
+    }
+
+    #[test]
+    fn twins() {
+        let state = State::from_fen("kbK5/pp6/1P6/8/8/8/8/R7 w - - 0 1").unwrap();
+        let stipulation = "#2".parse().unwrap();
+        let changes: Vec<Change> = ["-a1", "+wRh1", "h#1"]
+            .iter()
+            .map(|change| change.parse().unwrap())
+            .collect();
+        let (part, new) = twin(&state, stipulation, &changes).unwrap();
+        assert_eq!(part.to_fen(), "kbK5/pp6/1P6/8/8/8/8/7R w - - 0 1");
+        assert_eq!(new.to_string(), "h#1");
+
+        let moved = ["a1-a3".parse().unwrap(), "c8<->a3".parse().unwrap()];
+        let (part, _) = twin(&state, stipulation, &moved).unwrap();
+        assert_eq!(part.to_fen(), "kbR5/pp6/1P6/8/8/K7/8/8 w - - 0 1");
+        // no king, black in check with white to move, a pawn on the last rank, a piece
+        // added on top of another
+        for change in ["-c8", "-a7", "+wPd8", "+wQb6"] {
+            assert!(twin(&state, stipulation, &[change.parse().unwrap()]).is_err());
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use crate::errors::ChessError;
+use crate::piece::Color;
 use crate::utils::impl_index;
 use std::{fmt, mem::transmute, str::FromStr};
 
@@ -19,6 +20,14 @@ pub const RANK_COUNT: usize = 8;
 impl_index! { Rank(RANK_COUNT) }
 
 impl Rank {
+    // where the king and rooks start
+    pub(crate) fn back_rank(color: Color) -> Rank {
+        match color {
+            Color::White => Rank::Rank1,
+            Color::Black => Rank::Rank8,
+        }
+    }
+
     pub fn to_char(self) -> char {
         (self.index() as u8 + b'1') as char
     }
@@ -44,11 +53,11 @@ impl Rank {
     }
 
     pub fn wrapping_up(self) -> Rank {
-        unsafe { transmute((self.index().wrapping_add(1) % RANK_COUNT) as u8) }
+        unsafe { transmute::<u8, Rank>((self.index().wrapping_add(1) % RANK_COUNT) as u8) }
     }
 
     pub fn wrapping_down(self) -> Rank {
-        unsafe { transmute((self.index().wrapping_sub(1) % RANK_COUNT) as u8) }
+        unsafe { transmute::<u8, Rank>((self.index().wrapping_sub(1) % RANK_COUNT) as u8) }
     }
 }
 
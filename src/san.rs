@@ -0,0 +1,218 @@
+use crate::errors::ChessError;
+use crate::file::File;
+use crate::moves::{Move, MoveKind};
+use crate::piece::*;
+use crate::rank::Rank;
+use crate::square::Square;
+use crate::state::{CastlingSide, State};
+use std::str::FromStr;
+
+impl State {
+    pub fn to_san(&self, mv: Move) -> String {
+        let mut san = String::new();
+        let piece = self.board.piece(mv.from).expect("No piece to move");
+
+        match mv.castling_side() {
+            Some(CastlingSide::King) => san.push_str("O-O"),
+            Some(CastlingSide::Queen) => san.push_str("O-O-O"),
+            None => {
+                let capture = mv.kind == MoveKind::EnPassant || self.board.piece(mv.to).is_some();
+                if piece.piece_type() == PieceType::Pawn {
+                    if capture {
+                        san.push(mv.from.file().to_char());
+                    }
+                } else {
+                    san.push_str(Piece::new(Color::White, piece.piece_type()).as_str());
+
+                    // only as much of the origin as is needed to tell the moves apart
+                    let others: Vec<Square> = self
+                        .legal_moves()
+                        .into_iter()
+                        .filter(|other| {
+                            other.to == mv.to
+                                && other.from != mv.from
+                                && !other.is_castling()
+                                && self.board.piece(other.from) == Some(piece)
+                        })
+                        .map(|other| other.from)
+                        .collect();
+                    if !others.is_empty() {
+                        if others.iter().all(|from| from.file() != mv.from.file()) {
+                            san.push(mv.from.file().to_char());
+                        } else if others.iter().all(|from| from.rank() != mv.from.rank()) {
+                            san.push(mv.from.rank().to_char());
+                        } else {
+                            san.push_str(&mv.from.to_string());
+                        }
+                    }
+                }
+                if capture {
+                    san.push('x');
+                }
+                san.push_str(&mv.to.to_string());
+                if let MoveKind::Promotion(piece_type) = mv.kind {
+                    san.push('=');
+                    san.push_str(Piece::new(Color::White, piece_type).as_str());
+                }
+            }
+        }
+
+        let mut next = self.clone();
+        next.make_move(mv);
+        if next.in_check() {
+            san.push(if next.legal_moves().is_empty() {
+                '#'
+            } else {
+                '+'
+            });
+        }
+        san
+    }
+
+    // lenient about check marks, annotations, missing capture marks and over-disambiguation
+    pub fn parse_san(&self, s: &str) -> Result<Move, ChessError> {
+        let invalid = || ChessError::ParseError(s.to_string(), "SAN move");
+        let trimmed = s.trim_end_matches(['+', '#', '!', '?']);
+        let legal_moves = self.legal_moves();
+
+        let castling_side = match trimmed {
+            "O-O" | "0-0" => Some(CastlingSide::King),
+            "O-O-O" | "0-0-0" => Some(CastlingSide::Queen),
+            _ => None,
+        };
+        if let Some(side) = castling_side {
+            return legal_moves
+                .into_iter()
+                .find(|mv| mv.castling_side() == Some(side))
+                .ok_or_else(invalid);
+        }
+
+        let mut chars: Vec<char> = trimmed.chars().filter(|&c| c != 'x' && c != '-').collect();
+
+        let mut promotion = None;
+        if let Some(&last) = chars.last() {
+            if "QRBNqrbn".contains(last) && chars.len() > 2 {
+                promotion =
+                    Some(Piece::from_str(&last.to_ascii_uppercase().to_string())?.piece_type());
+                chars.pop();
+                if chars.last() == Some(&'=') {
+                    chars.pop();
+                }
+            }
+        }
+
+        let piece_type = match chars.first() {
+            Some(&c) if "NBRQK".contains(c) => {
+                chars.remove(0);
+                Piece::from_str(&c.to_string())?.piece_type()
+            }
+            _ => PieceType::Pawn,
+        };
+
+        if chars.len() < 2 {
+            return Err(invalid());
+        }
+        let to: String = chars[chars.len() - 2..].iter().collect();
+        let to = to.parse::<Square>().map_err(|_| invalid())?;
+        let hints = &chars[..chars.len() - 2];
+        let mut from_file = None;
+        let mut from_rank = None;
+        for &c in hints {
+            if let Some(file) = File::from_char(c) {
+                from_file = Some(file);
+            } else if let Some(rank) = Rank::from_char(c) {
+                from_rank = Some(rank);
+            } else {
+                return Err(invalid());
+            }
+        }
+
+        let mut candidates = legal_moves.into_iter().filter(|mv| {
+            !mv.is_castling()
+                && mv.to == to
+                && self.board.piece(mv.from).map(Piece::piece_type) == Some(piece_type)
+                && from_file.is_none_or(|file| mv.from.file() == file)
+                && from_rank.is_none_or(|rank| mv.from.rank() == rank)
+                && match mv.kind {
+                    MoveKind::Promotion(piece_type) => promotion == Some(piece_type),
+                    _ => promotion.is_none(),
+                }
+        });
+
+        match (candidates.next(), candidates.next()) {
+            (Some(mv), None) => Ok(mv),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn san(fen: &str, uci: &str) -> String {
+        let state = State::from_fen(fen).unwrap();
+        state.to_san(state.parse_uci_move(uci, false).unwrap())
+    }
+
+    #[test]
+    fn round_trip() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "r3k2r/pPppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 1",
+            "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+        ] {
+            let state = State::from_fen(fen).unwrap();
+            for mv in state.legal_moves() {
+                let san = state.to_san(mv);
+                assert_eq!(state.parse_san(&san).unwrap(), mv, "{} in {}", san, fen);
+            }
+        }
+    }
+
+    #[test]
+    fn notation() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(san(start, "e2e4"), "e4");
+        assert_eq!(san(start, "g1f3"), "Nf3");
+        assert_eq!(san("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1g1"), "O-O");
+        assert_eq!(san("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1c1"), "O-O-O");
+        assert_eq!(san("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), "exd6");
+        assert_eq!(san("3k4/4P3/8/8/8/8/8/4K3 w - - 0 1", "e7e8q"), "e8=Q+");
+        assert_eq!(san("3k4/4P3/8/8/8/8/8/4K3 w - - 0 1", "e7e8n"), "e8=N");
+        assert_eq!(san("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "a1a8"), "Ra8#");
+    }
+
+    #[test]
+    fn disambiguation() {
+        let knights = "4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1";
+        assert_eq!(san(knights, "b1d2"), "Nbd2");
+        assert_eq!(san(knights, "f1d2"), "Nfd2");
+        let rooks = "4k3/8/8/R7/8/8/8/R3K3 w - - 0 1";
+        assert_eq!(san(rooks, "a1a3"), "R1a3");
+        assert_eq!(san(rooks, "a5a3"), "R5a3");
+        let queens = "4k3/8/8/8/8/Q1Q5/8/Q3K3 w - - 0 1";
+        assert_eq!(san(queens, "a1b2"), "Q1b2");
+        assert_eq!(san(queens, "a3b2"), "Qa3b2");
+        assert_eq!(san(queens, "c3b2"), "Qcb2");
+        // a pinned knight doesn't count
+        assert_eq!(san("4k3/8/8/8/8/8/4r3/1N2KN2 w - - 0 1", "b1d2"), "Nd2");
+
+        let state = State::from_fen(knights).unwrap();
+        assert!(state.parse_san("Nd2").is_err());
+        let b1d2 = state.parse_uci_move("b1d2", false).unwrap();
+        assert_eq!(state.parse_san("Nb1d2").unwrap(), b1d2);
+        assert_eq!(state.parse_san("Nbd2+!?").unwrap(), b1d2);
+        assert!(state.parse_san("Nbd3").is_err());
+        assert!(state.parse_san("Qd2").is_err());
+        assert!(state.parse_san("O-O").is_err());
+        let promotion = State::from_fen("3k4/4P3/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(
+            promotion.parse_san("e8Q").unwrap(),
+            promotion.parse_uci_move("e7e8q", false).unwrap()
+        );
+        assert!(promotion.parse_san("e8").is_err());
+    }
+}
@@ -0,0 +1,566 @@
+use crate::eval::{Evaluator, PIECE_VALUES};
+use crate::moves::{Move, MoveKind, Undo};
+use crate::nnue::{self, AccumulatorStack};
+use crate::state::State;
+use crate::syzygy::{self, Tablebases};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub const MATE_SCORE: i32 = 30000;
+pub const INFINITY: i32 = 32000;
+pub const MAX_DEPTH: u32 = 64;
+// tablebase wins score below any mate so a found mate is still preferred
+pub const TB_WIN_SCORE: i32 = MATE_SCORE - MAX_DEPTH as i32 * 4;
+
+// anything this close to MATE_SCORE is a forced mate
+pub fn is_mate_score(score: i32) -> bool {
+    score.abs() >= MATE_SCORE - MAX_DEPTH as i32 * 2
+}
+
+// full moves until mate, negative when getting mated
+pub fn mate_in(score: i32) -> Option<i32> {
+    if !is_mate_score(score) {
+        None
+    } else if score > 0 {
+        Some((MATE_SCORE - score + 1) / 2)
+    } else {
+        Some(-(MATE_SCORE + score) / 2)
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SearchLimits {
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+    pub movetime: Option<Duration>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PvLine {
+    pub score: i32,
+    pub pv: Vec<Move>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchResult {
+    pub best_move: Option<Move>,
+    pub score: i32,
+    pub depth: u32,
+    pub nodes: u64,
+    pub elapsed: Duration,
+    pub pv: Vec<Move>,
+    pub tb_hits: u64,
+    // best first, the first one is score and pv; more than one only with multipv
+    pub lines: Vec<PvLine>,
+}
+
+pub struct Search {
+    limits: SearchLimits,
+    start: Instant,
+    // when the movetime started counting, later than start after a ponder hit
+    clock_start: Instant,
+    nodes: u64,
+    stopped: bool,
+    // principal variation of the previous iteration, searched first
+    previous_pv: Vec<Move>,
+    // how many of the best root moves get a line of their own
+    multipv: usize,
+    // root moves already given a line in this iteration
+    excluded: Vec<Move>,
+    // set from another thread to end the search early
+    stop: Arc<AtomicBool>,
+    // while set there is no time limit, clearing it from another thread starts the clock
+    ponder: Arc<AtomicBool>,
+    pondering: bool,
+    // only these moves are searched at the root, all of them when empty
+    root_moves: Vec<Move>,
+    tablebases: Option<Arc<Tablebases>>,
+    tb_probe_depth: u32,
+    tb_rule50: bool,
+    // positions with at most this many pieces are probed in the search
+    tb_pieces: usize,
+    tb_hits: u64,
+    evaluator: Arc<dyn Evaluator>,
+    // one per ply while searching with a network
+    accumulators: Option<AccumulatorStack>,
+}
+
+impl Search {
+    // picks up the tablebases and settings configured in the syzygy module, and the
+    // evaluation chosen in the nnue module
+    pub fn new(limits: SearchLimits) -> Search {
+        Search {
+            limits,
+            start: Instant::now(),
+            clock_start: Instant::now(),
+            nodes: 0,
+            stopped: false,
+            previous_pv: Vec::new(),
+            multipv: 1,
+            excluded: Vec::new(),
+            stop: Arc::new(AtomicBool::new(false)),
+            ponder: Arc::new(AtomicBool::new(false)),
+            pondering: false,
+            root_moves: Vec::new(),
+            tablebases: syzygy::tablebases(),
+            tb_probe_depth: syzygy::probe_depth(),
+            tb_rule50: syzygy::rule50(),
+            tb_pieces: 0,
+            tb_hits: 0,
+            evaluator: nnue::evaluator(),
+            accumulators: None,
+        }
+    }
+
+    pub fn set_tablebases(&mut self, tablebases: Option<Arc<Tablebases>>) {
+        self.tablebases = tablebases;
+    }
+
+    pub fn set_evaluator(&mut self, evaluator: Arc<dyn Evaluator>) {
+        self.evaluator = evaluator;
+    }
+
+    pub fn set_limits(&mut self, limits: SearchLimits) {
+        self.limits = limits;
+    }
+
+    // searches the best `lines` root moves each with a full window, one after the other
+    pub fn set_multipv(&mut self, lines: usize) {
+        self.multipv = lines.max(1);
+    }
+
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    // set it before running to ponder, and clear it on a ponder hit
+    pub fn ponder_flag(&self) -> Arc<AtomicBool> {
+        self.ponder.clone()
+    }
+
+    // iterative deepening, the last fully searched depth is returned
+    pub fn run(&mut self, state: &mut State) -> SearchResult {
+        self.run_with_info(state, |_| {})
+    }
+
+    // calls `info` after every completed iteration
+    pub fn run_with_info(
+        &mut self,
+        state: &mut State,
+        mut info: impl FnMut(&SearchResult),
+    ) -> SearchResult {
+        self.start = Instant::now();
+        self.clock_start = self.start;
+        self.pondering = self.ponder.load(Ordering::Relaxed);
+        self.nodes = 0;
+        self.stopped = false;
+        self.previous_pv.clear();
+        self.tb_hits = 0;
+        self.filter_root_moves(state);
+
+        let mut result = SearchResult {
+            best_move: None,
+            score: 0,
+            depth: 0,
+            nodes: 0,
+            elapsed: Duration::ZERO,
+            pv: Vec::new(),
+            tb_hits: 0,
+            lines: Vec::new(),
+        };
+
+        self.accumulators = self
+            .evaluator
+            .network()
+            .map(|network| AccumulatorStack::new(network, &state.board));
+        let max_depth = self.limits.depth.unwrap_or(MAX_DEPTH).min(MAX_DEPTH);
+        for depth in 1..=max_depth {
+            // each pass leaves out the moves found by the ones before it
+            self.excluded.clear();
+            let mut lines: Vec<PvLine> = Vec::new();
+            while lines.len() < self.multipv {
+                self.previous_pv = result
+                    .lines
+                    .get(lines.len())
+                    .map_or(Vec::new(), |line| line.pv.clone());
+                let mut pv = Vec::new();
+                let score = self.negamax(state, depth, 0, -INFINITY, INFINITY, &mut pv);
+                let found = pv.first().copied();
+                // the first pass counts even when stopped or without a legal move
+                if lines.is_empty() || (found.is_some() && !self.stopped) {
+                    lines.push(PvLine { score, pv });
+                }
+                match found {
+                    Some(mv) if !self.stopped => self.excluded.push(mv),
+                    _ => break,
+                }
+            }
+            if self.stopped && depth > 1 {
+                break;
+            }
+            lines.sort_by_key(|line| -line.score);
+            // stopped before depth 1 got through a single move, any legal one beats none
+            if self.stopped && lines[0].pv.is_empty() {
+                lines[0].pv.extend(self.fallback_move(state));
+            }
+
+            let score = lines[0].score;
+            result.best_move = lines[0].pv.first().copied();
+            result.score = score;
+            result.depth = depth;
+            result.pv = lines[0].pv.clone();
+            result.lines = lines;
+            result.nodes = self.nodes;
+            result.elapsed = self.start.elapsed();
+            result.tb_hits = self.tb_hits;
+            info(&result);
+
+            if self.stopped
+                || (is_mate_score(score) && mate_in(score).unwrap().unsigned_abs() * 2 <= depth)
+            {
+                break;
+            }
+        }
+
+        self.accumulators = None;
+        result.nodes = self.nodes;
+        result.elapsed = self.start.elapsed();
+        result.tb_hits = self.tb_hits;
+        result
+    }
+
+    // when the root is in the tablebases only the moves that keep the best result are
+    // searched; with DTZ available that's enough, so the search doesn't probe further
+    fn filter_root_moves(&mut self, state: &State) {
+        self.root_moves.clear();
+        let tablebases = match &self.tablebases {
+            Some(tablebases) => tablebases,
+            None => return,
+        };
+        self.tb_pieces = tablebases.max_pieces();
+
+        if let Some(ranked) = tablebases.rank_root_moves(state, self.tb_rule50) {
+            if let Some(&(_, best)) = ranked.first() {
+                self.tb_hits += 1;
+                self.root_moves = ranked
+                    .iter()
+                    .filter(|&&(_, rank)| rank == best)
+                    .map(|&(mv, _)| mv)
+                    .collect();
+                if best <= 0 || tablebases.probe_dtz(state).is_some() {
+                    self.tb_pieces = 0;
+                }
+            }
+        }
+    }
+
+    // the first root move in the usual order, for a search stopped before it found one
+    fn fallback_move(&self, state: &State) -> Option<Move> {
+        let mut moves = if self.root_moves.is_empty() {
+            state.legal_moves()
+        } else {
+            self.root_moves.clone()
+        };
+        self.order_moves(state, &mut moves, 0);
+        moves.first().copied()
+    }
+
+    // the WDL score of a position in the tablebases
+    fn probe_tablebases(&mut self, state: &State, depth: u32, ply: usize) -> Option<i32> {
+        let tablebases = self.tablebases.as_ref()?;
+        let pieces = state.board.occupied().count() as usize;
+        if pieces > self.tb_pieces
+            || (pieces == self.tb_pieces && depth < self.tb_probe_depth)
+            || state.halfmove_clock() != 0
+            || !state.castling.is_empty()
+        {
+            return None;
+        }
+        let wdl = tablebases.probe_wdl(state)?;
+        self.tb_hits += 1;
+        let draw = if self.tb_rule50 { 1 } else { 0 };
+        Some(match wdl.value() {
+            value if value > draw => TB_WIN_SCORE - ply as i32,
+            value if value < -draw => -TB_WIN_SCORE + ply as i32,
+            // cursed wins and blessed losses are a hair better or worse than a draw
+            value => value * 2 * draw,
+        })
+    }
+
+    fn check_limits(&mut self) {
+        if let Some(nodes) = self.limits.nodes {
+            if self.nodes >= nodes {
+                self.stopped = true;
+            }
+        }
+        if self.nodes.is_multiple_of(1024) {
+            if self.stop.load(Ordering::Relaxed) {
+                self.stopped = true;
+            }
+            if self.pondering && !self.ponder.load(Ordering::Relaxed) {
+                self.pondering = false;
+                self.clock_start = Instant::now();
+            }
+            if let Some(movetime) = self.limits.movetime {
+                if !self.pondering && self.clock_start.elapsed() >= movetime {
+                    self.stopped = true;
+                }
+            }
+        }
+    }
+
+    // previous PV move first, then captures by MVV-LVA, then everything else
+    fn order_moves(&self, state: &State, moves: &mut [Move], ply: usize) {
+        let pv_move = self.previous_pv.get(ply).copied();
+        moves.sort_by_cached_key(|&mv| {
+            if Some(mv) == pv_move {
+                return i32::MIN;
+            }
+            let victim = match mv.kind {
+                MoveKind::EnPassant => PIECE_VALUES[0],
+                MoveKind::Castling => 0,
+                _ => state
+                    .board
+                    .piece(mv.to)
+                    .map_or(0, |piece| PIECE_VALUES[piece.piece_type().index()]),
+            };
+            let promotion = match mv.kind {
+                MoveKind::Promotion(piece_type) => PIECE_VALUES[piece_type.index()],
+                _ => 0,
+            };
+            let attacker = state
+                .board
+                .piece(mv.from)
+                .map_or(0, |piece| piece.piece_type().index() as i32);
+            if victim > 0 || promotion > 0 {
+                -(victim * 10 + promotion - attacker)
+            } else {
+                0
+            }
+        });
+    }
+
+    fn negamax(
+        &mut self,
+        state: &mut State,
+        depth: u32,
+        ply: usize,
+        mut alpha: i32,
+        beta: i32,
+        pv: &mut Vec<Move>,
+    ) -> i32 {
+        self.nodes += 1;
+        self.check_limits();
+        if self.stopped {
+            return 0;
+        }
+
+        if ply > 0 && state.halfmove_clock() >= 100 {
+            return 0;
+        }
+
+        if ply > 0 {
+            if let Some(score) = self.probe_tablebases(state, depth, ply) {
+                return score;
+            }
+        }
+
+        let in_check = state.in_check();
+        let depth = if in_check { depth + 1 } else { depth };
+        if depth == 0 || ply >= MAX_DEPTH as usize * 2 {
+            return self.quiescence(state, alpha, beta);
+        }
+
+        let mut moves = Vec::new();
+        state.pseudo_legal_moves(&mut moves);
+        self.order_moves(state, &mut moves, ply);
+
+        let us = state.side_to_move();
+        let mut legal_moves = 0;
+        let mut line = Vec::new();
+        for mv in moves {
+            if ply == 0
+                && ((!self.root_moves.is_empty() && !self.root_moves.contains(&mv))
+                    || self.excluded.contains(&mv))
+            {
+                continue;
+            }
+            let undo = self.make_move(state, mv);
+            if state.king_attacked(us) {
+                self.unmake_move(state, mv, undo);
+                continue;
+            }
+            legal_moves += 1;
+
+            line.clear();
+            let score = -self.negamax(state, depth - 1, ply + 1, -beta, -alpha, &mut line);
+            self.unmake_move(state, mv, undo);
+            if self.stopped {
+                return 0;
+            }
+
+            if score > alpha {
+                alpha = score;
+                pv.clear();
+                pv.push(mv);
+                pv.extend_from_slice(&line);
+                if alpha >= beta {
+                    break;
+                }
+            }
+        }
+
+        if legal_moves == 0 {
+            return if in_check {
+                -MATE_SCORE + ply as i32
+            } else {
+                0
+            };
+        }
+
+        alpha
+    }
+
+    // keeps the accumulators, if there are any, in step with the board
+    fn make_move(&mut self, state: &mut State, mv: Move) -> Undo {
+        match &mut self.accumulators {
+            Some(stack) => stack.make_move(state, mv),
+            None => state.make_move(mv),
+        }
+    }
+
+    fn unmake_move(&mut self, state: &mut State, mv: Move, undo: Undo) {
+        match &mut self.accumulators {
+            Some(stack) => stack.unmake_move(state, mv, undo),
+            None => state.unmake_move(mv, undo),
+        }
+    }
+
+    fn quiescence(&mut self, state: &mut State, mut alpha: i32, beta: i32) -> i32 {
+        self.nodes += 1;
+        self.check_limits();
+        if self.stopped {
+            return 0;
+        }
+
+        let stand_pat = match &self.accumulators {
+            Some(stack) => self.evaluator.evaluate_incremental(state, stack.current()),
+            None => self.evaluator.evaluate(state),
+        };
+        if stand_pat >= beta {
+            return stand_pat;
+        }
+        alpha = alpha.max(stand_pat);
+
+        let mut moves = Vec::new();
+        state.pseudo_legal_captures(&mut moves);
+        self.order_moves(state, &mut moves, usize::MAX);
+
+        let us = state.side_to_move();
+        for mv in moves {
+            let undo = self.make_move(state, mv);
+            if state.king_attacked(us) {
+                self.unmake_move(state, mv, undo);
+                continue;
+            }
+            let score = -self.quiescence(state, -beta, -alpha);
+            self.unmake_move(state, mv, undo);
+            if self.stopped {
+                return 0;
+            }
+
+            if score >= beta {
+                return score;
+            }
+            alpha = alpha.max(score);
+        }
+
+        alpha
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search(fen: &str, depth: u32, multipv: usize) -> (SearchResult, usize) {
+        let mut state = State::from_fen(fen).unwrap();
+        let mut search = Search::new(SearchLimits {
+            depth: Some(depth),
+            ..SearchLimits::default()
+        });
+        search.set_multipv(multipv);
+        let mut iterations = 0;
+        let result = search.run_with_info(&mut state, |_| iterations += 1);
+        (result, iterations)
+    }
+
+    #[test]
+    fn multipv() {
+        let (result, iterations) = search("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 3, 3);
+        assert!(iterations > 0);
+        assert_eq!(result.lines.len(), 3);
+        assert_eq!(result.lines[0].score, MATE_SCORE - 1);
+        assert_eq!(result.lines[0].pv, result.pv);
+        assert_eq!(result.best_move, result.pv.first().copied());
+        for pair in result.lines.windows(2) {
+            assert!(pair[0].score >= pair[1].score);
+            assert_ne!(pair[0].pv[0], pair[1].pv[0]);
+        }
+        // the same best line as without multipv
+        let (single, _) = search("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 3, 1);
+        assert_eq!(single.lines.len(), 1);
+        assert_eq!(single.pv, result.pv);
+
+        // no more lines than legal moves
+        let (result, _) = search("k7/8/8/8/8/8/8/7K w - - 0 1", 2, 5);
+        assert_eq!(result.lines.len(), 3);
+
+        // a position without moves still reports its score
+        let mated = "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3";
+        let (result, _) = search(mated, 2, 2);
+        assert_eq!(result.best_move, None);
+        assert_eq!(result.lines.len(), 1);
+        assert_eq!(result.score, -MATE_SCORE);
+    }
+
+    #[test]
+    fn stopped_searches_still_move() {
+        // a node limit stops depth 1 after its first node
+        let mut search = Search::new(SearchLimits {
+            nodes: Some(1),
+            ..SearchLimits::default()
+        });
+        let mut state = State::default();
+        let result = search.run(&mut state);
+        assert_eq!(result.depth, 1);
+        let best = result.best_move.unwrap();
+        assert!(state.legal_moves().contains(&best));
+        assert_eq!(result.pv, [best]);
+        let mut mated =
+            State::from_fen("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3")
+                .unwrap();
+        assert_eq!(search.run(&mut mated).best_move, None);
+    }
+
+    #[test]
+    fn pondering_waits_for_the_hit() {
+        let mut state = State::default();
+        let mut search = Search::new(SearchLimits {
+            movetime: Some(Duration::from_millis(1)),
+            ..SearchLimits::default()
+        });
+        let ponder = search.ponder_flag();
+        ponder.store(true, Ordering::Relaxed);
+        let handle = std::thread::spawn(move || search.run(&mut state));
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(!handle.is_finished());
+
+        // after the hit the usual movetime applies from then on
+        ponder.store(false, Ordering::Relaxed);
+        let result = handle.join().unwrap();
+        assert!(result.best_move.is_some());
+        assert!(result.elapsed >= Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,165 @@
+// serde support behind the `serde` feature: human readable formats get the same strings
+// FEN and UCI use, binary formats get indices and a packed board
+use crate::bitboard::*;
+use crate::file::File;
+use crate::piece::Piece;
+use crate::rank::Rank;
+use crate::square::Square;
+use crate::state::{Castling, Ply, State};
+use serde::de::{self, Deserializer};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+
+// "e4" / "e" / "4" / "N" in text, the index as a u8 otherwise
+macro_rules! impl_serde_index {
+    ($name:ident, $what:expr) => {
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                if serializer.is_human_readable() {
+                    serializer.collect_str(self)
+                } else {
+                    serializer.serialize_u8(self.index() as u8)
+                }
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                if deserializer.is_human_readable() {
+                    String::deserialize(deserializer)?
+                        .parse()
+                        .map_err(de::Error::custom)
+                } else {
+                    let index = u8::deserialize(deserializer)?;
+                    $name::from_index(index as usize).ok_or_else(|| {
+                        de::Error::custom(format!("invalid {} index {}", $what, index))
+                    })
+                }
+            }
+        }
+    };
+}
+
+impl_serde_index!(Square, "square");
+impl_serde_index!(File, "file");
+impl_serde_index!(Rank, "rank");
+impl_serde_index!(Piece, "piece");
+
+// occupancy plus one nibble per occupied square in square order
+#[derive(Serialize, Deserialize)]
+struct PackedState {
+    occupied: BitBoard,
+    pieces: Vec<u8>,
+    ply: Ply,
+    halfmove_clock: Ply,
+    en_passant: Option<Square>,
+    castling: Castling,
+}
+
+impl Serialize for State {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            return serializer.serialize_str(&self.to_fen());
+        }
+
+        let occupied = self.board.occupied();
+        let mut pieces = vec![0; (occupied.count() as usize).div_ceil(2)];
+        for (i, square) in occupied.enumerate() {
+            let piece = self.board.piece(square).unwrap().index() as u8;
+            pieces[i / 2] |= piece << (4 * (i % 2));
+        }
+        PackedState {
+            occupied,
+            pieces,
+            ply: self.ply,
+            halfmove_clock: self.halfmove_clock,
+            en_passant: self.en_passant,
+            castling: self.castling,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for State {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let fen = String::deserialize(deserializer)?;
+            return State::from_fen(&fen).map_err(de::Error::custom);
+        }
+
+        let packed = PackedState::deserialize(deserializer)?;
+        if packed.pieces.len() != (packed.occupied.count() as usize).div_ceil(2) {
+            return Err(de::Error::custom("piece list doesn't match the occupancy"));
+        }
+        let mut state = State::new();
+        for (i, square) in packed.occupied.enumerate() {
+            let index = (packed.pieces[i / 2] >> (4 * (i % 2))) & 0xF;
+            let piece = Piece::from_index(index as usize)
+                .ok_or_else(|| de::Error::custom(format!("invalid piece index {}", index)))?;
+            state.board.set_piece(square, Some(piece));
+        }
+        state.ply = packed.ply;
+        state.halfmove_clock = packed.halfmove_clock;
+        state.en_passant = packed.en_passant;
+        state.castling = packed.castling;
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::{Color, PieceType};
+    use std::fmt::Debug;
+
+    fn round_trip<T: Serialize + for<'de> Deserialize<'de> + PartialEq + Debug>(value: T) {
+        let json = serde_json::to_string(&value).unwrap();
+        assert_eq!(serde_json::from_str::<T>(&json).unwrap(), value, "{}", json);
+        let binary = bincode::serialize(&value).unwrap();
+        assert_eq!(bincode::deserialize::<T>(&binary).unwrap(), value);
+    }
+
+    #[test]
+    fn core_types() {
+        round_trip(Square::E4);
+        round_trip(File::H);
+        round_trip(Rank::Rank8);
+        round_trip(Color::Black);
+        round_trip(PieceType::Knight);
+        round_trip(Piece::BlackQueen);
+        round_trip(LIGHT_SQUARES);
+        round_trip(Castling::standard());
+
+        assert_eq!(serde_json::to_string(&Square::E4).unwrap(), r#""e4""#);
+        assert_eq!(
+            serde_json::to_string(&Piece::WhiteKnight).unwrap(),
+            r#""N""#
+        );
+        assert_eq!(serde_json::to_string(&Color::White).unwrap(), r#""white""#);
+        assert_eq!(bincode::serialize(&Square::H8).unwrap(), vec![63]);
+        assert!(serde_json::from_str::<Square>(r#""i9""#).is_err());
+        assert!(bincode::deserialize::<Square>(&[64]).is_err());
+    }
+
+    #[test]
+    fn states() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "1r2k1r1/8/8/8/8/8/8/1R2K1R1 b Kq - 17 60",
+        ] {
+            let state = State::from_fen(fen).unwrap();
+            let json = serde_json::to_string(&state).unwrap();
+            assert_eq!(json, format!("\"{}\"", fen));
+            assert_eq!(serde_json::from_str::<State>(&json).unwrap().to_fen(), fen);
+
+            let binary = bincode::serialize(&state).unwrap();
+            assert!(binary.len() < fen.len());
+            assert_eq!(
+                bincode::deserialize::<State>(&binary).unwrap().to_fen(),
+                fen
+            );
+        }
+    }
+}
@@ -0,0 +1,197 @@
+// match statistics: the Elo difference a win/draw/loss record suggests with its 95% margin,
+// the likelihood of superiority, and a sequential probability ratio test deciding between
+// two Elo hypotheses. The test uses the usual normal approximation of the generalized SPRT
+// on per-game scores, with logistic Elo.
+use std::f64::consts::SQRT_2;
+
+// two-sided 95%
+const Z_95: f64 = 1.959964;
+
+pub fn score_from_elo(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+pub fn elo_from_score(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+// Abramowitz and Stegun 7.1.26, good to about 1e-7
+fn erf(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+    let polynomial = t
+        * (0.254829592
+            + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let y = 1.0 - polynomial * (-x * x).exp();
+    if x < 0.0 {
+        -y
+    } else {
+        y
+    }
+}
+
+// results from one player's point of view
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl Record {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    // points per game
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games().max(1) as f64
+    }
+
+    // the variance of a single game's score
+    fn variance(&self) -> f64 {
+        let score = self.score();
+        let games = self.games().max(1) as f64;
+        (self.wins as f64 * (1.0 - score).powi(2)
+            + self.draws as f64 * (0.5 - score).powi(2)
+            + self.losses as f64 * score.powi(2))
+            / games
+    }
+
+    // the Elo difference and the half-width of its 95% interval, None until both are finite
+    pub fn elo(&self) -> Option<(f64, f64)> {
+        let score = self.score();
+        if self.games() == 0 || score <= 0.0 || score >= 1.0 {
+            return None;
+        }
+        let deviation = (self.variance() / self.games() as f64).sqrt();
+        let high = (score + Z_95 * deviation).min(1.0 - f64::EPSILON);
+        let low = (score - Z_95 * deviation).max(f64::EPSILON);
+        let margin = (elo_from_score(high) - elo_from_score(low)) / 2.0;
+        Some((elo_from_score(score), margin))
+    }
+
+    // the probability that this player is the stronger one, draws telling nothing
+    pub fn los(&self) -> f64 {
+        let decisive = (self.wins + self.losses) as f64;
+        if decisive == 0.0 {
+            return 0.5;
+        }
+        0.5 * (1.0 + erf((self.wins as f64 - self.losses as f64) / (SQRT_2 * decisive.sqrt())))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SprtStatus {
+    Continue,
+    // the player is no better than elo0
+    AcceptH0,
+    // the player is at least elo1 better
+    AcceptH1,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    // the chances of accepting H1 when H0 holds and the other way around
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+impl Default for Sprt {
+    fn default() -> Sprt {
+        Sprt {
+            elo0: 0.0,
+            elo1: 5.0,
+            alpha: 0.05,
+            beta: 0.05,
+        }
+    }
+}
+
+impl Sprt {
+    // the log-likelihood ratios at which H0 and H1 are accepted
+    pub fn bounds(&self) -> (f64, f64) {
+        (
+            (self.beta / (1.0 - self.alpha)).ln(),
+            ((1.0 - self.beta) / self.alpha).ln(),
+        )
+    }
+
+    pub fn llr(&self, record: &Record) -> f64 {
+        let variance = record.variance();
+        if record.games() == 0 || variance == 0.0 {
+            return 0.0;
+        }
+        let (score0, score1) = (score_from_elo(self.elo0), score_from_elo(self.elo1));
+        record.games() as f64 * (score1 - score0) * (2.0 * record.score() - score0 - score1)
+            / (2.0 * variance)
+    }
+
+    pub fn status(&self, record: &Record) -> SprtStatus {
+        let llr = self.llr(record);
+        let (lower, upper) = self.bounds();
+        if llr >= upper {
+            SprtStatus::AcceptH1
+        } else if llr <= lower {
+            SprtStatus::AcceptH0
+        } else {
+            SprtStatus::Continue
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(wins: u32, draws: u32, losses: u32) -> Record {
+        Record {
+            wins,
+            draws,
+            losses,
+        }
+    }
+
+    #[test]
+    fn elo_and_margins() {
+        assert!((elo_from_score(0.75) - 190.849).abs() < 0.01);
+        assert!((score_from_elo(elo_from_score(0.3)) - 0.3).abs() < 1e-12);
+
+        let (elo, margin) = record(40, 20, 40).elo().unwrap();
+        assert!(elo.abs() < 1e-9);
+        // 0.5 +- 1.96 * sqrt(0.2 / 100)
+        assert!((margin - 61.5).abs() < 0.1);
+        let (elo, _) = record(60, 30, 10).elo().unwrap();
+        assert!((elo - elo_from_score(0.75)).abs() < 1e-9);
+        // more games, a narrower interval
+        let (_, narrow) = record(400, 200, 400).elo().unwrap();
+        assert!(narrow < margin / 3.0);
+
+        assert_eq!(record(5, 0, 0).elo(), None);
+        assert_eq!(Record::default().elo(), None);
+    }
+
+    #[test]
+    fn likelihood_of_superiority() {
+        assert!((record(10, 5, 10).los() - 0.5).abs() < 1e-6);
+        assert_eq!(record(0, 5, 0).los(), 0.5);
+        assert!((record(60, 0, 40).los() - 0.97725).abs() < 1e-4);
+        assert!((record(40, 0, 60).los() - 0.02275).abs() < 1e-4);
+    }
+
+    #[test]
+    fn sprt() {
+        let sprt = Sprt::default();
+        let (lower, upper) = sprt.bounds();
+        assert!((lower + 2.944).abs() < 1e-3 && (upper - 2.944).abs() < 1e-3);
+
+        // an even score is closer to elo0 = 0 than to elo1 = 5
+        assert!(sprt.llr(&record(100, 100, 100)) < 0.0);
+        assert!(sprt.llr(&record(120, 100, 80)) > 0.0);
+        assert_eq!(sprt.status(&record(1, 0, 0)), SprtStatus::Continue);
+        assert_eq!(sprt.status(&record(900, 1000, 700)), SprtStatus::AcceptH1);
+        assert_eq!(sprt.status(&record(650, 1000, 850)), SprtStatus::AcceptH0);
+        assert_eq!(sprt.llr(&Record::default()), 0.0);
+    }
+}
@@ -84,15 +84,15 @@ impl_index! { Square(SQUARE_COUNT) }
 impl Square {
     pub fn new(rank: Rank, file: File) -> Square {
         // unsafe { transmute(file as u8 + rank as u8 * 8) }
-        unsafe { transmute((rank.index() * FILE_COUNT + file.index()) as u8) }
+        unsafe { transmute::<u8, Square>((rank.index() * FILE_COUNT + file.index()) as u8) }
     }
 
     pub fn rank(self) -> Rank {
-        unsafe { transmute((self.index() / FILE_COUNT) as u8) }
+        unsafe { transmute::<u8, Rank>((self.index() / FILE_COUNT) as u8) }
     }
 
     pub fn file(self) -> File {
-        unsafe { transmute((self.index() % FILE_COUNT) as u8) }
+        unsafe { transmute::<u8, File>((self.index() % FILE_COUNT) as u8) }
     }
 
     pub fn up(self) -> Option<Square> {
@@ -117,6 +117,10 @@ impl Square {
             Direction::Down => self.down(),
             Direction::Left => self.left(),
             Direction::Right => self.right(),
+            Direction::UpLeft => self.up()?.left(),
+            Direction::DownRight => self.down()?.right(),
+            Direction::UpRight => self.up()?.right(),
+            Direction::DownLeft => self.down()?.left(),
         }
     }
 
@@ -127,6 +131,15 @@ impl Square {
         }
     }
 
+    // the color of the square itself, a1 is dark
+    pub fn color(self) -> Color {
+        if (self.rank().index() + self.file().index()).is_multiple_of(2) {
+            Color::Black
+        } else {
+            Color::White
+        }
+    }
+
     pub fn wrapping_up(self) -> Option<Square> {
         Some(Square::new(self.rank().wrapping_up(), self.file()))
     }
@@ -149,18 +162,18 @@ impl FromStr for Square {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut chars = s.chars();
-        let rank = chars
-            .next()
-            .ok_or(ChessError::ParseError("".to_string(), "Rank"))
-            .and_then(|c| {
-                Rank::from_char(c).ok_or(ChessError::ParseError(c.to_string(), "Rank"))
-            })?;
         let file = chars
             .next()
             .ok_or(ChessError::ParseError("".to_string(), "File"))
             .and_then(|c| {
                 File::from_char(c).ok_or(ChessError::ParseError(c.to_string(), "File"))
             })?;
+        let rank = chars
+            .next()
+            .ok_or(ChessError::ParseError("".to_string(), "Rank"))
+            .and_then(|c| {
+                Rank::from_char(c).ok_or(ChessError::ParseError(c.to_string(), "Rank"))
+            })?;
         Ok(Square::new(rank, file))
     }
 }
@@ -1,14 +1,15 @@
 use crate::bitboard::*;
-use crate::errors::ChessError;
+use crate::file::File;
 use crate::piece::*;
 use crate::rank::Rank;
 use crate::square::*;
+use crate::utils::impl_index;
 use std::fmt;
 use std::mem::transmute;
-use std::str::FromStr;
 
 pub type Ply = u16;
 
+#[derive(Clone)]
 pub struct Board {
     pub board: [Option<Piece>; 64],
     pub color_bb: [BitBoard; 2],
@@ -42,61 +43,104 @@ impl Board {
     }
 }
 
-#[derive(Debug, Clone, Copy)] // TODO: formatable using FEN-style notation (kKqQ etc.)
-pub struct Castling {
-    pub white_king: bool,
-    pub black_king: bool,
-    pub white_queen: bool,
-    pub black_queen: bool,
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum CastlingSide {
+    King,
+    Queen,
 }
 
-impl FromStr for Castling {
-    type Err = ChessError;
+pub const CASTLING_SIDE_COUNT: usize = 2;
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut castling = Castling {
-            white_king: false,
-            black_king: false,
-            white_queen: false,
-            black_queen: false,
-        };
+impl_index! { CastlingSide(CASTLING_SIDE_COUNT) }
 
-        if s == "-" {
-            return Ok(castling);
+impl CastlingSide {
+    // where the king and rook end up, these are the same in chess960
+    pub fn king_file(self) -> File {
+        match self {
+            CastlingSide::King => File::G,
+            CastlingSide::Queen => File::C,
         }
+    }
 
-        for c in s.chars() {
-            match c {
-                'K' => castling.white_king = true,
-                'Q' => castling.white_queen = true,
-                'k' => castling.black_king = true,
-                'q' => castling.black_queen = true,
-                _ => return Err(ChessError::ParseError(s.to_string(), "Castling")),
-            }
+    pub fn rook_file(self) -> File {
+        match self {
+            CastlingSide::King => File::F,
+            CastlingSide::Queen => File::D,
         }
-
-        Ok(castling)
     }
 }
 
-impl fmt::Display for Castling {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut castling = String::new();
+// rook files instead of flags so chess960 positions fit in the same struct
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Castling {
+    pub white_king: Option<File>,
+    pub black_king: Option<File>,
+    pub white_queen: Option<File>,
+    pub black_queen: Option<File>,
+}
+
+impl Castling {
+    pub fn none() -> Castling {
+        Castling::default()
+    }
 
-        if self.white_king {
-            castling.push('K');
+    pub fn standard() -> Castling {
+        Castling {
+            white_king: Some(File::H),
+            black_king: Some(File::H),
+            white_queen: Some(File::A),
+            black_queen: Some(File::A),
         }
+    }
 
-        if self.white_queen {
-            castling.push('Q');
+    pub fn rook_file(&self, color: Color, side: CastlingSide) -> Option<File> {
+        match (color, side) {
+            (Color::White, CastlingSide::King) => self.white_king,
+            (Color::Black, CastlingSide::King) => self.black_king,
+            (Color::White, CastlingSide::Queen) => self.white_queen,
+            (Color::Black, CastlingSide::Queen) => self.black_queen,
         }
+    }
 
-        if self.black_king {
-            castling.push('k');
+    pub fn set_rook_file(&mut self, color: Color, side: CastlingSide, file: Option<File>) {
+        match (color, side) {
+            (Color::White, CastlingSide::King) => self.white_king = file,
+            (Color::Black, CastlingSide::King) => self.black_king = file,
+            (Color::White, CastlingSide::Queen) => self.white_queen = file,
+            (Color::Black, CastlingSide::Queen) => self.black_queen = file,
         }
+    }
+
+    pub fn clear(&mut self, color: Color) {
+        self.set_rook_file(color, CastlingSide::King, None);
+        self.set_rook_file(color, CastlingSide::Queen, None);
+    }
 
-        if self.black_queen {
-            castling.push('q');
+    pub fn is_empty(&self) -> bool {
+        *self == Castling::none()
+    }
+}
+
+// board independent, so this assumes the outermost rooks (use the FEN functions for X-FEN)
+impl fmt::Display for Castling {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut castling = String::new();
+
+        for color in [Color::White, Color::Black] {
+            for side in [CastlingSide::King, CastlingSide::Queen] {
+                if let Some(file) = self.rook_file(color, side) {
+                    let c = match (side, file) {
+                        (CastlingSide::King, File::H) => 'K',
+                        (CastlingSide::Queen, File::A) => 'Q',
+                        _ => file.to_char().to_ascii_uppercase(),
+                    };
+                    castling.push(match color {
+                        Color::White => c,
+                        Color::Black => c.to_ascii_lowercase(),
+                    });
+                }
+            }
         }
 
         if castling.is_empty() {
@@ -107,6 +151,7 @@ impl fmt::Display for Castling {
     }
 }
 
+#[derive(Clone)]
 pub struct State {
     pub board: Board,
     pub ply: Ply,
@@ -115,24 +160,9 @@ pub struct State {
     pub castling: Castling,
 }
 
-impl fmt::Debug for State {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for (i, rank) in self.board.board.iter().enumerate() {
-            if rank.is_some() {
-                write!(f, "{} ", rank.unwrap().as_str())?;
-            } else {
-                write!(f, ". ")?;
-            }
-            if i % 8 == 7 {
-                write!(f, "\n")?;
-            }
-        }
-        write!(
-            f,
-            "ply = {}, ep = {:?}, castling = {}\n",
-            self.ply, self.en_passant, self.castling
-        )?;
-        Ok(())
+impl Default for Board {
+    fn default() -> Board {
+        Board::new()
     }
 }
 
@@ -173,6 +203,15 @@ impl Board {
     pub fn by_piece(&self, piece: Piece) -> BitBoard {
         self.by_color(piece.color()) & self.by_piece_type(piece.piece_type())
     }
+
+    pub fn occupied(&self) -> BitBoard {
+        self.color_bb[0] | self.color_bb[1]
+    }
+
+    pub fn king_square(&self, color: Color) -> Option<Square> {
+        self.by_piece(Piece::new(color, PieceType::King))
+            .to_square()
+    }
 }
 
 impl Default for State {
@@ -189,18 +228,13 @@ impl State {
             ply: 0,
             halfmove_clock: 0,
             en_passant: None,
-            castling: Castling {
-                white_king: false,
-                black_king: false,
-                white_queen: false,
-                black_queen: false,
-            },
+            castling: Castling::none(),
         }
     }
 
     // maybe Side is a better name for Color?
     pub fn side_to_move(&self) -> Color {
-        unsafe { transmute((self.ply % 2) as u8) }
+        unsafe { transmute::<u8, Color>((self.ply % 2) as u8) }
     }
 
     pub fn ply(&self) -> Ply {
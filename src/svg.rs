@@ -0,0 +1,398 @@
+use crate::bitboard::*;
+use crate::cli::{
+    exit_on_error, flag_groups, flag_value, has_flag, parse_flag, parse_value, usage,
+};
+use crate::moves::Move;
+use crate::piece::*;
+use crate::square::Square;
+use crate::state::{squares, State};
+use std::fs;
+
+// everything is drawn in units of 45 per square and scaled to `size` through the viewBox
+const SQUARE_SIZE: f64 = 45.0;
+const MARGIN: f64 = 20.0;
+
+// piece outlines in a 45x45 box; {fill} is the body, {detail} contrasts with it
+const PAWN: &str = r#"<circle cx="22.5" cy="14" r="6" fill="{fill}"/><path d="M16 33 C16 27 19 23 22.5 21 C26 23 29 27 29 33 Z" fill="{fill}"/><rect x="11" y="33" width="23" height="5" rx="2" fill="{fill}"/>"#;
+const KNIGHT: &str = r#"<path d="M14 33 C14 27 18 24 21 19 L13 22 C11 22 10 20 11 18 L19 10 C23 7 31 8 33 16 C35 23 34 29 32 33 Z" fill="{fill}"/><circle cx="24" cy="14" r="1.5" fill="{detail}" stroke="none"/><rect x="11" y="33" width="23" height="5" rx="2" fill="{fill}"/>"#;
+const BISHOP: &str = r#"<circle cx="22.5" cy="8" r="3" fill="{fill}"/><path d="M16 31 C13 25 16 17 22.5 11 C29 17 32 25 29 31 Z" fill="{fill}"/><path d="M20 20 L25 15" stroke="{detail}"/><rect x="11" y="31" width="23" height="7" rx="2" fill="{fill}"/>"#;
+const ROOK: &str = r#"<path d="M12 9 h4 v3 h4 v-3 h5 v3 h4 v-3 h4 v7 h-21 Z" fill="{fill}"/><rect x="15" y="16" width="15" height="17" fill="{fill}"/><rect x="10" y="33" width="25" height="5" rx="1" fill="{fill}"/>"#;
+const QUEEN: &str = r#"<path d="M12 32 L9 14 L16 25 L18 11 L22.5 24 L27 11 L29 25 L36 14 L33 32 Z" fill="{fill}"/><circle cx="9" cy="13" r="2" fill="{fill}"/><circle cx="18" cy="10" r="2" fill="{fill}"/><circle cx="27" cy="10" r="2" fill="{fill}"/><circle cx="36" cy="13" r="2" fill="{fill}"/><rect x="11" y="32" width="23" height="6" rx="2" fill="{fill}"/>"#;
+const KING: &str = r#"<rect x="21" y="5" width="3" height="12" fill="{fill}"/><rect x="17.5" y="8" width="10" height="3" fill="{fill}"/><path d="M12 32 C8 24 14 18 22.5 19 C31 18 37 24 33 32 Z" fill="{fill}"/><rect x="11" y="32" width="23" height="6" rx="2" fill="{fill}"/>"#;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Arrow {
+    pub from: Square,
+    pub to: Square,
+    // falls back to SvgOptions::arrow_color
+    pub color: Option<String>,
+}
+
+impl Arrow {
+    pub fn new(from: Square, to: Square) -> Arrow {
+        Arrow {
+            from,
+            to,
+            color: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SvgOptions {
+    // width and height in pixels, including the coordinate margin
+    pub size: u32,
+    pub orientation: Color,
+    pub coordinates: bool,
+    pub light_color: String,
+    pub dark_color: String,
+    pub highlight: BitBoard,
+    pub highlight_color: String,
+    pub last_move: Option<Move>,
+    pub last_move_color: String,
+    pub arrows: Vec<Arrow>,
+    pub arrow_color: String,
+}
+
+impl Default for SvgOptions {
+    fn default() -> SvgOptions {
+        SvgOptions {
+            size: 400,
+            orientation: Color::White,
+            coordinates: true,
+            light_color: "#f0d9b5".to_string(),
+            dark_color: "#b58863".to_string(),
+            highlight: EMPTY,
+            highlight_color: "#ff000066".to_string(),
+            last_move: None,
+            last_move_color: "#cdd26a99".to_string(),
+            arrows: Vec::new(),
+            arrow_color: "#15781bcc".to_string(),
+        }
+    }
+}
+
+fn glyph_id(piece: Piece) -> String {
+    let color = match piece.color() {
+        Color::White => "white",
+        Color::Black => "black",
+    };
+    let name = match piece.piece_type() {
+        PieceType::Pawn => "pawn",
+        PieceType::Knight => "knight",
+        PieceType::Bishop => "bishop",
+        PieceType::Rook => "rook",
+        PieceType::Queen => "queen",
+        PieceType::King => "king",
+    };
+    format!("{}-{}", color, name)
+}
+
+fn glyph_defs() -> String {
+    let mut defs = String::new();
+    for index in 0..PIECE_COUNT {
+        let piece = Piece::from_index(index).unwrap();
+        let (fill, detail) = match piece.color() {
+            Color::White => ("#ffffff", "#000000"),
+            Color::Black => ("#000000", "#ffffff"),
+        };
+        let shape = match piece.piece_type() {
+            PieceType::Pawn => PAWN,
+            PieceType::Knight => KNIGHT,
+            PieceType::Bishop => BISHOP,
+            PieceType::Rook => ROOK,
+            PieceType::Queen => QUEEN,
+            PieceType::King => KING,
+        };
+        defs.push_str(&format!(
+            r##"<g id="{}" stroke="#000000" stroke-width="1.5" stroke-linejoin="round">{}</g>"##,
+            glyph_id(piece),
+            shape.replace("{fill}", fill).replace("{detail}", detail)
+        ));
+    }
+    defs
+}
+
+impl SvgOptions {
+    fn offset(&self) -> f64 {
+        if self.coordinates {
+            MARGIN
+        } else {
+            0.0
+        }
+    }
+
+    // top left corner of the square in viewBox units
+    fn corner(&self, square: Square) -> (f64, f64) {
+        let (column, row) = match self.orientation {
+            Color::White => (square.file().index(), 7 - square.rank().index()),
+            Color::Black => (7 - square.file().index(), square.rank().index()),
+        };
+        (
+            self.offset() + column as f64 * SQUARE_SIZE,
+            self.offset() + row as f64 * SQUARE_SIZE,
+        )
+    }
+
+    fn center(&self, square: Square) -> (f64, f64) {
+        let (x, y) = self.corner(square);
+        (x + SQUARE_SIZE / 2.0, y + SQUARE_SIZE / 2.0)
+    }
+}
+
+// colours come from the caller and end up inside attribute values
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn fill_square(svg: &mut String, options: &SvgOptions, square: Square, color: &str) {
+    let (x, y) = options.corner(square);
+    svg.push_str(&format!(
+        r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}"/>"#,
+        x,
+        y,
+        SQUARE_SIZE,
+        SQUARE_SIZE,
+        escape(color)
+    ));
+}
+
+// shaft and head are a single polygon so translucent colors don't double up where they meet
+fn arrow(svg: &mut String, options: &SvgOptions, arrow: &Arrow) {
+    let (x1, y1) = options.center(arrow.from);
+    let (x2, y2) = options.center(arrow.to);
+    let (dx, dy) = (x2 - x1, y2 - y1);
+    let length = (dx * dx + dy * dy).sqrt();
+    if length == 0.0 {
+        return;
+    }
+    let (ux, uy) = (dx / length, dy / length);
+    let (nx, ny) = (-uy, ux);
+    let (head_length, head_width, shaft_width) = (18.0, 11.0, 4.5);
+    let (bx, by) = (x2 - ux * head_length, y2 - uy * head_length);
+
+    let points = [
+        (x1 + nx * shaft_width, y1 + ny * shaft_width),
+        (bx + nx * shaft_width, by + ny * shaft_width),
+        (bx + nx * head_width, by + ny * head_width),
+        (x2, y2),
+        (bx - nx * head_width, by - ny * head_width),
+        (bx - nx * shaft_width, by - ny * shaft_width),
+        (x1 - nx * shaft_width, y1 - ny * shaft_width),
+    ];
+    let points: Vec<String> = points
+        .iter()
+        .map(|(x, y)| format!("{:.1},{:.1}", x, y))
+        .collect();
+    svg.push_str(&format!(
+        r#"<polygon points="{}" fill="{}"/>"#,
+        points.join(" "),
+        escape(arrow.color.as_deref().unwrap_or(&options.arrow_color))
+    ));
+}
+
+impl State {
+    // a standalone SVG document, the glyphs are defined inline so nothing else is needed to view it
+    pub fn to_svg(&self, options: &SvgOptions) -> String {
+        let extent = SQUARE_SIZE * 8.0 + options.offset() * 2.0;
+        let mut svg = String::new();
+        svg.push_str(&format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" version="1.1" width="{size}" height="{size}" viewBox="0 0 {extent} {extent}">"#,
+            size = options.size,
+            extent = extent
+        ));
+        svg.push_str(&format!("<defs>{}</defs>", glyph_defs()));
+
+        if options.coordinates {
+            svg.push_str(&format!(
+                r##"<rect x="0" y="0" width="{0}" height="{0}" fill="#262421"/>"##,
+                extent
+            ));
+        }
+
+        for square in squares() {
+            let color = if square.color() == Color::White {
+                &options.light_color
+            } else {
+                &options.dark_color
+            };
+            fill_square(&mut svg, options, square, color);
+        }
+
+        if let Some(mv) = options.last_move {
+            let to = if mv.is_castling() {
+                mv.king_destination()
+            } else {
+                mv.to
+            };
+            fill_square(&mut svg, options, mv.from, &options.last_move_color);
+            fill_square(&mut svg, options, to, &options.last_move_color);
+        }
+        for square in options.highlight {
+            fill_square(&mut svg, options, square, &options.highlight_color);
+        }
+
+        if options.coordinates {
+            for i in 0..8 {
+                let file = (b'a' + i as u8) as char;
+                let rank = (b'1' + i as u8) as char;
+                let square = Square::from_index(i * 9).unwrap();
+                let (x, y) = options.center(square);
+                svg.push_str(&format!(
+                    r##"<text x="{}" y="{}" fill="#e5e5e5" font-family="sans-serif" font-size="14" text-anchor="middle">{}</text>"##,
+                    x,
+                    extent - MARGIN / 2.0 + 5.0,
+                    file
+                ));
+                svg.push_str(&format!(
+                    r##"<text x="{}" y="{}" fill="#e5e5e5" font-family="sans-serif" font-size="14" text-anchor="middle">{}</text>"##,
+                    MARGIN / 2.0,
+                    y + 5.0,
+                    rank
+                ));
+            }
+        }
+
+        for (square, piece) in self.board.pieces() {
+            if let Some(piece) = piece {
+                let (x, y) = options.corner(square);
+                svg.push_str(&format!(
+                    r##"<use href="#{0}" xlink:href="#{0}" x="{1}" y="{2}"/>"##,
+                    glyph_id(piece),
+                    x,
+                    y
+                ));
+            }
+        }
+
+        for a in &options.arrows {
+            arrow(&mut svg, options, a);
+        }
+
+        svg.push_str("</svg>");
+        svg
+    }
+}
+
+const USAGE: &str = "svg <fen> [--output file] [--size n] [--flip] [--no-coordinates] \
+                     [--last-move e2e4] [--arrow e2e4 ..]";
+
+// two squares written together like e2e4, for arrows and the last move
+fn parse_squares(flag: &str, text: &str) -> (Square, Square) {
+    let (from, to) = text.split_at_checked(2).unwrap_or_else(|| usage(USAGE));
+    (parse_value(flag, from), parse_value(flag, to))
+}
+
+// svg <fen> [--output file] [--size n] [--flip] [--no-coordinates] [--last-move e2e4]
+//   [--arrow e2e4 ..]
+// writes to stdout without --output
+pub fn command(args: &[String]) {
+    let Some(fen) = args.first() else {
+        usage(USAGE);
+    };
+    let state = exit_on_error(State::from_fen(fen));
+    let defaults = SvgOptions::default();
+    let options = SvgOptions {
+        size: parse_flag(args, "--size").unwrap_or(defaults.size),
+        orientation: if has_flag(args, "--flip") {
+            Color::Black
+        } else {
+            Color::White
+        },
+        coordinates: !has_flag(args, "--no-coordinates"),
+        // the move was already played, so it isn't checked against the position
+        last_move: flag_value(args, "--last-move").map(|text| {
+            let (from, to) = parse_squares("--last-move", text);
+            Move::new(from, to)
+        }),
+        arrows: flag_groups(args, "--arrow")
+            .into_iter()
+            .flatten()
+            .map(|text| {
+                let (from, to) = parse_squares("--arrow", text);
+                Arrow::new(from, to)
+            })
+            .collect(),
+        ..defaults
+    };
+
+    let svg = state.to_svg(&options);
+    match flag_value(args, "--output") {
+        Some(path) => exit_on_error(fs::write(path, svg).map_err(Into::into)),
+        None => println!("{}", svg),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn start_position() {
+        let svg = State::default().to_svg(&SvgOptions::default());
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.ends_with("</svg>"));
+        assert_eq!(svg.matches("<use ").count(), 32);
+        assert_eq!(svg.matches("<text ").count(), 16);
+        assert!(svg.contains(r##"<g id="white-king""##));
+        // a1 sits in the bottom left corner, next to the margin
+        assert!(
+            svg.contains(r##"<use href="#white-rook" xlink:href="#white-rook" x="20" y="335"/>"##)
+        );
+    }
+
+    #[test]
+    fn overlays_and_orientation() {
+        let state = State::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        let options = SvgOptions {
+            orientation: Color::Black,
+            coordinates: false,
+            highlight: BitBoard::from_square(Square::H1),
+            last_move: Some(Move::new(Square::E2, Square::E1)),
+            arrows: vec![
+                Arrow::new(Square::E1, Square::E8),
+                Arrow {
+                    color: Some("blue".to_string()),
+                    ..Arrow::new(Square::E1, Square::A5)
+                },
+            ],
+            ..SvgOptions::default()
+        };
+        let svg = state.to_svg(&options);
+        assert_eq!(svg.matches("<text ").count(), 0);
+        assert_eq!(svg.matches("<polygon ").count(), 2);
+        assert!(svg.contains(r#"fill="blue""#));
+        // with black at the bottom h1 is the top left square
+        assert!(svg.contains(r##"<rect x="0" y="0" width="45" height="45" fill="#ff000066"/>"##));
+        assert!(
+            svg.contains(r##"<use href="#white-king" xlink:href="#white-king" x="135" y="0"/>"##)
+        );
+    }
+
+    #[test]
+    fn escaped_colors() {
+        let options = SvgOptions {
+            light_color: r#"red"/><script/>"#.to_string(),
+            arrows: vec![Arrow {
+                color: Some("a&b'<c>".to_string()),
+                ..Arrow::new(Square::E2, Square::E4)
+            }],
+            ..SvgOptions::default()
+        };
+        let svg = State::default().to_svg(&options);
+        assert!(!svg.contains("<script"));
+        assert!(svg.contains(r#"fill="red&quot;/&gt;&lt;script/&gt;""#));
+        assert!(svg.contains(r#"fill="a&amp;b&apos;&lt;c&gt;""#));
+    }
+}
@@ -0,0 +1,2176 @@
+// Syzygy endgame tablebases: WDL tables (.rtbw) are probed inside the search, DTZ tables
+// (.rtbz) at the root. The file layout and the position indexing follow Ronald de Man's
+// reference prober, positions with castling rights are never in the tables.
+use crate::bitboard::*;
+use crate::bitboard_moves::king_moves;
+use crate::errors::ChessError;
+use crate::moves::Move;
+use crate::piece::*;
+use crate::square::Square;
+use crate::state::{Board, State};
+use memmap2::Mmap;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::ops::Neg;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+
+pub const MAX_PIECES: usize = 7;
+// root move ranks lie in -MAX_DTZ..=MAX_DTZ, see rank_root_moves
+pub const MAX_DTZ: i32 = 1 << 18;
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+
+// per sub-table flags, everything but SINGLE_VALUE only appears in DTZ tables
+const STM: u8 = 1;
+const MAPPED: u8 = 2;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const WIDE: u8 = 16;
+const SINGLE_VALUE: u8 = 128;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss = -2,
+    // lost, but saved by the fifty-move rule
+    BlessedLoss = -1,
+    Draw = 0,
+    // won, but not before the fifty-move rule kicks in
+    CursedWin = 1,
+    Win = 2,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Wdl {
+        match value {
+            i32::MIN..=-2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            0 => Wdl::Draw,
+            1 => Wdl::CursedWin,
+            _ => Wdl::Win,
+        }
+    }
+
+    pub fn value(self) -> i32 {
+        self as i32
+    }
+
+    // what the result is worth when the fifty-move rule is ignored
+    pub fn ignoring_fifty_moves(self) -> Wdl {
+        match self {
+            Wdl::BlessedLoss => Wdl::Loss,
+            Wdl::CursedWin => Wdl::Win,
+            wdl => wdl,
+        }
+    }
+}
+
+impl Neg for Wdl {
+    type Output = Wdl;
+
+    fn neg(self) -> Wdl {
+        Wdl::from_value(-self.value())
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ProbeResult {
+    pub wdl: Wdl,
+    // plies to the next capture or pawn move when playing it out, signed like wdl;
+    // None when the DTZ table is missing
+    pub dtz: Option<i32>,
+}
+
+// the dtz of a position whose best move resets the fifty-move counter
+fn dtz_before_zeroing(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Win => 1,
+        Wdl::CursedWin => 101,
+        Wdl::BlessedLoss => -101,
+        Wdl::Loss => -1,
+        Wdl::Draw => 0,
+    }
+}
+
+// lookup tables for turning a position into a table index
+struct Encoding {
+    binomial: [[u64; 64]; 6],
+    map_a1d1d4: [usize; 64],
+    map_kk: [[usize; 64]; 10],
+    map_b1h1h7: [usize; 64],
+    map_pawns: [usize; 64],
+    lead_pawn_index: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6],
+}
+
+// positive above the a1-h8 diagonal, zero on it
+fn off_diagonal(square: usize) -> i32 {
+    (square / 8) as i32 - (square % 8) as i32
+}
+
+// the square of the a1-d1-d4 triangle with the given code
+fn triangle_square(map_a1d1d4: &[usize; 64], index: usize) -> usize {
+    (0..64)
+        .find(|&s| s % 8 < 4 && s / 8 < 4 && off_diagonal(s) <= 0 && map_a1d1d4[s] == index)
+        .unwrap()
+}
+
+fn encoding() -> &'static Encoding {
+    static ENCODING: OnceLock<Encoding> = OnceLock::new();
+    ENCODING.get_or_init(|| {
+        let mut enc = Encoding {
+            binomial: [[0; 64]; 6],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+            map_b1h1h7: [0; 64],
+            map_pawns: [0; 64],
+            lead_pawn_index: [[0; 64]; 6],
+            lead_pawns_size: [[0; 4]; 6],
+        };
+
+        // the 28 squares below the diagonal
+        let mut code = 0;
+        for square in 0..64 {
+            if off_diagonal(square) < 0 {
+                enc.map_b1h1h7[square] = code;
+                code += 1;
+            }
+        }
+
+        // the a1-d1-d4 triangle, squares on the diagonal last
+        let mut diagonal = Vec::new();
+        code = 0;
+        for rank in 0..4 {
+            for file in 0..4 {
+                let square = rank * 8 + file;
+                match off_diagonal(square) {
+                    0 => diagonal.push(square),
+                    off if off < 0 => {
+                        enc.map_a1d1d4[square] = code;
+                        code += 1;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for square in diagonal {
+            enc.map_a1d1d4[square] = code;
+            code += 1;
+        }
+
+        // the 462 ways to place two kings with the first one in the triangle; when the
+        // first is on the diagonal the second isn't above it, and pairs both on the
+        // diagonal come last
+        let mut both_on_diagonal = Vec::new();
+        code = 0;
+        for index in 0..10 {
+            let square = triangle_square(&enc.map_a1d1d4, index);
+            let near = king_moves(Square::from_index(square).unwrap())
+                | BitBoard::from_square(Square::from_index(square).unwrap());
+            for second in 0..64 {
+                let illegal = near.contains(Square::from_index(second).unwrap())
+                    || (off_diagonal(square) == 0 && off_diagonal(second) > 0);
+                if illegal {
+                    continue;
+                } else if off_diagonal(square) == 0 && off_diagonal(second) == 0 {
+                    both_on_diagonal.push((index, second));
+                } else {
+                    enc.map_kk[index][second] = code;
+                    code += 1;
+                }
+            }
+        }
+        for (index, second) in both_on_diagonal {
+            enc.map_kk[index][second] = code;
+            code += 1;
+        }
+
+        enc.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..6.min(n + 1) {
+                enc.binomial[k][n] = if k > 0 { enc.binomial[k - 1][n - 1] } else { 0 }
+                    + if k < n { enc.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        // pawns on a2..h7 get 47 down to 0, the leading pawn (nearest the edge, then
+        // lowest rank) has the highest value
+        let mut available = 47;
+        for lead_pawns in 1..6 {
+            for file in 0..4 {
+                let mut index = 0;
+                for rank in 1..7 {
+                    let square = rank * 8 + file;
+                    if lead_pawns == 1 {
+                        enc.map_pawns[square] = available;
+                        enc.map_pawns[square ^ 7] = available - 1;
+                        available = available.saturating_sub(2);
+                    }
+                    enc.lead_pawn_index[lead_pawns][square] = index;
+                    index += enc.binomial[lead_pawns - 1][enc.map_pawns[square]];
+                }
+                enc.lead_pawns_size[lead_pawns][file] = index;
+            }
+        }
+        enc
+    })
+}
+
+// piece counts of the side named first in a table name ("KRP" in KRPvKR) and the other one
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Material {
+    pub counts: [[u8; PIECE_TYPE_COUNT]; 2],
+}
+
+pub const NAME_ORDER: [PieceType; PIECE_TYPE_COUNT] = [
+    PieceType::King,
+    PieceType::Queen,
+    PieceType::Rook,
+    PieceType::Bishop,
+    PieceType::Knight,
+    PieceType::Pawn,
+];
+
+impl Material {
+    pub fn from_board(board: &Board, first: Color) -> Material {
+        let mut counts = [[0; PIECE_TYPE_COUNT]; 2];
+        for (side, color) in [first, !first].into_iter().enumerate() {
+            for piece_type in NAME_ORDER {
+                counts[side][piece_type.index()] =
+                    board.by_piece(Piece::new(color, piece_type)).count() as u8;
+            }
+        }
+        Material { counts }
+    }
+
+    // "KRPvKR"
+    pub fn parse(name: &str) -> Option<Material> {
+        let (first, second) = name.split_once('v')?;
+        let mut counts = [[0; PIECE_TYPE_COUNT]; 2];
+        for (side, pieces) in [first, second].into_iter().enumerate() {
+            for c in pieces.chars() {
+                let piece: Piece = c.to_string().parse().ok()?;
+                counts[side][piece.piece_type().index()] += 1;
+            }
+            if counts[side][PieceType::King.index()] != 1 {
+                return None;
+            }
+        }
+        let material = Material { counts };
+        (material.piece_count() <= MAX_PIECES && material.name() == name).then_some(material)
+    }
+
+    pub fn name(&self) -> String {
+        let side = |counts: &[u8; PIECE_TYPE_COUNT]| -> String {
+            NAME_ORDER
+                .iter()
+                .map(|piece_type| {
+                    let c = Piece::new(Color::White, *piece_type).as_str();
+                    c.repeat(counts[piece_type.index()] as usize)
+                })
+                .collect()
+        };
+        format!("{}v{}", side(&self.counts[0]), side(&self.counts[1]))
+    }
+
+    pub fn flipped(&self) -> Material {
+        Material {
+            counts: [self.counts[1], self.counts[0]],
+        }
+    }
+
+    pub fn piece_count(&self) -> usize {
+        self.counts.iter().flatten().map(|&n| n as usize).sum()
+    }
+
+    fn pawns(&self, side: usize) -> u8 {
+        self.counts[side][PieceType::Pawn.index()]
+    }
+
+    pub fn has_pawns(&self) -> bool {
+        self.pawns(0) + self.pawns(1) > 0
+    }
+
+    fn is_symmetric(&self) -> bool {
+        self.counts[0] == self.counts[1]
+    }
+
+    fn has_unique_pieces(&self) -> bool {
+        self.counts
+            .iter()
+            .any(|side| side[..PieceType::King.index()].contains(&1))
+    }
+
+    // pawns of the leading side and of the other one; the side with fewer pawns leads
+    // because that compresses better
+    fn pawn_counts(&self) -> [u8; 2] {
+        let first_leads =
+            self.pawns(1) == 0 || (self.pawns(0) > 0 && self.pawns(1) >= self.pawns(0));
+        if first_leads {
+            [self.pawns(0), self.pawns(1)]
+        } else {
+            [self.pawns(1), self.pawns(0)]
+        }
+    }
+}
+
+// pieces as stored in the files: the piece type from 1 (pawn) to 6 (king), plus 8 for black
+fn piece_code(piece: Piece) -> u8 {
+    piece.piece_type().index() as u8 + 1 + 8 * piece.color().index() as u8
+}
+
+fn read(data: &[u8], offset: usize, len: usize) -> Result<&[u8], ChessError> {
+    data.get(offset..offset + len)
+        .ok_or_else(|| ChessError::InvalidTablebase("unexpected end of file".to_string()))
+}
+
+fn read_u8(data: &[u8], offset: usize) -> Result<usize, ChessError> {
+    Ok(read(data, offset, 1)?[0] as usize)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<usize, ChessError> {
+    let bytes = read(data, offset, 2)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+}
+
+// while decompressing, bytes past the end of the file read as zero
+fn big_endian(data: &[u8], offset: usize, len: usize) -> u64 {
+    (0..len).fold(0, |value, i| {
+        (value << 8) | data.get(offset + i).copied().unwrap_or(0) as u64
+    })
+}
+
+fn little_endian(data: &[u8], offset: usize, len: usize) -> u64 {
+    (0..len).rev().fold(0, |value, i| {
+        (value << 8) | data.get(offset + i).copied().unwrap_or(0) as u64
+    })
+}
+
+// one sub-table: a side to move (WDL) and a file of the leading pawn (pawn tables)
+#[derive(Default)]
+struct PairsData {
+    flags: u8,
+    min_sym_len: usize,
+    block_size: usize,
+    span: usize,
+    num_blocks: usize,
+    // everything below is a byte offset into the file
+    lowest_sym: usize,
+    btree: usize,
+    block_lengths: usize,
+    block_length_count: usize,
+    sparse_index: usize,
+    sparse_index_count: usize,
+    data: usize,
+    // base64[l] is the lowest code of length min_sym_len + l, left aligned
+    base64: Vec<u64>,
+    // number of values a symbol expands to, minus one
+    symlen: Vec<u32>,
+    pieces: [u8; MAX_PIECES],
+    group_index: [u64; MAX_PIECES + 1],
+    group_len: [usize; MAX_PIECES + 1],
+    // where the four DTZ value maps start, by WDL win, loss, cursed win, blessed loss
+    map_index: [usize; 4],
+}
+
+// a symbol either stands for a value (right == 0xFFF) or for the pair of symbols it replaced
+fn symbol_pair(data: &[u8], btree: usize, symbol: usize) -> (usize, usize) {
+    let lr = little_endian(data, btree + 3 * symbol, 3) as usize;
+    (lr & 0xFFF, lr >> 12)
+}
+
+impl PairsData {
+    // group pieces that are indexed together: the leading group is the pawns of the leading
+    // side, or three unique pieces, or the two kings; after that identical pieces
+    fn set_groups(&mut self, material: &Material, order: [usize; 2], file: usize) {
+        let enc = encoding();
+        let has_pawns = material.has_pawns();
+        let mut first_len: i32 = if has_pawns {
+            0
+        } else if material.has_unique_pieces() {
+            3
+        } else {
+            2
+        };
+        let mut n = 0;
+        self.group_len[0] = 1;
+        for i in 1..material.piece_count() {
+            first_len -= 1;
+            if first_len > 0 || self.pieces[i] == self.pieces[i - 1] {
+                self.group_len[n] += 1;
+            } else {
+                n += 1;
+                self.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        self.group_len[n] = 0;
+
+        // the groups are combined in the order the file asks for
+        let both_pawns = has_pawns && material.pawn_counts()[1] > 0;
+        let mut next = if both_pawns { 2 } else { 1 };
+        let mut free_squares =
+            64 - self.group_len[0] - if both_pawns { self.group_len[1] } else { 0 };
+        let mut index = 1;
+        let mut k = 0;
+        while next < n || k == order[0] || k == order[1] {
+            if k == order[0] {
+                self.group_index[0] = index;
+                index *= if has_pawns {
+                    enc.lead_pawns_size[self.group_len[0]][file]
+                } else if material.has_unique_pieces() {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] {
+                self.group_index[1] = index;
+                index *= enc.binomial[self.group_len[1]][48 - self.group_len[0]];
+            } else {
+                self.group_index[next] = index;
+                index *= enc.binomial[self.group_len[next]][free_squares];
+                free_squares -= self.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        self.group_index[n] = index;
+    }
+
+    fn set_sizes(&mut self, data: &[u8], mut offset: usize) -> Result<usize, ChessError> {
+        self.flags = read_u8(data, offset)? as u8;
+        if self.flags & SINGLE_VALUE != 0 {
+            self.min_sym_len = read_u8(data, offset + 1)?;
+            return Ok(offset + 2);
+        }
+
+        let groups = self.group_len.iter().position(|&len| len == 0).unwrap();
+        let size = self.group_index[groups];
+        self.block_size = 1 << read_u8(data, offset + 1)?;
+        self.span = 1 << read_u8(data, offset + 2)?;
+        self.sparse_index_count = size.div_ceil(self.span as u64) as usize;
+        let padding = read_u8(data, offset + 3)?;
+        self.num_blocks = little_endian(read(data, offset + 4, 4)?, 0, 4) as usize;
+        self.block_length_count = self.num_blocks + padding;
+        let max_sym_len = read_u8(data, offset + 8)?;
+        self.min_sym_len = read_u8(data, offset + 9)?;
+        offset += 10;
+        if max_sym_len < self.min_sym_len || self.min_sym_len == 0 {
+            return Err(ChessError::InvalidTablebase(
+                "bad symbol lengths".to_string(),
+            ));
+        }
+
+        // canonical Huffman codes: longer codes have lower values, so base64 decreases
+        self.lowest_sym = offset;
+        let lengths = max_sym_len - self.min_sym_len + 1;
+        read(data, offset, 2 * lengths)?;
+        let lowest = |i: usize| little_endian(data, offset + 2 * i, 2);
+        self.base64 = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            self.base64[i] = self.base64[i + 1]
+                .wrapping_add(lowest(i))
+                .wrapping_sub(lowest(i + 1))
+                / 2;
+        }
+        for (i, base) in self.base64.iter_mut().enumerate() {
+            let shift = 64usize.saturating_sub(i + self.min_sym_len) as u32;
+            *base = base.checked_shl(shift).unwrap_or(0);
+        }
+        offset += 2 * lengths;
+
+        let symbols = read_u16(data, offset)?;
+        offset += 2;
+        self.btree = offset;
+        read(data, offset, 3 * symbols)?;
+        self.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for symbol in 0..symbols {
+            if !visited[symbol] {
+                self.set_symlen(data, symbol, &mut visited)?;
+            }
+        }
+        Ok(offset + 3 * symbols + (symbols & 1))
+    }
+
+    fn set_symlen(
+        &mut self,
+        data: &[u8],
+        symbol: usize,
+        visited: &mut [bool],
+    ) -> Result<(), ChessError> {
+        visited[symbol] = true;
+        let (left, right) = symbol_pair(data, self.btree, symbol);
+        if right == 0xFFF {
+            return Ok(());
+        }
+        if left >= self.symlen.len() || right >= self.symlen.len() {
+            return Err(ChessError::InvalidTablebase("bad symbol tree".to_string()));
+        }
+        for child in [left, right] {
+            if !visited[child] {
+                self.set_symlen(data, child, visited)?;
+            }
+        }
+        self.symlen[symbol] = self.symlen[left] + self.symlen[right] + 1;
+        Ok(())
+    }
+
+    // the value stored at `index`
+    fn decompress(&self, data: &[u8], index: u64) -> i32 {
+        if self.flags & SINGLE_VALUE != 0 {
+            return self.min_sym_len as i32;
+        }
+
+        // sparse_index[k] locates the value at k * span + span / 2, walk from there
+        let k = (index / self.span as u64) as usize;
+        let entry = self.sparse_index + 6 * k;
+        let mut block = little_endian(data, entry, 4) as usize;
+        let mut offset = little_endian(data, entry + 4, 2) as i64;
+        offset += (index % self.span as u64) as i64 - (self.span / 2) as i64;
+        let block_length = |block: usize| little_endian(data, self.block_lengths + 2 * block, 2);
+        while offset < 0 && block > 0 {
+            block -= 1;
+            offset += block_length(block) as i64 + 1;
+        }
+        while offset > block_length(block) as i64 && block + 1 < self.block_length_count {
+            offset -= block_length(block) as i64 + 1;
+            block += 1;
+        }
+
+        // find the symbol covering our offset, each one expands to symlen + 1 values
+        let mut ptr = self.data + block * self.block_size;
+        let mut buf = big_endian(data, ptr, 8);
+        let mut buf_size = 64;
+        ptr += 8;
+        let mut symbol;
+        loop {
+            let mut len = 0;
+            while len + 1 < self.base64.len() && buf < self.base64[len] {
+                len += 1;
+            }
+            let shift = (64 - len - self.min_sym_len) as u32;
+            symbol = ((buf - self.base64[len]).checked_shr(shift).unwrap_or(0)) as usize;
+            symbol += little_endian(data, self.lowest_sym + 2 * len, 2) as usize;
+            let count = self.symlen.get(symbol).map_or(0, |&n| n as i64) + 1;
+            if offset < count {
+                break;
+            }
+            offset -= count;
+            let len = len + self.min_sym_len;
+            buf <<= len;
+            buf_size -= len;
+            if buf_size <= 32 {
+                buf_size += 32;
+                buf |= big_endian(data, ptr, 4) << (64 - buf_size);
+                ptr += 4;
+            }
+        }
+
+        // then descend the pair tree to the value itself
+        while self.symlen.get(symbol).is_some_and(|&n| n > 0) {
+            let (left, right) = symbol_pair(data, self.btree, symbol);
+            let left_count = self.symlen[left] as i64 + 1;
+            if offset < left_count {
+                symbol = left;
+            } else {
+                offset -= left_count;
+                symbol = right;
+            }
+        }
+        symbol_pair(data, self.btree, symbol).0 as i32
+    }
+}
+
+struct Table {
+    data: Mmap,
+    material: Material,
+    dtz: bool,
+    // [side to move][file of the leading pawn]; one side for DTZ and symmetric tables,
+    // one file without pawns
+    pairs: Vec<PairsData>,
+    files: usize,
+}
+
+impl Table {
+    fn open(path: &Path, material: Material, dtz: bool) -> Result<Table, ChessError> {
+        let file = fs::File::open(path)?;
+        // safety: tables are read-only, nothing is expected to modify them while mapped
+        let data = unsafe { Mmap::map(&file)? };
+        let magic = if dtz { DTZ_MAGIC } else { WDL_MAGIC };
+        if read(&data, 0, 4)? != magic {
+            return Err(ChessError::InvalidTablebase(format!(
+                "{} has a bad header",
+                path.display()
+            )));
+        }
+        let (pairs, files) = Table::parse(&data, &material, dtz)?;
+        Ok(Table {
+            data,
+            material,
+            dtz,
+            pairs,
+            files,
+        })
+    }
+
+    fn parse(
+        data: &[u8],
+        material: &Material,
+        dtz: bool,
+    ) -> Result<(Vec<PairsData>, usize), ChessError> {
+        let has_pawns = material.has_pawns();
+        if (read_u8(data, 4)? & 2 != 0) != has_pawns {
+            return Err(ChessError::InvalidTablebase(
+                "file doesn't match its name".to_string(),
+            ));
+        }
+        let sides = if !dtz && !material.is_symmetric() {
+            2
+        } else {
+            1
+        };
+        let files = if has_pawns { 4 } else { 1 };
+        let both_pawns = has_pawns && material.pawn_counts()[1] > 0;
+        let mut pairs: Vec<PairsData> = (0..sides * files).map(|_| PairsData::default()).collect();
+
+        let mut offset = 5;
+        for file in 0..files {
+            let first = read_u8(data, offset)?;
+            let second = if both_pawns {
+                read_u8(data, offset + 1)?
+            } else {
+                0xFF
+            };
+            let order = [[first & 0xF, second & 0xF], [first >> 4, second >> 4]];
+            offset += 1 + both_pawns as usize;
+
+            for k in 0..material.piece_count() {
+                let byte = read_u8(data, offset)? as u8;
+                for side in 0..sides {
+                    pairs[side * files + file].pieces[k] =
+                        if side == 1 { byte >> 4 } else { byte & 0xF };
+                }
+                offset += 1;
+            }
+            for side in 0..sides {
+                pairs[side * files + file].set_groups(material, order[side], file);
+            }
+        }
+        offset += offset & 1;
+
+        for file in 0..files {
+            for side in 0..sides {
+                offset = pairs[side * files + file].set_sizes(data, offset)?;
+            }
+        }
+
+        if dtz {
+            for d in pairs.iter_mut() {
+                if d.flags & MAPPED == 0 {
+                    continue;
+                }
+                for index in d.map_index.iter_mut() {
+                    if d.flags & WIDE != 0 {
+                        offset += offset & 1;
+                        *index = offset + 2;
+                        offset += 2 * read_u16(data, offset)? + 2;
+                    } else {
+                        *index = offset + 1;
+                        offset += read_u8(data, offset)? + 1;
+                    }
+                }
+            }
+            offset += offset & 1;
+        }
+
+        // the remaining sections go file by file, then side by side
+        let order: Vec<usize> = (0..files)
+            .flat_map(|file| (0..sides).map(move |side| side * files + file))
+            .collect();
+        for &i in &order {
+            pairs[i].sparse_index = offset;
+            offset += 6 * pairs[i].sparse_index_count;
+        }
+        for &i in &order {
+            pairs[i].block_lengths = offset;
+            offset += 2 * pairs[i].block_length_count;
+        }
+        read(data, 0, offset)?;
+        for &i in &order {
+            let d = &mut pairs[i];
+            offset = (offset + 0x3F) & !0x3F;
+            d.data = offset;
+            offset += d.num_blocks * d.block_size;
+            if d.num_blocks > 0 {
+                read(data, 0, offset)?;
+            }
+        }
+        Ok((pairs, files))
+    }
+
+    fn pairs(&self, side: usize, file: usize) -> &PairsData {
+        let sides = self.pairs.len() / self.files;
+        &self.pairs[(side % sides) * self.files + file]
+    }
+
+    // (side, file, index) of the position, None when a DTZ table only stores the other
+    // side to move; `flip` swaps colors when the table names the position's black side first
+    fn encode(&self, state: &State, flip: bool) -> Option<(usize, usize, u64)> {
+        let enc = encoding();
+        let material = &self.material;
+        let flip_color = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let side = flip as usize ^ state.side_to_move().index();
+
+        let mut squares = [0; MAX_PIECES];
+        let mut pieces = [0; MAX_PIECES];
+        let mut size = 0;
+        let mut lead_pawns = EMPTY;
+        let mut file = 0;
+        if material.has_pawns() {
+            let code = self.pairs(0, 0).pieces[0] ^ flip_color;
+            let color = if code & 8 != 0 {
+                Color::Black
+            } else {
+                Color::White
+            };
+            lead_pawns = state.board.by_piece(Piece::new(color, PieceType::Pawn));
+            for square in lead_pawns {
+                squares[size] = square.index() ^ flip_squares;
+                size += 1;
+            }
+            let lead = (0..size)
+                .max_by_key(|&i| enc.map_pawns[squares[i]])
+                .unwrap();
+            squares.swap(0, lead);
+            file = (squares[0] % 8).min(7 - squares[0] % 8);
+        }
+        let lead_pawn_count = size;
+
+        if self.dtz {
+            let flags = self.pairs(side, file).flags;
+            // symmetric pawnless tables answer for both sides
+            let both_sides = material.is_symmetric() && !material.has_pawns();
+            if (flags & STM) as usize != side && !both_sides {
+                return None;
+            }
+        }
+
+        for square in state.board.occupied() - lead_pawns {
+            squares[size] = square.index() ^ flip_squares;
+            pieces[size] = piece_code(state.board.piece(square).unwrap()) ^ flip_color;
+            size += 1;
+        }
+
+        // put the pieces in the order the table expects them
+        let d = self.pairs(side, file);
+        for i in lead_pawn_count..size.saturating_sub(1) {
+            if let Some(j) = (i + 1..size).find(|&j| pieces[j] == d.pieces[i]) {
+                pieces.swap(i, j);
+                squares.swap(i, j);
+            }
+        }
+
+        // mirror so the leading piece is on files a-d
+        if squares[0] % 8 > 3 {
+            for square in &mut squares[..size] {
+                *square ^= 7;
+            }
+        }
+
+        let mut index;
+        if material.has_pawns() {
+            index = enc.lead_pawn_index[lead_pawn_count][squares[0]];
+            squares[1..lead_pawn_count].sort_by_key(|&square| enc.map_pawns[square]);
+            for (i, &square) in squares.iter().enumerate().take(lead_pawn_count).skip(1) {
+                index += enc.binomial[i][enc.map_pawns[square]];
+            }
+        } else {
+            // without pawns also mirror to ranks 1-4 and below the a1-h8 diagonal
+            if squares[0] / 8 > 3 {
+                for square in &mut squares[..size] {
+                    *square ^= 56;
+                }
+            }
+            for i in 0..d.group_len[0] {
+                let off = off_diagonal(squares[i]);
+                if off == 0 {
+                    continue;
+                }
+                if off > 0 {
+                    for square in &mut squares[i..size] {
+                        *square = ((*square >> 3) | (*square << 3)) & 63;
+                    }
+                }
+                break;
+            }
+
+            let s = &squares;
+            if material.has_unique_pieces() {
+                let adjust1 = (s[1] > s[0]) as usize;
+                let adjust2 = (s[2] > s[0]) as usize + (s[2] > s[1]) as usize;
+                let rank = |square: usize| square / 8;
+                index = if off_diagonal(s[0]) != 0 {
+                    (enc.map_a1d1d4[s[0]] * 63 + (s[1] - adjust1)) * 62 + s[2] - adjust2
+                } else if off_diagonal(s[1]) != 0 {
+                    (6 * 63 + rank(s[0]) * 28 + enc.map_b1h1h7[s[1]]) * 62 + s[2] - adjust2
+                } else if off_diagonal(s[2]) != 0 {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + rank(s[0]) * 7 * 28
+                        + (rank(s[1]) - adjust1) * 28
+                        + enc.map_b1h1h7[s[2]]
+                } else {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + 4 * 7 * 28
+                        + rank(s[0]) * 7 * 6
+                        + (rank(s[1]) - adjust1) * 6
+                        + (rank(s[2]) - adjust2)
+                } as u64;
+            } else {
+                index = enc.map_kk[enc.map_a1d1d4[s[0]]][s[1]] as u64;
+            }
+        }
+
+        // the remaining groups, each as a combination of the squares the earlier groups left
+        index *= d.group_index[0];
+        let mut start = d.group_len[0];
+        let mut remaining_pawns = material.has_pawns() && material.pawn_counts()[1] > 0;
+        let mut next = 1;
+        while d.group_len[next] != 0 {
+            let len = d.group_len[next];
+            squares[start..start + len].sort_unstable();
+            let mut n = 0;
+            for i in 0..len {
+                let square = squares[start + i];
+                let adjust = squares[..start].iter().filter(|&&s| square > s).count();
+                let pawn_adjust = if remaining_pawns { 8 } else { 0 };
+                n += enc.binomial[i + 1][square - adjust - pawn_adjust];
+            }
+            remaining_pawns = false;
+            index += n * d.group_index[next];
+            start += len;
+            next += 1;
+        }
+        Some((side, file, index))
+    }
+
+    fn probe_wdl(&self, state: &State, flip: bool) -> Wdl {
+        let (side, file, index) = self.encode(state, flip).unwrap();
+        Wdl::from_value(self.pairs(side, file).decompress(&self.data, index) - 2)
+    }
+
+    // None when the table is stored for the other side to move
+    fn probe_dtz(&self, state: &State, flip: bool, wdl: Wdl) -> Option<i32> {
+        let (side, file, index) = self.encode(state, flip)?;
+        let d = self.pairs(side, file);
+        let mut value = d.decompress(&self.data, index) as usize;
+
+        // values are remapped by frequency, separately for each outcome
+        let map = self.pairs(0, file);
+        if map.flags & MAPPED != 0 {
+            let start = map.map_index[match wdl {
+                Wdl::Win => 0,
+                Wdl::Loss => 1,
+                Wdl::CursedWin => 2,
+                _ => 3,
+            }];
+            value = if map.flags & WIDE != 0 {
+                little_endian(&self.data, start + 2 * value, 2) as usize
+            } else {
+                little_endian(&self.data, start + value, 1) as usize
+            };
+        }
+
+        // some tables count moves rather than plies
+        let value = value as i32;
+        let plies = match wdl {
+            Wdl::Win => map.flags & WIN_PLIES != 0,
+            Wdl::Loss => map.flags & LOSS_PLIES != 0,
+            _ => false,
+        };
+        Some(if plies { value } else { value * 2 } + 1)
+    }
+}
+
+struct TableFiles {
+    material: Material,
+    wdl_path: Option<PathBuf>,
+    dtz_path: Option<PathBuf>,
+    wdl: OnceLock<Result<Table, String>>,
+    dtz: OnceLock<Result<Table, String>>,
+}
+
+impl TableFiles {
+    // tables are mapped on first use, None if the file is missing or unreadable
+    fn get(&self, dtz: bool) -> Option<&Table> {
+        self.load(dtz)?.as_ref().ok()
+    }
+
+    fn load(&self, dtz: bool) -> Option<&Result<Table, String>> {
+        let (cell, path) = if dtz {
+            (&self.dtz, &self.dtz_path)
+        } else {
+            (&self.wdl, &self.wdl_path)
+        };
+        let path = path.as_ref()?;
+        Some(cell.get_or_init(|| {
+            Table::open(path, self.material, dtz).map_err(|e| format!("{}: {}", path.display(), e))
+        }))
+    }
+}
+
+pub struct Tablebases {
+    tables: HashMap<String, TableFiles>,
+    max_pieces: usize,
+}
+
+impl Tablebases {
+    // `path` lists directories the way PATH does; files with unknown names are skipped
+    pub fn open(path: &str) -> Result<Tablebases, ChessError> {
+        let mut tablebases = Tablebases {
+            tables: HashMap::new(),
+            max_pieces: 0,
+        };
+        for dir in env::split_paths(path).filter(|dir| !dir.as_os_str().is_empty()) {
+            for entry in fs::read_dir(&dir)? {
+                let path = entry?.path();
+                let (name, dtz) = match (
+                    path.file_stem().and_then(|stem| stem.to_str()),
+                    path.extension().and_then(|ext| ext.to_str()),
+                ) {
+                    (Some(name), Some("rtbw")) => (name, false),
+                    (Some(name), Some("rtbz")) => (name, true),
+                    _ => continue,
+                };
+                let material = match Material::parse(name) {
+                    Some(material) => material,
+                    None => continue,
+                };
+                let files = tablebases
+                    .tables
+                    .entry(name.to_string())
+                    .or_insert_with(|| TableFiles {
+                        material,
+                        wdl_path: None,
+                        dtz_path: None,
+                        wdl: OnceLock::new(),
+                        dtz: OnceLock::new(),
+                    });
+                if dtz {
+                    files.dtz_path.get_or_insert(path);
+                } else {
+                    files.wdl_path.get_or_insert(path);
+                    tablebases.max_pieces = tablebases.max_pieces.max(material.piece_count());
+                }
+            }
+        }
+        Ok(tablebases)
+    }
+
+    // maps every table now instead of on first use; returns why some couldn't be read
+    pub fn load_all(&self) -> Vec<String> {
+        let mut errors: Vec<String> = self
+            .tables
+            .values()
+            .flat_map(|files| [files.load(false), files.load(true)])
+            .flatten()
+            .filter_map(|table| table.as_ref().err().cloned())
+            .collect();
+        errors.sort();
+        errors
+    }
+
+    // number of WDL tables found
+    pub fn len(&self) -> usize {
+        self.tables
+            .values()
+            .filter(|t| t.wdl_path.is_some())
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // the most pieces any table has, positions with more can't be probed
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    pub fn can_probe(&self, state: &State) -> bool {
+        state.castling.is_empty() && state.board.occupied().count() as usize <= self.max_pieces
+    }
+
+    // the table for the position's material, and whether colors have to be swapped
+    fn table(&self, state: &State, dtz: bool) -> Option<(&Table, bool)> {
+        let material = Material::from_board(&state.board, Color::White);
+        let (files, flipped) = match self.tables.get(&material.name()) {
+            Some(files) => (files, false),
+            None => (self.tables.get(&material.flipped().name())?, true),
+        };
+        // symmetric tables only store white to move
+        let flip = flipped || (material.is_symmetric() && state.side_to_move() == Color::Black);
+        Some((files.get(dtz)?, flip))
+    }
+
+    fn probe_wdl_table(&self, state: &State) -> Option<Wdl> {
+        if state.board.occupied().count() == 2 {
+            return Some(Wdl::Draw);
+        }
+        let (table, flip) = self.table(state, false)?;
+        Some(table.probe_wdl(state, flip))
+    }
+
+    // tables don't store positions where a capture (or, for DTZ, a pawn move) is best and
+    // know nothing about en passant, so those moves are searched here; returns the result
+    // and whether a zeroing move is best
+    fn search(&self, state: &mut State, pawn_moves: bool) -> Option<(Wdl, bool)> {
+        let moves = state.legal_moves();
+        let mut best = Wdl::Loss;
+        let mut searched = 0;
+        for &mv in &moves {
+            let pawn = state.board.piece(mv.from).map(Piece::piece_type) == Some(PieceType::Pawn);
+            let undo = state.make_move(mv);
+            if undo.captured.is_none() && !(pawn_moves && pawn) {
+                state.unmake_move(mv, undo);
+                continue;
+            }
+            searched += 1;
+            let value = self.search(state, false).map(|(wdl, _)| -wdl);
+            state.unmake_move(mv, undo);
+            let value = value?;
+            if value > best {
+                best = value;
+                if value == Wdl::Win {
+                    return Some((value, true));
+                }
+            }
+        }
+
+        let no_more_moves = searched > 0 && searched == moves.len();
+        let value = if no_more_moves {
+            best
+        } else {
+            self.probe_wdl_table(state)?
+        };
+        if best >= value {
+            Some((best, best > Wdl::Draw || no_more_moves))
+        } else {
+            Some((value, false))
+        }
+    }
+
+    pub fn probe_wdl(&self, state: &State) -> Option<Wdl> {
+        if !self.can_probe(state) {
+            return None;
+        }
+        self.search(&mut state.clone(), false).map(|(wdl, _)| wdl)
+    }
+
+    // plies until the fifty-move counter is reset by a winning (positive) or losing
+    // (negative) line, 101 and up for cursed wins and blessed losses, 0 for draws
+    pub fn probe_dtz(&self, state: &State) -> Option<i32> {
+        if !self.can_probe(state) {
+            return None;
+        }
+        self.dtz(&mut state.clone())
+    }
+
+    fn dtz(&self, state: &mut State) -> Option<i32> {
+        let (wdl, zeroing) = self.search(state, true)?;
+        if wdl == Wdl::Draw {
+            return Some(0);
+        }
+        if zeroing {
+            return Some(dtz_before_zeroing(wdl));
+        }
+
+        let (table, flip) = self.table(state, true)?;
+        if let Some(dtz) = table.probe_dtz(state, flip, wdl) {
+            let cursed = matches!(wdl, Wdl::CursedWin | Wdl::BlessedLoss);
+            return Some((dtz + if cursed { 100 } else { 0 }) * wdl.value().signum());
+        }
+
+        // the table stores the other side to move, look one ply ahead
+        let mut best = i32::MAX;
+        for mv in state.legal_moves() {
+            let pawn = state.board.piece(mv.from).map(Piece::piece_type) == Some(PieceType::Pawn);
+            let undo = state.make_move(mv);
+            let zeroing = pawn || undo.captured.is_some();
+            let dtz = if zeroing {
+                self.search(state, false)
+                    .map(|(wdl, _)| -dtz_before_zeroing(wdl))
+            } else {
+                self.dtz(state).map(|dtz| -dtz)
+            };
+            let mates = state.in_check() && state.legal_moves().is_empty();
+            state.unmake_move(mv, undo);
+            let mut dtz = dtz?;
+
+            if dtz == 1 && mates {
+                best = 1;
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < best && dtz.signum() == wdl.value().signum() {
+                best = dtz;
+            }
+        }
+        // no legal moves: mated
+        Some(if best == i32::MAX { -1 } else { best })
+    }
+
+    pub fn probe(&self, state: &State) -> Option<ProbeResult> {
+        Some(ProbeResult {
+            wdl: self.probe_wdl(state)?,
+            dtz: self.probe_dtz(state),
+        })
+    }
+
+    // ranks every legal move, higher is better: wins that beat the fifty-move rule rank
+    // MAX_DTZ, slower wins less, losses mirror that and draws are 0; falls back to WDL
+    // ranks when DTZ tables are missing. Sorted best first, None if some probe failed.
+    pub fn rank_root_moves(&self, state: &State, rule50: bool) -> Option<Vec<(Move, i32)>> {
+        if !self.can_probe(state) {
+            return None;
+        }
+        let mut ranked = self
+            .rank_by_dtz(state, rule50)
+            .or_else(|| self.rank_by_wdl(state, rule50))?;
+        ranked.sort_by_key(|&(_, rank)| -rank);
+        Some(ranked)
+    }
+
+    // without the fifty-move rule cursed wins and blessed losses rank as plain wins and losses
+    fn rank_by_dtz(&self, state: &State, rule50: bool) -> Option<Vec<(Move, i32)>> {
+        let counter = state.halfmove_clock() as i32;
+        let mut state = state.clone();
+        let mut ranked = Vec::new();
+        for mv in state.legal_moves() {
+            let undo = state.make_move(mv);
+            let dtz = if state.halfmove_clock() == 0 {
+                let wdl = self.search(&mut state, false).map(|(wdl, _)| -wdl);
+                wdl.map(dtz_before_zeroing)
+            } else if rule50 && state.halfmove_clock() >= 100 {
+                Some(0)
+            } else {
+                self.dtz(&mut state).map(|dtz| match -dtz {
+                    dtz if dtz > 0 => dtz + 1,
+                    dtz if dtz < 0 => dtz - 1,
+                    dtz => dtz,
+                })
+            };
+            let mates = state.in_check() && state.legal_moves().is_empty();
+            state.unmake_move(mv, undo);
+            let dtz = if dtz? == 2 && mates { 1 } else { dtz? };
+
+            let rank = if dtz > 0 {
+                if !rule50 || dtz + counter <= 99 {
+                    MAX_DTZ
+                } else {
+                    MAX_DTZ - (dtz + counter)
+                }
+            } else if dtz < 0 {
+                if !rule50 || -dtz * 2 + counter < 100 {
+                    -MAX_DTZ
+                } else {
+                    -MAX_DTZ + (-dtz + counter)
+                }
+            } else {
+                0
+            };
+            ranked.push((mv, rank));
+        }
+        Some(ranked)
+    }
+
+    fn rank_by_wdl(&self, state: &State, rule50: bool) -> Option<Vec<(Move, i32)>> {
+        let mut state = state.clone();
+        let mut ranked = Vec::new();
+        for mv in state.legal_moves() {
+            let undo = state.make_move(mv);
+            let wdl = if rule50 && state.halfmove_clock() >= 100 {
+                Some(Wdl::Draw)
+            } else {
+                self.search(&mut state, false).map(|(wdl, _)| -wdl)
+            };
+            state.unmake_move(mv, undo);
+            let wdl = if rule50 {
+                wdl?
+            } else {
+                wdl?.ignoring_fifty_moves()
+            };
+            let rank = match wdl {
+                Wdl::Loss => -MAX_DTZ,
+                Wdl::BlessedLoss => -MAX_DTZ + 101,
+                Wdl::Draw => 0,
+                Wdl::CursedWin => MAX_DTZ - 101,
+                Wdl::Win => MAX_DTZ,
+            };
+            ranked.push((mv, rank));
+        }
+        Some(ranked)
+    }
+}
+
+// the tables and settings behind the SyzygyPath, SyzygyProbeDepth and Syzygy50MoveRule
+// UCI options, picked up by every new Search
+static TABLEBASES: RwLock<Option<Arc<Tablebases>>> = RwLock::new(None);
+static PROBE_DEPTH: AtomicU32 = AtomicU32::new(1);
+static RULE50: AtomicBool = AtomicBool::new(true);
+
+// an empty path unloads the tables; returns how many were found and the tables that
+// couldn't be read, which are left out of probing
+pub fn set_path(path: &str) -> Result<(usize, Vec<String>), ChessError> {
+    let tablebases = if path.is_empty() || path == "<empty>" {
+        None
+    } else {
+        Some(Arc::new(Tablebases::open(path)?))
+    };
+    let count = tablebases.as_ref().map_or(0, |tb| tb.len());
+    let errors = tablebases.as_ref().map_or(Vec::new(), |tb| tb.load_all());
+    *TABLEBASES.write().unwrap() = tablebases;
+    Ok((count, errors))
+}
+
+pub fn tablebases() -> Option<Arc<Tablebases>> {
+    TABLEBASES.read().unwrap().clone()
+}
+
+// the search only probes at this depth and above, unless there are fewer pieces than
+// the largest tables have
+pub fn probe_depth() -> u32 {
+    PROBE_DEPTH.load(Ordering::Relaxed)
+}
+
+pub fn set_probe_depth(depth: u32) {
+    PROBE_DEPTH.store(depth, Ordering::Relaxed);
+}
+
+// when off, cursed wins count as wins and blessed losses as losses
+pub fn rule50() -> bool {
+    RULE50.load(Ordering::Relaxed)
+}
+
+pub fn set_rule50(rule50: bool) {
+    RULE50.store(rule50, Ordering::Relaxed);
+}
+
+// probe the configured tables
+pub fn probe(state: &State) -> Option<ProbeResult> {
+    tablebases()?.probe(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::{Search, SearchLimits};
+    use crate::tbgen::{Dtm, TableSet};
+    use std::cmp::Reverse;
+    use std::collections::BinaryHeap;
+
+    #[test]
+    fn encoding_tables() {
+        let enc = encoding();
+        let mut kk = Vec::new();
+        for index in 0..10 {
+            let first = triangle_square(&enc.map_a1d1d4, index);
+            let near = king_moves(Square::from_index(first).unwrap());
+            for second in 0..64 {
+                let legal = !near.contains(Square::from_index(second).unwrap())
+                    && second != first
+                    && !(off_diagonal(first) == 0 && off_diagonal(second) > 0);
+                if legal {
+                    kk.push(enc.map_kk[index][second]);
+                }
+            }
+        }
+        kk.sort_unstable();
+        assert_eq!(kk, (0..462).collect::<Vec<_>>());
+
+        assert_eq!(enc.binomial[2][5], 10);
+        assert_eq!(enc.binomial[5][63], 7028847);
+        assert_eq!(enc.map_pawns[Square::A2.index()], 47);
+        assert_eq!(enc.map_pawns[Square::H2.index()], 46);
+        assert_eq!(enc.map_pawns[Square::E7.index()], 0);
+        assert_eq!(enc.lead_pawns_size[1], [6; 4]);
+    }
+
+    #[test]
+    fn table_names() {
+        let state = State::from_fen("8/8/4k3/8/3p4/8/2PK4/7R b - - 0 1").unwrap();
+        let material = Material::from_board(&state.board, Color::White);
+        assert_eq!(material.name(), "KRPvKP");
+        assert_eq!(material.flipped().name(), "KPvKRP");
+        assert_eq!(Material::parse("KRPvKP"), Some(material));
+        assert_eq!(material.pawn_counts(), [1, 1]);
+        assert!(material.has_unique_pieces());
+        assert_eq!(Material::parse("KPRvKP"), None);
+        assert_eq!(Material::parse("KRvR"), None);
+        assert!(Material::parse("KNNvK").is_some_and(|m| !m.has_unique_pieces()));
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "chesstionable-syzygy-{}-{}",
+            name,
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // the table header: flags, then per file the group order and piece order of each side
+    fn header(magic: [u8; 4], material: &Material, pieces: &[u8], sides: usize) -> Vec<u8> {
+        let mut bytes = magic.to_vec();
+        bytes.push(!material.is_symmetric() as u8 | (material.has_pawns() as u8) << 1);
+        let files = if material.has_pawns() { 4 } else { 1 };
+        for _ in 0..files {
+            bytes.push(if sides == 2 { 0x00 } else { 0xF0 });
+            bytes.extend(
+                pieces
+                    .iter()
+                    .map(|&p| p | if sides == 2 { p << 4 } else { 0 }),
+            );
+        }
+        if bytes.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    // a table where every position of each side to move has the same value
+    fn write_single_value(dir: &Path, name: &str, dtz: bool, pieces: &[u8], values: &[(u8, u8)]) {
+        let material = Material::parse(name).unwrap();
+        let magic = if dtz { DTZ_MAGIC } else { WDL_MAGIC };
+        let mut bytes = header(magic, &material, pieces, values.len());
+        for _ in 0..if material.has_pawns() { 4 } else { 1 } {
+            for &(flags, value) in values {
+                bytes.extend([flags | SINGLE_VALUE, value]);
+            }
+        }
+        let ext = if dtz { "rtbz" } else { "rtbw" };
+        fs::write(dir.join(format!("{}.{}", name, ext)), bytes).unwrap();
+    }
+
+    // white to move wins, black to move loses (unless it can take the rook)
+    fn krk(dir: &Path) -> Tablebases {
+        let pieces = [6, 4, 14];
+        write_single_value(dir, "KRvK", false, &pieces, &[(0, 4), (0, 0)]);
+        Tablebases::open(dir.to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn single_value_tables() {
+        let dir = temp_dir("single");
+        let tb = krk(&dir);
+        assert_eq!(tb.len(), 1);
+        assert_eq!(tb.max_pieces(), 3);
+
+        let probe = |fen: &str| tb.probe_wdl(&State::from_fen(fen).unwrap());
+        assert_eq!(probe("8/8/8/8/8/2k5/8/R3K3 w - - 0 1"), Some(Wdl::Win));
+        assert_eq!(probe("8/8/8/8/8/2k5/8/R3K3 b - - 0 1"), Some(Wdl::Loss));
+        // black takes the rook
+        assert_eq!(probe("8/8/8/8/8/8/1k6/R3K3 b - - 0 1"), Some(Wdl::Draw));
+        // the same with colors swapped
+        assert_eq!(probe("r3k3/8/2K5/8/8/8/8/8 b - - 0 1"), Some(Wdl::Win));
+        assert_eq!(probe("r3k3/8/2K5/8/8/8/8/8 w - - 0 1"), Some(Wdl::Loss));
+        assert_eq!(probe("8/8/8/8/8/2k5/8/Q3K3 w - - 0 1"), None);
+        assert_eq!(probe("8/8/8/8/8/8/8/K6k w - - 0 1"), Some(Wdl::Draw));
+        assert!(tb.load_all().is_empty());
+        // no DTZ table
+        let state = State::from_fen("8/8/8/8/8/2k5/8/R3K3 w - - 0 1").unwrap();
+        assert_eq!(
+            tb.probe(&state),
+            Some(ProbeResult {
+                wdl: Wdl::Win,
+                dtz: None
+            })
+        );
+
+        // moves that hang the rook are ranked as draws
+        let state = State::from_fen("8/8/8/8/8/k7/1R6/7K w - - 0 1").unwrap();
+        let ranked = tb.rank_root_moves(&state, true).unwrap();
+        assert_eq!(ranked.len(), state.legal_moves().len());
+        for (mv, rank) in ranked {
+            let expected =
+                if mv.from == Square::H1 || [Square::A2, Square::B3, Square::B4].contains(&mv.to) {
+                    0
+                } else {
+                    MAX_DTZ
+                };
+            assert_eq!(rank, expected, "{}", mv);
+        }
+
+        // and the search only looks at the others
+        let mut search = Search::new(SearchLimits {
+            depth: Some(3),
+            ..SearchLimits::default()
+        });
+        search.set_tablebases(Some(Arc::new(tb)));
+        let best = search.run(&mut state.clone()).best_move.unwrap();
+        assert_eq!(best.from, Square::B2);
+        assert!(![Square::A2, Square::B3, Square::B4].contains(&best.to));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn unreadable_tables() {
+        let dir = temp_dir("unreadable");
+        fs::write(dir.join("KQvK.rtbw"), b"not a table").unwrap();
+        let tb = krk(&dir);
+        assert_eq!(tb.len(), 2);
+        let errors = tb.load_all();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("KQvK.rtbw"), "{}", errors[0]);
+        let state = State::from_fen("8/8/8/8/8/2k5/8/Q3K3 w - - 0 1").unwrap();
+        assert_eq!(tb.probe_wdl(&state), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn dtz_tables() {
+        let dir = temp_dir("dtz");
+        // stored for white to move: 5 moves to a zeroing move
+        write_single_value(&dir, "KRvK", true, &[6, 4, 14], &[(0, 5)]);
+        let tb = krk(&dir);
+        let state = State::from_fen("8/8/8/8/8/2k5/8/R3K3 w - - 0 1").unwrap();
+        assert_eq!(tb.probe_dtz(&state), Some(11));
+        // black to move looks a ply ahead
+        let state = State::from_fen("8/8/8/8/8/2k5/8/R3K3 b - - 0 1").unwrap();
+        assert_eq!(tb.probe_dtz(&state), Some(-12));
+        let state = State::from_fen("8/8/8/8/8/8/1k6/R3K3 b - - 0 1").unwrap();
+        assert_eq!(tb.probe_dtz(&state), Some(0));
+
+        let state = State::from_fen("8/8/8/8/8/k7/1R6/7K w - - 0 1").unwrap();
+        let ranked = tb.rank_root_moves(&state, true).unwrap();
+        assert_eq!(ranked[0].1, MAX_DTZ);
+        assert_eq!(ranked.last().unwrap().1, 0);
+
+        // 13 plies to zero with 90 on the clock is a cursed win, unless the rule is off
+        let state = State::from_fen("8/8/8/8/8/k7/1R6/7K w - - 90 50").unwrap();
+        let ranked = tb.rank_root_moves(&state, true).unwrap();
+        assert_eq!(ranked[0].1, MAX_DTZ - 103);
+        let ranked = tb.rank_root_moves(&state, false).unwrap();
+        assert_eq!(ranked[0].1, MAX_DTZ);
+        assert_eq!(ranked.last().unwrap().1, 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    // one side and file of a table, None where no position maps to the index
+    struct SubTable {
+        flags: u8,
+        values: Vec<Option<u16>>,
+    }
+
+    // symbols as the btree stores them, (value, 0xFFF) or a pair of other symbols, with the
+    // number of values each one stands for
+    #[derive(Default)]
+    struct Symbols {
+        pairs: Vec<(usize, usize)>,
+        copies: Vec<usize>,
+        ids: HashMap<(usize, usize), usize>,
+    }
+
+    impl Symbols {
+        fn add(&mut self, pair: (usize, usize), copies: usize) -> usize {
+            *self.ids.entry(pair).or_insert_with(|| {
+                self.pairs.push(pair);
+                self.copies.push(copies);
+                self.pairs.len() - 1
+            })
+        }
+    }
+
+    // Huffman code lengths, flattened until none is longer than the decoder's 32 bits
+    fn code_lengths(mut counts: Vec<u64>) -> Vec<usize> {
+        loop {
+            let mut heap: BinaryHeap<Reverse<(u64, usize)>> = counts
+                .iter()
+                .enumerate()
+                .map(|(i, &count)| Reverse((count, i)))
+                .collect();
+            let mut parent = vec![usize::MAX; counts.len()];
+            while heap.len() > 1 {
+                let Reverse((a, i)) = heap.pop().unwrap();
+                let Reverse((b, j)) = heap.pop().unwrap();
+                parent[i] = parent.len();
+                parent[j] = parent.len();
+                heap.push(Reverse((a + b, parent.len())));
+                parent.push(usize::MAX);
+            }
+            let lengths: Vec<usize> = (0..counts.len())
+                .map(|mut node| {
+                    let mut length = 0;
+                    while parent[node] != usize::MAX {
+                        node = parent[node];
+                        length += 1;
+                    }
+                    length
+                })
+                .collect();
+            if lengths.iter().all(|&length| length <= 32) {
+                return lengths;
+            }
+            counts = counts.iter().map(|count| count / 2 + 1).collect();
+        }
+    }
+
+    // runs of a value become symbols standing for 2^k copies of it, frequent neighbours are
+    // paired up after that, and every symbol gets a canonical Huffman code. Returns the sizes
+    // section, the sparse index, the block lengths and the blocks.
+    fn compress(table: &SubTable) -> [Vec<u8>; 4] {
+        const MAX_RUN: usize = 10;
+        const MAX_SYMBOLS: usize = 4000;
+        const MIN_PAIRS: usize = 8;
+        let (block_log, span_log) = (10u8, 14u8);
+        let block_bits = 8 << block_log;
+        let span = 1usize << span_log;
+        // an anchor past the last value still has to fit the u16 offset
+        let max_block_values = (1 << 16) - span;
+
+        // indices no position maps to continue the run before them
+        let first = table.values.iter().flatten().next().copied().unwrap_or(0);
+        let mut last = first;
+        let values: Vec<u16> = table
+            .values
+            .iter()
+            .map(|value| {
+                last = value.unwrap_or(last);
+                last
+            })
+            .collect();
+        if values.iter().all(|&value| value == first) {
+            return [
+                vec![table.flags | SINGLE_VALUE, first as u8],
+                Vec::new(),
+                Vec::new(),
+                Vec::new(),
+            ];
+        }
+
+        let mut symbols = Symbols::default();
+        let mut sequence = Vec::new();
+        let mut i = 0;
+        while i < values.len() {
+            let value = values[i];
+            let mut run = values[i..].iter().take_while(|&&v| v == value).count();
+            i += run;
+            while run > 0 {
+                let k = (run.ilog2() as usize).min(MAX_RUN);
+                let mut id = symbols.add((value as usize, 0xFFF), 1);
+                for _ in 0..k {
+                    id = symbols.add((id, id), symbols.copies[id] * 2);
+                }
+                sequence.push(id);
+                run -= 1 << k;
+            }
+        }
+
+        // then, Re-Pair style, the most common neighbours are merged into a new symbol
+        while symbols.pairs.len() < MAX_SYMBOLS {
+            let mut pairs: HashMap<(usize, usize), usize> = HashMap::new();
+            for pair in sequence.windows(2) {
+                *pairs.entry((pair[0], pair[1])).or_default() += 1;
+            }
+            let Some((&(left, right), &count)) = pairs
+                .iter()
+                .filter(|&(&(left, right), _)| {
+                    symbols.copies[left] + symbols.copies[right] <= max_block_values
+                        && !symbols.ids.contains_key(&(left, right))
+                })
+                .max_by_key(|&(&pair, &count)| (count, Reverse(pair)))
+            else {
+                break;
+            };
+            if count < MIN_PAIRS {
+                break;
+            }
+            let id = symbols.add((left, right), symbols.copies[left] + symbols.copies[right]);
+            let mut merged = Vec::with_capacity(sequence.len());
+            let mut i = 0;
+            while i < sequence.len() {
+                if i + 1 < sequence.len() && (sequence[i], sequence[i + 1]) == (left, right) {
+                    merged.push(id);
+                    i += 2;
+                } else {
+                    merged.push(sequence[i]);
+                    i += 1;
+                }
+            }
+            sequence = merged;
+        }
+
+        // symbols nothing uses still need a code, the pairs refer to them
+        let mut counts = vec![1u64; symbols.pairs.len()];
+        for &symbol in &sequence {
+            counts[symbol] += 1;
+        }
+        let lengths = code_lengths(counts);
+        let (min_len, max_len) = (
+            *lengths.iter().min().unwrap(),
+            *lengths.iter().max().unwrap(),
+        );
+
+        // longer codes get the lower symbol numbers and the lower code values
+        let mut order: Vec<usize> = (0..symbols.pairs.len()).collect();
+        order.sort_by_key(|&symbol| Reverse(lengths[symbol]));
+        let mut renumbered = vec![0; symbols.pairs.len()];
+        for (new, &old) in order.iter().enumerate() {
+            renumbered[old] = new;
+        }
+        let count = |length: usize| lengths.iter().filter(|&&l| l == length).count();
+        let lowest = |length: usize| lengths.iter().filter(|&&l| l > length).count();
+        let mut base = vec![0u64; max_len + 2];
+        for length in (min_len..max_len).rev() {
+            base[length] = (base[length + 1] + count(length + 1) as u64) / 2;
+        }
+        let code = |symbol: usize| {
+            let length = lengths[symbol];
+            let offset = renumbered[symbol] - lowest(length);
+            (base[length] + offset as u64, length)
+        };
+
+        let mut sizes = vec![table.flags, block_log, span_log, 0];
+        let mut blocks: Vec<Vec<bool>> = Vec::new();
+        let mut block_values: Vec<usize> = Vec::new();
+        for &symbol in &sequence {
+            let (code, length) = code(symbol);
+            let copies = symbols.copies[symbol];
+            let full = blocks
+                .last()
+                .is_none_or(|bits| bits.len() + length > block_bits)
+                || block_values
+                    .last()
+                    .is_some_and(|&count| count + copies > max_block_values);
+            if full {
+                blocks.push(Vec::new());
+                block_values.push(0);
+            }
+            let bits = blocks.last_mut().unwrap();
+            bits.extend((0..length).rev().map(|bit| (code >> bit) & 1 == 1));
+            *block_values.last_mut().unwrap() += copies;
+        }
+        sizes.extend((blocks.len() as u32).to_le_bytes());
+        sizes.extend([max_len as u8, min_len as u8]);
+        for length in min_len..=max_len {
+            sizes.extend((lowest(length) as u16).to_le_bytes());
+        }
+        sizes.extend((symbols.pairs.len() as u16).to_le_bytes());
+        for &old in &order {
+            let (left, right) = match symbols.pairs[old] {
+                (value, 0xFFF) => (value, 0xFFF),
+                (left, right) => (renumbered[left], renumbered[right]),
+            };
+            sizes.extend(&((left | right << 12) as u32).to_le_bytes()[..3]);
+        }
+        if symbols.pairs.len() % 2 == 1 {
+            sizes.push(0);
+        }
+
+        // the sparse index points at the middle of every span
+        let mut starts = vec![0];
+        for &count in &block_values {
+            starts.push(starts.last().unwrap() + count);
+        }
+        let mut sparse = Vec::new();
+        for k in 0..values.len().div_ceil(span) {
+            let index = k * span + span / 2;
+            let block = (starts.partition_point(|&start| start <= index) - 1).min(blocks.len() - 1);
+            sparse.extend((block as u32).to_le_bytes());
+            sparse.extend(((index - starts[block]) as u16).to_le_bytes());
+        }
+        let block_lengths = block_values
+            .iter()
+            .flat_map(|&count| ((count - 1) as u16).to_le_bytes())
+            .collect();
+        let data = blocks
+            .iter()
+            .flat_map(|bits| {
+                let mut block: Vec<u8> = bits
+                    .chunks(8)
+                    .map(|byte| {
+                        byte.iter()
+                            .enumerate()
+                            .fold(0, |acc, (i, &bit)| acc | (bit as u8) << (7 - i))
+                    })
+                    .collect();
+                block.resize(1 << block_log, 0);
+                block
+            })
+            .collect();
+        [sizes, sparse, block_lengths, data]
+    }
+
+    // sub-tables go file by file, then side by side
+    fn table_bytes(material: &Material, pieces: &[u8], dtz: bool, tables: &[SubTable]) -> Vec<u8> {
+        let magic = if dtz { DTZ_MAGIC } else { WDL_MAGIC };
+        let files = if material.has_pawns() { 4 } else { 1 };
+        let mut bytes = header(magic, material, pieces, tables.len() / files);
+        let sections: Vec<[Vec<u8>; 4]> = tables.iter().map(compress).collect();
+        for part in 0..4 {
+            for section in &sections {
+                if part == 3 {
+                    bytes.resize(bytes.len().next_multiple_of(64), 0);
+                }
+                bytes.extend(&section[part]);
+            }
+        }
+        bytes
+    }
+
+    // a single value table in its own directory runs the encoder before the real one exists
+    fn template(name: &str, pieces: &[u8]) -> Table {
+        let dir = temp_dir(&format!("template-{}", name.to_lowercase()));
+        write_single_value(&dir, name, false, pieces, &[(0, 2), (0, 2)]);
+        let material = Material::parse(name).unwrap();
+        let table = Table::open(&dir.join(format!("{}.rtbw", name)), material, false).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+        table
+    }
+
+    fn sub_table_size(table: &Table, side: usize, file: usize) -> usize {
+        let d = table.pairs(side, file);
+        d.group_index[d.group_len.iter().position(|&l| l == 0).unwrap()] as usize
+    }
+
+    // every way to put the pieces on the board, with either side to move, legal or not
+    fn for_each_placement(pieces: &[u8], mut f: impl FnMut(State)) {
+        let pieces: Vec<Piece> = pieces
+            .iter()
+            .map(|&code| {
+                let color = if code & 8 != 0 {
+                    Color::Black
+                } else {
+                    Color::White
+                };
+                Piece::new(
+                    color,
+                    PieceType::from_index((code & 7) as usize - 1).unwrap(),
+                )
+            })
+            .collect();
+        let mut squares = vec![0; pieces.len()];
+        loop {
+            let distinct = (0..squares.len()).all(|i| !squares[..i].contains(&squares[i]));
+            let pawns_fit = pieces.iter().zip(&squares).all(|(piece, &square)| {
+                piece.piece_type() != PieceType::Pawn || (8..56).contains(&square)
+            });
+            if distinct && pawns_fit {
+                for color in [Color::White, Color::Black] {
+                    let mut state = State::new();
+                    for (&piece, &square) in pieces.iter().zip(&squares) {
+                        state
+                            .board
+                            .set_piece(Square::from_index(square).unwrap(), Some(piece));
+                    }
+                    state.set_ply(color.index() as u16);
+                    f(state);
+                }
+            }
+            let Some(i) = squares.iter().rposition(|&square| square < 63) else {
+                break;
+            };
+            squares[i] += 1;
+            squares[i + 1..].fill(0);
+        }
+    }
+
+    // fills a table from every placement of the pieces, checking that positions sharing
+    // an index are really the same up to symmetry
+    fn compressed_round_trip(name: &str, pieces: &[u8], sample: u64) {
+        let dir = temp_dir(&name.to_lowercase());
+        let material = Material::parse(name).unwrap();
+        let files = if material.has_pawns() { 4 } else { 1 };
+        let value = |state: &State| -> u8 {
+            let kings: Vec<Square> = [Color::White, Color::Black]
+                .iter()
+                .map(|&c| state.board.king_square(c).unwrap())
+                .collect();
+            let distance = crate::geometry::distance(kings[0], kings[1]) as usize;
+            let pawn_rank = state
+                .board
+                .by_piece_type(PieceType::Pawn)
+                .map(|s| s.rank().index())
+                .sum::<usize>();
+            ((distance + pawn_rank + state.side_to_move().index()) % 5) as u8
+        };
+
+        let template = template(name, pieces);
+        let size = |side: usize, file: usize| sub_table_size(&template, side, file);
+
+        let mut tables: HashMap<(usize, usize), Vec<u8>> = HashMap::new();
+        for side in 0..2 {
+            for file in 0..files {
+                tables.insert((side, file), vec![0xFF; size(side, file)]);
+            }
+        }
+        let mut states = Vec::new();
+        for_each_placement(pieces, |state| {
+            let (side, file, index) = template.encode(&state, false).unwrap();
+            let slot = &mut tables.get_mut(&(side, file)).unwrap()[index as usize];
+            let v = value(&state);
+            assert!(
+                *slot == 0xFF || *slot == v,
+                "index collision for {}",
+                state.to_fen()
+            );
+            *slot = v;
+            if index % sample == 0 {
+                states.push(state);
+            }
+        });
+
+        let mut sub_tables = Vec::new();
+        for file in 0..files {
+            for side in 0..2 {
+                sub_tables.push(SubTable {
+                    flags: 0,
+                    values: tables[&(side, file)]
+                        .iter()
+                        .map(|&value| (value != 0xFF).then_some(value as u16))
+                        .collect(),
+                });
+            }
+        }
+        fs::write(
+            dir.join(format!("{}.rtbw", name)),
+            table_bytes(&material, pieces, false, &sub_tables),
+        )
+        .unwrap();
+        let tb = Tablebases::open(dir.to_str().unwrap()).unwrap();
+        for state in &states {
+            let expected = Wdl::from_value(value(state) as i32 - 2);
+            assert_eq!(
+                tb.probe_wdl_table(state),
+                Some(expected),
+                "{}",
+                state.to_fen()
+            );
+            // and with colors swapped
+            let mut flipped = State::new();
+            for square in state.board.occupied() {
+                let piece = state.board.piece(square).unwrap();
+                let square = Square::from_index(square.index() ^ 56).unwrap();
+                flipped
+                    .board
+                    .set_piece(square, Some(Piece::new(!piece.color(), piece.piece_type())));
+            }
+            flipped.set_ply(1 - state.ply());
+            assert_eq!(
+                tb.probe_wdl_table(&flipped),
+                Some(expected),
+                "{}",
+                flipped.to_fen()
+            );
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn compressed_tables() {
+        compressed_round_trip("KRvK", &[6, 4, 14], 97);
+        compressed_round_trip("KPvK", &[1, 6, 14], 97);
+    }
+
+    // the checked in tables, with the piece order each file lists; the minor pieces are
+    // there because the prober needs every table a promotion leads to
+    const FIXTURES: [(&str, &[u8]); 6] = [
+        ("KQvK", &[6, 5, 14]),
+        ("KRvK", &[6, 4, 14]),
+        ("KBvK", &[6, 3, 14]),
+        ("KNvK", &[6, 2, 14]),
+        ("KPvK", &[1, 6, 14]),
+        ("KQvKR", &[6, 5, 14, 12]),
+    ];
+
+    fn fixture_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/syzygy")
+    }
+
+    // rebuilds testdata/syzygy from tbgen's retrograde analysis, which knows nothing about
+    // the Syzygy format: cargo test --release -- --ignored write_fixtures
+    #[test]
+    #[ignore]
+    fn write_fixtures() {
+        let mut dtm = TableSet::new();
+        for (name, pieces) in FIXTURES {
+            dtm.generate(name).unwrap();
+            write_fixture(name, pieces, &dtm);
+        }
+    }
+
+    fn write_fixture(name: &str, codes: &[u8], dtm: &TableSet) {
+        let material = Material::parse(name).unwrap();
+        let files = if material.has_pawns() { 4 } else { 1 };
+        let template = template(name, codes);
+
+        // sub-tables by side and file laid end to end, with one position for every index
+        let mut offsets = vec![0];
+        for side in 0..2 {
+            for file in 0..files {
+                offsets.push(offsets.last().unwrap() + sub_table_size(&template, side, file));
+            }
+        }
+        let id = |state: &State| {
+            let (side, file, index) = template.encode(state, false).unwrap();
+            offsets[side * files + file] + index as usize
+        };
+        let mut positions: Vec<Option<State>> = vec![None; *offsets.last().unwrap()];
+        for_each_placement(codes, |state| {
+            if !state.king_attacked(!state.side_to_move()) {
+                let id = id(&state);
+                positions[id].get_or_insert(state);
+            }
+        });
+
+        // WDL straight from the distance to mate; DTZ counts plies to a capture, a pawn move
+        // or mate, 1 when one of those wins right away and -1 when all moves are zeroing losses
+        let wdl: Vec<i32> = positions
+            .iter()
+            .map(
+                |state| match state.as_ref().map(|state| dtm.probe(state).unwrap()) {
+                    Some(Dtm::Win(_)) => 2,
+                    Some(Dtm::Loss(_)) => -2,
+                    _ => 0,
+                },
+            )
+            .collect();
+        let mut dtz = vec![0i32; positions.len()];
+        let mut stored = vec![false; positions.len()];
+        let mut children: Vec<Vec<u32>> = vec![Vec::new(); positions.len()];
+        for (i, state) in positions.iter().enumerate() {
+            let Some(state) = state else { continue };
+            if wdl[i] == 0 {
+                continue;
+            }
+            let mut state = state.clone();
+            let (mut zeroing_win, mut mates) = (false, false);
+            let mut quiet = Vec::new();
+            for mv in state.legal_moves() {
+                let pawn =
+                    state.board.piece(mv.from).map(Piece::piece_type) == Some(PieceType::Pawn);
+                let undo = state.make_move(mv);
+                if pawn || undo.captured.is_some() {
+                    zeroing_win |= matches!(dtm.probe(&state), Some(Dtm::Loss(_)));
+                } else {
+                    mates |= state.in_check() && state.legal_moves().is_empty();
+                    quiet.push(id(&state) as u32);
+                }
+                state.unmake_move(mv, undo);
+            }
+            // the prober finds winning zeroing moves and zeroing-only losses by itself
+            if wdl[i] > 0 && zeroing_win {
+                dtz[i] = 1;
+            } else if wdl[i] > 0 && mates {
+                dtz[i] = 1;
+                stored[i] = true;
+            } else if wdl[i] > 0 || !quiet.is_empty() {
+                stored[i] = true;
+                children[i] = quiet;
+            } else {
+                dtz[i] = -1;
+                stored[i] = state.in_check() && state.legal_moves().is_empty();
+            }
+        }
+        // a win is settled by the first loss it reaches, a loss once every move is settled
+        let mut plies = 2;
+        loop {
+            let settled: Vec<(usize, i32)> = (0..positions.len())
+                .filter(|&i| wdl[i] != 0 && dtz[i] == 0)
+                .filter_map(|i| {
+                    let reached = |&child: &u32| {
+                        let dtz = dtz[child as usize];
+                        dtz != 0 && dtz.abs() < plies
+                    };
+                    if wdl[i] > 0 {
+                        children[i]
+                            .iter()
+                            .any(|&child| dtz[child as usize] == 1 - plies)
+                            .then_some((i, plies))
+                    } else {
+                        children[i].iter().all(reached).then_some((i, -plies))
+                    }
+                })
+                .collect();
+            if settled.is_empty() {
+                break;
+            }
+            for (i, value) in settled {
+                dtz[i] = value;
+            }
+            plies += 1;
+        }
+        assert!((0..positions.len()).all(|i| wdl[i] == 0 || dtz[i] != 0));
+        assert!(dtz.iter().all(|dtz| dtz.abs() < 100));
+
+        let sub_tables = |dtz_side: Option<usize>| -> Vec<SubTable> {
+            let mut tables = Vec::new();
+            for file in 0..files {
+                for side in 0..2 {
+                    if dtz_side.is_some_and(|dtz_side| dtz_side != side) {
+                        continue;
+                    }
+                    let start = offsets[side * files + file];
+                    let end = offsets[side * files + file + 1];
+                    let values = (start..end)
+                        .map(|i| match dtz_side {
+                            None => positions[i].as_ref().map(|_| (wdl[i] + 2) as u16),
+                            Some(_) => stored[i].then(|| dtz[i].unsigned_abs() as u16 - 1),
+                        })
+                        .collect();
+                    let flags = match dtz_side {
+                        None => 0,
+                        Some(side) => side as u8 | WIN_PLIES | LOSS_PLIES,
+                    };
+                    tables.push(SubTable { flags, values });
+                }
+            }
+            tables
+        };
+        let dir = fixture_dir();
+        fs::create_dir_all(&dir).unwrap();
+        let wdl_bytes = table_bytes(&material, codes, false, &sub_tables(None));
+        fs::write(dir.join(format!("{}.rtbw", name)), wdl_bytes).unwrap();
+        // DTZ tables only keep the side to move that compresses better
+        let dtz_bytes = (0..2)
+            .map(|side| table_bytes(&material, codes, true, &sub_tables(Some(side))))
+            .min_by_key(Vec::len)
+            .unwrap();
+        fs::write(dir.join(format!("{}.rtbz", name)), dtz_bytes).unwrap();
+    }
+
+    fn fixtures() -> Tablebases {
+        Tablebases::open(fixture_dir().to_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn fixture_tables() {
+        let tb = fixtures();
+        assert_eq!(tb.len(), 6);
+        assert_eq!(tb.max_pieces(), 4);
+        assert!(tb.load_all().is_empty());
+
+        let probe = |fen: &str| {
+            let state = State::from_fen(fen).unwrap();
+            (tb.probe_wdl(&state), tb.probe_dtz(&state))
+        };
+        // Rh8 mates, and with black to move Kb8 only delays it
+        assert_eq!(
+            probe("k7/8/1K6/8/8/8/8/7R w - - 0 1"),
+            (Some(Wdl::Win), Some(1))
+        );
+        assert_eq!(
+            probe("k7/8/1K6/8/8/8/8/7R b - - 0 1"),
+            (Some(Wdl::Loss), Some(-2))
+        );
+        assert_eq!(
+            probe("8/8/8/8/8/8/1k6/R3K3 b - - 0 1"),
+            (Some(Wdl::Draw), Some(0))
+        );
+
+        // the king in front of its pawn on the sixth wins whoever moves, stalemate doesn't
+        assert_eq!(probe("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1").0, Some(Wdl::Win));
+        assert_eq!(probe("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1").0, Some(Wdl::Loss));
+        assert_eq!(probe("4k3/4P3/4K3/8/8/8/8/8 b - - 0 1").0, Some(Wdl::Draw));
+        assert_eq!(probe("k7/8/K7/P7/8/8/8/8 w - - 0 1").0, Some(Wdl::Draw));
+        assert_eq!(
+            probe("8/4P3/8/8/8/8/k7/4K3 w - - 0 1"),
+            (Some(Wdl::Win), Some(1))
+        );
+        assert_eq!(
+            probe("8/4P3/8/8/8/8/k7/4K3 b - - 0 1"),
+            (Some(Wdl::Loss), Some(-2))
+        );
+        // and the same from black's side
+        assert_eq!(
+            probe("4k3/8/8/8/8/4p3/8/K7 w - - 0 1"),
+            (Some(Wdl::Loss), Some(-2))
+        );
+
+        // Re8+ skewers the queen and the rook wins
+        let skewer = State::from_fen("7r/k7/8/8/4K3/8/8/4Q3 b - - 0 1").unwrap();
+        assert_eq!(tb.probe_wdl(&skewer), Some(Wdl::Win));
+        assert_eq!(tb.probe_dtz(&skewer), Some(3));
+        let ranked = tb.rank_root_moves(&skewer, true).unwrap();
+        let re8 = skewer.parse_uci_move("h8e8", false).unwrap();
+        assert_eq!(ranked[0].1, MAX_DTZ);
+        assert!(ranked.contains(&(re8, MAX_DTZ)));
+        assert!(ranked.last().unwrap().1 < 0);
+        assert_eq!(probe("7r/k7/8/8/4K3/8/8/4Q3 w - - 0 1").0, Some(Wdl::Win));
+    }
+
+    // without pawns or captures for the winner DTZ is the distance to mate, which tbgen
+    // works out on its own
+    fn assert_matches_tbgen(tb: &Tablebases, name: &str, pieces: &[u8], every: usize) {
+        let mut dtm = TableSet::new();
+        dtm.generate(name).unwrap();
+        let mut checked = 0;
+        for_each_placement(pieces, |state| {
+            if state.king_attacked(!state.side_to_move()) {
+                return;
+            }
+            checked += 1;
+            if checked % every != 0 {
+                return;
+            }
+            let expected = match dtm.probe(&state).unwrap() {
+                Dtm::Win(plies) => (Wdl::Win, plies as i32),
+                Dtm::Draw => (Wdl::Draw, 0),
+                Dtm::Loss(plies) => (Wdl::Loss, -(plies.max(1) as i32)),
+            };
+            assert_eq!(
+                (tb.probe_wdl(&state), tb.probe_dtz(&state)),
+                (Some(expected.0), Some(expected.1)),
+                "{}",
+                state.to_fen()
+            );
+        });
+    }
+
+    #[test]
+    fn fixtures_match_tbgen() {
+        let tb = fixtures();
+        assert_matches_tbgen(&tb, "KRvK", &[6, 4, 14], 211);
+        assert_matches_tbgen(&tb, "KQvK", &[6, 5, 14], 211);
+    }
+
+    // the fixtures above are written by this file's own compressor, so they can't catch a
+    // misreading of the format that the writer shares; this reads the tables from the
+    // official Syzygy set, which have to be copied into testdata/syzygy/official
+    #[test]
+    #[ignore = "needs the official KRvK.rtbw and KRvK.rtbz in testdata/syzygy/official"]
+    fn official_tables() {
+        let dir = fixture_dir().join("official");
+        let tb = Tablebases::open(dir.to_str().unwrap()).unwrap();
+        assert!(!tb.is_empty(), "no tables in {}", dir.display());
+        assert!(tb.load_all().is_empty());
+
+        let probe = |fen: &str| {
+            let state = State::from_fen(fen).unwrap();
+            (tb.probe_wdl(&state), tb.probe_dtz(&state))
+        };
+        assert_eq!(
+            probe("k7/8/1K6/8/8/8/8/7R w - - 0 1"),
+            (Some(Wdl::Win), Some(1))
+        );
+        assert_eq!(
+            probe("k7/8/1K6/8/8/8/8/7R b - - 0 1"),
+            (Some(Wdl::Loss), Some(-2))
+        );
+        // black takes the undefended rook
+        assert_eq!(
+            probe("8/8/8/8/8/8/1k6/R3K3 b - - 0 1"),
+            (Some(Wdl::Draw), Some(0))
+        );
+
+        assert_matches_tbgen(&tb, "KRvK", &[6, 4, 14], 13);
+    }
+}
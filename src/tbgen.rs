@@ -0,0 +1,1009 @@
+// retrograde generation of distance-to-mate tables for endgames of up to five pieces, to
+// check the engine's endgame play offline. Captures and promotions lead into smaller
+// tables, so those are generated first and kept together in a TableSet.
+//
+// file layout, all integers little endian:
+//   0..4   magic "CDTM"
+//   4      format version
+//   5      length of the material name, followed by the name itself ("KRvK")
+//   ..     entry count as a u64, followed by one byte per entry
+//
+// an entry is 0 for a draw (or an index no legal position maps to) and otherwise the
+// distance to mate in plies plus one; odd distances are wins for the side to move, even
+// ones losses, 0 plies being checkmate.
+//
+// entries are indexed by side to move, the white king, then the other pieces in name order
+// with white's first, 64 squares each. Without pawns the board is mirrored and rotated
+// until the white king is in the a1-d1-d4 triangle, with pawns it's only mirrored onto
+// files a-d; the lowest index of the symmetric versions of a position is the one used and
+// identical pieces go in square order. Castling and en passant are ignored.
+use crate::bitboard::*;
+use crate::bitboard_moves::*;
+use crate::cli::{exit_on_error, flag_value, usage};
+use crate::errors::ChessError;
+use crate::moves::{Move, MoveKind};
+use crate::piece::*;
+use crate::rank::Rank;
+use crate::square::Square;
+use crate::state::{Board, Ply, State};
+use crate::syzygy::{Material, NAME_ORDER};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::mem;
+use std::path::Path;
+use std::time::Instant;
+
+pub const MAX_PIECES: usize = 5;
+
+const MAGIC: [u8; 4] = *b"CDTM";
+const VERSION: u8 = 1;
+// generation needs about four bytes per entry
+const MAX_ENTRIES: usize = 1 << 29;
+// the longest distance an entry holds
+const MAX_PLIES: u16 = 254;
+
+// a1-d1-d4
+const TRIANGLE: [usize; 10] = [0, 1, 2, 3, 9, 10, 11, 18, 19, 27];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Dtm {
+    // the side to move mates in this many plies
+    Win(u16),
+    Draw,
+    // the side to move gets mated in this many plies, 0 when it already is
+    Loss(u16),
+}
+
+impl Dtm {
+    fn from_plies(plies: u16) -> Dtm {
+        if plies % 2 == 1 {
+            Dtm::Win(plies)
+        } else {
+            Dtm::Loss(plies)
+        }
+    }
+
+    fn from_entry(entry: u8) -> Dtm {
+        match entry {
+            0 => Dtm::Draw,
+            entry => Dtm::from_plies(entry as u16 - 1),
+        }
+    }
+
+    fn entry(self) -> u8 {
+        match self {
+            Dtm::Win(plies) | Dtm::Loss(plies) => plies as u8 + 1,
+            Dtm::Draw => 0,
+        }
+    }
+
+    // the same result for the side that moved into the position
+    pub fn before_move(self) -> Dtm {
+        match self {
+            Dtm::Win(plies) => Dtm::Loss(plies + 1),
+            Dtm::Draw => Dtm::Draw,
+            Dtm::Loss(plies) => Dtm::Win(plies + 1),
+        }
+    }
+
+    // higher is better for the side to move
+    pub fn score(self) -> i32 {
+        match self {
+            Dtm::Win(plies) => 1000 - plies as i32,
+            Dtm::Draw => 0,
+            Dtm::Loss(plies) => plies as i32 - 1000,
+        }
+    }
+
+    // full moves to mate, negative when the side to move is the one mated, like search::mate_in
+    pub fn mate_in(self) -> Option<i32> {
+        match self {
+            Dtm::Win(plies) => Some((plies as i32 + 1) / 2),
+            Dtm::Draw => None,
+            Dtm::Loss(plies) => Some(-(plies as i32) / 2),
+        }
+    }
+}
+
+// symmetry bit 2 swaps files and ranks, bit 0 mirrors the files and bit 1 the ranks
+fn transform(square: usize, symmetry: usize) -> usize {
+    let (mut file, mut rank) = (square % 8, square / 8);
+    if symmetry & 4 != 0 {
+        mem::swap(&mut file, &mut rank);
+    }
+    if symmetry & 1 != 0 {
+        file = 7 - file;
+    }
+    if symmetry & 2 != 0 {
+        rank = 7 - rank;
+    }
+    rank * 8 + file
+}
+
+// squares a pawn on `square` can have come from without capturing
+fn pawn_origins(color: Color, square: Square, occupied: BitBoard) -> BitBoard {
+    let (first, second) = match color {
+        Color::White => (Rank::Rank1, Rank::Rank2),
+        Color::Black => (Rank::Rank8, Rank::Rank7),
+    };
+    let single =
+        BitBoard::from_square(square).pawn_moves(!color) & !occupied & !BitBoard::from_rank(first);
+    let double = single.pawn_moves(!color) & !occupied & BitBoard::from_rank(second);
+    single | double
+}
+
+// the same position with the colors swapped and the board flipped
+fn flip_colors(state: &State) -> State {
+    let mut flipped = State::new();
+    for square in state.board.occupied() {
+        let piece = state.board.piece(square).unwrap();
+        let target = Square::from_index(square.index() ^ 56).unwrap();
+        flipped
+            .board
+            .set_piece(target, Some(Piece::new(!piece.color(), piece.piece_type())));
+    }
+    flipped.ply = (!state.side_to_move()).index() as Ply;
+    flipped
+}
+
+// materials a capture or a promotion leads to
+fn successors(material: &Material) -> Vec<Material> {
+    let mut result = Vec::new();
+    for side in 0..2 {
+        for piece_type in &NAME_ORDER[1..] {
+            if material.counts[side][piece_type.index()] > 0 {
+                let mut captured = *material;
+                captured.counts[side][piece_type.index()] -= 1;
+                result.push(captured);
+            }
+        }
+
+        if material.counts[side][PieceType::Pawn.index()] == 0 {
+            continue;
+        }
+        for promotion in [
+            PieceType::Queen,
+            PieceType::Rook,
+            PieceType::Bishop,
+            PieceType::Knight,
+        ] {
+            let mut promoted = *material;
+            promoted.counts[side][PieceType::Pawn.index()] -= 1;
+            promoted.counts[side][promotion.index()] += 1;
+            result.push(promoted);
+            for piece_type in &NAME_ORDER[1..] {
+                if promoted.counts[1 - side][piece_type.index()] > 0 {
+                    let mut captured = promoted;
+                    captured.counts[1 - side][piece_type.index()] -= 1;
+                    result.push(captured);
+                }
+            }
+        }
+    }
+    result
+}
+
+// maps positions of one material to table indices and back
+struct Layout {
+    material: Material,
+    // every piece but the white king, white's first, in name order
+    pieces: Vec<Piece>,
+    // the squares the white king is indexed on
+    king_squares: Vec<usize>,
+    // for each white king square, the symmetries taking it there and where it ends up
+    king_symmetries: [Vec<(usize, usize)>; 64],
+}
+
+impl Layout {
+    fn new(material: Material) -> Layout {
+        let mut pieces = Vec::new();
+        for (side, color) in [Color::White, Color::Black].into_iter().enumerate() {
+            for piece_type in NAME_ORDER {
+                let count = material.counts[side][piece_type.index()] as usize;
+                let skip = (color == Color::White && piece_type == PieceType::King) as usize;
+                for _ in skip..count {
+                    pieces.push(Piece::new(color, piece_type));
+                }
+            }
+        }
+
+        let (king_squares, symmetries) = if material.has_pawns() {
+            ((0..64).filter(|square| square % 8 < 4).collect(), 2)
+        } else {
+            (TRIANGLE.to_vec(), 8)
+        };
+        let king_symmetries = std::array::from_fn(|square| {
+            (0..symmetries)
+                .filter_map(|symmetry| {
+                    let target = transform(square, symmetry);
+                    let code = king_squares.iter().position(|&king| king == target)?;
+                    Some((symmetry, code))
+                })
+                .collect()
+        });
+
+        Layout {
+            material,
+            pieces,
+            king_squares,
+            king_symmetries,
+        }
+    }
+
+    fn size(&self) -> usize {
+        2 * self.king_squares.len() * 64usize.pow(self.pieces.len() as u32)
+    }
+
+    // None when the board doesn't have this layout's pieces
+    fn index(&self, board: &Board, side: Color) -> Option<usize> {
+        let king = board.king_square(Color::White)?.index();
+        let mut best: Option<usize> = None;
+        for &(symmetry, code) in &self.king_symmetries[king] {
+            let mut index = side.index() * self.king_squares.len() + code;
+            let mut i = 0;
+            while i < self.pieces.len() {
+                let piece = self.pieces[i];
+                let count = self.pieces[i..].iter().take_while(|&&p| p == piece).count();
+                if board.by_piece(piece).count() as usize != count {
+                    return None;
+                }
+                let mut squares = [0; MAX_PIECES];
+                for (j, square) in board.by_piece(piece).enumerate() {
+                    squares[j] = transform(square.index(), symmetry);
+                }
+                squares[..count].sort_unstable();
+                for &square in &squares[..count] {
+                    index = index * 64 + square;
+                }
+                i += count;
+            }
+            best = Some(best.map_or(index, |best| best.min(index)));
+        }
+        best
+    }
+
+    // None unless the position is legal and stored under this index
+    fn position(&self, index: usize) -> Option<State> {
+        let mut rest = index;
+        let mut squares = [0; MAX_PIECES];
+        for square in squares[..self.pieces.len()].iter_mut().rev() {
+            *square = rest % 64;
+            rest /= 64;
+        }
+        let king = self.king_squares[rest % self.king_squares.len()];
+        let side = rest / self.king_squares.len();
+
+        let mut state = State::new();
+        state
+            .board
+            .set_piece(Square::from_index(king).unwrap(), Some(Piece::WhiteKing));
+        for (i, &piece) in self.pieces.iter().enumerate() {
+            let square = Square::from_index(squares[i]).unwrap();
+            let back_rank = piece.piece_type() == PieceType::Pawn && !(8..56).contains(&squares[i]);
+            let unordered = i > 0 && self.pieces[i - 1] == piece && squares[i - 1] >= squares[i];
+            if back_rank || unordered || state.board.piece(square).is_some() {
+                return None;
+            }
+            state.board.set_piece(square, Some(piece));
+        }
+        state.ply = side as Ply;
+
+        let legal = !state.king_attacked(!state.side_to_move());
+        (legal && self.index(&state.board, state.side_to_move()) == Some(index)).then_some(state)
+    }
+
+    // indices of the positions one move earlier, not counting captures and promotions
+    fn predecessors(&self, state: &State) -> Vec<usize> {
+        let side = state.side_to_move();
+        let mover = !side;
+        let occupied = state.board.occupied();
+        let mut before = state.clone();
+        before.ply = mover.index() as Ply;
+        let mut indices = Vec::new();
+        for from in state.board.by_color(mover) {
+            let piece = state.board.piece(from).unwrap();
+            let origins = match piece.piece_type() {
+                PieceType::Pawn => pawn_origins(mover, from, occupied),
+                PieceType::Knight => knight_moves(from),
+                PieceType::Bishop => bishop_moves(occupied, from),
+                PieceType::Rook => rook_moves(occupied, from),
+                PieceType::Queen => queen_moves(occupied, from),
+                PieceType::King => king_moves(from),
+            } & !occupied;
+
+            before.board.set_piece(from, None);
+            for origin in origins {
+                before.board.set_piece(origin, Some(piece));
+                if !before.king_attacked(side) {
+                    indices.extend(self.index(&before.board, mover));
+                }
+                before.board.set_piece(origin, None);
+            }
+            before.board.set_piece(from, Some(piece));
+        }
+        indices.sort_unstable();
+        indices.dedup();
+        indices
+    }
+}
+
+const UNKNOWN: u16 = DONE - 1;
+const DONE: u16 = 1 << 15;
+const NOT_LOST: u8 = u8::MAX;
+
+struct Generator<'a> {
+    layout: Layout,
+    tables: &'a TableSet,
+    // the best distance found so far, with DONE set once it's final
+    plies: Vec<u16>,
+    // moves within the table not yet known to lose, or NOT_LOST when a capture or a
+    // promotion draws or wins
+    open: Vec<u8>,
+    // the slowest loss through a capture or a promotion
+    exit_loss: Vec<u8>,
+    // indices by the distance they were given
+    queue: Vec<Vec<u32>>,
+}
+
+impl Generator<'_> {
+    fn schedule(&mut self, index: usize, plies: u16) -> Result<(), ChessError> {
+        if plies > MAX_PLIES {
+            return Err(ChessError::InvalidTablebase(format!(
+                "{} has mates longer than {} plies",
+                self.layout.material.name(),
+                MAX_PLIES
+            )));
+        }
+        if self.plies[index] & DONE == 0 && plies < self.plies[index] {
+            self.plies[index] = plies;
+            if self.queue.len() <= plies as usize {
+                self.queue.resize(plies as usize + 1, Vec::new());
+            }
+            self.queue[plies as usize].push(index as u32);
+        }
+        Ok(())
+    }
+
+    // one pass over every move: mates, and the captures and promotions leaving the table
+    fn initialize(&mut self) -> Result<(), ChessError> {
+        for index in 0..self.layout.size() {
+            let Some(mut state) = self.layout.position(index) else {
+                continue;
+            };
+            let us = state.side_to_move();
+            let pieces = state.board.occupied().count();
+            let mut moves = Vec::new();
+            state.pseudo_legal_moves(&mut moves);
+            let mut legal = false;
+            let mut successors = Vec::new();
+            let mut best_exit: Option<Dtm> = None;
+            for mv in moves {
+                let undo = state.make_move(mv);
+                if state.king_attacked(us) {
+                    state.unmake_move(mv, undo);
+                    continue;
+                }
+                legal = true;
+                let promotion = matches!(mv.kind, MoveKind::Promotion(_));
+                if promotion || state.board.occupied().count() < pieces {
+                    let dtm = self.tables.probe(&state).ok_or_else(|| {
+                        let material = Material::from_board(&state.board, Color::White);
+                        ChessError::InvalidTablebase(format!("no table for {}", material.name()))
+                    })?;
+                    let dtm = dtm.before_move();
+                    if best_exit.is_none_or(|best| dtm.score() > best.score()) {
+                        best_exit = Some(dtm);
+                    }
+                } else {
+                    successors.extend(self.layout.index(&state.board, !us));
+                }
+                state.unmake_move(mv, undo);
+            }
+            if !legal {
+                // stalemates stay draws
+                if state.in_check() {
+                    self.schedule(index, 0)?;
+                }
+                continue;
+            }
+            successors.sort_unstable();
+            successors.dedup();
+            self.open[index] = successors.len() as u8;
+
+            match best_exit {
+                Some(Dtm::Win(plies)) => {
+                    self.open[index] = NOT_LOST;
+                    self.schedule(index, plies)?;
+                }
+                Some(Dtm::Draw) => self.open[index] = NOT_LOST,
+                Some(Dtm::Loss(plies)) => {
+                    self.exit_loss[index] = plies.min(MAX_PLIES) as u8;
+                    if successors.is_empty() {
+                        self.schedule(index, plies)?;
+                    }
+                }
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    // settles positions in order of distance: a position is won as soon as a move reaches a
+    // loss, and lost once every move reaches a win
+    fn propagate(&mut self) -> Result<(), ChessError> {
+        let mut plies = 0;
+        while plies < self.queue.len() {
+            for index in mem::take(&mut self.queue[plies]) {
+                let index = index as usize;
+                if self.plies[index] != plies as u16 {
+                    continue;
+                }
+                self.plies[index] |= DONE;
+
+                let state = self.layout.position(index).unwrap();
+                for before in self.layout.predecessors(&state) {
+                    if self.plies[before] & DONE != 0 {
+                        continue;
+                    }
+                    if plies % 2 == 0 {
+                        self.schedule(before, plies as u16 + 1)?;
+                    } else if self.open[before] != NOT_LOST {
+                        self.open[before] -= 1;
+                        if self.open[before] == 0 {
+                            let loss = (plies as u16 + 1).max(self.exit_loss[before] as u16);
+                            self.schedule(before, loss)?;
+                        }
+                    }
+                }
+            }
+            plies += 1;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct TableStats {
+    pub positions: usize,
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize,
+    // the position where the side to move needs longest to mate
+    pub longest: Option<(State, Dtm)>,
+}
+
+pub struct DtmTable {
+    layout: Layout,
+    entries: Vec<u8>,
+}
+
+impl DtmTable {
+    // every table a capture or a promotion leads to has to be in `tables`
+    fn generate(material: Material, tables: &TableSet) -> Result<DtmTable, ChessError> {
+        let layout = Layout::new(material);
+        let size = layout.size();
+        if size > MAX_ENTRIES {
+            return Err(ChessError::InvalidTablebase(format!(
+                "{} is too large to generate",
+                material.name()
+            )));
+        }
+
+        let mut generator = Generator {
+            layout,
+            tables,
+            plies: vec![UNKNOWN; size],
+            open: vec![0; size],
+            exit_loss: vec![0; size],
+            queue: Vec::new(),
+        };
+        generator.initialize()?;
+        generator.propagate()?;
+
+        let entries = generator
+            .plies
+            .iter()
+            .map(|&plies| match plies & DONE {
+                0 => 0,
+                _ => Dtm::from_plies(plies & !DONE).entry(),
+            })
+            .collect();
+        Ok(DtmTable {
+            layout: generator.layout,
+            entries,
+        })
+    }
+
+    pub fn material(&self) -> Material {
+        self.layout.material
+    }
+
+    pub fn name(&self) -> String {
+        self.layout.material.name()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // None for positions of another material, with castling rights, or illegal ones
+    pub fn probe(&self, state: &State) -> Option<Dtm> {
+        if Material::from_board(&state.board, Color::White) != self.layout.material
+            || !state.castling.is_empty()
+            || state.king_attacked(!state.side_to_move())
+        {
+            return None;
+        }
+        let index = self.layout.index(&state.board, state.side_to_move())?;
+        Some(Dtm::from_entry(self.entries[index]))
+    }
+
+    pub fn stats(&self) -> TableStats {
+        let mut stats = TableStats::default();
+        for (index, &entry) in self.entries.iter().enumerate() {
+            let Some(state) = self.layout.position(index) else {
+                continue;
+            };
+            stats.positions += 1;
+            let dtm = Dtm::from_entry(entry);
+            match dtm {
+                Dtm::Win(_) => stats.wins += 1,
+                Dtm::Draw => stats.draws += 1,
+                Dtm::Loss(_) => stats.losses += 1,
+            }
+            let longer = matches!(dtm, Dtm::Win(_))
+                && (stats.longest.as_ref())
+                    .is_none_or(|(_, longest)| dtm.score() < longest.score());
+            if longer {
+                stats.longest = Some((state, dtm));
+            }
+        }
+        stats
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<(), ChessError> {
+        let name = self.name();
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[VERSION, name.len() as u8])?;
+        writer.write_all(name.as_bytes())?;
+        writer.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+        writer.write_all(&self.entries)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn read<R: Read>(mut reader: R) -> Result<DtmTable, ChessError> {
+        let invalid = |reason: String| ChessError::InvalidTablebase(reason);
+        let mut header = [0; 6];
+        reader.read_exact(&mut header)?;
+        if header[..4] != MAGIC || header[4] != VERSION {
+            return Err(invalid("not a distance-to-mate table".to_string()));
+        }
+
+        let mut name = vec![0; header[5] as usize];
+        reader.read_exact(&mut name)?;
+        let name = String::from_utf8_lossy(&name).into_owned();
+        let material = Material::parse(&name)
+            .filter(|material| material.piece_count() <= MAX_PIECES)
+            .ok_or_else(|| invalid(format!("unknown material {}", name)))?;
+
+        let layout = Layout::new(material);
+        let mut count = [0; 8];
+        reader.read_exact(&mut count)?;
+        let count = u64::from_le_bytes(count);
+        if count != layout.size() as u64 {
+            return Err(invalid(format!("{} entries for {}", count, name)));
+        }
+        let mut entries = vec![0; layout.size()];
+        reader.read_exact(&mut entries)?;
+        Ok(DtmTable { layout, entries })
+    }
+}
+
+// "KRK" or "KRvK", white's pieces first
+pub fn parse_signature(signature: &str) -> Result<Material, ChessError> {
+    let name = match signature.get(1..).and_then(|rest| rest.find('K')) {
+        Some(i) if !signature.contains('v') => {
+            format!("{}v{}", &signature[..i + 1], &signature[i + 1..])
+        }
+        _ => signature.to_string(),
+    };
+    Material::parse(&name)
+        .filter(|material| material.piece_count() <= MAX_PIECES)
+        .ok_or_else(|| ChessError::ParseError(signature.to_string(), "material signature"))
+}
+
+// tables by name, each also answering for the material with the colors swapped
+#[derive(Default)]
+pub struct TableSet {
+    tables: HashMap<String, DtmTable>,
+}
+
+impl TableSet {
+    pub fn new() -> TableSet {
+        TableSet::default()
+    }
+
+    // every .dtm file in `dir`
+    pub fn open(dir: &Path) -> Result<TableSet, ChessError> {
+        let mut set = TableSet::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "dtm") {
+                set.insert(DtmTable::read(BufReader::new(File::open(&path)?))?);
+            }
+        }
+        Ok(set)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&DtmTable> {
+        self.tables.get(name)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.tables.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    pub fn insert(&mut self, table: DtmTable) {
+        self.tables.insert(table.name(), table);
+    }
+
+    fn contains(&self, material: &Material) -> bool {
+        self.tables.contains_key(&material.name())
+            || self.tables.contains_key(&material.flipped().name())
+    }
+
+    // generates the table for `signature` and whatever it depends on that's missing
+    pub fn generate(&mut self, signature: &str) -> Result<String, ChessError> {
+        let material = parse_signature(signature)?;
+        self.generate_material(material)?;
+        Ok(material.name())
+    }
+
+    fn generate_material(&mut self, material: Material) -> Result<(), ChessError> {
+        if self.contains(&material) {
+            return Ok(());
+        }
+        for successor in successors(&material) {
+            self.generate_material(successor)?;
+        }
+        let table = DtmTable::generate(material, self)?;
+        self.insert(table);
+        Ok(())
+    }
+
+    pub fn probe(&self, state: &State) -> Option<Dtm> {
+        let material = Material::from_board(&state.board, Color::White);
+        if let Some(table) = self.tables.get(&material.name()) {
+            return table.probe(state);
+        }
+        let table = self.tables.get(&material.flipped().name())?;
+        if !state.castling.is_empty() {
+            return None;
+        }
+        table.probe(&flip_colors(state))
+    }
+
+    // every legal move with the result it leads to for the side to move, best first
+    pub fn rank_moves(&self, state: &State) -> Option<Vec<(Move, Dtm)>> {
+        self.probe(state)?;
+        let mut state = state.clone();
+        let mut ranked = Vec::new();
+        for mv in state.legal_moves() {
+            let undo = state.make_move(mv);
+            let dtm = self.probe(&state);
+            state.unmake_move(mv, undo);
+            ranked.push((mv, dtm?.before_move()));
+        }
+        ranked.sort_by_key(|&(_, dtm)| Reverse(dtm.score()));
+        Some(ranked)
+    }
+}
+
+fn describe(dtm: Dtm) -> String {
+    match dtm {
+        Dtm::Win(plies) => format!("mate in {} ({} plies)", dtm.mate_in().unwrap(), plies),
+        Dtm::Draw => "draw".to_string(),
+        Dtm::Loss(0) => "checkmated".to_string(),
+        Dtm::Loss(plies) => format!("mated in {} ({} plies)", -dtm.mate_in().unwrap(), plies),
+    }
+}
+
+// tbgen <material> [--dir path] [--probe fen]
+// tables already in the directory are reused, new ones are written there as <name>.dtm
+pub fn command(args: &[String]) {
+    let Some(signature) = args.first() else {
+        usage("tbgen <material, e.g. KRK or KBNvK> [--dir path] [--probe fen]");
+    };
+    let dir = Path::new(flag_value(args, "--dir").unwrap_or("."));
+
+    exit_on_error(fs::create_dir_all(dir).map_err(Into::into));
+    let mut tables = exit_on_error(TableSet::open(dir));
+    let existing = tables.names();
+    let start = Instant::now();
+    exit_on_error(tables.generate(signature));
+
+    for name in tables.names() {
+        if existing.contains(&name) {
+            continue;
+        }
+        let table = tables.get(&name).unwrap();
+        let path = dir.join(format!("{}.dtm", name));
+        let file = exit_on_error(File::create(&path).map_err(Into::into));
+        exit_on_error(table.write(BufWriter::new(file)));
+
+        let stats = table.stats();
+        let longest = match &stats.longest {
+            Some((state, dtm)) => format!(", longest {}: {}", describe(*dtm), state.to_fen()),
+            None => String::new(),
+        };
+        println!(
+            "{}: {} positions, {} wins, {} draws, {} losses{}",
+            name, stats.positions, stats.wins, stats.draws, stats.losses, longest
+        );
+    }
+    println!(
+        "{} tables in {}, generated in {:.1}s",
+        tables.len(),
+        dir.display(),
+        start.elapsed().as_secs_f64()
+    );
+
+    if let Some(fen) = flag_value(args, "--probe") {
+        let state = exit_on_error(State::from_fen(fen));
+        match tables.rank_moves(&state) {
+            Some(moves) => {
+                println!("{}", describe(tables.probe(&state).unwrap()));
+                for (mv, dtm) in moves {
+                    println!("  {:<7} {}", state.to_san(mv), describe(dtm));
+                }
+            }
+            None => println!("{} is not in the generated tables", fen),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitbase;
+    use crate::search::{mate_in, Search, SearchLimits};
+    use std::sync::OnceLock;
+
+    // KPvK and everything it promotes to, shared since generating takes a while
+    fn pawn_endings() -> &'static TableSet {
+        static TABLES: OnceLock<TableSet> = OnceLock::new();
+        TABLES.get_or_init(|| {
+            let mut tables = TableSet::new();
+            tables.generate("KPK").unwrap();
+            tables
+        })
+    }
+
+    fn probe(tables: &TableSet, fen: &str) -> Option<Dtm> {
+        tables.probe(&State::from_fen(fen).unwrap())
+    }
+
+    #[test]
+    fn signatures() {
+        assert_eq!(parse_signature("KRK").unwrap().name(), "KRvK");
+        assert_eq!(parse_signature("KBNK").unwrap().name(), "KBNvK");
+        assert_eq!(parse_signature("KPvK").unwrap().name(), "KPvK");
+        assert_eq!(parse_signature("KK").unwrap().name(), "KvK");
+        assert!(parse_signature("KQRBNK").is_err());
+        assert!(parse_signature("QK").is_err());
+        assert!(parse_signature("").is_err());
+
+        let names: Vec<String> = successors(&parse_signature("KPvKN").unwrap())
+            .iter()
+            .map(Material::name)
+            .collect();
+        for name in ["KvKN", "KPvK", "KQvKN", "KQvK", "KNvKN", "KNvK"] {
+            assert!(names.contains(&name.to_string()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn symmetries() {
+        let layout = Layout::new(parse_signature("KRvK").unwrap());
+        let state = State::from_fen("8/8/8/8/8/2k5/8/K6R w - - 0 1").unwrap();
+        let index = layout.index(&state.board, Color::White).unwrap();
+        for fen in [
+            "8/8/8/8/8/5k2/8/R6K w - - 0 1",
+            "K6R/8/2k5/8/8/8/8/8 w - - 0 1",
+            "R7/8/8/8/8/2k5/8/K7 w - - 0 1",
+        ] {
+            let mirrored = State::from_fen(fen).unwrap();
+            assert_eq!(layout.index(&mirrored.board, Color::White), Some(index));
+        }
+        assert_eq!(layout.position(index).unwrap().board.occupied().count(), 3);
+        assert_ne!(layout.index(&state.board, Color::Black), Some(index));
+
+        // identical pieces are stored once, in square order
+        let layout = Layout::new(parse_signature("KNNvK").unwrap());
+        let state = State::from_fen("4k3/8/8/8/8/8/8/1N1K2N1 w - - 0 1").unwrap();
+        let index = ((3 * 64 + 1) * 64 + 6) * 64 + 60;
+        assert_eq!(layout.index(&state.board, Color::White), Some(index));
+        assert!(layout.position(index).is_some());
+        assert!(layout.position(((3 * 64 + 6) * 64 + 1) * 64 + 60).is_none());
+    }
+
+    #[test]
+    fn queen_and_rook_mates() {
+        let tables = pawn_endings();
+
+        // the longest mates are known to be 10 and 16 moves
+        for (name, plies) in [("KQvK", 19), ("KRvK", 31)] {
+            let stats = tables.get(name).unwrap().stats();
+            let (state, longest) = stats.longest.unwrap();
+            assert_eq!(longest, Dtm::Win(plies), "{}", state.to_fen());
+            assert!(stats.draws > 0 && stats.losses > 0);
+            assert_eq!(stats.positions, stats.wins + stats.draws + stats.losses);
+        }
+
+        let mate_in_one = "k7/8/1K6/8/8/8/7Q/8 w - - 0 1";
+        assert_eq!(probe(tables, mate_in_one), Some(Dtm::Win(1)));
+        assert_eq!(
+            probe(tables, "8/7q/8/8/8/1k6/8/K7 b - - 0 1"),
+            Some(Dtm::Win(1))
+        );
+        assert_eq!(
+            probe(tables, "k6Q/8/1K6/8/8/8/8/8 b - - 0 1"),
+            Some(Dtm::Loss(0))
+        );
+        // stalemate, and black capturing the rook
+        assert_eq!(
+            probe(tables, "k7/2Q5/1K6/8/8/8/8/8 b - - 0 1"),
+            Some(Dtm::Draw)
+        );
+        assert_eq!(
+            probe(tables, "8/8/8/8/8/8/1R6/1k5K b - - 0 1"),
+            Some(Dtm::Draw)
+        );
+        // black in check with white to move
+        assert_eq!(probe(tables, "k6Q/8/1K6/8/8/8/8/8 w - - 0 1"), None);
+        assert_eq!(probe(tables, "k7/8/1K6/8/8/8/8/6NQ w - - 0 1"), None);
+
+        let ranked = tables
+            .rank_moves(&State::from_fen(mate_in_one).unwrap())
+            .unwrap();
+        assert_eq!(ranked[0].0.to_uci(false), "h2h8");
+        assert_eq!(ranked[0].1, Dtm::Win(1));
+        assert_eq!(ranked.len(), 27);
+    }
+
+    // both sides following the table mate in exactly the distance it gives, and the search
+    // finds mates of the same length
+    #[test]
+    fn optimal_play() {
+        let tables = pawn_endings();
+
+        let mut state = State::from_fen("8/8/8/4k3/8/8/8/R3K3 w - - 0 1").unwrap();
+        let Some(Dtm::Win(plies)) = tables.probe(&state) else {
+            panic!("KRK should be won");
+        };
+        for played in 0..plies {
+            let (mv, dtm) = tables.rank_moves(&state).unwrap()[0];
+            assert_eq!(
+                dtm.mate_in().unwrap().abs(),
+                (plies - played).div_ceil(2) as i32
+            );
+            state.make_move(mv);
+        }
+        assert!(state.in_check() && state.legal_moves().is_empty());
+
+        let table = tables.get("KRvK").unwrap();
+        for target in [Dtm::Win(3), Dtm::Loss(4), Dtm::Win(5)] {
+            let index = table
+                .entries
+                .iter()
+                .position(|&entry| entry == target.entry())
+                .unwrap();
+            let mut state = table.layout.position(index).unwrap();
+            let mut search = Search::new(SearchLimits {
+                depth: Some(8),
+                nodes: None,
+                movetime: None,
+            });
+            let result = search.run(&mut state);
+            assert_eq!(
+                mate_in(result.score),
+                target.mate_in(),
+                "{}",
+                state.to_fen()
+            );
+        }
+    }
+
+    #[test]
+    fn pawn_tables() {
+        let tables = pawn_endings();
+        assert_eq!(
+            tables.names(),
+            ["KBvK", "KNvK", "KPvK", "KQvK", "KRvK", "KvK"]
+        );
+
+        // whoever has to move gives up the opposition
+        let white = probe(tables, "8/4k3/8/4K3/4P3/8/8/8 w - - 0 1");
+        assert_eq!(white, Some(Dtm::Draw));
+        let black = probe(tables, "8/4k3/8/4K3/4P3/8/8/8 b - - 0 1");
+        assert!(matches!(black, Some(Dtm::Loss(_))));
+        assert_eq!(probe(tables, "8/8/8/4p3/4k3/8/4K3/8 w - - 0 1"), black);
+        // with the king on the sixth rank it doesn't matter
+        let sixth = "4k3/8/4K3/4P3/8/8/8/8";
+        for side in ["w", "b"] {
+            let dtm = probe(tables, &format!("{} {} - - 0 1", sixth, side));
+            assert_ne!(dtm.and_then(Dtm::mate_in), None);
+        }
+        assert_eq!(
+            probe(tables, "4k3/4P3/4K3/8/8/8/8/8 b - - 0 1"),
+            Some(Dtm::Draw)
+        );
+        // a rook pawn with the defending king in front
+        assert_eq!(
+            probe(tables, "k7/8/8/8/P7/8/8/K7 w - - 0 1"),
+            Some(Dtm::Draw)
+        );
+        // the pawn outruns the king
+        assert!(matches!(
+            probe(tables, "8/8/8/8/8/k7/6P1/K7 w - - 0 1"),
+            Some(Dtm::Win(_))
+        ));
+
+        // and agrees with the bitbase everywhere
+        let table = tables.get("KPvK").unwrap();
+        for (index, &entry) in table.entries.iter().enumerate() {
+            let Some(state) = table.layout.position(index) else {
+                continue;
+            };
+            let board = &state.board;
+            let pawn = board.by_piece(Piece::WhitePawn).to_square().unwrap();
+            let white_wins = bitbase::probe(
+                Color::White,
+                board.king_square(Color::White).unwrap(),
+                pawn,
+                board.king_square(Color::Black).unwrap(),
+                state.side_to_move(),
+            );
+            let expected = match Dtm::from_entry(entry) {
+                Dtm::Win(_) => state.side_to_move() == Color::White,
+                Dtm::Loss(_) => state.side_to_move() == Color::Black,
+                Dtm::Draw => false,
+            };
+            assert_eq!(white_wins, expected, "{}", state.to_fen());
+        }
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let tables = pawn_endings();
+        let table = tables.get("KQvK").unwrap();
+
+        let mut bytes = Vec::new();
+        table.write(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 4 + 2 + 4 + 8 + table.len());
+        let read = DtmTable::read(&bytes[..]).unwrap();
+        assert_eq!(read.name(), "KQvK");
+        assert_eq!(read.entries, table.entries);
+
+        assert!(DtmTable::read(&bytes[..bytes.len() - 1]).is_err());
+        bytes[0] = b'X';
+        assert!(DtmTable::read(&bytes[..]).is_err());
+    }
+}
@@ -0,0 +1,247 @@
+use crate::cli::{exit_on_error, search_limits, usage};
+use crate::epd::Epd;
+use crate::errors::ChessError;
+use crate::moves::Move;
+use crate::search::{Search, SearchLimits, SearchResult};
+use std::fs;
+use std::time::Duration;
+
+pub struct PositionResult {
+    pub id: String,
+    pub expected: String,
+    pub found: Option<String>,
+    pub solved: bool,
+    pub points: u32,
+    pub max_points: u32,
+    pub search: SearchResult,
+}
+
+// what became of one position, as it is reported while the suite runs
+pub enum Progress<'a> {
+    Scored(&'a PositionResult),
+    // neither bm nor am, so there is nothing to score the move against
+    Skipped,
+    // the bm or am moves don't parse
+    Error(&'a ChessError),
+}
+
+#[derive(Default)]
+pub struct SuiteReport {
+    pub results: Vec<PositionResult>,
+    pub skipped: Vec<String>,
+    pub errors: Vec<(String, ChessError)>,
+}
+
+impl SuiteReport {
+    pub fn solved(&self) -> usize {
+        self.results.iter().filter(|r| r.solved).count()
+    }
+
+    pub fn points(&self) -> u32 {
+        self.results.iter().map(|r| r.points).sum()
+    }
+
+    pub fn max_points(&self) -> u32 {
+        self.results.iter().map(|r| r.max_points).sum()
+    }
+}
+
+pub fn load_suite(path: &str) -> Result<Vec<Epd>, ChessError> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| ChessError::InvalidEPD(format!("Could not read {}: {}", path, e)))?;
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.parse())
+        .collect()
+}
+
+fn describe(epd: &Epd) -> String {
+    let mut expected = Vec::new();
+    if !epd.operands("bm").is_empty() {
+        expected.push(format!("bm {}", epd.operands("bm").join(" ")));
+    }
+    if !epd.operands("am").is_empty() {
+        expected.push(format!("am {}", epd.operands("am").join(" ")));
+    }
+    expected.join(", ")
+}
+
+// the bm and am moves a record is scored against
+pub struct ExpectedMoves {
+    pub best: Vec<Move>,
+    pub avoid: Vec<Move>,
+}
+
+// None when the record has neither bm nor am
+pub fn expected_moves(epd: &Epd) -> Result<Option<ExpectedMoves>, ChessError> {
+    let best = epd.best_moves()?;
+    let avoid = epd.avoid_moves()?;
+    if best.is_empty() && avoid.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(ExpectedMoves { best, avoid }))
+}
+
+// a position counts as solved when the move is one of bm and none of am,
+// points come from an STS-style c0 when present and are 0 or 1 otherwise
+pub fn score_position(
+    epd: &Epd,
+    expected: &ExpectedMoves,
+    found: Option<Move>,
+) -> (bool, u32, u32) {
+    let solved = found.is_some_and(|mv| {
+        (expected.best.is_empty() || expected.best.contains(&mv)) && !expected.avoid.contains(&mv)
+    });
+
+    match epd.move_points() {
+        Some(points) => {
+            let max_points = points.iter().map(|&(_, p)| p).max().unwrap_or(0);
+            let earned = points
+                .iter()
+                .find(|&&(mv, _)| Some(mv) == found)
+                .map_or(0, |&(_, p)| p);
+            (solved, earned, max_points)
+        }
+        None => (solved, solved as u32, 1),
+    }
+}
+
+// a position that can't be scored is reported and left out, the rest still run
+pub fn run_suite<F: FnMut(usize, &str, Progress)>(
+    suite: &[Epd],
+    limits: SearchLimits,
+    mut on_result: F,
+) -> SuiteReport {
+    let mut report = SuiteReport::default();
+
+    for (index, epd) in suite.iter().enumerate() {
+        let id = epd
+            .id()
+            .map_or_else(|| format!("#{}", index + 1), str::to_string);
+        let expected = match expected_moves(epd) {
+            Ok(Some(moves)) => moves,
+            Ok(None) => {
+                on_result(index, &id, Progress::Skipped);
+                report.skipped.push(id);
+                continue;
+            }
+            Err(e) => {
+                on_result(index, &id, Progress::Error(&e));
+                report.errors.push((id, e));
+                continue;
+            }
+        };
+
+        let mut state = epd.state.clone();
+        let search = Search::new(limits).run(&mut state);
+        let (solved, points, max_points) = score_position(epd, &expected, search.best_move);
+
+        let result = PositionResult {
+            id,
+            expected: describe(epd),
+            found: search.best_move.map(|mv| epd.state.to_san(mv)),
+            solved,
+            points,
+            max_points,
+            search,
+        };
+        on_result(index, &result.id, Progress::Scored(&result));
+        report.results.push(result);
+    }
+
+    report
+}
+
+// testsuite <file.epd> [--movetime ms] [--nodes n] [--depth d]
+pub fn command(args: &[String]) {
+    let Some(path) = args.first() else {
+        usage("testsuite <file.epd> [--movetime ms] [--nodes n] [--depth d]");
+    };
+    let limits = search_limits(args, Duration::from_secs(1));
+
+    let suite = exit_on_error(load_suite(path));
+    let report = run_suite(&suite, limits, |index, id, progress| match progress {
+        Progress::Scored(result) => println!(
+            "{:>4}/{} {:<24} {:<7} found {:<8} expected {} (points {}/{}, depth {}, nodes {})",
+            index + 1,
+            suite.len(),
+            id,
+            if result.solved { "solved" } else { "failed" },
+            result.found.as_deref().unwrap_or("-"),
+            result.expected,
+            result.points,
+            result.max_points,
+            result.search.depth,
+            result.search.nodes,
+        ),
+        Progress::Skipped => println!(
+            "{:>4}/{} {:<24} skipped, no bm or am",
+            index + 1,
+            suite.len(),
+            id
+        ),
+        Progress::Error(e) => println!("{:>4}/{} {:<24} error   {}", index + 1, suite.len(), id, e),
+    });
+
+    println!(
+        "solved {}/{}, score {}/{} ({:.1}%), {} skipped, {} errors",
+        report.solved(),
+        report.results.len(),
+        report.points(),
+        report.max_points(),
+        100.0 * report.points() as f64 / report.max_points().max(1) as f64,
+        report.skipped.len(),
+        report.errors.len()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_and_unscorable_records_dont_stop_the_suite() {
+        let suite: Vec<Epd> = [
+            "6k1/5ppp/8/8/8/8/8/R5K1 w - - bm Qh5; id \"no queen\";",
+            "6k1/5ppp/8/8/8/8/8/R5K1 w - - id \"no bm\";",
+            "6k1/5ppp/8/8/8/8/8/R5K1 w - - bm Ra8#; id \"mate\";",
+            "6k1/5ppp/8/8/8/8/8/R5K1 w - - am Ra8#; id \"avoid mate\";",
+        ]
+        .iter()
+        .map(|line| line.parse().unwrap())
+        .collect();
+        let limits = SearchLimits {
+            depth: Some(2),
+            ..SearchLimits::default()
+        };
+
+        let mut seen = Vec::new();
+        let report = run_suite(&suite, limits, |index, id, progress| {
+            let kind = match progress {
+                Progress::Scored(_) => "scored",
+                Progress::Skipped => "skipped",
+                Progress::Error(_) => "error",
+            };
+            seen.push((index, id.to_string(), kind));
+        });
+
+        assert_eq!(
+            seen,
+            [
+                (0, "no queen".to_string(), "error"),
+                (1, "no bm".to_string(), "skipped"),
+                (2, "mate".to_string(), "scored"),
+                (3, "avoid mate".to_string(), "scored"),
+            ]
+        );
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.skipped, ["no bm"]);
+        assert_eq!(report.results.len(), 2);
+        assert!(report.results[0].solved);
+        assert!(!report.results[1].solved);
+        assert_eq!(report.solved(), 1);
+        assert_eq!(report.max_points(), 2);
+    }
+}
@@ -0,0 +1,922 @@
+// engine matches: round robins between UCI engines under a time control. Every opening is
+// played twice with the colors swapped, games run concurrently with each worker keeping its
+// own engine processes, and hopeless or dead drawn games can be adjudicated by score.
+use crate::cli::{exit_on_error, flag_groups, flag_value, parse_flag, setting, usage};
+use crate::errors::ChessError;
+use crate::game::{Game, GameResult, Outcome, Termination};
+use crate::moves::Move;
+use crate::pgn::PgnGame;
+use crate::piece::Color;
+use crate::search::MATE_SCORE;
+use crate::sprt::{Record, Sprt, SprtStatus};
+use crate::state::State;
+use crate::testsuite;
+use crate::uci_client::{Go, Score, UciEngine};
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// for the handshake and everything else that isn't a search
+const ENGINE_TIMEOUT: Duration = Duration::from_secs(10);
+// how far past its clock an engine may get before it loses on time
+const TIME_MARGIN: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EngineConfig {
+    pub name: String,
+    // "self" runs this binary
+    pub command: String,
+    pub args: Vec<String>,
+    // sent with setoption after every start
+    pub options: Vec<(String, String)>,
+}
+
+impl EngineConfig {
+    // from settings like "cmd=./engine name=dev arg=--uci option.Hash=64"
+    pub fn parse(tokens: &[String]) -> Result<EngineConfig, ChessError> {
+        let (mut command, mut name) = (None, None);
+        let (mut args, mut options) = (Vec::new(), Vec::new());
+        for token in tokens {
+            let invalid = || ChessError::ParseError(token.clone(), "engine setting");
+            let (key, value) = token.split_once('=').ok_or_else(invalid)?;
+            match key {
+                "cmd" => command = Some(value.to_string()),
+                "name" => name = Some(value.to_string()),
+                "arg" => args.push(value.to_string()),
+                _ => {
+                    let option = key.strip_prefix("option.").ok_or_else(invalid)?;
+                    options.push((option.to_string(), value.to_string()));
+                }
+            }
+        }
+        let command: String = command
+            .ok_or_else(|| ChessError::ParseError(tokens.join(" "), "engine without cmd="))?;
+        let name = name.unwrap_or_else(|| match command.as_str() {
+            "self" => env!("CARGO_PKG_NAME").to_string(),
+            _ => Path::new(&command)
+                .file_stem()
+                .map_or(command.clone(), |stem| stem.to_string_lossy().into_owned()),
+        });
+        Ok(EngineConfig {
+            name,
+            command,
+            args,
+            options,
+        })
+    }
+
+    fn program(&self) -> Result<String, ChessError> {
+        match self.command.as_str() {
+            "self" => Ok(env::current_exe()?.to_string_lossy().into_owned()),
+            command => Ok(command.to_string()),
+        }
+    }
+}
+
+// "[moves/]base+increment" in seconds, all moves when moves is None
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TimeControl {
+    pub moves: Option<u32>,
+    pub base: Duration,
+    pub increment: Duration,
+}
+
+impl FromStr for TimeControl {
+    type Err = ChessError;
+
+    fn from_str(s: &str) -> Result<TimeControl, ChessError> {
+        let invalid = || ChessError::ParseError(s.to_string(), "time control");
+        let seconds = |text: &str| {
+            let value: f64 = text.parse().map_err(|_| invalid())?;
+            Duration::try_from_secs_f64(value).map_err(|_| invalid())
+        };
+        let (moves, rest) = match s.split_once('/') {
+            Some((moves, rest)) => (Some(moves.parse().map_err(|_| invalid())?), rest),
+            None => (None, s),
+        };
+        if moves == Some(0) {
+            return Err(invalid());
+        }
+        let (base, increment) = match rest.split_once('+') {
+            Some((base, increment)) => (seconds(base)?, seconds(increment)?),
+            None => (seconds(rest)?, Duration::ZERO),
+        };
+        if base.is_zero() {
+            return Err(invalid());
+        }
+        Ok(TimeControl {
+            moves,
+            base,
+            increment,
+        })
+    }
+}
+
+// also the PGN TimeControl tag
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(moves) = self.moves {
+            write!(f, "{}/", moves)?;
+        }
+        write!(f, "{}", self.base.as_secs_f64())?;
+        if !self.increment.is_zero() {
+            write!(f, "+{}", self.increment.as_secs_f64())?;
+        }
+        Ok(())
+    }
+}
+
+// a draw once both engines have scored within `score` of zero for `move_count` moves in a
+// row, from move `move_number` on
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DrawAdjudication {
+    pub move_number: u32,
+    pub move_count: u32,
+    pub score: i32,
+}
+
+// a loss once an engine has scored `score` or worse for `move_count` of its moves in a row
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ResignAdjudication {
+    pub move_count: u32,
+    pub score: i32,
+}
+
+#[derive(Clone, Default)]
+pub struct Opening {
+    pub start: State,
+    pub moves: Vec<Move>,
+}
+
+// the positions of an EPD file, or the first `plies` moves of each game of a PGN file
+pub fn load_openings(path: &str, plies: Option<usize>) -> Result<Vec<Opening>, ChessError> {
+    if !path.ends_with(".pgn") {
+        let suite = testsuite::load_suite(path)?;
+        return Ok(suite
+            .into_iter()
+            .map(|epd| Opening {
+                start: epd.state,
+                moves: Vec::new(),
+            })
+            .collect());
+    }
+    let games = PgnGame::parse_all(&fs::read_to_string(path)?)?;
+    Ok(games
+        .into_iter()
+        .map(|game| Opening {
+            moves: game
+                .moves
+                .iter()
+                .take(plies.unwrap_or(usize::MAX))
+                .map(|pgn_move| pgn_move.mv)
+                .collect(),
+            start: game.start,
+        })
+        .collect())
+}
+
+pub struct Settings {
+    pub engines: Vec<EngineConfig>,
+    pub time_control: TimeControl,
+    // for every pair of engines
+    pub games: usize,
+    pub concurrency: usize,
+    // played in order and repeated as needed, the start position when empty
+    pub openings: Vec<Opening>,
+    pub draw: Option<DrawAdjudication>,
+    pub resign: Option<ResignAdjudication>,
+    pub event: String,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            engines: Vec::new(),
+            time_control: TimeControl {
+                moves: None,
+                base: Duration::from_secs(10),
+                increment: Duration::from_millis(100),
+            },
+            games: 2,
+            concurrency: 1,
+            openings: Vec::new(),
+            draw: None,
+            resign: None,
+            event: format!("{} match", env!("CARGO_PKG_NAME")),
+        }
+    }
+}
+
+pub struct GameRecord {
+    // numbered from 1 in the order the games were scheduled
+    pub round: usize,
+    // indices into Settings::engines
+    pub white: usize,
+    pub black: usize,
+    pub result: GameResult,
+    // "White mates", "Black loses on time", ...
+    pub reason: String,
+    pub pgn: PgnGame,
+}
+
+// (white, black, opening) for every game; each opening is played by both colors before the
+// next one starts
+fn schedule(settings: &Settings, openings: usize) -> Vec<(usize, usize, usize)> {
+    let engines = settings.engines.len();
+    let mut games = Vec::new();
+    for pair in 0..settings.games.div_ceil(2) {
+        for first in 0..engines {
+            for second in first + 1..engines {
+                games.push((first, second, pair % openings));
+                if 2 * pair + 1 < settings.games {
+                    games.push((second, first, pair % openings));
+                }
+            }
+        }
+    }
+    games
+}
+
+// plays the games on `settings.concurrency` threads and hands each finished one to
+// `on_game`, which returns whether to keep going; games already running when it says no
+// are still finished and handed over
+pub fn run<F>(settings: &Settings, mut on_game: F) -> Result<(), ChessError>
+where
+    F: FnMut(&GameRecord) -> Result<bool, ChessError>,
+{
+    let openings = if settings.openings.is_empty() {
+        vec![Opening::default()]
+    } else {
+        settings.openings.clone()
+    };
+    let games = schedule(settings, openings.len());
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..settings.concurrency.max(1) {
+            let sender = sender.clone();
+            let (games, openings, next, stop) = (&games, &openings, &next, &stop);
+            scope.spawn(move || {
+                let mut players: Vec<Player> = settings.engines.iter().map(Player::new).collect();
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    if index >= games.len() || stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let (white, black, opening) = games[index];
+                    let record = play_game(
+                        settings,
+                        &mut players,
+                        index + 1,
+                        [white, black],
+                        &openings[opening],
+                    );
+                    if sender.send(record).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        for record in receiver {
+            match on_game(&record) {
+                Ok(true) => {}
+                Ok(false) => stop.store(true, Ordering::Relaxed),
+                Err(e) => {
+                    stop.store(true, Ordering::Relaxed);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+// an engine process that is started when first needed and again after it dies
+struct Player<'a> {
+    config: &'a EngineConfig,
+    engine: Option<UciEngine>,
+}
+
+impl<'a> Player<'a> {
+    fn new(config: &'a EngineConfig) -> Player<'a> {
+        Player {
+            config,
+            engine: None,
+        }
+    }
+
+    fn engine(&mut self) -> Result<&mut UciEngine, ChessError> {
+        if self.engine.is_none() {
+            let program = self.config.program()?;
+            let mut engine = UciEngine::spawn(&program, &self.config.args, ENGINE_TIMEOUT)?;
+            for (name, value) in &self.config.options {
+                engine.set_option(name, value)?;
+            }
+            engine.is_ready()?;
+            self.engine = Some(engine);
+        }
+        Ok(self.engine.as_mut().unwrap())
+    }
+}
+
+fn color_name(color: Color) -> &'static str {
+    match color {
+        Color::White => "White",
+        Color::Black => "Black",
+    }
+}
+
+struct Ending {
+    result: GameResult,
+    reason: String,
+    // the PGN Termination tag
+    termination: &'static str,
+}
+
+impl Ending {
+    fn loss(color: Color, reason: &str, termination: &'static str) -> Ending {
+        Ending {
+            result: GameResult::win_for(!color),
+            reason: format!("{} {}", color_name(color), reason),
+            termination,
+        }
+    }
+
+    fn outcome(outcome: Outcome, to_move: Color) -> Ending {
+        let reason = match outcome.termination {
+            Termination::Checkmate => format!("{} mates", color_name(!to_move)),
+            termination => format!("Draw by {}", termination),
+        };
+        Ending {
+            result: outcome.result,
+            reason,
+            termination: "normal",
+        }
+    }
+}
+
+// mates count as the mate score so adjudication thresholds work on them too
+fn centipawns(score: Score) -> i32 {
+    match score {
+        Score::Cp(cp) => cp,
+        Score::Mate(moves) if moves > 0 => MATE_SCORE,
+        Score::Mate(_) => -MATE_SCORE,
+    }
+}
+
+// like "+0.34/12 0.52s" or "-M3/20 1.10s"
+fn move_comment(score: Option<Score>, depth: Option<u32>, elapsed: Duration) -> String {
+    let mut comment = match score {
+        Some(Score::Cp(cp)) => format!("{:+.2}", cp as f64 / 100.0),
+        Some(Score::Mate(moves)) if moves > 0 => format!("+M{}", moves),
+        Some(Score::Mate(moves)) => format!("-M{}", -moves),
+        None => String::new(),
+    };
+    if let (false, Some(depth)) = (comment.is_empty(), depth) {
+        comment += &format!("/{}", depth);
+    }
+    if !comment.is_empty() {
+        comment.push(' ');
+    }
+    comment + &format!("{:.2}s", elapsed.as_secs_f64())
+}
+
+// the civil date in UTC as a PGN Date tag
+fn today() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    date((seconds / 86400) as i64)
+}
+
+// days since 1970-01-01 to YYYY.MM.DD, after Howard Hinnant's civil_from_days
+fn date(days: i64) -> String {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+fn play_game(
+    settings: &Settings,
+    players: &mut [Player],
+    round: usize,
+    engines: [usize; 2],
+    opening: &Opening,
+) -> GameRecord {
+    let mut game = Game::new(opening.start.clone());
+    let mut pgn = PgnGame::new(opening.start.clone());
+    for &mv in &opening.moves {
+        game.make_move(mv);
+        pgn.push(mv, None);
+    }
+    let ending = play_moves(settings, players, engines, &mut game, &mut pgn);
+
+    if let Some(last) = pgn.moves.last_mut() {
+        last.comment = Some(match &last.comment {
+            Some(comment) => format!("{}, {}", comment, ending.reason),
+            None => ending.reason.clone(),
+        });
+    }
+    pgn.set_result(Some(ending.result));
+    pgn.set_tag("Event", &settings.event);
+    pgn.set_tag("Date", &today());
+    pgn.set_tag("Round", &round.to_string());
+    pgn.set_tag("White", &settings.engines[engines[0]].name);
+    pgn.set_tag("Black", &settings.engines[engines[1]].name);
+    pgn.set_tag("TimeControl", &settings.time_control.to_string());
+    pgn.set_tag("Termination", ending.termination);
+
+    GameRecord {
+        round,
+        white: engines[0],
+        black: engines[1],
+        result: ending.result,
+        reason: ending.reason,
+        pgn,
+    }
+}
+
+fn play_moves(
+    settings: &Settings,
+    players: &mut [Player],
+    engines: [usize; 2],
+    game: &mut Game,
+    pgn: &mut PgnGame,
+) -> Ending {
+    let fen = game.start().to_fen();
+    // chess960 openings need engines that send castling as king-takes-rook
+    let chess960 = game.start().is_chess960();
+    let mut uci_moves: Vec<String> = game.moves().iter().map(|mv| mv.to_uci(chess960)).collect();
+    let time_control = settings.time_control;
+    let mut clocks = [time_control.base; 2];
+    let mut moves_made = [0; 2];
+    let mut resign_counts = [0; 2];
+    let mut draw_plies = 0;
+
+    for color in [Color::White, Color::Black] {
+        let player = &mut players[engines[color.index()]];
+        let started = player.engine().and_then(|engine| {
+            let option = engine
+                .options
+                .iter()
+                .any(|option| option.name.eq_ignore_ascii_case("UCI_Chess960"));
+            if option {
+                engine.set_option("UCI_Chess960", &chess960.to_string())?;
+            }
+            engine.new_game()
+        });
+        if started.is_err() {
+            player.engine = None;
+            return Ending::loss(color, "fails to start", "abandoned");
+        }
+    }
+
+    loop {
+        let to_move = game.state().side_to_move();
+        if let Some(outcome) = game.outcome() {
+            return Ending::outcome(outcome, to_move);
+        }
+        let us = to_move.index();
+        let player = &mut players[engines[us]];
+
+        let go = Go {
+            wtime: Some(clocks[0]),
+            btime: Some(clocks[1]),
+            winc: Some(time_control.increment),
+            binc: Some(time_control.increment),
+            movestogo: time_control
+                .moves
+                .map(|moves| moves - moves_made[us] % moves),
+            ..Go::default()
+        };
+        let started = Instant::now();
+        let searched = player.engine().and_then(|engine| {
+            engine.position(Some(&fen), &uci_moves)?;
+            engine.go(&go, clocks[us] + TIME_MARGIN, |_| {})
+        });
+        let elapsed = started.elapsed();
+        let (best, info) = match searched {
+            Ok(searched) => searched,
+            Err(e) => {
+                player.engine = None;
+                return match e {
+                    ChessError::EngineTimeout(_) => {
+                        Ending::loss(to_move, "loses on time", "time forfeit")
+                    }
+                    e => Ending::loss(to_move, &format!("disconnects ({})", e), "abandoned"),
+                };
+            }
+        };
+        if elapsed > clocks[us] + TIME_MARGIN {
+            return Ending::loss(to_move, "loses on time", "time forfeit");
+        }
+        clocks[us] = clocks[us].saturating_sub(elapsed) + time_control.increment;
+        moves_made[us] += 1;
+        if let Some(moves) = time_control.moves {
+            if moves_made[us] % moves == 0 {
+                clocks[us] += time_control.base;
+            }
+        }
+
+        let uci = best.best.unwrap_or_else(|| "0000".to_string());
+        let mv = match game.state().parse_uci_move(&uci, chess960) {
+            Ok(mv) if game.legal_moves().contains(&mv) => mv,
+            _ => {
+                let reason = format!("makes an illegal move: {}", uci);
+                return Ending::loss(to_move, &reason, "rules infraction");
+            }
+        };
+        let score = info.as_ref().and_then(|info| info.score);
+        let depth = info.as_ref().and_then(|info| info.depth);
+        game.make_move(mv);
+        pgn.push(mv, Some(move_comment(score, depth, elapsed)));
+        uci_moves.push(uci);
+
+        let Some(score) = score.map(centipawns) else {
+            continue;
+        };
+        if let Some(resign) = settings.resign {
+            resign_counts[us] = if score <= -resign.score {
+                resign_counts[us] + 1
+            } else {
+                0
+            };
+            if resign_counts[us] >= resign.move_count {
+                return Ending::loss(to_move, "resigns", "adjudication");
+            }
+        }
+        if let Some(draw) = settings.draw {
+            let move_number = game.state().ply() as u32 / 2 + 1;
+            draw_plies = if move_number >= draw.move_number && score.abs() <= draw.score {
+                draw_plies + 1
+            } else {
+                0
+            };
+            if draw_plies >= 2 * draw.move_count {
+                return Ending {
+                    result: GameResult::Draw,
+                    reason: "Draw by adjudication".to_string(),
+                    termination: "adjudication",
+                };
+            }
+        }
+    }
+}
+
+fn print_score(engines: &[EngineConfig], first: usize, second: usize, record: &Record) {
+    println!(
+        "Score of {} vs {}: {} - {} - {}  [{:.3}] {}",
+        engines[first].name,
+        engines[second].name,
+        record.wins,
+        record.losses,
+        record.draws,
+        record.score(),
+        record.games()
+    );
+    let elo = match record.elo() {
+        Some((elo, margin)) => format!("{:.1} +/- {:.1}", elo, margin),
+        None => "unknown".to_string(),
+    };
+    println!(
+        "Elo difference: {}, LOS: {:.1} %",
+        elo,
+        100.0 * record.los()
+    );
+}
+
+// match --engine cmd=<path|self> [name=n] [arg=a].. [option.<name>=<value>].. --engine ..
+//   [--tc [moves/]base+inc] [--games n] [--concurrency n] [--openings file.epd|file.pgn]
+//   [--plies n] [--pgnout file.pgn] [--draw movenumber=n movecount=n score=cp]
+//   [--resign movecount=n score=cp] [--sprt elo0=e elo1=e alpha=a beta=b] [--event name]
+// every pair of engines plays --games games; a decided SPRT, which needs exactly two
+// engines, stops the match
+pub fn command(args: &[String]) {
+    let engines: Vec<EngineConfig> = flag_groups(args, "--engine")
+        .into_iter()
+        .map(|tokens| exit_on_error(EngineConfig::parse(tokens)))
+        .collect();
+    if engines.len() < 2 {
+        usage(
+            "match --engine cmd=<path|self> [name=n] [arg=a] [option.<name>=<value>] \
+             --engine ... [--tc [moves/]base+inc] [--games n] [--concurrency n] \
+             [--openings file.epd|file.pgn] [--plies n] [--pgnout file.pgn] \
+             [--draw movenumber=n movecount=n score=cp] [--resign movecount=n score=cp] \
+             [--sprt elo0=e elo1=e alpha=a beta=b] [--event name]",
+        );
+    }
+
+    let draw = flag_groups(args, "--draw")
+        .first()
+        .map(|tokens| DrawAdjudication {
+            move_number: setting(tokens, "movenumber").unwrap_or(40),
+            move_count: setting(tokens, "movecount").unwrap_or(8),
+            score: setting(tokens, "score").unwrap_or(10),
+        });
+    let resign = flag_groups(args, "--resign")
+        .first()
+        .map(|tokens| ResignAdjudication {
+            move_count: setting(tokens, "movecount").unwrap_or(3),
+            score: setting(tokens, "score").unwrap_or(1000),
+        });
+    let sprt = flag_groups(args, "--sprt").first().map(|tokens| {
+        let defaults = Sprt::default();
+        Sprt {
+            elo0: setting(tokens, "elo0").unwrap_or(defaults.elo0),
+            elo1: setting(tokens, "elo1").unwrap_or(defaults.elo1),
+            alpha: setting(tokens, "alpha").unwrap_or(defaults.alpha),
+            beta: setting(tokens, "beta").unwrap_or(defaults.beta),
+        }
+    });
+    if sprt.is_some() && engines.len() != 2 {
+        eprintln!("--sprt needs exactly two engines");
+        process::exit(1);
+    }
+
+    let defaults = Settings::default();
+    let settings = Settings {
+        time_control: match flag_value(args, "--tc") {
+            Some(tc) => exit_on_error(tc.parse()),
+            None => defaults.time_control,
+        },
+        games: parse_flag(args, "--games").unwrap_or(defaults.games),
+        concurrency: parse_flag(args, "--concurrency").unwrap_or(defaults.concurrency),
+        openings: match flag_value(args, "--openings") {
+            Some(path) => exit_on_error(load_openings(path, parse_flag(args, "--plies"))),
+            None => Vec::new(),
+        },
+        draw,
+        resign,
+        event: flag_value(args, "--event").map_or(defaults.event, str::to_string),
+        engines,
+    };
+    let mut pgn_out = flag_value(args, "--pgnout")
+        .map(|path| BufWriter::new(exit_on_error(File::create(path).map_err(Into::into))));
+
+    // records[first][second] for first < second, from the first engine's point of view
+    let count = settings.engines.len();
+    let mut records = vec![vec![Record::default(); count]; count];
+    let mut decided = false;
+    exit_on_error(run(&settings, |game| {
+        if let Some(pgn_out) = &mut pgn_out {
+            writeln!(pgn_out, "{}", game.pgn)?;
+            pgn_out.flush()?;
+        }
+        let engines = &settings.engines;
+        println!(
+            "Finished game {} ({} vs {}): {} {{{}}}",
+            game.round,
+            engines[game.white].name,
+            engines[game.black].name,
+            game.result,
+            game.reason
+        );
+        let (first, second) = (game.white.min(game.black), game.white.max(game.black));
+        let record = &mut records[first][second];
+        match (game.result, first == game.white) {
+            (GameResult::Draw, _) => record.draws += 1,
+            (GameResult::WhiteWins, true) | (GameResult::BlackWins, false) => record.wins += 1,
+            _ => record.losses += 1,
+        }
+        print_score(engines, first, second, record);
+
+        let Some(sprt) = sprt else {
+            return Ok(true);
+        };
+        let (lower, upper) = sprt.bounds();
+        println!(
+            "SPRT: llr {:.2} ({:.2}, {:.2}) [{}, {}]",
+            sprt.llr(record),
+            lower,
+            upper,
+            sprt.elo0,
+            sprt.elo1
+        );
+        if !decided {
+            match sprt.status(record) {
+                SprtStatus::Continue => return Ok(true),
+                SprtStatus::AcceptH0 => println!("SPRT: H0 was accepted"),
+                SprtStatus::AcceptH1 => println!("SPRT: H1 was accepted"),
+            }
+            decided = true;
+        }
+        Ok(false)
+    }));
+
+    if count > 2 {
+        println!();
+        for (first, row) in records.iter().enumerate() {
+            for (second, record) in row.iter().enumerate().skip(first + 1) {
+                print_score(&settings.engines, first, second, record);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(tokens: &str) -> Vec<String> {
+        tokens.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn parses_settings() {
+        let tc: TimeControl = "40/60+0.6".parse().unwrap();
+        assert_eq!(tc.moves, Some(40));
+        assert_eq!(tc.base, Duration::from_secs(60));
+        assert_eq!(tc.increment, Duration::from_millis(600));
+        assert_eq!(tc.to_string(), "40/60+0.6");
+        assert_eq!("5".parse::<TimeControl>().unwrap().to_string(), "5");
+        assert_eq!(
+            "0.5+0.05".parse::<TimeControl>().unwrap().to_string(),
+            "0.5+0.05"
+        );
+        for invalid in ["", "0/10", "10+", "-1+1", "0+1", "a/10"] {
+            assert!(invalid.parse::<TimeControl>().is_err(), "{}", invalid);
+        }
+
+        let engine = EngineConfig::parse(&strings(
+            "cmd=/usr/bin/stockfish arg=--quiet option.Hash=64 option.Move_Overhead=10",
+        ))
+        .unwrap();
+        assert_eq!(engine.name, "stockfish");
+        assert_eq!(engine.args, ["--quiet"]);
+        assert_eq!(engine.options[0], ("Hash".to_string(), "64".to_string()));
+        let tokens = ["cmd=self", "name=nnue", "option.Use NNUE=true"].map(String::from);
+        let engine = EngineConfig::parse(&tokens).unwrap();
+        assert_eq!(engine.name, "nnue");
+        assert_eq!(engine.options[0].0, "Use NNUE");
+        assert!(EngineConfig::parse(&strings("name=nothing")).is_err());
+        assert!(EngineConfig::parse(&strings("cmd=self hash=1")).is_err());
+
+        assert_eq!(date(0), "1970.01.01");
+        assert_eq!(date(20000), "2024.10.04");
+        assert_eq!(date(-1), "1969.12.31");
+    }
+
+    #[test]
+    fn schedules_color_swapped_pairs() {
+        let engine = EngineConfig::parse(&strings("cmd=self")).unwrap();
+        let settings = Settings {
+            engines: vec![engine.clone(), engine.clone(), engine],
+            games: 3,
+            ..Settings::default()
+        };
+        assert_eq!(
+            schedule(&settings, 2),
+            [
+                (0, 1, 0),
+                (1, 0, 0),
+                (0, 2, 0),
+                (2, 0, 0),
+                (1, 2, 0),
+                (2, 1, 0),
+                (0, 1, 1),
+                (0, 2, 1),
+                (1, 2, 1),
+            ]
+        );
+    }
+
+    // plays the fool's mate from either side, or only the illegal 0000 when started with
+    // an argument
+    #[cfg(unix)]
+    const STUB: &str = r#"
+while read -r line; do
+    case "$line" in
+        uci) echo "uciok" ;;
+        isready) echo "readyok" ;;
+        "position fen "*) position="$line" ;;
+        go*)
+            case "$1$position" in
+                x*) move=0000 ;;
+                *f2f3) move=e7e5 ;;
+                *e7e5) move=g2g4 ;;
+                *g2g4) move=d8h4 ;;
+                *) move=f2f3 ;;
+            esac
+            echo "info depth 3 score cp -25 pv $move"
+            echo "bestmove $move" ;;
+        quit) exit 0 ;;
+    esac
+done
+"#;
+
+    #[cfg(unix)]
+    fn stub(name: &str, extra: &[&str]) -> EngineConfig {
+        let mut args = vec!["-c".to_string(), STUB.to_string(), "sh".to_string()];
+        args.extend(extra.iter().map(|arg| arg.to_string()));
+        EngineConfig {
+            name: name.to_string(),
+            command: "sh".to_string(),
+            args,
+            options: vec![("Hash".to_string(), "16".to_string())],
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn plays_matches() {
+        let settings = Settings {
+            engines: vec![stub("first", &[]), stub("second", &[])],
+            games: 2,
+            concurrency: 2,
+            ..Settings::default()
+        };
+        let mut records = Vec::new();
+        run(&settings, |record| {
+            records.push((
+                record.round,
+                record.white,
+                record.result,
+                record.pgn.to_string(),
+            ));
+            Ok(true)
+        })
+        .unwrap();
+        records.sort_by_key(|record| record.0);
+        assert_eq!(records.len(), 2);
+        assert_eq!((records[0].1, records[1].1), (0, 1));
+        for (_, _, result, pgn) in &records {
+            assert_eq!(*result, GameResult::BlackWins);
+            assert!(pgn.contains("[Result \"0-1\"]"));
+            assert!(pgn.contains("[TimeControl \"10+0.1\"]"));
+            assert!(pgn.contains("[Termination \"normal\"]"));
+            assert!(pgn.contains("1. f3 {-0.25/3"));
+            assert!(pgn.contains("Qh4#"));
+            assert!(pgn.contains("s, Black mates} 0-1"));
+        }
+        assert!(records[0].3.contains("[White \"first\"]"));
+        assert!(records[1].3.contains("[White \"second\"]"));
+
+        // the broken engine loses every game; a worker may have started one more game by
+        // the time it's told to stop, but never all of them
+        let settings = Settings {
+            engines: vec![stub("good", &[]), stub("broken", &["x"])],
+            games: 4,
+            ..Settings::default()
+        };
+        let mut reasons = Vec::new();
+        run(&settings, |record| {
+            let (loser, result) = match record.white {
+                1 => ("White", GameResult::BlackWins),
+                _ => ("Black", GameResult::WhiteWins),
+            };
+            assert_eq!(record.result, result);
+            assert_eq!(
+                record.reason,
+                format!("{} makes an illegal move: 0000", loser)
+            );
+            reasons.push(record.reason.clone());
+            Ok(reasons.len() < 2)
+        })
+        .unwrap();
+        assert!(reasons.len() == 2 || reasons.len() == 3);
+
+        // the stub always thinks it's worse, so with adjudication the first move resigns
+        let settings = Settings {
+            engines: vec![stub("first", &[]), stub("second", &[])],
+            games: 1,
+            resign: Some(ResignAdjudication {
+                move_count: 1,
+                score: 20,
+            }),
+            ..Settings::default()
+        };
+        run(&settings, |record| {
+            assert_eq!(record.reason, "White resigns");
+            assert!(record
+                .pgn
+                .to_string()
+                .contains("[Termination \"adjudication\"]"));
+            Ok(true)
+        })
+        .unwrap();
+    }
+}
@@ -0,0 +1,556 @@
+// the UCI protocol on stdin and stdout, searches run on their own thread so `stop` and
+// `isready` are answered while thinking
+use crate::errors::ChessError;
+use crate::game::Game;
+use crate::mate::{MateSettings, MateSolver, MateTree};
+use crate::moves::Move;
+use crate::nnue;
+use crate::piece::Color;
+use crate::search::{mate_in, Search, SearchLimits, SearchResult};
+use crate::state::State;
+use crate::syzygy;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+pub const ENGINE_NAME: &str = "chesstionable";
+// kept in reserve on the clock for communication delays
+const MOVE_OVERHEAD: u64 = 50;
+const MAX_MULTIPV: usize = 256;
+// how deep the reply is searched when the PV stops at our move
+const PONDER_DEPTH: u32 = 3;
+
+// a search on its own thread
+struct Running {
+    stop: Arc<AtomicBool>,
+    ponder: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+pub struct Uci {
+    game: Game,
+    search: Option<Running>,
+    multipv: usize,
+    // the GUI may ponder, so the opponent's time is partly ours
+    ponder: bool,
+    // castling is sent as king-takes-rook
+    chess960: bool,
+}
+
+impl Default for Uci {
+    fn default() -> Uci {
+        Uci::new()
+    }
+}
+
+impl Uci {
+    pub fn new() -> Uci {
+        Uci {
+            game: Game::default(),
+            search: None,
+            multipv: 1,
+            ponder: false,
+            chess960: false,
+        }
+    }
+
+    // false once the GUI says quit
+    pub fn handle(&mut self, line: &str) -> bool {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        match args.first().copied() {
+            Some("uci") => {
+                println!("id name {}", ENGINE_NAME);
+                println!("option name SyzygyPath type string default <empty>");
+                println!("option name SyzygyProbeDepth type spin default 1 min 1 max 100");
+                println!("option name Syzygy50MoveRule type check default true");
+                println!("option name EvalFile type string default <empty>");
+                println!("option name Use NNUE type check default false");
+                println!(
+                    "option name MultiPV type spin default 1 min 1 max {}",
+                    MAX_MULTIPV
+                );
+                println!("option name Ponder type check default false");
+                println!("option name UCI_Chess960 type check default false");
+                println!("uciok");
+            }
+            Some("isready") => println!("readyok"),
+            Some("setoption") => {
+                if let Err(e) = self.set_option(line) {
+                    println!("info string {}", e);
+                }
+            }
+            Some("ucinewgame") => {
+                self.stop();
+                self.game = Game::default();
+            }
+            Some("position") => {
+                self.stop();
+                if let Err(e) = self.position(&args[1..]) {
+                    println!("info string {}", e);
+                }
+            }
+            Some("go") => self.go(&args[1..]),
+            Some("stop") => self.stop(),
+            Some("ponderhit") => {
+                if let Some(running) = &self.search {
+                    running.ponder.store(false, Ordering::Relaxed);
+                    running.handle.thread().unpark();
+                }
+            }
+            Some("quit") => {
+                self.stop();
+                return false;
+            }
+            Some(command) => println!("info string unknown command {}", command),
+            None => {}
+        }
+        true
+    }
+
+    // setoption name <id> [value <x>], names may contain spaces
+    fn set_option(&mut self, line: &str) -> Result<(), String> {
+        let rest = line
+            .split_once(" name ")
+            .map(|(_, rest)| rest)
+            .ok_or("setoption without a name")?;
+        let (name, value) = match rest.split_once(" value ") {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None => (rest.trim(), ""),
+        };
+        // the options that belong to the protocol rather than the engine
+        if name.eq_ignore_ascii_case("multipv") {
+            self.multipv = value
+                .parse()
+                .ok()
+                .filter(|lines| (1..=MAX_MULTIPV).contains(lines))
+                .ok_or_else(|| format!("bad MultiPV {}", value))?;
+            return Ok(());
+        }
+        if name.eq_ignore_ascii_case("ponder") {
+            self.ponder = value == "true";
+            return Ok(());
+        }
+        if name.eq_ignore_ascii_case("uci_chess960") {
+            self.chess960 = value == "true";
+            return Ok(());
+        }
+        if let Some(message) = apply_option(name, value)? {
+            println!("info string {}", message);
+        }
+        Ok(())
+    }
+
+    // position [startpos | fen <fen>] [moves <move>...]
+    fn position(&mut self, args: &[&str]) -> Result<(), ChessError> {
+        let moves = args.iter().position(|&arg| arg == "moves");
+        let setup = &args[..moves.unwrap_or(args.len())];
+        let mut game = match setup.first().copied() {
+            Some("startpos") => Game::default(),
+            Some("fen") => Game::from_fen(&setup[1..].join(" "))?,
+            _ => return Err(ChessError::ParseError(args.join(" "), "position")),
+        };
+        if let Some(moves) = moves {
+            for mv in &args[moves + 1..] {
+                game.play_uci(mv, self.chess960)?;
+            }
+        }
+        self.game = game;
+        Ok(())
+    }
+
+    fn go(&mut self, args: &[&str]) {
+        self.stop();
+        let limits = go_limits(args, self.game.state().side_to_move(), self.ponder);
+        let infinite = args.contains(&"infinite");
+        let mate = go_value(args, "mate").map(|moves| moves as u32);
+        let chess960 = self.chess960;
+        let mut state = self.game.state().clone();
+        let mut search = Search::new(limits);
+        search.set_multipv(self.multipv);
+        let stop = search.stop_flag();
+        let ponder = search.ponder_flag();
+        // go ponder searches the position after the predicted move until ponderhit or stop
+        ponder.store(args.contains(&"ponder"), Ordering::Relaxed);
+        let (stopped, pondering) = (stop.clone(), ponder.clone());
+
+        let handle = thread::spawn(move || {
+            // the GUI expects bestmove only after stop when it asked for an infinite search,
+            // and after ponderhit or stop when pondering; both of those unpark this thread
+            let finish = |bestmove: String| {
+                while (infinite || pondering.load(Ordering::Relaxed))
+                    && !stopped.load(Ordering::Relaxed)
+                {
+                    thread::park();
+                }
+                println!("{}", bestmove);
+            };
+            // go mate asks the mate solver first with half the nodes or time, and falls back
+            // on the regular search with what's left
+            if let Some(moves) = mate {
+                let start = Instant::now();
+                let mut solver = MateSolver::new(MateSettings {
+                    moves,
+                    max_nodes: limits.nodes.map(|nodes| nodes / 2),
+                    movetime: limits.movetime.map(|movetime| movetime / 2),
+                    ..MateSettings::default()
+                });
+                solver.set_stop_flag(stopped.clone());
+                let result = solver.solve(&state);
+                if let Some(tree) = result.solutions.first() {
+                    let (info, bestmove) =
+                        mate_lines(tree, result.nodes, start.elapsed(), chess960);
+                    println!("{}", info);
+                    return finish(bestmove);
+                }
+                if result.complete {
+                    println!("info string no mate in {}", moves);
+                }
+                search.set_limits(SearchLimits {
+                    nodes: limits
+                        .nodes
+                        .map(|nodes| nodes.saturating_sub(result.nodes).max(1)),
+                    movetime: limits.movetime.map(|movetime| {
+                        movetime
+                            .saturating_sub(start.elapsed())
+                            .max(Duration::from_millis(1))
+                    }),
+                    ..limits
+                });
+            }
+            let result = search.run_with_info(&mut state, |result| {
+                for line in info_lines(result, chess960) {
+                    println!("{}", line);
+                }
+            });
+            finish(bestmove_line(&result, &state, chess960));
+        });
+        self.search = Some(Running {
+            stop,
+            ponder,
+            handle,
+        });
+    }
+
+    // waits for the running search, if any, to print its bestmove
+    fn stop(&mut self) {
+        if let Some(running) = self.search.take() {
+            running.stop.store(true, Ordering::Relaxed);
+            running.handle.thread().unpark();
+            running.handle.join().unwrap();
+        }
+    }
+}
+
+// sets one of the options listed for `uci`, also used by the xboard front-end; the message
+// is something worth telling the user
+pub fn apply_option(name: &str, value: &str) -> Result<Option<String>, String> {
+    let message = match name.to_lowercase().as_str() {
+        "syzygypath" => {
+            let (count, errors) = syzygy::set_path(value).map_err(|e| e.to_string())?;
+            if errors.is_empty() {
+                Some(format!("found {} tablebases", count))
+            } else {
+                Some(format!(
+                    "found {} tablebases, could not read {}",
+                    count,
+                    errors.join(", ")
+                ))
+            }
+        }
+        "syzygyprobedepth" => {
+            syzygy::set_probe_depth(
+                value
+                    .parse()
+                    .map_err(|_| format!("bad SyzygyProbeDepth {}", value))?,
+            );
+            None
+        }
+        "syzygy50moverule" => {
+            syzygy::set_rule50(value == "true");
+            None
+        }
+        "evalfile" => {
+            let hidden = nnue::set_eval_file(value).map_err(|e| e.to_string())?;
+            (hidden > 0).then(|| format!("loaded a network with {} hidden neurons", hidden))
+        }
+        "use nnue" => {
+            nnue::set_enabled(value == "true");
+            (nnue::enabled() && nnue::network().is_none())
+                .then(|| "no EvalFile loaded, using the classical evaluation".to_string())
+        }
+        _ => return Err(format!("unknown option {}", name)),
+    };
+    Ok(message)
+}
+
+// a share of the remaining time plus half the increment, all in milliseconds
+pub fn time_budget(time: u64, increment: u64, moves_to_go: Option<u64>) -> Duration {
+    let budget = time / moves_to_go.unwrap_or(30).max(1) + increment / 2;
+    Duration::from_millis(budget.min(time.saturating_sub(MOVE_OVERHEAD)).max(1))
+}
+
+// the expected reply after `mv`, from a quick search when the PV doesn't go that far
+fn ponder_move(result: &SearchResult, state: &State, mv: Move) -> Option<Move> {
+    if let Some(&reply) = result.pv.get(1) {
+        return Some(reply);
+    }
+    let mut state = state.clone();
+    state.make_move(mv);
+    let mut search = Search::new(SearchLimits {
+        depth: Some(PONDER_DEPTH),
+        ..SearchLimits::default()
+    });
+    search.run(&mut state).best_move
+}
+
+// the move to ponder on is the expected reply from the PV
+fn bestmove_line(result: &SearchResult, state: &State, chess960: bool) -> String {
+    let ponder = result
+        .best_move
+        .and_then(|mv| ponder_move(result, state, mv));
+    match (result.best_move, ponder) {
+        (Some(mv), Some(reply)) => format!(
+            "bestmove {} ponder {}",
+            mv.to_uci(chess960),
+            reply.to_uci(chess960)
+        ),
+        (Some(mv), None) => format!("bestmove {}", mv.to_uci(chess960)),
+        (None, _) => "bestmove 0000".to_string(),
+    }
+}
+
+// the time budget, unless there's a fixed movetime; a quarter more when pondering is on
+fn go_limits(args: &[&str], side: Color, ponder: bool) -> SearchLimits {
+    let value = |key: &str| go_value(args, key);
+    // a mate in n is n moves for us and as many replies
+    let mate_depth = value("mate").map(|moves| moves * 2);
+    let mut limits = SearchLimits {
+        depth: value("depth").or(mate_depth).map(|depth| depth as u32),
+        nodes: value("nodes"),
+        movetime: value("movetime").map(Duration::from_millis),
+    };
+
+    let (time, increment) = match side {
+        Color::White => (value("wtime"), value("winc")),
+        Color::Black => (value("btime"), value("binc")),
+    };
+    if let Some(time) = time {
+        if limits.movetime.is_none() && !args.contains(&"infinite") {
+            let mut budget = time_budget(time, increment.unwrap_or(0), value("movestogo"));
+            if ponder {
+                let most = Duration::from_millis(time.saturating_sub(MOVE_OVERHEAD).max(1));
+                budget = (budget * 5 / 4).min(most);
+            }
+            limits.movetime = Some(budget);
+        }
+    }
+    limits
+}
+
+// the number following a go parameter
+fn go_value(args: &[&str], key: &str) -> Option<u64> {
+    let index = args.iter().position(|&arg| arg == key)?;
+    args.get(index + 1)?.parse().ok()
+}
+
+// the info and bestmove lines for a solved go mate
+fn mate_lines(tree: &MateTree, nodes: u64, elapsed: Duration, chess960: bool) -> (String, String) {
+    let millis = elapsed.as_millis().max(1);
+    let line = tree.main_line();
+    let pv: Vec<String> = line.iter().map(|mv| mv.to_uci(chess960)).collect();
+    let info = format!(
+        "info depth {} score mate {} nodes {} nps {} time {} pv {}",
+        line.len(),
+        tree.moves(),
+        nodes,
+        nodes as u128 * 1000 / millis,
+        millis,
+        pv.join(" ")
+    );
+    let bestmove = match pv.get(1) {
+        Some(reply) => format!("bestmove {} ponder {}", pv[0], reply),
+        None => format!("bestmove {}", pv[0]),
+    };
+    (info, bestmove)
+}
+
+// one line per PV, best first
+fn info_lines(result: &SearchResult, chess960: bool) -> Vec<String> {
+    let millis = result.elapsed.as_millis().max(1);
+    let lines = result.lines.iter().enumerate().map(|(index, line)| {
+        let score = match mate_in(line.score) {
+            Some(moves) => format!("mate {}", moves),
+            None => format!("cp {}", line.score),
+        };
+        let pv: Vec<String> = line.pv.iter().map(|mv| mv.to_uci(chess960)).collect();
+        format!(
+            "info depth {} multipv {} score {} nodes {} nps {} time {} tbhits {} pv {}",
+            result.depth,
+            index + 1,
+            score,
+            result.nodes,
+            result.nodes as u128 * 1000 / millis,
+            millis,
+            result.tb_hits,
+            pv.join(" ")
+        )
+    });
+    lines.collect()
+}
+
+pub fn run(lines: impl Iterator<Item = String>) {
+    let mut uci = Uci::new();
+    for line in lines {
+        if !uci.handle(line.trim()) {
+            break;
+        }
+    }
+    uci.stop();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions() {
+        let mut uci = Uci::new();
+        uci.position(&["startpos", "moves", "e2e4", "e7e5", "g1f3"])
+            .unwrap();
+        assert_eq!(
+            uci.game.state().to_fen(),
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2"
+        );
+
+        let fen = "8/8/4k3/8/8/8/4P3/4K3 w - - 0 1";
+        let line = format!("fen {} moves e2e4", fen);
+        let args: Vec<&str> = line.split(' ').collect();
+        uci.position(&args).unwrap();
+        assert_eq!(
+            uci.game.state().to_fen(),
+            "8/8/4k3/8/4P3/8/8/4K3 b - e3 0 1"
+        );
+
+        assert!(uci.position(&["startpos", "moves", "e2e5"]).is_err());
+        assert!(uci.position(&["somewhere"]).is_err());
+    }
+
+    #[test]
+    fn options() {
+        let mut uci = Uci::new();
+        assert!(uci
+            .set_option("setoption name EvalFile value /nonexistent/net.nnue")
+            .is_err());
+        assert!(nnue::network().is_none());
+        assert!(uci.set_option("setoption name Hash value 16").is_err());
+        assert!(uci.set_option("setoption").is_err());
+
+        uci.set_option("setoption name MultiPV value 3").unwrap();
+        assert_eq!(uci.multipv, 3);
+        assert!(uci.set_option("setoption name multipv value 0").is_err());
+        assert_eq!(uci.multipv, 3);
+
+        // only king-takes-rook castles under UCI_Chess960
+        let castle = |uci: &mut Uci, mv: &str| {
+            let line = format!("fen 4k3/8/8/8/8/8/8/4K2R w K - 0 1 moves {}", mv);
+            let args: Vec<&str> = line.split(' ').collect();
+            uci.position(&args).map(|_| uci.game.state().to_fen())
+        };
+        let castled = "4k3/8/8/8/8/8/8/5RK1 b - - 1 1";
+        assert_eq!(castle(&mut uci, "e1g1").unwrap(), castled);
+        uci.set_option("setoption name UCI_Chess960 value true")
+            .unwrap();
+        assert!(uci.chess960);
+        assert!(castle(&mut uci, "e1g1").is_err());
+        assert_eq!(castle(&mut uci, "e1h1").unwrap(), castled);
+    }
+
+    #[test]
+    fn multipv_info() {
+        let mut state = State::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        let mut search = Search::new(SearchLimits {
+            depth: Some(2),
+            ..SearchLimits::default()
+        });
+        search.set_multipv(2);
+        let lines = info_lines(&search.run(&mut state), false);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("info depth 2 multipv 1 score mate 1 "));
+        assert!(lines[0].ends_with(" pv a1a8"));
+        assert!(lines[1].starts_with("info depth 2 multipv 2 score cp "));
+    }
+
+    #[test]
+    fn pondering() {
+        let mut uci = Uci::new();
+        uci.handle("setoption name Ponder value true");
+        assert!(uci.ponder);
+        uci.handle("position startpos moves e2e4");
+        uci.handle("go ponder wtime 100 btime 100");
+        // no time limit until the ponder hit, and the usual one after it
+        thread::sleep(Duration::from_millis(100));
+        assert!(!uci.search.as_ref().unwrap().handle.is_finished());
+        uci.handle("ponderhit");
+        uci.search.take().unwrap().handle.join().unwrap();
+
+        let mut search = Search::new(SearchLimits {
+            depth: Some(2),
+            ..SearchLimits::default()
+        });
+        let state = State::default();
+        let mut result = search.run(&mut state.clone());
+        let line = bestmove_line(&result, &state, false);
+        assert!(line.starts_with("bestmove ") && line.contains(" ponder "));
+        // a PV cut short after our move still gives something to ponder on
+        result.pv.truncate(1);
+        assert!(bestmove_line(&result, &state, false).contains(" ponder "));
+        let mate = State::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        assert_eq!(
+            bestmove_line(&search.run(&mut mate.clone()), &mate, false),
+            "bestmove a1a8"
+        );
+    }
+
+    #[test]
+    fn time_management() {
+        let args = ["wtime", "60000", "btime", "1000", "winc", "1000"];
+        let white = go_limits(&args, Color::White, false);
+        assert_eq!(white.movetime, Some(Duration::from_millis(2500)));
+        let black = go_limits(&args, Color::Black, false);
+        assert_eq!(black.movetime, Some(Duration::from_millis(33)));
+
+        let fixed = go_limits(&["movetime", "200", "depth", "5"], Color::White, true);
+        assert_eq!(fixed.movetime, Some(Duration::from_millis(200)));
+        assert_eq!(fixed.depth, Some(5));
+        assert_eq!(go_limits(&["infinite"], Color::White, false).movetime, None);
+        let pondering = go_limits(&args, Color::White, true);
+        assert_eq!(pondering.movetime, Some(Duration::from_millis(3125)));
+        let short = go_limits(&["wtime", "100", "winc", "1000"], Color::White, true);
+        assert_eq!(short.movetime, Some(Duration::from_millis(50)));
+        assert_eq!(
+            go_limits(&["mate", "3"], Color::White, false).depth,
+            Some(6)
+        );
+    }
+
+    #[test]
+    fn mate_search() {
+        let state =
+            State::from_fen("r1b2k1r/ppp1bppp/8/1B1Q4/5q2/2P5/PPP2PPP/R3R1K1 w - - 1 1").unwrap();
+        let result = MateSolver::new(MateSettings::default()).solve(&state);
+        let (info, bestmove) =
+            mate_lines(&result.solutions[0], result.nodes, Duration::ZERO, false);
+        assert!(info.starts_with("info depth 3 score mate 2 nodes "));
+        assert!(info.ends_with(" pv d5d8 e7d8 e1e8"));
+        assert_eq!(bestmove, "bestmove d5d8 ponder e7d8");
+
+        // without a mate in sight the solver gives up in time for the search to answer
+        let mut uci = Uci::new();
+        uci.handle("position startpos");
+        let start = Instant::now();
+        uci.handle("go mate 8 movetime 200");
+        uci.search.take().unwrap().handle.join().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}
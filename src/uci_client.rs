@@ -0,0 +1,645 @@
+// the other end of the UCI protocol: runs an engine as a child process and talks to it.
+// Its output is read on a thread of its own, so every wait can time out and an engine that
+// exits shows up as an error instead of a hang.
+use crate::errors::ChessError;
+use crate::search::SearchLimits;
+use std::io::{BufRead, BufReader, Write};
+use std::iter::Peekable;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::str::{FromStr, SplitWhitespace};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Score {
+    Cp(i32),
+    // full moves, negative when the engine gets mated
+    Mate(i32),
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum Bound {
+    #[default]
+    Exact,
+    Lower,
+    Upper,
+}
+
+// everything an info line may carry, missing fields are None
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Info {
+    pub depth: Option<u32>,
+    pub seldepth: Option<u32>,
+    pub multipv: Option<u32>,
+    pub score: Option<Score>,
+    pub bound: Bound,
+    pub nodes: Option<u64>,
+    pub nps: Option<u64>,
+    pub time: Option<Duration>,
+    pub hashfull: Option<u32>,
+    pub tbhits: Option<u64>,
+    // win, draw and loss per mille
+    pub wdl: Option<(u32, u32, u32)>,
+    pub currmove: Option<String>,
+    pub pv: Vec<String>,
+    pub string: Option<String>,
+}
+
+const INFO_KEYWORDS: [&str; 16] = [
+    "depth",
+    "seldepth",
+    "time",
+    "nodes",
+    "pv",
+    "multipv",
+    "score",
+    "currmove",
+    "currmovenumber",
+    "hashfull",
+    "nps",
+    "tbhits",
+    "sbhits",
+    "cpuload",
+    "string",
+    "wdl",
+];
+
+fn number<T: FromStr>(tokens: &mut Peekable<SplitWhitespace>) -> Option<T> {
+    tokens.next()?.parse().ok()
+}
+
+// None when the line isn't an info line
+pub fn parse_info(line: &str) -> Option<Info> {
+    let mut tokens = line.split_whitespace().peekable();
+    if tokens.next() != Some("info") {
+        return None;
+    }
+
+    let mut info = Info::default();
+    while let Some(token) = tokens.next() {
+        let tokens = &mut tokens;
+        match token {
+            "depth" => info.depth = number(tokens),
+            "seldepth" => info.seldepth = number(tokens),
+            "multipv" => info.multipv = number(tokens),
+            "nodes" => info.nodes = number(tokens),
+            "nps" => info.nps = number(tokens),
+            "time" => info.time = number(tokens).map(Duration::from_millis),
+            "hashfull" => info.hashfull = number(tokens),
+            "tbhits" => info.tbhits = number(tokens),
+            "score" => loop {
+                match tokens.peek().copied() {
+                    Some("cp") | Some("mate") => {
+                        let kind = tokens.next();
+                        let value = number(tokens);
+                        info.score = match (kind, value) {
+                            (Some("cp"), Some(value)) => Some(Score::Cp(value)),
+                            (_, Some(value)) => Some(Score::Mate(value)),
+                            _ => None,
+                        };
+                    }
+                    Some("lowerbound") => {
+                        tokens.next();
+                        info.bound = Bound::Lower;
+                    }
+                    Some("upperbound") => {
+                        tokens.next();
+                        info.bound = Bound::Upper;
+                    }
+                    _ => break,
+                }
+            },
+            "wdl" => {
+                let wdl: Vec<u32> = (0..3).filter_map(|_| number(tokens)).collect();
+                info.wdl = (wdl.len() == 3).then(|| (wdl[0], wdl[1], wdl[2]));
+            }
+            "currmove" => info.currmove = tokens.next().map(str::to_string),
+            "pv" => {
+                while let Some(&mv) = tokens.peek() {
+                    if INFO_KEYWORDS.contains(&mv) {
+                        break;
+                    }
+                    info.pv.push(mv.to_string());
+                    tokens.next();
+                }
+            }
+            "string" => {
+                info.string = Some(tokens.by_ref().collect::<Vec<_>>().join(" "));
+            }
+            // currmovenumber, sbhits, cpuload and anything unknown
+            _ => {}
+        }
+    }
+    Some(info)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BestMove {
+    // None when the engine had no move to play
+    pub best: Option<String>,
+    pub ponder: Option<String>,
+}
+
+pub fn parse_bestmove(line: &str) -> Option<BestMove> {
+    let mut tokens = line.split_whitespace();
+    if tokens.next() != Some("bestmove") {
+        return None;
+    }
+    let best = tokens
+        .next()
+        .filter(|&mv| mv != "0000" && mv != "(none)")
+        .map(str::to_string);
+    let ponder = match tokens.next() {
+        Some("ponder") => tokens.next().map(str::to_string),
+        _ => None,
+    };
+    Some(BestMove { best, ponder })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionKind {
+    Check,
+    Spin { min: i64, max: i64 },
+    Combo { vars: Vec<String> },
+    Button,
+    String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EngineOption {
+    pub name: String,
+    pub kind: OptionKind,
+    pub default: Option<String>,
+}
+
+// option name <id> type <t> [default <x>] [min <x>] [max <x>] [var <x>]*, where names and
+// values may contain spaces
+pub fn parse_option(line: &str) -> Option<EngineOption> {
+    let rest = line.strip_prefix("option ")?.trim();
+    let mut fields: Vec<(&str, Vec<&str>)> = Vec::new();
+    for token in rest.split_whitespace() {
+        match token {
+            "name" | "type" | "default" | "min" | "max" | "var" => fields.push((token, Vec::new())),
+            _ => fields.last_mut()?.1.push(token),
+        }
+    }
+    let field = |key: &str| {
+        fields
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, words)| words.join(" "))
+    };
+    let number = |key: &str| field(key).and_then(|value| value.parse().ok());
+
+    let kind = match field("type")?.as_str() {
+        "check" => OptionKind::Check,
+        "spin" => OptionKind::Spin {
+            min: number("min")?,
+            max: number("max")?,
+        },
+        "combo" => OptionKind::Combo {
+            vars: fields
+                .iter()
+                .filter(|(key, _)| *key == "var")
+                .map(|(_, words)| words.join(" "))
+                .collect(),
+        },
+        "button" => OptionKind::Button,
+        "string" => OptionKind::String,
+        _ => return None,
+    };
+    Some(EngineOption {
+        name: field("name").filter(|name| !name.is_empty())?,
+        kind,
+        default: field("default"),
+    })
+}
+
+// the arguments of a go command
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Go {
+    pub wtime: Option<Duration>,
+    pub btime: Option<Duration>,
+    pub winc: Option<Duration>,
+    pub binc: Option<Duration>,
+    pub movestogo: Option<u32>,
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+    pub mate: Option<u32>,
+    pub movetime: Option<Duration>,
+    pub infinite: bool,
+}
+
+impl From<SearchLimits> for Go {
+    fn from(limits: SearchLimits) -> Go {
+        Go {
+            depth: limits.depth,
+            nodes: limits.nodes,
+            movetime: limits.movetime,
+            ..Go::default()
+        }
+    }
+}
+
+impl Go {
+    pub fn command(&self) -> String {
+        let mut command = "go".to_string();
+        let millis = |time: Duration| time.as_millis().to_string();
+        for (key, value) in [
+            ("wtime", self.wtime.map(millis)),
+            ("btime", self.btime.map(millis)),
+            ("winc", self.winc.map(millis)),
+            ("binc", self.binc.map(millis)),
+            ("movestogo", self.movestogo.map(|n| n.to_string())),
+            ("depth", self.depth.map(|n| n.to_string())),
+            ("nodes", self.nodes.map(|n| n.to_string())),
+            ("mate", self.mate.map(|n| n.to_string())),
+            ("movetime", self.movetime.map(millis)),
+        ] {
+            if let Some(value) = value {
+                command += &format!(" {} {}", key, value);
+            }
+        }
+        if self.infinite {
+            command += " infinite";
+        }
+        command
+    }
+}
+
+pub struct UciEngine {
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+    // how long the engine gets to answer anything but a search
+    timeout: Duration,
+    pub name: Option<String>,
+    pub author: Option<String>,
+    pub options: Vec<EngineOption>,
+}
+
+impl UciEngine {
+    // starts the engine and waits for uciok
+    pub fn spawn(
+        program: &str,
+        args: &[String],
+        timeout: Duration,
+    ) -> Result<UciEngine, ChessError> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut engine = UciEngine {
+            child,
+            stdin,
+            lines,
+            timeout,
+            name: None,
+            author: None,
+            options: Vec::new(),
+        };
+        engine.handshake()?;
+        Ok(engine)
+    }
+
+    fn handshake(&mut self) -> Result<(), ChessError> {
+        self.send("uci")?;
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let line = self.read_line(deadline, "uciok")?;
+            if line.trim() == "uciok" {
+                return Ok(());
+            } else if let Some(name) = line.strip_prefix("id name ") {
+                self.name = Some(name.trim().to_string());
+            } else if let Some(author) = line.strip_prefix("id author ") {
+                self.author = Some(author.trim().to_string());
+            } else if let Some(option) = parse_option(&line) {
+                self.options.push(option);
+            }
+        }
+    }
+
+    pub fn send(&mut self, command: &str) -> Result<(), ChessError> {
+        let written = writeln!(self.stdin, "{}", command).and_then(|_| self.stdin.flush());
+        written.map_err(|_| self.crashed())
+    }
+
+    fn crashed(&mut self) -> ChessError {
+        let status = match self.child.try_wait() {
+            Ok(Some(status)) => status.to_string(),
+            _ => "closed its output".to_string(),
+        };
+        ChessError::EngineCrashed(status)
+    }
+
+    fn read_line(&mut self, deadline: Instant, waiting_for: &str) -> Result<String, ChessError> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match self.lines.recv_timeout(timeout) {
+            Ok(line) => Ok(line),
+            Err(RecvTimeoutError::Timeout) => {
+                Err(ChessError::EngineTimeout(waiting_for.to_string()))
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                // give the process a moment to be reaped so the status is known
+                let _ = self.child.wait();
+                Err(self.crashed())
+            }
+        }
+    }
+
+    pub fn is_ready(&mut self) -> Result<(), ChessError> {
+        self.send("isready")?;
+        let deadline = Instant::now() + self.timeout;
+        while self.read_line(deadline, "readyok")?.trim() != "readyok" {}
+        Ok(())
+    }
+
+    pub fn set_option(&mut self, name: &str, value: &str) -> Result<(), ChessError> {
+        if value.is_empty() {
+            self.send(&format!("setoption name {}", name))
+        } else {
+            self.send(&format!("setoption name {} value {}", name, value))
+        }
+    }
+
+    pub fn new_game(&mut self) -> Result<(), ChessError> {
+        self.send("ucinewgame")?;
+        self.is_ready()
+    }
+
+    // the start position when `fen` is None
+    pub fn position(&mut self, fen: Option<&str>, moves: &[String]) -> Result<(), ChessError> {
+        let mut command = match fen {
+            Some(fen) => format!("position fen {}", fen),
+            None => "position startpos".to_string(),
+        };
+        if !moves.is_empty() {
+            command += " moves ";
+            command += &moves.join(" ");
+        }
+        self.send(&command)
+    }
+
+    // runs a search and returns its bestmove along with the last info line that had a
+    // score; after `timeout` the engine is told to stop and gets the usual timeout to answer
+    pub fn go(
+        &mut self,
+        go: &Go,
+        timeout: Duration,
+        mut on_info: impl FnMut(&Info),
+    ) -> Result<(BestMove, Option<Info>), ChessError> {
+        self.send(&go.command())?;
+        let mut deadline = Instant::now() + timeout;
+        let mut stopped = false;
+        let mut last = None;
+        loop {
+            let line = match self.read_line(deadline, "bestmove") {
+                Err(ChessError::EngineTimeout(_)) if !stopped => {
+                    self.send("stop")?;
+                    stopped = true;
+                    deadline = Instant::now() + self.timeout;
+                    continue;
+                }
+                line => line?,
+            };
+            if let Some(best) = parse_bestmove(&line) {
+                return Ok((best, last));
+            }
+            if let Some(info) = parse_info(&line) {
+                on_info(&info);
+                if info.score.is_some() {
+                    last = Some(info);
+                }
+            }
+        }
+    }
+
+    pub fn stop(&mut self) -> Result<(), ChessError> {
+        self.send("stop")
+    }
+
+    // asks the engine to quit, and kills it if it doesn't within the timeout
+    pub fn quit(mut self) -> Result<(), ChessError> {
+        self.shut_down()
+    }
+
+    fn shut_down(&mut self) -> Result<(), ChessError> {
+        if let Ok(Some(_)) = self.child.try_wait() {
+            return Ok(());
+        }
+        let _ = self.send("quit");
+        let deadline = Instant::now() + self.timeout;
+        while Instant::now() < deadline {
+            if self.child.try_wait()?.is_some() {
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        self.child.kill()?;
+        self.child.wait()?;
+        Ok(())
+    }
+}
+
+impl Drop for UciEngine {
+    fn drop(&mut self) {
+        let _ = self.shut_down();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn info_lines() {
+        let info = parse_info(
+            "info depth 12 seldepth 18 multipv 2 score cp -35 upperbound nodes 123456 \
+             nps 987654 time 125 hashfull 42 tbhits 3 wdl 120 700 180 pv e7e5 g1f3 b8c6",
+        )
+        .unwrap();
+        assert_eq!(info.depth, Some(12));
+        assert_eq!(info.seldepth, Some(18));
+        assert_eq!(info.multipv, Some(2));
+        assert_eq!(info.score, Some(Score::Cp(-35)));
+        assert_eq!(info.bound, Bound::Upper);
+        assert_eq!(info.nodes, Some(123456));
+        assert_eq!(info.nps, Some(987654));
+        assert_eq!(info.time, Some(Duration::from_millis(125)));
+        assert_eq!(info.hashfull, Some(42));
+        assert_eq!(info.tbhits, Some(3));
+        assert_eq!(info.wdl, Some((120, 700, 180)));
+        assert_eq!(info.pv, ["e7e5", "g1f3", "b8c6"]);
+
+        let info = parse_info("info score mate -3 lowerbound pv a1a8 nodes 5").unwrap();
+        assert_eq!(info.score, Some(Score::Mate(-3)));
+        assert_eq!(info.bound, Bound::Lower);
+        assert_eq!(info.pv, ["a1a8"]);
+        assert_eq!(info.nodes, Some(5));
+
+        let info = parse_info("info string NNUE evaluation enabled").unwrap();
+        assert_eq!(info.string.as_deref(), Some("NNUE evaluation enabled"));
+        assert_eq!(parse_info("bestmove e2e4"), None);
+    }
+
+    #[test]
+    fn bestmove_lines() {
+        assert_eq!(
+            parse_bestmove("bestmove e2e4 ponder e7e5"),
+            Some(BestMove {
+                best: Some("e2e4".to_string()),
+                ponder: Some("e7e5".to_string())
+            })
+        );
+        assert_eq!(parse_bestmove("bestmove 0000").unwrap().best, None);
+        assert_eq!(parse_bestmove("bestmove (none)").unwrap().best, None);
+        assert_eq!(parse_bestmove("info depth 1"), None);
+    }
+
+    #[test]
+    fn option_lines() {
+        let hash =
+            parse_option("option name Hash type spin default 16 min 1 max 33554432").unwrap();
+        assert_eq!(hash.name, "Hash");
+        assert_eq!(
+            hash.kind,
+            OptionKind::Spin {
+                min: 1,
+                max: 33554432
+            }
+        );
+        assert_eq!(hash.default.as_deref(), Some("16"));
+
+        let style =
+            parse_option("option name Play Style type combo default Normal var Solid var Normal")
+                .unwrap();
+        assert_eq!(style.name, "Play Style");
+        assert_eq!(
+            style.kind,
+            OptionKind::Combo {
+                vars: vec!["Solid".to_string(), "Normal".to_string()]
+            }
+        );
+
+        let clear = parse_option("option name Clear Hash type button").unwrap();
+        assert_eq!((clear.kind, clear.default), (OptionKind::Button, None));
+        assert!(parse_option("option name Broken type spin default 1").is_none());
+        assert!(parse_option("option type check default true").is_none());
+    }
+
+    #[test]
+    fn go_commands() {
+        let go = Go {
+            wtime: Some(Duration::from_secs(60)),
+            btime: Some(Duration::from_millis(59500)),
+            winc: Some(Duration::from_secs(1)),
+            binc: Some(Duration::from_secs(1)),
+            ..Go::default()
+        };
+        assert_eq!(
+            go.command(),
+            "go wtime 60000 btime 59500 winc 1000 binc 1000"
+        );
+        let limits = SearchLimits {
+            depth: Some(8),
+            ..SearchLimits::default()
+        };
+        assert_eq!(Go::from(limits).command(), "go depth 8");
+    }
+
+    // answers like an engine would, except that `go depth 99` never finishes and
+    // `go nodes 13` crashes it
+    #[cfg(unix)]
+    const STUB: &str = r#"
+while read -r line; do
+    case "$line" in
+        uci)
+            echo "id name Stub Engine"
+            echo "id author Nobody"
+            echo "option name Hash type spin default 16 min 1 max 64"
+            echo "option name Style type combo default Normal var Solid var Normal"
+            echo "uciok" ;;
+        isready) echo "readyok" ;;
+        "go depth 99") ;;
+        "go nodes 13") exit 3 ;;
+        go*)
+            echo "info depth 1 score cp 13 nodes 20 pv e2e4"
+            echo "info depth 2 seldepth 3 score cp 21 lowerbound nodes 90 pv e2e4 e7e5"
+            echo "info string done"
+            echo "bestmove e2e4 ponder e7e5" ;;
+        quit) exit 0 ;;
+    esac
+done
+"#;
+
+    #[cfg(unix)]
+    fn stub() -> UciEngine {
+        let args = ["-c".to_string(), STUB.to_string()];
+        UciEngine::spawn("sh", &args, Duration::from_secs(5)).unwrap()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn drives_an_engine() {
+        let mut engine = stub();
+        assert_eq!(engine.name.as_deref(), Some("Stub Engine"));
+        assert_eq!(engine.author.as_deref(), Some("Nobody"));
+        assert_eq!(engine.options.len(), 2);
+        engine.set_option("Hash", "32").unwrap();
+        engine.new_game().unwrap();
+        engine.position(None, &["e2e4".to_string()]).unwrap();
+
+        let mut infos = 0;
+        let go = Go {
+            movetime: Some(Duration::from_millis(100)),
+            ..Go::default()
+        };
+        let (best, last) = engine
+            .go(&go, Duration::from_secs(5), |_| infos += 1)
+            .unwrap();
+        assert_eq!(infos, 3);
+        assert_eq!(best.best.as_deref(), Some("e2e4"));
+        assert_eq!(best.ponder.as_deref(), Some("e7e5"));
+        let last = last.unwrap();
+        assert_eq!((last.depth, last.score), (Some(2), Some(Score::Cp(21))));
+        engine.quit().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn timeouts_and_crashes() {
+        let mut engine = stub();
+        engine.timeout = Duration::from_millis(50);
+        let hanging = Go {
+            depth: Some(99),
+            ..Go::default()
+        };
+        let result = engine.go(&hanging, Duration::from_millis(50), |_| {});
+        assert!(matches!(result, Err(ChessError::EngineTimeout(_))));
+        engine.is_ready().unwrap();
+
+        let crashing = Go {
+            nodes: Some(13),
+            ..Go::default()
+        };
+        let result = engine.go(&crashing, Duration::from_secs(5), |_| {});
+        assert!(matches!(result, Err(ChessError::EngineCrashed(_))));
+        assert!(engine.is_ready().is_err());
+    }
+}
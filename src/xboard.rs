@@ -0,0 +1,519 @@
+// the xboard protocol (CECP version 2) for GUIs that don't speak UCI. Unlike UCI the engine
+// keeps the game itself: moves arrive one at a time, and when it's the engine's turn a search
+// thread prints its move, which is applied to the game before the next command touches it.
+use crate::game::{Game, GameResult, Termination};
+use crate::moves::Move;
+use crate::piece::Color;
+use crate::search::{mate_in, Search, SearchLimits, SearchResult};
+use crate::state::{CastlingSide, State};
+use crate::uci::{self, ENGINE_NAME};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// the UCI options as xboard option features, check options take 1 and 0
+const OPTIONS: [(&str, &str); 5] = [
+    ("SyzygyPath", "-path "),
+    ("SyzygyProbeDepth", "-spin 1 1 100"),
+    ("Syzygy50MoveRule", "-check 1"),
+    ("EvalFile", "-file "),
+    ("Use NNUE", "-check 0"),
+];
+// how xboard expects mate scores, plus or minus the moves to mate
+const MATE_BASE: i32 = 100000;
+
+struct Thinking {
+    stop: Arc<AtomicBool>,
+    // keeps a stopped search from playing its move
+    abandon: Arc<AtomicBool>,
+    // the move that was played, if any
+    handle: JoinHandle<Option<Move>>,
+}
+
+pub struct Xboard {
+    game: Game,
+    // None in force mode
+    engine_side: Option<Color>,
+    post: bool,
+    analyzing: bool,
+    // the level command: moves per session (0 for the whole game), base time and increment
+    session_moves: u32,
+    base: Duration,
+    increment: Duration,
+    // st and sd
+    move_time: Option<Duration>,
+    depth: Option<u32>,
+    // the engine's clock as last reported by the time command
+    time_left: Option<Duration>,
+    search: Option<Thinking>,
+    // variant fischerandom, castling moves are O-O and O-O-O
+    chess960: bool,
+}
+
+impl Default for Xboard {
+    fn default() -> Xboard {
+        Xboard::new()
+    }
+}
+
+impl Xboard {
+    pub fn new() -> Xboard {
+        Xboard {
+            game: Game::default(),
+            engine_side: Some(Color::Black),
+            post: false,
+            analyzing: false,
+            session_moves: 0,
+            base: Duration::from_secs(300),
+            increment: Duration::ZERO,
+            move_time: None,
+            depth: None,
+            time_left: None,
+            search: None,
+            chess960: false,
+        }
+    }
+
+    // false once the GUI says quit
+    pub fn handle(&mut self, line: &str) -> bool {
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let rest = rest.trim();
+        match command {
+            // nothing to do for these
+            "xboard" | "accepted" | "rejected" | "random" | "computer" | "name" | "rating"
+            | "hard" | "easy" | "draw" | "hint" | "bk" | "otim" | "." | "white" | "black" | "" => {}
+            "protover" => features(),
+            "new" => {
+                self.abandon();
+                self.game = Game::default();
+                self.chess960 = false;
+                self.engine_side = Some(Color::Black);
+                self.depth = None;
+                self.time_left = None;
+                self.restart_analysis();
+            }
+            "variant" => match rest {
+                "normal" => self.chess960 = false,
+                "fischerandom" => self.chess960 = true,
+                _ => println!("Error (unsupported variant): {}", rest),
+            },
+            "force" | "result" => {
+                self.abandon();
+                self.engine_side = None;
+            }
+            "go" => {
+                self.abandon();
+                self.engine_side = Some(self.game.state().side_to_move());
+                self.think();
+            }
+            "playother" => {
+                self.abandon();
+                self.engine_side = Some(!self.game.state().side_to_move());
+            }
+            "level" => match parse_level(rest) {
+                Some((moves, base, increment)) => {
+                    (self.session_moves, self.base, self.increment) = (moves, base, increment);
+                    self.move_time = None;
+                }
+                None => println!("Error (bad level): {}", rest),
+            },
+            "st" => match rest.parse().map(Duration::try_from_secs_f64) {
+                Ok(Ok(move_time)) => self.move_time = Some(move_time),
+                _ => println!("Error (bad time): {}", rest),
+            },
+            "sd" => match rest.parse() {
+                Ok(depth) => self.depth = Some(depth),
+                Err(_) => println!("Error (bad depth): {}", rest),
+            },
+            // in centiseconds
+            "time" => match rest.parse::<u64>() {
+                Ok(centis) => self.time_left = Some(Duration::from_millis(centis * 10)),
+                Err(_) => println!("Error (bad time): {}", rest),
+            },
+            "usermove" => self.user_move(rest),
+            // move now
+            "?" => self.stop(),
+            "ping" => println!("pong {}", rest),
+            "setboard" => {
+                self.abandon();
+                match Game::from_fen(rest) {
+                    Ok(game) => self.game = game,
+                    Err(e) => println!("tellusererror Illegal position: {}", e),
+                }
+                self.restart_analysis();
+            }
+            "undo" | "remove" => {
+                self.abandon();
+                let plies = if command == "undo" { 1 } else { 2 };
+                for _ in 0..plies {
+                    self.game.undo();
+                }
+                self.restart_analysis();
+            }
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "analyze" => {
+                self.analyzing = true;
+                self.engine_side = None;
+                self.restart_analysis();
+            }
+            "exit" => {
+                self.abandon();
+                self.analyzing = false;
+            }
+            "option" => {
+                let (name, value) = rest.split_once('=').unwrap_or((rest, ""));
+                let check = OPTIONS
+                    .iter()
+                    .any(|&(option, kind)| option == name && kind.starts_with("-check"));
+                let value = match (check, value) {
+                    (true, "1") => "true",
+                    (true, "0") => "false",
+                    _ => value,
+                };
+                match uci::apply_option(name, value) {
+                    Ok(Some(message)) => println!("telluser {}", message),
+                    Ok(None) => {}
+                    Err(e) => println!("Error ({}): option", e),
+                }
+            }
+            "quit" => {
+                self.abandon();
+                return false;
+            }
+            // moves without usermove, for GUIs that refused the feature
+            _ if parse_move(self.game.state(), command, self.chess960).is_some() => {
+                self.user_move(command)
+            }
+            _ => println!("Error (unknown command): {}", command),
+        }
+        true
+    }
+
+    fn user_move(&mut self, text: &str) {
+        self.abandon();
+        let mv = parse_move(self.game.state(), text, self.chess960);
+        let mv = match mv {
+            Some(mv) if self.game.legal_moves().contains(&mv) => mv,
+            _ => {
+                println!("Illegal move: {}", text);
+                return;
+            }
+        };
+        self.game.make_move(mv);
+        if self.analyzing {
+            self.restart_analysis();
+        } else if let Some(line) = result_line(&self.game) {
+            println!("{}", line);
+        } else if self.engine_side == Some(self.game.state().side_to_move()) {
+            self.think();
+        }
+    }
+
+    fn limits(&self) -> SearchLimits {
+        let mut limits = SearchLimits {
+            depth: self.depth,
+            ..SearchLimits::default()
+        };
+        limits.movetime = Some(match self.move_time {
+            Some(move_time) => move_time,
+            None => {
+                let time = self.time_left.unwrap_or(self.base).as_millis() as u64;
+                let moves_to_go = (self.session_moves > 0).then(|| {
+                    let played = (self.game.moves().len() / 2) as u32;
+                    (self.session_moves - played % self.session_moves) as u64
+                });
+                uci::time_budget(time, self.increment.as_millis() as u64, moves_to_go)
+            }
+        });
+        limits
+    }
+
+    fn think(&mut self) {
+        if self.game.outcome().is_none() {
+            self.start(self.limits(), true);
+        }
+    }
+
+    fn restart_analysis(&mut self) {
+        if self.analyzing {
+            self.abandon();
+            self.start(SearchLimits::default(), false);
+        }
+    }
+
+    // analysis always shows its thinking and never plays
+    fn start(&mut self, limits: SearchLimits, play: bool) {
+        let mut search = Search::new(limits);
+        let stop = search.stop_flag();
+        let abandon = Arc::new(AtomicBool::new(false));
+        let abandoned = abandon.clone();
+        let post = self.post || !play;
+        let chess960 = self.chess960;
+        let mut game = self.game.clone();
+
+        let handle = thread::spawn(move || {
+            let start = game.state().clone();
+            let mut state = start.clone();
+            let result = search.run_with_info(&mut state, |result| {
+                if post {
+                    println!("{}", thinking_line(&start, result));
+                }
+            });
+            let mv = result.best_move.filter(|_| play)?;
+            if abandoned.load(Ordering::Relaxed) {
+                return None;
+            }
+            println!("move {}", move_text(mv, chess960));
+            game.make_move(mv);
+            if let Some(line) = result_line(&game) {
+                println!("{}", line);
+            }
+            Some(mv)
+        });
+        self.search = Some(Thinking {
+            stop,
+            abandon,
+            handle,
+        });
+    }
+
+    // ends the running search, playing its move
+    fn stop(&mut self) {
+        self.finish(false);
+    }
+
+    // ends the running search, dropping its move unless it was already played
+    fn abandon(&mut self) {
+        self.finish(true);
+    }
+
+    fn finish(&mut self, abandon: bool) {
+        if let Some(thinking) = self.search.take() {
+            thinking.abandon.store(abandon, Ordering::Relaxed);
+            thinking.stop.store(true, Ordering::Relaxed);
+            if let Some(mv) = thinking.handle.join().unwrap() {
+                self.game.make_move(mv);
+            }
+        }
+    }
+}
+
+fn features() {
+    println!("feature done=0");
+    println!(
+        "feature myname=\"{}\" ping=1 setboard=1 usermove=1 playother=1 time=1 draw=0 \
+         sigint=0 sigterm=0 reuse=1 analyze=1 colors=0 san=0 variants=\"normal,fischerandom\"",
+        ENGINE_NAME
+    );
+    for (name, kind) in OPTIONS {
+        println!("feature option=\"{} {}\"", name, kind);
+    }
+    println!("feature done=1");
+}
+
+// level <moves> <minutes[:seconds]> <increment seconds>
+fn parse_level(args: &str) -> Option<(u32, Duration, Duration)> {
+    let args: Vec<&str> = args.split_whitespace().collect();
+    let [moves, base, increment] = args[..] else {
+        return None;
+    };
+    let (minutes, seconds) = base.split_once(':').unwrap_or((base, "0"));
+    let base = minutes.parse::<u64>().ok()? * 60 + seconds.parse::<u64>().ok()?;
+    let increment = Duration::try_from_secs_f64(increment.parse().ok()?).ok()?;
+    Some((moves.parse().ok()?, Duration::from_secs(base), increment))
+}
+
+// a move from the GUI, castling is O-O or O-O-O in fischerandom and the king's two-square
+// move otherwise, though king-takes-rook is understood either way
+fn parse_move(state: &State, text: &str, chess960: bool) -> Option<Move> {
+    let side = match text {
+        "O-O" | "0-0" => CastlingSide::King,
+        "O-O-O" | "0-0-0" => CastlingSide::Queen,
+        _ => return state.parse_uci_move(text, chess960).ok(),
+    };
+    state
+        .legal_moves()
+        .into_iter()
+        .find(|mv| mv.castling_side() == Some(side))
+}
+
+fn move_text(mv: Move, chess960: bool) -> String {
+    match mv.castling_side() {
+        Some(CastlingSide::King) if chess960 => "O-O".to_string(),
+        Some(CastlingSide::Queen) if chess960 => "O-O-O".to_string(),
+        _ => mv.to_uci(chess960),
+    }
+}
+
+// depth, score, time in centiseconds, nodes and the PV in SAN
+fn thinking_line(start: &State, result: &SearchResult) -> String {
+    let score = match mate_in(result.score) {
+        Some(moves) if moves > 0 => MATE_BASE + moves,
+        Some(moves) => -MATE_BASE + moves,
+        None => result.score,
+    };
+    let mut state = start.clone();
+    let mut pv = Vec::new();
+    for &mv in &result.pv {
+        pv.push(state.to_san(mv));
+        state.make_move(mv);
+    }
+    format!(
+        "{} {} {} {} {}",
+        result.depth,
+        score,
+        result.elapsed.as_millis() / 10,
+        result.nodes,
+        pv.join(" ")
+    )
+}
+
+// the result command for a finished game, claiming draws by repetition and the fifty moves
+fn result_line(game: &Game) -> Option<String> {
+    let outcome = game.outcome()?;
+    let reason = match (outcome.termination, outcome.result) {
+        (Termination::Checkmate, GameResult::WhiteWins) => "White mates".to_string(),
+        (Termination::Checkmate, _) => "Black mates".to_string(),
+        (termination, _) => format!("Draw by {}", termination),
+    };
+    Some(format!("{} {{{}}}", outcome.result, reason))
+}
+
+pub fn run(lines: impl Iterator<Item = String>) {
+    let mut xboard = Xboard::new();
+    for line in lines {
+        if !xboard.handle(line.trim()) {
+            break;
+        }
+    }
+    xboard.abandon();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::square::Square;
+
+    fn fen(xboard: &Xboard) -> String {
+        xboard.game.state().to_fen()
+    }
+
+    #[test]
+    fn plays_games() {
+        let mut xboard = Xboard::new();
+        for command in ["xboard", "protover 2", "new", "sd 2", "usermove e2e4"] {
+            assert!(xboard.handle(command));
+        }
+        // the engine plays black and answers right away
+        xboard.stop();
+        assert_eq!(xboard.game.moves().len(), 2);
+
+        xboard.handle("force");
+        xboard.handle("usermove d2d4");
+        xboard.handle("usermove e2e5");
+        assert!(xboard.search.is_none());
+        assert_eq!(xboard.game.moves().len(), 3);
+        xboard.handle("remove");
+        assert_eq!(xboard.game.moves().len(), 1);
+        xboard.handle("undo");
+        assert_eq!(fen(&xboard), State::default().to_fen());
+
+        // go makes the engine play the side to move, and a bare move is a usermove too
+        xboard.handle("go");
+        xboard.stop();
+        assert_eq!(xboard.engine_side, Some(Color::White));
+        assert_eq!(xboard.game.moves().len(), 1);
+        xboard.handle("e7e5");
+        xboard.stop();
+        assert_eq!(xboard.game.moves().len(), 3);
+
+        // a mate from the GUI ends the game without a reply
+        xboard.handle("setboard 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1");
+        xboard.handle("playother");
+        xboard.handle("usermove a1a8");
+        assert!(xboard.search.is_none());
+        assert_eq!(result_line(&xboard.game).unwrap(), "1-0 {White mates}");
+
+        xboard.handle("setboard not a fen");
+        assert!(xboard.handle("ping 7"));
+        assert!(!xboard.handle("quit"));
+    }
+
+    #[test]
+    fn fischerandom() {
+        let mut xboard = Xboard::new();
+        xboard.handle("variant fischerandom");
+        xboard.handle("force");
+        xboard.handle("setboard 4k3/8/8/8/8/8/8/1R4KR w HB - 0 1");
+        // g1h1 is castling, O-O keeps the king where it is
+        xboard.handle("usermove O-O");
+        assert_eq!(fen(&xboard), "4k3/8/8/8/8/8/8/1R3RK1 b - - 1 1");
+        xboard.handle("undo");
+        xboard.handle("usermove g1b1");
+        assert_eq!(fen(&xboard), "4k3/8/8/8/8/8/8/2KR3R b - - 1 1");
+        let castle = Move::castling(Square::G1, Square::B1);
+        assert_eq!(move_text(castle, true), "O-O-O");
+        assert_eq!(move_text(castle, false), "g1c1");
+
+        xboard.handle("new");
+        assert!(!xboard.chess960);
+    }
+
+    #[test]
+    fn analysis_never_plays() {
+        let mut xboard = Xboard::new();
+        xboard.handle("setboard 4k3/8/8/8/8/8/4P3/4K3 w - - 0 1");
+        xboard.handle("analyze");
+        thread::sleep(Duration::from_millis(20));
+        xboard.handle("usermove e2e4");
+        xboard.handle("undo");
+        assert!(xboard.search.is_some());
+        xboard.handle("exit");
+        assert!(xboard.search.is_none());
+        assert_eq!(fen(&xboard), "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1");
+    }
+
+    #[test]
+    fn time_controls() {
+        assert_eq!(
+            parse_level("40 5 0"),
+            Some((40, Duration::from_secs(300), Duration::ZERO))
+        );
+        assert_eq!(
+            parse_level("0 2:30 1.5"),
+            Some((0, Duration::from_secs(150), Duration::from_millis(1500)))
+        );
+        assert_eq!(parse_level("0 2"), None);
+        assert_eq!(parse_level("0 x 1"), None);
+
+        let mut xboard = Xboard::new();
+        xboard.handle("level 40 5 0");
+        xboard.handle("time 6000");
+        // 60s over the 40 moves left in the session
+        assert_eq!(xboard.limits().movetime, Some(Duration::from_millis(1500)));
+        xboard.handle("level 0 1 2");
+        xboard.handle("time 1000");
+        assert_eq!(xboard.limits().movetime, Some(Duration::from_millis(1333)));
+        xboard.handle("st 3");
+        xboard.handle("sd 7");
+        let limits = xboard.limits();
+        assert_eq!(limits.movetime, Some(Duration::from_secs(3)));
+        assert_eq!(limits.depth, Some(7));
+    }
+
+    #[test]
+    fn thinking_output() {
+        let state = State::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        let mut search = Search::new(SearchLimits {
+            depth: Some(3),
+            ..SearchLimits::default()
+        });
+        let result = search.run(&mut state.clone());
+        let line = thinking_line(&state, &result);
+        let fields: Vec<&str> = line.split(' ').collect();
+        assert_eq!(fields[1], "100001");
+        assert_eq!(fields[4], "Ra8#");
+    }
+}
@@ -0,0 +1,74 @@
+use crate::bitboard::*;
+use crate::bitboard_moves::pawn_attacks;
+use crate::file::FILE_COUNT;
+use crate::piece::*;
+use crate::square::SQUARE_COUNT;
+use crate::state::{CastlingSide, State, CASTLING_SIDE_COUNT};
+
+// the next state and an output, also handy wherever something needs to be random
+pub const fn splitmix64(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    (state, z ^ (z >> 31))
+}
+
+const fn generate_keys<const N: usize>(seed: u64) -> [u64; N] {
+    let mut keys = [0; N];
+    let mut state = seed;
+    let mut i = 0;
+    while i < N {
+        let (next, key) = splitmix64(state);
+        state = next;
+        keys[i] = key;
+        i += 1;
+    }
+    keys
+}
+
+const PIECE_KEYS: [u64; PIECE_COUNT * SQUARE_COUNT] = generate_keys(0x1);
+// castling rights are rook files, so there's a key per color, side and file
+const CASTLING_KEYS: [u64; COLOR_COUNT * CASTLING_SIDE_COUNT * FILE_COUNT] = generate_keys(0x2);
+const EN_PASSANT_KEYS: [u64; FILE_COUNT] = generate_keys(0x3);
+const SIDE_KEY: u64 = generate_keys::<1>(0x4)[0];
+
+impl State {
+    // the en passant square only matters when a pawn could actually take,
+    // otherwise identical positions would hash differently for repetitions
+    fn en_passant_capturable(&self) -> bool {
+        match self.en_passant {
+            Some(ep) => {
+                let us = self.side_to_move();
+                let pawns = self.board.by_piece(Piece::new(us, PieceType::Pawn));
+                pawn_attacks(!us, ep) & pawns != EMPTY
+            }
+            None => false,
+        }
+    }
+
+    pub fn hash(&self) -> u64 {
+        let mut hash = 0;
+        for (square, piece) in self.board.pieces() {
+            if let Some(piece) = piece {
+                hash ^= PIECE_KEYS[piece.index() * SQUARE_COUNT + square.index()];
+            }
+        }
+        for color in [Color::White, Color::Black] {
+            for side in [CastlingSide::King, CastlingSide::Queen] {
+                if let Some(file) = self.castling.rook_file(color, side) {
+                    let index = (color.index() * CASTLING_SIDE_COUNT + side.index()) * FILE_COUNT
+                        + file.index();
+                    hash ^= CASTLING_KEYS[index];
+                }
+            }
+        }
+        if self.en_passant_capturable() {
+            hash ^= EN_PASSANT_KEYS[self.en_passant.unwrap().file().index()];
+        }
+        if self.side_to_move() == Color::Black {
+            hash ^= SIDE_KEY;
+        }
+        hash
+    }
+}